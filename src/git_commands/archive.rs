@@ -0,0 +1,849 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+
+use crate::git_commands::log::{civil_from_days, format_date, timezone_offset};
+use crate::git_commands::refs::resolve_revision;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::tree_diff::{lookup_path, read_tree};
+use crate::git_commands::utils::read_raw_object;
+use crate::models::attributes::Attributes;
+use crate::models::commit::Commit;
+use crate::models::tree::TreeEntryMode;
+
+const USAGE: &str = "usage: git archive [--format=<fmt>] [--prefix=<prefix>/] [-o <file>] \
+                     [-<level>] <tree-ish> [<path>...]\n   or: git archive --list";
+const FORMATS: [&str; 4] = ["tar", "tgz", "tar.gz", "zip"];
+const RECORD_SIZE: usize = 512;
+// tar output is written in blocks of 20 records, like upstream
+const BLOCK_SIZE: usize = RECORD_SIZE * 20;
+
+/// Writes the files of a tree-ish as a tar, gzipped tar or zip archive, every path under
+/// `--prefix`, to standard output or the `-o` file (whose extension picks the format when
+/// `--format` does not). Executable bits and symlinks are kept, every entry is dated with the
+/// commit's time, and the commit id goes into a pax global header (a tar) or the archive
+/// comment (a zip). Paths limit the archive to those files and directories. Attributes from
+/// the tree's `.gitattributes` files and `info/attributes` leave out `export-ignore` paths and
+/// expand `$Format:...$` placeholders in `export-subst` files.
+pub fn archive<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut format = None;
+    let mut prefix = String::new();
+    let mut output_path = None;
+    let mut level = Compression::default();
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "-l" | "--list" => {
+                return FORMATS
+                    .iter()
+                    .try_for_each(|format| writeln!(writer, "{}", format))
+                    .map_err(|err| err.to_string())
+            }
+            "-o" => output_path = Some(*args.next().ok_or(USAGE)?),
+            _ if arg.starts_with("--output=") => output_path = Some(&arg["--output=".len()..]),
+            _ if arg.starts_with("--format=") => format = Some(&arg["--format=".len()..]),
+            _ if arg.starts_with("--prefix=") => prefix = arg["--prefix=".len()..].to_string(),
+            _ if arg.len() == 2 && arg.as_bytes()[1].is_ascii_digit() && arg.starts_with('-') => {
+                level = Compression::new((arg.as_bytes()[1] - b'0') as u32)
+            }
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => positional.push(*arg),
+        }
+    }
+
+    let Some((tree_ish, paths)) = positional.split_first() else {
+        return Err(USAGE.to_string());
+    };
+    let format = format
+        .or_else(|| output_path.and_then(format_from_extension))
+        .unwrap_or("tar");
+
+    if !FORMATS.contains(&format) {
+        return Err(format!("fatal: Unknown archive format '{}'\n", format));
+    }
+
+    let sha = RevWalk::peel(git_dir, &resolve_revision(git_dir, tree_ish)?)?;
+    let (object_type, content) = read_raw_object(git_dir, &sha)?;
+    let (tree, commit) = match object_type.as_str() {
+        "commit" => {
+            let commit = Commit::new(content)?;
+
+            (commit.tree.clone(), Some((sha, commit)))
+        }
+        "tree" => (sha, None),
+        _ => return Err(format!("fatal: not a tree object: {}\n", sha)),
+    };
+
+    for path in paths {
+        if lookup_path(git_dir, &tree, path)?.is_none() {
+            return Err(format!(
+                "fatal: pathspec '{}' did not match any files\n",
+                path
+            ));
+        }
+    }
+
+    // without a commit, entries are dated now, like upstream
+    let (time, timezone) = match &commit {
+        Some((_, commit)) => signature_date(&commit.committer),
+        None => (
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|err| err.to_string())?
+                .as_secs() as i64,
+            "+0000".to_string(),
+        ),
+    };
+    let mut info_attributes = Attributes::default();
+
+    if let Ok(content) = fs::read_to_string(git_dir.join("info/attributes")) {
+        info_attributes.add("", &content);
+    }
+
+    let mut walker = TreeWalker {
+        git_dir,
+        prefix: &prefix,
+        paths: paths
+            .iter()
+            .map(|path| path.trim_end_matches('/').to_string())
+            .collect(),
+        commit: commit.as_ref(),
+        info_attributes,
+        attributes: Attributes::default(),
+    };
+    let commit_id = commit.as_ref().map(|(sha, _)| sha.as_str());
+    let mut file;
+    let output: &mut dyn Write = match output_path {
+        Some(path) => {
+            file = File::create(path).map_err(|err| {
+                format!("fatal: could not create archive file '{}': {}\n", path, err)
+            })?;
+            &mut file
+        }
+        None => writer,
+    };
+
+    match format {
+        "zip" => {
+            let mut zip = ZipWriter::new(output, time, &timezone, level);
+
+            walker.write(&tree, &mut zip)?;
+            zip.finish(commit_id)
+        }
+        "tgz" | "tar.gz" => {
+            let mut gzip = GzEncoder::new(output, level);
+            let mut tar = TarWriter::new(&mut gzip, time, commit_id)?;
+
+            walker.write(&tree, &mut tar)?;
+            tar.finish()?;
+            gzip.finish().map(|_| ()).map_err(|err| err.to_string())
+        }
+        _ => {
+            let mut tar = TarWriter::new(output, time, commit_id)?;
+
+            walker.write(&tree, &mut tar)?;
+            tar.finish()
+        }
+    }
+}
+
+fn format_from_extension(path: &str) -> Option<&'static str> {
+    if path.ends_with(".zip") {
+        Some("zip")
+    } else if path.ends_with(".tgz") || path.ends_with(".tar.gz") {
+        Some("tgz")
+    } else if path.ends_with(".tar") {
+        Some("tar")
+    } else {
+        None
+    }
+}
+
+/// Splits `Name <email> 1700000000 +0100` into `(name, email)`.
+fn signature_identity(signature: &str) -> (&str, &str) {
+    let email_start = signature.find('<').unwrap_or(signature.len());
+    let email_end = signature.rfind('>').unwrap_or(signature.len());
+
+    (
+        signature[..email_start].trim_end(),
+        signature.get(email_start + 1..email_end).unwrap_or(""),
+    )
+}
+
+/// The timestamp and timezone at the end of a signature.
+fn signature_date(signature: &str) -> (i64, String) {
+    let email_end = signature.rfind('>').map(|idx| idx + 1).unwrap_or(0);
+    let mut parts = signature[email_end..].split_whitespace();
+    let timestamp = parts.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+
+    (timestamp, parts.next().unwrap_or("+0000").to_string())
+}
+
+/// Where an archive's entries go, one at a time and parents first.
+trait ArchiveWriter {
+    /// Adds an entry: a directory (its path ending in `/`), a file with its content, or a
+    /// symlink with its target. `sha` names the object for the entries that need a made-up
+    /// name.
+    fn add(
+        &mut self,
+        path: &str,
+        mode: &TreeEntryMode,
+        sha: &str,
+        content: &[u8],
+    ) -> Result<(), String>;
+}
+
+struct TreeWalker<'a> {
+    git_dir: &'a Path,
+    prefix: &'a str,
+    paths: Vec<String>,
+    commit: Option<&'a (String, Commit)>,
+    info_attributes: Attributes,
+    attributes: Attributes,
+}
+
+impl TreeWalker<'_> {
+    fn write<A: ArchiveWriter>(&mut self, tree: &str, archive: &mut A) -> Result<(), String> {
+        if self.prefix.ends_with('/') {
+            archive.add(self.prefix, &TreeEntryMode::Directory, tree, &[])?;
+        }
+
+        self.walk(tree, "", archive)
+    }
+
+    fn walk<A: ArchiveWriter>(
+        &mut self,
+        tree: &str,
+        dir: &str,
+        archive: &mut A,
+    ) -> Result<(), String> {
+        let entries = read_tree(self.git_dir, tree)?.tree_entries;
+        let attributes_file = entries
+            .iter()
+            .find(|entry| entry.name == ".gitattributes" && entry.mode != TreeEntryMode::Directory);
+
+        if let Some(entry) = attributes_file {
+            let content = read_raw_object(self.git_dir, &entry.sha)?.1;
+
+            self.attributes.add(dir, &String::from_utf8_lossy(&content));
+        }
+
+        for entry in &entries {
+            let path = format!("{}{}", dir, entry.name);
+            let is_dir = matches!(
+                entry.mode,
+                TreeEntryMode::Directory | TreeEntryMode::Submodule
+            );
+
+            if self.has_attribute(&path, is_dir, "export-ignore") || !self.is_wanted(&path, is_dir)
+            {
+                continue;
+            }
+
+            let archived_path = format!("{}{}", self.prefix, path);
+
+            match entry.mode {
+                TreeEntryMode::Directory => {
+                    archive.add(&format!("{}/", archived_path), &entry.mode, &entry.sha, &[])?;
+                    self.walk(&entry.sha, &format!("{}/", path), archive)?;
+                }
+                TreeEntryMode::Submodule => {
+                    archive.add(&format!("{}/", archived_path), &entry.mode, &entry.sha, &[])?
+                }
+                _ => {
+                    let mut content = read_raw_object(self.git_dir, &entry.sha)?.1;
+
+                    if let Some((sha, commit)) = self.commit {
+                        if entry.mode != TreeEntryMode::SymbolicLink
+                            && self.has_attribute(&path, false, "export-subst")
+                        {
+                            content = expand_format_placeholders(&content, sha, commit);
+                        }
+                    }
+
+                    archive.add(&archived_path, &entry.mode, &entry.sha, &content)?;
+                }
+            }
+        }
+
+        if attributes_file.is_some() {
+            self.attributes.remove(dir);
+        }
+
+        Ok(())
+    }
+
+    /// `info/attributes` wins over the tree's attribute files.
+    fn has_attribute(&self, path: &str, is_dir: bool, name: &str) -> bool {
+        match self.info_attributes.get(path, is_dir, name) {
+            Some(_) => self.info_attributes.is_set(path, is_dir, name),
+            None => self.attributes.is_set(path, is_dir, name),
+        }
+    }
+
+    /// Whether a path is one the archive was limited to, inside one, or a directory on the
+    /// way to one.
+    fn is_wanted(&self, path: &str, is_dir: bool) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|wanted| {
+                path == wanted
+                    || path
+                        .strip_prefix(wanted.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+                    || (is_dir
+                        && wanted
+                            .strip_prefix(path)
+                            .is_some_and(|rest| rest.starts_with('/')))
+            })
+    }
+}
+
+/// Replaces each `$Format:<format>$` in an `export-subst` file with the commit formatted by
+/// `<format>`.
+fn expand_format_placeholders(content: &[u8], sha: &str, commit: &Commit) -> Vec<u8> {
+    const START: &[u8] = b"$Format:";
+
+    let mut expanded = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.windows(START.len()).position(|window| window == START) {
+        let after = &rest[start + START.len()..];
+        let Some(end) = after.iter().position(|&b| b == b'$') else {
+            break;
+        };
+
+        expanded.extend_from_slice(&rest[..start]);
+        expanded.extend(
+            format_commit(&String::from_utf8_lossy(&after[..end]), sha, commit).into_bytes(),
+        );
+        rest = &after[end + 1..];
+    }
+
+    expanded.extend_from_slice(rest);
+    expanded
+}
+
+/// Formats a commit like `git log --pretty=format:`, for the placeholders `%H`, `%h`, `%T`,
+/// `%t`, `%P`, `%p`, `%an`, `%ae`, `%ad`, `%at`, `%cn`, `%ce`, `%cd`, `%ct`, `%s`, `%b`, `%B`,
+/// `%n` and `%%`. Others are kept as they are.
+fn format_commit(format: &str, sha: &str, commit: &Commit) -> String {
+    let (subject, body) = match commit.message.split_once("\n\n") {
+        Some((subject, body)) => (subject, body),
+        None => (commit.message.trim_end_matches('\n'), ""),
+    };
+    let signature = |signature: &str, field: char| -> String {
+        let (name, email) = signature_identity(signature);
+        let (timestamp, timezone) = signature_date(signature);
+
+        match field {
+            'n' => name.to_string(),
+            'e' => email.to_string(),
+            'd' => format_date(timestamp, &timezone),
+            _ => timestamp.to_string(),
+        }
+    };
+    let mut formatted = String::new();
+    let mut rest = format;
+
+    while let Some(idx) = rest.find('%') {
+        formatted.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let mut chars = rest.chars();
+        let (expansion, used) = match (chars.next(), chars.next()) {
+            (Some('H'), _) => (sha.to_string(), 1),
+            (Some('h'), _) => (sha[..7].to_string(), 1),
+            (Some('T'), _) => (commit.tree.clone(), 1),
+            (Some('t'), _) => (commit.tree[..7].to_string(), 1),
+            (Some('P'), _) => (commit.parents.join(" "), 1),
+            (Some('p'), _) => (
+                commit
+                    .parents
+                    .iter()
+                    .map(|parent| &parent[..7])
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                1,
+            ),
+            (Some('a'), Some(field @ ('n' | 'e' | 'd' | 't'))) => {
+                (signature(&commit.author, field), 2)
+            }
+            (Some('c'), Some(field @ ('n' | 'e' | 'd' | 't'))) => {
+                (signature(&commit.committer, field), 2)
+            }
+            (Some('s'), _) => (subject.lines().collect::<Vec<_>>().join(" "), 1),
+            (Some('b'), _) => (body.to_string(), 1),
+            (Some('B'), _) => (commit.message.clone(), 1),
+            (Some('n'), _) => ("\n".to_string(), 1),
+            (Some('%'), _) => ("%".to_string(), 1),
+            _ => ("%".to_string(), 0),
+        };
+
+        formatted.push_str(&expansion);
+        rest = &rest[used..];
+    }
+
+    formatted.push_str(rest);
+    formatted
+}
+
+/// Writes a POSIX tar archive the way upstream does: ustar headers owned by root with modes
+/// from a 002 umask, pax extended headers for paths and link targets that do not fit, and
+/// zeros up to a whole block at the end.
+struct TarWriter<W: Write> {
+    writer: W,
+    written: usize,
+    time: i64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Starts the archive with a pax global header carrying the commit id, if there is one.
+    fn new(writer: W, time: i64, commit_id: Option<&str>) -> Result<Self, String> {
+        let mut tar = Self {
+            writer,
+            written: 0,
+            time,
+        };
+
+        if let Some(commit_id) = commit_id {
+            let mut extended = Vec::new();
+
+            push_pax_record(&mut extended, "comment", commit_id.as_bytes());
+            tar.write_header(b"pax_global_header", b"", b"", b'g', 0o666, extended.len())?;
+            tar.write_blocked(&extended)?;
+        }
+
+        Ok(tar)
+    }
+
+    fn write_blocked(&mut self, data: &[u8]) -> Result<(), String> {
+        let padding = (RECORD_SIZE - data.len() % RECORD_SIZE) % RECORD_SIZE;
+
+        self.write_raw(data)?;
+        self.write_raw(&vec![0; padding])
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> Result<(), String> {
+        self.written += data.len();
+        self.writer.write_all(data).map_err(|err| err.to_string())
+    }
+
+    fn write_header(
+        &mut self,
+        name: &[u8],
+        prefix: &[u8],
+        linkname: &[u8],
+        typeflag: u8,
+        mode: u32,
+        size: usize,
+    ) -> Result<(), String> {
+        let mut header = [0u8; RECORD_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(0, name);
+        put(100, format!("{:07o}", mode).as_bytes());
+        put(108, b"0000000");
+        put(116, b"0000000");
+        put(124, format!("{:011o}", size).as_bytes());
+        put(136, format!("{:011o}", self.time).as_bytes());
+        put(148, b"        ");
+        put(156, &[typeflag]);
+        put(157, linkname);
+        put(257, b"ustar\0");
+        put(263, b"00");
+        put(265, b"root");
+        put(297, b"root");
+        put(329, b"0000000");
+        put(337, b"0000000");
+        put(345, prefix);
+
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+
+        header[148..156].copy_from_slice(format!("{:07o}\0", checksum).as_bytes());
+        self.write_raw(&header)
+    }
+
+    /// Ends the archive: zeros to the end of the block, and a whole block more when that
+    /// leaves less than the two empty records a tar ends with.
+    fn finish(mut self) -> Result<(), String> {
+        let tail = BLOCK_SIZE - self.written % BLOCK_SIZE;
+
+        self.write_raw(&vec![0; tail])?;
+
+        if tail < 2 * RECORD_SIZE {
+            self.write_raw(&[0; BLOCK_SIZE])?;
+        }
+
+        self.writer.flush().map_err(|err| err.to_string())
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add(
+        &mut self,
+        path: &str,
+        mode: &TreeEntryMode,
+        sha: &str,
+        content: &[u8],
+    ) -> Result<(), String> {
+        let (typeflag, mode, size) = match mode {
+            TreeEntryMode::Directory | TreeEntryMode::Submodule => (b'5', 0o775, 0),
+            TreeEntryMode::SymbolicLink => (b'2', 0o777, 0),
+            TreeEntryMode::ExecutableFile => (b'0', 0o775, content.len()),
+            TreeEntryMode::RegularFile => (b'0', 0o664, content.len()),
+        };
+        let path = path.as_bytes();
+        let mut extended = Vec::new();
+        let data_name = format!("{}.data", sha);
+        let (prefix, name) = if path.len() <= 100 {
+            (&b""[..], path)
+        } else {
+            let split = path_prefix(path, 155);
+
+            if split > 0 && path.len() - split - 1 <= 100 {
+                (&path[..split], &path[split + 1..])
+            } else {
+                push_pax_record(&mut extended, "path", path);
+                (&b""[..], data_name.as_bytes())
+            }
+        };
+        let see_pax = format!("see {}.paxheader", sha);
+        let linkname = match typeflag {
+            b'2' if content.len() > 100 => {
+                push_pax_record(&mut extended, "linkpath", content);
+                see_pax.as_bytes()
+            }
+            b'2' => content,
+            _ => b"",
+        };
+
+        if !extended.is_empty() {
+            let pax_name = format!("{}.paxheader", sha);
+
+            self.write_header(pax_name.as_bytes(), b"", b"", b'x', 0o666, extended.len())?;
+            self.write_blocked(&extended)?;
+        }
+
+        self.write_header(name, prefix, linkname, typeflag, mode, size)?;
+
+        if size > 0 {
+            self.write_blocked(content)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a path too long for a ustar name is split into the prefix field and the name: at
+/// the last `/` that keeps the prefix within `max` bytes.
+fn path_prefix(path: &[u8], max: usize) -> usize {
+    let mut idx = path.len();
+
+    if idx > 1 && path[idx - 1] == b'/' {
+        idx -= 1;
+    }
+
+    idx = idx.min(max);
+
+    loop {
+        idx -= 1;
+
+        if idx == 0 || path[idx] == b'/' {
+            return idx;
+        }
+    }
+}
+
+/// Appends a `<length> <keyword>=<value>\n` pax record, whose length counts itself.
+fn push_pax_record(extended: &mut Vec<u8>, keyword: &str, value: &[u8]) {
+    // counted like upstream, with as many digits as the length without them has
+    let len = keyword.len() + value.len() + 3;
+    let len = len + len.to_string().len();
+
+    extended.extend_from_slice(format!("{} {}=", len, keyword).as_bytes());
+    extended.extend_from_slice(value);
+    extended.push(b'\n');
+}
+
+/// Writes a zip archive: each file deflated when it is not empty, with its Unix mode for
+/// executables and symlinks, and the commit id as the archive comment.
+struct ZipWriter<W: Write> {
+    writer: W,
+    written: u32,
+    central_directory: Vec<u8>,
+    entries: u16,
+    time: u32,
+    dos_time: u16,
+    dos_date: u16,
+    level: Compression,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn new(writer: W, time: i64, timezone: &str, level: Compression) -> Self {
+        // zip dates are local times, here the commit's
+        let local = time + timezone_offset(timezone);
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        let seconds = local.rem_euclid(86400);
+
+        Self {
+            writer,
+            written: 0,
+            central_directory: Vec::new(),
+            entries: 0,
+            time: time as u32,
+            dos_time: ((seconds / 3600) << 11 | (seconds % 3600 / 60) << 5 | ((seconds % 60) / 2))
+                as u16,
+            dos_date: (((year - 1980).max(0)) << 9 | month << 5 | day) as u16,
+            level,
+        }
+    }
+
+    fn finish(mut self, commit_id: Option<&str>) -> Result<(), String> {
+        let comment = commit_id.unwrap_or_default().as_bytes();
+        let mut end = Vec::new();
+
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&(self.central_directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&self.written.to_le_bytes());
+        end.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        end.extend_from_slice(comment);
+
+        let central_directory = std::mem::take(&mut self.central_directory);
+
+        self.writer
+            .write_all(&central_directory)
+            .and_then(|_| self.writer.write_all(&end))
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn add(
+        &mut self,
+        path: &str,
+        mode: &TreeEntryMode,
+        _sha: &str,
+        content: &[u8],
+    ) -> Result<(), String> {
+        // external attributes: the MS-DOS directory flag, or a Unix mode in the high bits
+        let (external, creator) = match mode {
+            TreeEntryMode::Directory | TreeEntryMode::Submodule => (0x10, 0),
+            TreeEntryMode::SymbolicLink => (0o120777 << 16, 0x0317),
+            TreeEntryMode::ExecutableFile => (0o100755 << 16, 0x0317),
+            TreeEntryMode::RegularFile => (0, 0),
+        };
+        let is_file = matches!(
+            mode,
+            TreeEntryMode::RegularFile | TreeEntryMode::ExecutableFile
+        );
+        let data = if is_file && !content.is_empty() && self.level.level() > 0 {
+            let mut encoder = DeflateEncoder::new(Vec::new(), self.level);
+
+            encoder.write_all(content).map_err(|err| err.to_string())?;
+            Some(encoder.finish().map_err(|err| err.to_string())?)
+        } else {
+            None
+        };
+        // stored as is when deflating does not make it smaller
+        let data = data.filter(|deflated| deflated.len() < content.len());
+        let (method, data) = match &data {
+            Some(deflated) => (8u16, deflated.as_slice()),
+            None => (0, content),
+        };
+        let mut crc = Crc::new();
+
+        crc.update(content);
+
+        // names that are not ASCII are flagged as UTF-8
+        let flags: u16 = if path.is_ascii() { 0 } else { 1 << 11 };
+        let mut extended_timestamp = vec![0x55, 0x54, 5, 0, 1];
+
+        extended_timestamp.extend_from_slice(&self.time.to_le_bytes());
+
+        let mut common = Vec::new();
+
+        // version 1.0 is needed to extract, as upstream claims even for deflated entries
+        common.extend_from_slice(&10u16.to_le_bytes());
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&self.dos_time.to_le_bytes());
+        common.extend_from_slice(&self.dos_date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        common.extend_from_slice(&(extended_timestamp.len() as u16).to_le_bytes());
+
+        let mut local = 0x04034b50u32.to_le_bytes().to_vec();
+
+        local.extend_from_slice(&common);
+        local.extend_from_slice(path.as_bytes());
+        local.extend_from_slice(&extended_timestamp);
+        local.extend_from_slice(data);
+
+        let central = &mut self.central_directory;
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&(creator as u16).to_le_bytes());
+        central.extend_from_slice(&common);
+        // no comment, disk 0, then whether the entry is text: no NUL early on, like upstream
+        let is_text = !matches!(mode, TreeEntryMode::Directory | TreeEntryMode::Submodule)
+            && !content.iter().take(8000).any(|&b| b == 0);
+
+        central.extend_from_slice(&[0; 4]);
+        central.extend_from_slice(&(is_text as u16).to_le_bytes());
+        central.extend_from_slice(&(external as u32).to_le_bytes());
+        central.extend_from_slice(&self.written.to_le_bytes());
+        central.extend_from_slice(path.as_bytes());
+        central.extend_from_slice(&extended_timestamp);
+
+        self.writer
+            .write_all(&local)
+            .map_err(|err| err.to_string())?;
+        self.written += local.len() as u32;
+        self.entries += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+
+    /// The `(name, typeflag, mode, content)` of each entry of a tar archive.
+    fn tar_entries(data: &[u8]) -> Vec<(String, u8, String, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while data[offset] != 0 {
+            let header = &data[offset..offset + RECORD_SIZE];
+            let field = |start: usize, len: usize| {
+                let field = &header[start..start + len];
+                let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+
+                String::from_utf8_lossy(&field[..end]).to_string()
+            };
+            let size = usize::from_str_radix(&field(124, 12), 8).unwrap();
+
+            offset += RECORD_SIZE;
+            entries.push((
+                field(0, 100),
+                header[156],
+                field(100, 8),
+                data[offset..offset + size].to_vec(),
+            ));
+            offset += size.div_ceil(RECORD_SIZE) * RECORD_SIZE;
+        }
+
+        entries
+    }
+
+    #[test]
+    fn archive_writes_tar_with_modes_attributes_and_commit_id() {
+        let git_dir = init_git_dir();
+        let attributes = write_blob(
+            git_dir.path(),
+            "secret export-ignore\nversion export-subst\n",
+        );
+        let run = write_blob(git_dir.path(), "#!/bin/sh\n");
+        let secret = write_blob(git_dir.path(), "hidden\n");
+        let target = write_blob(git_dir.path(), "run");
+        let version = write_blob(git_dir.path(), "$Format:%H %s$\n");
+        let dir = write_tree(git_dir.path(), &[("120000", "link", &target)]);
+        let tree = write_tree(
+            git_dir.path(),
+            &[
+                ("100644", ".gitattributes", &attributes),
+                ("40000", "dir", &dir),
+                ("100755", "run", &run),
+                ("100644", "secret", &secret),
+                ("100644", "version", &version),
+            ],
+        );
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "release");
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+        let mut output = Vec::new();
+
+        archive(&["--prefix=p/", "main"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(output.len() % BLOCK_SIZE, 0);
+        assert_eq!(
+            tar_entries(&output),
+            vec![
+                (
+                    "pax_global_header".to_string(),
+                    b'g',
+                    "0000666".to_string(),
+                    format!("52 comment={}\n", commit).into_bytes()
+                ),
+                ("p/".to_string(), b'5', "0000775".to_string(), vec![]),
+                (
+                    "p/.gitattributes".to_string(),
+                    b'0',
+                    "0000664".to_string(),
+                    b"secret export-ignore\nversion export-subst\n".to_vec()
+                ),
+                ("p/dir/".to_string(), b'5', "0000775".to_string(), vec![]),
+                (
+                    "p/dir/link".to_string(),
+                    b'2',
+                    "0000777".to_string(),
+                    vec![]
+                ),
+                (
+                    "p/run".to_string(),
+                    b'0',
+                    "0000775".to_string(),
+                    b"#!/bin/sh\n".to_vec()
+                ),
+                (
+                    "p/version".to_string(),
+                    b'0',
+                    "0000664".to_string(),
+                    format!("{} release\n", commit).into_bytes()
+                ),
+            ]
+        );
+        // the link target and the commit's date
+        assert_eq!(&output[6 * RECORD_SIZE + 157..][..3], b"run");
+        assert_eq!(&output[RECORD_SIZE * 2 + 136..][..11], b"00000000144");
+
+        let mut output = Vec::new();
+
+        archive(&["main", "dir"], git_dir.path(), &mut output).unwrap();
+
+        let names: Vec<String> = tar_entries(&output)
+            .into_iter()
+            .map(|(name, ..)| name)
+            .collect();
+
+        assert_eq!(names, ["pax_global_header", "dir/", "dir/link"]);
+        assert!(archive(&["main", "missing"], git_dir.path(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn push_pax_record_counts_its_own_length() {
+        let mut extended = Vec::new();
+
+        push_pax_record(&mut extended, "path", &[b'a'; 93]);
+
+        assert_eq!(extended.len(), 103);
+        assert!(extended.starts_with(b"103 path=aaa"));
+    }
+}
@@ -0,0 +1,538 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::git_commands::multi_pack_index::{load_multi_pack_index, multi_pack_index_bitmap_file};
+use crate::git_commands::object_walk::referenced_objects;
+use crate::git_commands::packs::{load_packs, pack_dir};
+use crate::git_commands::refs::ref_tips;
+use crate::git_commands::rev_walk::{CommitInfo, RevWalk};
+use crate::git_commands::tree_diff::read_tree;
+use crate::git_commands::utils::read_raw_object;
+use crate::models::bitmap_index::{BitmapEntry, BitmapIndex};
+use crate::models::ewah::Bitmap;
+use crate::models::tree::TreeEntryMode;
+
+const OBJECT_TYPES: [&str; 4] = ["commit", "tree", "blob", "tag"];
+
+/// Reachability bitmaps loaded from a `.bitmap` file, together with the object order their bit
+/// positions refer to.
+pub struct ReachabilityBitmaps {
+    index: BitmapIndex,
+    objects: Vec<String>,
+    positions: HashMap<String, usize>,
+    commit_bitmaps: HashMap<String, usize>,
+}
+
+/// Loads the multi-pack-index bitmap if there is one, otherwise the first pack with a bitmap.
+pub fn load_bitmaps(git_dir: &Path) -> Result<Option<ReachabilityBitmaps>, String> {
+    if let Some(midx) = load_multi_pack_index(git_dir)? {
+        let path = multi_pack_index_bitmap_file(git_dir, &midx.checksum);
+
+        if path.exists() {
+            if midx.pseudo_pack_order.is_empty() {
+                return Err("multi-pack-index bitmap needs a RIDX chunk".to_string());
+            }
+
+            let objects = midx
+                .pseudo_pack_order
+                .iter()
+                .map(|&position| midx.entries[position as usize].sha.clone())
+                .collect();
+            let shas: Vec<&str> = midx.entries.iter().map(|e| e.sha.as_str()).collect();
+
+            return ReachabilityBitmaps::read(&path, &midx.checksum, objects, &shas).map(Some);
+        }
+    }
+
+    for pack_file in load_packs(git_dir)? {
+        let path = pack_dir(git_dir).join(format!("{}.bitmap", pack_file.name));
+
+        if path.exists() {
+            let entries = &pack_file.index.entries;
+            let objects = pack_file
+                .index
+                .pack_order()
+                .into_iter()
+                .map(|idx| entries[idx].sha.clone())
+                .collect();
+            let shas: Vec<&str> = entries.iter().map(|e| e.sha.as_str()).collect();
+
+            return ReachabilityBitmaps::read(
+                &path,
+                &pack_file.index.pack_checksum,
+                objects,
+                &shas,
+            )
+            .map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+impl ReachabilityBitmaps {
+    /// `objects` lists shas in bit order; `shas_by_position` lists them in sha order, which is
+    /// how bitmap entries name their commits.
+    fn read(
+        path: &Path,
+        checksum: &str,
+        objects: Vec<String>,
+        shas_by_position: &[&str],
+    ) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|err| format!("error reading {:?}: {}", path, err))?;
+        let index = BitmapIndex::from_bytes(&data)?;
+
+        if index.checksum != checksum {
+            return Err(format!("bitmap {:?} does not match its pack", path));
+        }
+
+        let commit_bitmaps = index
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                shas_by_position
+                    .get(entry.object_position as usize)
+                    .map(|sha| (sha.to_string(), idx))
+                    .ok_or_else(|| format!("bitmap {:?} names a missing commit", path))
+            })
+            .collect::<Result<HashMap<String, usize>, String>>()?;
+        let positions = objects
+            .iter()
+            .enumerate()
+            .map(|(position, sha)| (sha.clone(), position))
+            .collect();
+
+        Ok(Self {
+            index,
+            objects,
+            positions,
+            commit_bitmaps,
+        })
+    }
+
+    fn type_at(&self, position: usize) -> Option<&'static str> {
+        [
+            &self.index.commits,
+            &self.index.trees,
+            &self.index.blobs,
+            &self.index.tags,
+        ]
+        .into_iter()
+        .zip(OBJECT_TYPES)
+        .find(|(bitmap, _)| bitmap.get(position))
+        .map(|(_, object_type)| object_type)
+    }
+
+    /// Collects everything reachable from `tips`: a bitmap over the bitmapped objects, plus the
+    /// shas of reachable objects that are not covered by it (for example, loose objects).
+    pub fn reachable(
+        &self,
+        git_dir: &Path,
+        tips: &[String],
+    ) -> Result<(Bitmap, Vec<String>), String> {
+        let mut result = Bitmap::new();
+        let mut extra_objects = Vec::new();
+        let mut seen_extra_objects = HashSet::new();
+        let mut stack = tips.to_vec();
+
+        while let Some(sha) = stack.pop() {
+            if let Some(&entry) = self.commit_bitmaps.get(&sha) {
+                result.or(&self.index.entries[entry].bitmap);
+                continue;
+            }
+
+            let known_type = match self.positions.get(&sha) {
+                Some(&position) if result.get(position) => continue,
+                Some(&position) => {
+                    result.set(position);
+                    self.type_at(position)
+                }
+                None if !seen_extra_objects.insert(sha.clone()) => continue,
+                None => {
+                    extra_objects.push(sha.clone());
+                    None
+                }
+            };
+
+            // blobs have nothing to traverse, so their contents never need to be read
+            if known_type != Some("blob") {
+                stack.extend(referenced_objects(git_dir, &sha)?);
+            }
+        }
+
+        Ok((result, extra_objects))
+    }
+
+    /// Lists the objects in `bitmap` grouped by type (commits, trees, blobs, then tags), each
+    /// group in bit order, like upstream's bitmap traversal output.
+    pub fn objects(&self, bitmap: &Bitmap, commits_only: bool) -> Vec<String> {
+        let type_bitmaps = [
+            &self.index.commits,
+            &self.index.trees,
+            &self.index.blobs,
+            &self.index.tags,
+        ];
+        let type_bitmaps = if commits_only {
+            &type_bitmaps[..1]
+        } else {
+            &type_bitmaps[..]
+        };
+
+        type_bitmaps
+            .iter()
+            .flat_map(|type_bitmap| {
+                bitmap
+                    .ones()
+                    .filter(|&position| type_bitmap.get(position))
+                    .filter_map(|position| self.objects.get(position).cloned())
+            })
+            .collect()
+    }
+}
+
+/// Builds a `.bitmap` file for the pack (or multi-pack-index) with `checksum`, whose objects are
+/// listed in bit order by `objects`. Every object reachable from the selected commits must be
+/// among `objects`.
+pub fn write_bitmap_index(
+    git_dir: &Path,
+    objects: &[String],
+    checksum: &str,
+) -> Result<Vec<u8>, String> {
+    let positions: HashMap<&str, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(position, sha)| (sha.as_str(), position))
+        .collect();
+    let mut sorted_shas: Vec<&str> = objects.iter().map(|sha| sha.as_str()).collect();
+    sorted_shas.sort();
+
+    let mut walk = RevWalk::new(git_dir)?;
+    let mut tip_commits = HashSet::new();
+
+    for tip in ref_tips(git_dir)? {
+        let peeled = RevWalk::peel(git_dir, &tip)?;
+
+        if walk.lookup(&peeled).is_ok() {
+            walk.push(&peeled)?;
+            tip_commits.insert(peeled);
+        }
+    }
+
+    let commits: Vec<CommitInfo> = walk.collect::<Result<Vec<CommitInfo>, String>>()?;
+    let selected = select_commits(&commits, &tip_commits);
+    let commits_by_sha: HashMap<&str, &CommitInfo> = commits
+        .iter()
+        .map(|commit| (commit.sha.as_str(), commit))
+        .collect();
+    let mut types: HashMap<usize, &'static str> = HashMap::new();
+    let mut bitmaps: HashMap<&str, Bitmap> = HashMap::new();
+    let mut entries = Vec::new();
+
+    // oldest first, so a commit's selected ancestors usually have bitmaps to reuse already
+    for &commit_idx in selected.iter().rev() {
+        let sha = commits[commit_idx].sha.as_str();
+        let mut bitmap = Bitmap::new();
+        let mut stack = vec![(sha, "commit")];
+
+        while let Some((object, object_type)) = stack.pop() {
+            if object != sha {
+                if let Some(ancestor_bitmap) = bitmaps.get(object) {
+                    bitmap.or(ancestor_bitmap);
+                    continue;
+                }
+            }
+
+            let position = *positions.get(object).ok_or_else(|| {
+                format!(
+                    "object {} is reachable but not in the pack; cannot write bitmaps",
+                    object
+                )
+            })?;
+
+            if bitmap.get(position) {
+                continue;
+            }
+
+            bitmap.set(position);
+            types.insert(position, object_type);
+
+            match object_type {
+                "commit" => {
+                    let commit = commits_by_sha[object];
+
+                    stack.push((commit.tree.as_str(), "tree"));
+                    stack.extend(commit.parents.iter().map(|p| (p.as_str(), "commit")));
+                }
+                "tree" => {
+                    for entry in read_tree(git_dir, object)?.tree_entries {
+                        let entry_type = match entry.mode {
+                            TreeEntryMode::Directory => "tree",
+                            TreeEntryMode::Submodule => continue,
+                            _ => "blob",
+                        };
+                        let entry_sha = positions
+                            .get_key_value(entry.sha.as_str())
+                            .map(|(sha, _)| *sha)
+                            .ok_or_else(|| {
+                                format!(
+                                    "object {} is reachable but not in the pack; cannot write bitmaps",
+                                    entry.sha
+                                )
+                            })?;
+
+                        stack.push((entry_sha, entry_type));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        entries.push(BitmapEntry {
+            object_position: sorted_shas
+                .binary_search(&sha)
+                .map_err(|_| format!("commit {} is reachable but not in the pack", sha))?
+                as u32,
+            bitmap: bitmap.clone(),
+        });
+        bitmaps.insert(sha, bitmap);
+    }
+
+    let mut type_bitmaps = [Bitmap::new(), Bitmap::new(), Bitmap::new(), Bitmap::new()];
+
+    for (position, sha) in objects.iter().enumerate() {
+        let object_type = match types.get(&position) {
+            Some(object_type) => object_type.to_string(),
+            None => read_raw_object(git_dir, sha)?.0,
+        };
+        let type_idx = OBJECT_TYPES
+            .iter()
+            .position(|known| *known == object_type)
+            .ok_or_else(|| format!("object {} has unknown type {}", sha, object_type))?;
+
+        type_bitmaps[type_idx].set(position);
+    }
+
+    let [commits_bitmap, trees, blobs, tags] = type_bitmaps;
+
+    BitmapIndex {
+        checksum: checksum.to_string(),
+        commits: commits_bitmap,
+        trees,
+        blobs,
+        tags,
+        entries,
+    }
+    .to_bytes()
+}
+
+/// Picks the commits that get bitmaps, following upstream's heuristic: every commit among the
+/// most recent 100, then increasingly sparse ones further back, preferring ref tips and merges.
+/// `commits` is newest first; the returned indexes are too.
+fn select_commits(commits: &[CommitInfo], tip_commits: &HashSet<String>) -> Vec<usize> {
+    const MUST_REGION: usize = 100;
+    const MIN_REGION: usize = 20000;
+    const MIN_COMMITS: usize = 100;
+    const MAX_COMMITS: usize = 5000;
+
+    let next_commit_index = |idx: usize| {
+        if idx <= MUST_REGION {
+            0
+        } else if idx <= MIN_REGION {
+            (idx - MUST_REGION).min(MIN_COMMITS)
+        } else {
+            (idx - MIN_REGION).clamp(MIN_COMMITS, MAX_COMMITS)
+        }
+    };
+
+    if commits.len() < MUST_REGION {
+        return (0..commits.len()).collect();
+    }
+
+    let mut selected = Vec::new();
+    let mut idx = 0;
+
+    loop {
+        let next = next_commit_index(idx);
+
+        if idx + next >= commits.len() {
+            break;
+        }
+
+        let mut chosen = idx + next;
+
+        for (candidate, commit) in commits.iter().enumerate().skip(idx).take(next + 1) {
+            if tip_commits.contains(&commit.sha) {
+                chosen = candidate;
+                break;
+            }
+
+            if commit.parents.len() > 1 {
+                chosen = candidate;
+            }
+        }
+
+        selected.push(chosen);
+        idx += next + 1;
+    }
+
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::packs::write_pack_files;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::models::pack::write_pack;
+
+    /// Packs every loose object and deletes the loose copies.
+    fn pack_loose_objects(git_dir: &Path) -> Vec<String> {
+        let mut objects = Vec::new();
+
+        for dir in fs::read_dir(git_dir.join("objects")).unwrap() {
+            let dir = dir.unwrap().path();
+
+            if dir.file_name().unwrap().len() != 2 {
+                continue;
+            }
+
+            for file in fs::read_dir(&dir).unwrap() {
+                let sha = format!(
+                    "{}{}",
+                    dir.file_name().unwrap().to_string_lossy(),
+                    file.unwrap().file_name().to_string_lossy()
+                );
+
+                objects.push(read_raw_object(git_dir, &sha).unwrap());
+            }
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let (data, entries) = write_pack(&objects).unwrap();
+        let shas = entries.iter().map(|entry| entry.sha.clone()).collect();
+
+        write_pack_files(git_dir, &data, entries).unwrap();
+
+        shas
+    }
+
+    #[test]
+    fn write_bitmap_index_then_reachable_matches_the_object_graph() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let two = write_blob(git_dir.path(), "two");
+        let tree1 = write_tree(git_dir.path(), &[("100644", "f", &one)]);
+        let tree2 = write_tree(git_dir.path(), &[("100644", "f", &two)]);
+        let first = write_commit(git_dir.path(), &tree1, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+
+        let objects = pack_loose_objects(git_dir.path());
+        let pack_file = load_packs(git_dir.path()).unwrap().remove(0);
+        let pack_order: Vec<String> = pack_file
+            .index
+            .pack_order()
+            .into_iter()
+            .map(|idx| pack_file.index.entries[idx].sha.clone())
+            .collect();
+        let data = write_bitmap_index(git_dir.path(), &pack_order, &pack_file.index.pack_checksum)
+            .unwrap();
+
+        fs::write(
+            pack_dir(git_dir.path()).join(format!("{}.bitmap", pack_file.name)),
+            data,
+        )
+        .unwrap();
+
+        let bitmaps = load_bitmaps(git_dir.path()).unwrap().unwrap();
+        let (all, extra) = bitmaps
+            .reachable(git_dir.path(), std::slice::from_ref(&second))
+            .unwrap();
+        let (old, _) = bitmaps
+            .reachable(git_dir.path(), std::slice::from_ref(&first))
+            .unwrap();
+        let mut new_only = all.clone();
+        new_only.and_not(&old);
+
+        assert_eq!(objects.len(), 6);
+        assert!(extra.is_empty());
+        assert_eq!(all.count(), 6);
+        assert_eq!(
+            bitmaps.objects(&new_only, false),
+            vec![second.clone(), tree2, two]
+        );
+        assert_eq!(bitmaps.objects(&all, true).len(), 2);
+    }
+
+    #[test]
+    fn reachable_reports_objects_outside_the_bitmap() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &first);
+        pack_loose_objects(git_dir.path());
+
+        let pack_file = load_packs(git_dir.path()).unwrap().remove(0);
+        let pack_order: Vec<String> = pack_file
+            .index
+            .pack_order()
+            .into_iter()
+            .map(|idx| pack_file.index.entries[idx].sha.clone())
+            .collect();
+        let data = write_bitmap_index(git_dir.path(), &pack_order, &pack_file.index.pack_checksum)
+            .unwrap();
+        fs::write(
+            pack_dir(git_dir.path()).join(format!("{}.bitmap", pack_file.name)),
+            data,
+        )
+        .unwrap();
+
+        let loose = write_commit(git_dir.path(), &tree, &[&first], 200, "loose");
+        let bitmaps = load_bitmaps(git_dir.path()).unwrap().unwrap();
+        let (reachable, extra) = bitmaps
+            .reachable(git_dir.path(), std::slice::from_ref(&loose))
+            .unwrap();
+
+        assert_eq!(reachable.count(), 2);
+        assert_eq!(extra, vec![loose]);
+    }
+
+    #[test]
+    fn write_bitmap_index_fails_when_the_pack_is_not_closed() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &first);
+
+        let result = write_bitmap_index(git_dir.path(), &[first], &"0".repeat(40));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn select_commits_prefers_tips_and_merges_outside_the_recent_region() {
+        let commits: Vec<CommitInfo> = (0..300)
+            .map(|idx| CommitInfo {
+                sha: format!("{:040}", idx),
+                tree: String::new(),
+                parents: if idx == 105 {
+                    vec![String::new(), String::new()]
+                } else {
+                    vec![String::new()]
+                },
+                timestamp: 0,
+            })
+            .collect();
+        let tips: HashSet<String> = [format!("{:040}", 150)].into_iter().collect();
+
+        let selected = select_commits(&commits, &tips);
+
+        assert_eq!(&selected[..101], &(0..=100).collect::<Vec<usize>>()[..]);
+        assert_eq!(&selected[101..], &[102, 105, 114, 130, 150, 226]);
+    }
+}
@@ -0,0 +1,508 @@
+use std::fs;
+use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::fetch_pack::{
+    store_pack, Negotiator, RefAdvertisement, ShallowInfo, ShallowRequest,
+};
+use crate::git_commands::refs::{
+    check_refname_format, expand_ref_name, list_refs, or_head, resolve_ref, resolve_revision,
+};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::transport::Transport;
+use crate::git_commands::upload_pack::objects_to_send;
+use crate::git_commands::utils::{read_raw_object, write_atomically};
+use crate::models::bundle::BundleHeader;
+use crate::models::commit::Commit;
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pack::write_pack;
+
+const USAGE: &str = "usage: git bundle create [-q | --quiet] [--version=<version>] <file> \
+                     <git-rev-list-args>\n   or: git bundle verify [-q | --quiet] <file>\n   \
+                     or: git bundle list-heads <file> [<refname>...]\n   \
+                     or: git bundle unbundle <file> [<refname>...]";
+
+/// Moves history around as a single file: `create` writes the refs that the rev-list arguments
+/// name, the commits their history builds on (the prerequisites) and a pack of everything in
+/// between. `verify` checks that this repository has the prerequisites, `list-heads` lists the
+/// refs and `unbundle` stores the pack, printing the refs for the caller to update. v2 is
+/// written unless `--version=3` asks for the format with capabilities.
+pub fn bundle<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    match args.split_first() {
+        Some((&"create", args)) => create(args, git_dir),
+        Some((&"verify", args)) => verify(args, git_dir, writer),
+        Some((&"list-heads", [file, refnames @ ..])) => {
+            let (header, _) = read_bundle(Path::new(file))?;
+
+            list_heads(&header, refnames, writer)
+        }
+        Some((&"unbundle", [file, refnames @ ..])) => {
+            let (header, pack) = read_bundle(Path::new(file))?;
+
+            check_prerequisites(git_dir, &header)?;
+            store_pack(git_dir, pack)?;
+            list_heads(&header, refnames, writer)
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn create(args: &[&str], git_dir: &Path) -> Result<(), String> {
+    let mut version = 2;
+    let mut file = None;
+    // `(name, sha)` of what to include, where a bare sha has no ref name
+    let mut tips: Vec<(Option<String>, String)> = Vec::new();
+    let mut excluded = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "-q" | "--quiet" if file.is_none() => {}
+            _ if file.is_none() && arg.starts_with("--version=") => {
+                version = match &arg["--version=".len()..] {
+                    "2" => 2,
+                    "3" => 3,
+                    other => return Err(format!("fatal: unsupported bundle version {}\n", other)),
+                }
+            }
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(*arg),
+            "--all" => {
+                tips.extend(
+                    list_refs(git_dir)?
+                        .into_iter()
+                        .map(|(name, sha)| (Some(name), sha)),
+                );
+
+                if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+                    tips.push((Some("HEAD".to_string()), head));
+                }
+            }
+            "--branches" | "--tags" => {
+                let prefix = if *arg == "--branches" {
+                    "refs/heads/"
+                } else {
+                    "refs/tags/"
+                };
+
+                tips.extend(
+                    list_refs(git_dir)?
+                        .into_iter()
+                        .filter(|(name, _)| name.starts_with(prefix))
+                        .map(|(name, sha)| (Some(name), sha)),
+                );
+            }
+            _ if arg.starts_with('^') => excluded.push(resolve_revision(git_dir, &arg[1..])?),
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => {
+                let revision = match arg.split_once("..") {
+                    Some((from, to)) => {
+                        excluded.push(resolve_revision(git_dir, or_head(from))?);
+                        or_head(to)
+                    }
+                    None => arg,
+                };
+
+                tips.push(match expand_ref_name(git_dir, revision)? {
+                    Some((name, sha)) => (Some(name), sha),
+                    None => (None, resolve_revision(git_dir, revision)?),
+                });
+            }
+        }
+    }
+
+    let file = file.ok_or_else(|| USAGE.to_string())?;
+    let mut walk = RevWalk::new(git_dir)?;
+    let mut header = BundleHeader {
+        version,
+        ..BundleHeader::default()
+    };
+
+    if version >= 3 {
+        header.capabilities.push("object-format=sha1".to_string());
+    }
+
+    // the walks below want commits, not the tags that may point at them
+    let excluded = excluded
+        .iter()
+        .map(|sha| RevWalk::peel(git_dir, sha))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for sha in &excluded {
+        walk.hide(sha)?;
+    }
+
+    let mut commit_tips = Vec::new();
+
+    for (_, sha) in &tips {
+        let peeled = RevWalk::peel(git_dir, sha)?;
+        let is_commit = read_raw_object(git_dir, &peeled)?.0 == "commit";
+
+        if is_commit {
+            walk.push(&peeled)?;
+        }
+
+        commit_tips.push(is_commit.then_some(peeled));
+    }
+
+    let commits = walk.by_ref().collect::<Result<Vec<_>, String>>()?;
+
+    for ((name, sha), peeled) in tips.iter().zip(&commit_tips) {
+        // refs whose history the exclusions cover have nothing to bring, which the walk has
+        // found out by now
+        if peeled.as_ref().is_some_and(|peeled| walk.is_hidden(peeled)) {
+            continue;
+        }
+
+        if let Some(name) = name {
+            if !header.refs.iter().any(|(other, _)| other == name) {
+                header.refs.push((name.clone(), sha.clone()));
+            }
+        }
+    }
+
+    if header.refs.is_empty() {
+        return Err("fatal: Refusing to create empty bundle.\n".to_string());
+    }
+
+    for parent in commits.iter().flat_map(|commit| &commit.parents) {
+        if walk.is_hidden(parent) && !header.prerequisites.iter().any(|(sha, _)| sha == parent) {
+            let commit = Commit::new(read_raw_object(git_dir, parent)?.1)?;
+            let subject = commit
+                .message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+
+            header.prerequisites.push((parent.clone(), subject));
+        }
+    }
+
+    let mut wants: Vec<String> = Vec::new();
+
+    for (_, sha) in &header.refs {
+        if !wants.contains(sha) {
+            wants.push(sha.clone());
+        }
+    }
+
+    let objects = objects_to_send(git_dir, &wants, &excluded)?
+        .iter()
+        .map(|sha| read_raw_object(git_dir, sha))
+        .collect::<Result<Vec<_>, String>>()?;
+    let (pack, _) = write_pack(&objects)?;
+    let mut data = header.to_bytes();
+
+    data.extend_from_slice(&pack);
+    write_atomically(Path::new(file), &data)
+}
+
+fn verify<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let (quiet, file) = match args {
+        [file] => (false, file),
+        ["-q" | "--quiet", file] => (true, file),
+        _ => return Err(USAGE.to_string()),
+    };
+    let (header, _) = read_bundle(Path::new(file))?;
+
+    check_prerequisites(git_dir, &header)?;
+
+    if !quiet {
+        let counted = |count: usize| match count {
+            1 => "this ref".to_string(),
+            count => format!("these {} refs", count),
+        };
+        let mut lines = vec![format!(
+            "The bundle contains {}:",
+            counted(header.refs.len())
+        )];
+
+        lines.extend(
+            header
+                .refs
+                .iter()
+                .map(|(name, sha)| format!("{} {}", sha, name)),
+        );
+
+        if header.prerequisites.is_empty() {
+            lines.push("The bundle records a complete history.".to_string());
+        } else {
+            lines.push(format!(
+                "The bundle requires {}:",
+                counted(header.prerequisites.len())
+            ));
+            // like upstream, the subjects are not shown
+            lines.extend(
+                header
+                    .prerequisites
+                    .iter()
+                    .map(|(sha, _)| format!("{} ", sha)),
+            );
+        }
+
+        lines.push("The bundle uses this hash algorithm: sha1".to_string());
+
+        for line in lines {
+            writeln!(writer, "{}", line).map_err(|err| err.to_string())?;
+        }
+    }
+
+    let _ = writeln!(stderr(), "{} is okay", file);
+
+    Ok(())
+}
+
+/// Prints the bundle's refs, only those named in `refnames` when there are any.
+fn list_heads<W: Write>(
+    header: &BundleHeader,
+    refnames: &[&str],
+    writer: &mut W,
+) -> Result<(), String> {
+    for (name, sha) in &header.refs {
+        if refnames.is_empty() || refnames.contains(&name.as_str()) {
+            writeln!(writer, "{} {}", sha, name).map_err(|err| err.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a bundle file, returning its header and the pack after it. A bundle naming any ref
+/// that could not be one here is refused whole, as its refs get written when it is a remote.
+fn read_bundle(path: &Path) -> Result<(BundleHeader, Vec<u8>), String> {
+    let mut data = fs::read(path)
+        .map_err(|err| format!("fatal: could not open '{}': {}\n", path.display(), err))?;
+    let (header, pack_start) = BundleHeader::parse(&data)
+        .and_then(|(header, pack_start)| {
+            header
+                .refs
+                .iter()
+                .try_for_each(|(name, _)| check_refname_format(name))?;
+
+            Ok((header, pack_start))
+        })
+        .map_err(|err| format!("error: '{}' {}\n", path.display(), err))?;
+
+    Ok((header, data.split_off(pack_start)))
+}
+
+/// Whether the file at `path` is a bundle, telling bundles given as remotes from repositories.
+pub fn is_bundle_file(path: &Path) -> bool {
+    let mut signature = [0; 16];
+
+    path.is_file()
+        && fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut signature))
+            .is_ok()
+        && BundleHeader::is_bundle(&signature)
+}
+
+/// Fails, listing them, unless the repository has every prerequisite commit.
+fn check_prerequisites(git_dir: &Path, header: &BundleHeader) -> Result<(), String> {
+    let missing: Vec<&(String, String)> = header
+        .prerequisites
+        .iter()
+        .filter(|(sha, _)| !matches!(read_raw_object(git_dir, sha), Ok((object_type, _)) if object_type == "commit"))
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut err = "error: Repository lacks these prerequisite commits:\n".to_string();
+
+    for (sha, _) in missing {
+        err.push_str(&format!("error: {} \n", sha));
+    }
+
+    Err(err)
+}
+
+/// A bundle file used as a remote, as `clone` and `fetch` take one: its refs are the
+/// advertisement and every fetch gets its whole pack, once the repository has the
+/// prerequisites. Bundles cannot be pushed to.
+pub struct BundleTransport {
+    path: PathBuf,
+    git_dir: PathBuf,
+    header: BundleHeader,
+    pack: Vec<u8>,
+}
+
+impl BundleTransport {
+    pub fn open(path: &Path, git_dir: &Path) -> Result<Self, String> {
+        let (header, pack) = read_bundle(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            git_dir: git_dir.to_path_buf(),
+            header,
+            pack,
+        })
+    }
+}
+
+impl Transport for BundleTransport {
+    fn discover_refs(&mut self, _prefixes: &[&str]) -> Result<RefAdvertisement, String> {
+        Ok(RefAdvertisement {
+            refs: self.header.refs.clone(),
+            capabilities: Vec::new(),
+        })
+    }
+
+    fn fetch_pack(
+        &mut self,
+        _wants: &[String],
+        shallow: &ShallowRequest,
+        _filter: Option<&ObjectFilter>,
+        _negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String> {
+        if shallow.is_deepening() {
+            return Err(
+                "fatal: bundle transport does not support shallow capabilities\n".to_string(),
+            );
+        }
+
+        check_prerequisites(&self.git_dir, &self.header)?;
+
+        Ok((self.pack.clone(), ShallowInfo::default()))
+    }
+
+    fn send_pack(&mut self, _request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        Err(format!(
+            "fatal: {} is a bundle, which cannot be pushed to\n",
+            self.path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::clone::clone;
+    use crate::git_commands::fsck::fsck;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+
+    #[test]
+    fn bundle_create_then_unbundle_moves_history_past_the_prerequisites() {
+        let source = init_git_dir();
+        let readme = write_blob(source.path(), "hello\n");
+        let tree = write_tree(source.path(), &[("100644", "README", &readme)]);
+        let first = write_commit(source.path(), &tree, &[], 100, "first");
+        let changed = write_blob(source.path(), "changed\n");
+        let tree2 = write_tree(source.path(), &[("100644", "README", &changed)]);
+        let second = write_commit(source.path(), &tree2, &[&first], 200, "second");
+        write_ref(source.path(), "refs/heads/main", &second);
+        write_ref(source.path(), "refs/tags/v1", &first);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.bundle");
+        let file = file.to_str().unwrap();
+
+        bundle(
+            &["create", file, "v1..main"],
+            source.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let (header, _) = read_bundle(Path::new(file)).unwrap();
+
+        assert_eq!(header.version, 2);
+        assert_eq!(
+            header.prerequisites,
+            vec![(first.clone(), "first".to_string())]
+        );
+        assert_eq!(
+            header.refs,
+            vec![("refs/heads/main".to_string(), second.clone())]
+        );
+
+        let target = init_git_dir();
+
+        assert_eq!(
+            bundle(&["unbundle", file], target.path(), &mut Vec::new()),
+            Err(format!(
+                "error: Repository lacks these prerequisite commits:\nerror: {} \n",
+                first
+            ))
+        );
+
+        bundle(&["create", file, "--all"], source.path(), &mut Vec::new()).unwrap();
+        bundle(&["unbundle", file], target.path(), &mut Vec::new()).unwrap();
+        bundle(
+            &["create", file, "v1..main"],
+            source.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+
+        bundle(&["verify", file], target.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "The bundle contains this ref:\n{} refs/heads/main\nThe bundle requires this \
+                 ref:\n{} \nThe bundle uses this hash algorithm: sha1\n",
+                second, first
+            )
+        );
+
+        write_ref(target.path(), "refs/heads/main", &second);
+
+        assert!(fsck(&[], target.path(), &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn bundle_with_a_ref_outside_refs_is_refused_whole() {
+        let source = init_git_dir();
+        let tree = write_tree(source.path(), &[]);
+        let first = write_commit(source.path(), &tree, &[], 100, "first");
+        write_ref(source.path(), "refs/heads/main", &first);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("evil.bundle");
+        let file = file.to_str().unwrap();
+
+        bundle(&["create", file, "main"], source.path(), &mut Vec::new()).unwrap();
+
+        let data = fs::read(file).unwrap();
+        let (mut header, pack_start) = BundleHeader::parse(&data).unwrap();
+        header.refs[0].0 = "refs/heads/../../../../evil".to_string();
+        let mut evil = header.to_bytes();
+        evil.extend_from_slice(&data[pack_start..]);
+        fs::write(file, evil).unwrap();
+        let work_dir = dir.path().join("out");
+
+        assert_eq!(
+            bundle(&["list-heads", file], source.path(), &mut Vec::new()),
+            Err(format!(
+                "error: '{}' invalid ref name 'refs/heads/../../../../evil'\n",
+                file
+            ))
+        );
+        assert!(clone(&[file, work_dir.to_str().unwrap()], &mut Vec::new()).is_err());
+        assert!(!work_dir.exists());
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn bundle_create_refuses_an_empty_bundle() {
+        let source = init_git_dir();
+        let tree = write_tree(source.path(), &[]);
+        let first = write_commit(source.path(), &tree, &[], 100, "first");
+        write_ref(source.path(), "refs/heads/main", &first);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("empty.bundle");
+
+        assert_eq!(
+            bundle(
+                &["create", file.to_str().unwrap(), "main..main"],
+                source.path(),
+                &mut Vec::new()
+            ),
+            Err("fatal: Refusing to create empty bundle.\n".to_string())
+        );
+        assert!(!file.exists());
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::Write;
 
-use crate::git_commands::utils::{read_and_decompress_file, ObjectPathGetter};
+use crate::git_commands::utils::ObjectPathGetter;
 use crate::models::git_object::{GetContentString, GitObject};
 
 pub fn cat_file<O: ObjectPathGetter, W: Write>(
@@ -13,16 +13,7 @@ pub fn cat_file<O: ObjectPathGetter, W: Write>(
         return Err("flag not recognized. Available flags: -p".to_string());
     }
 
-    let object_path = object_path_getter.get_object_path(sha)?;
-    let decompressed_content = match read_and_decompress_file(&object_path.as_str()) {
-        Ok(c) => c,
-        Err(e) => {
-            return Err(format!(
-                "Error reading and decompressing file: {}",
-                e.to_string()
-            ))
-        }
-    };
+    let decompressed_content = object_path_getter.read_object_buffer(sha)?;
 
     let git_object = GitObject::from_object_file_buffer(&decompressed_content)?;
 
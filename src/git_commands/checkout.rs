@@ -0,0 +1,259 @@
+use std::fs;
+use std::path::Path;
+
+use crate::git_commands::promisor::{fetch_promised, promisor_remote};
+use crate::git_commands::tree_diff::read_tree;
+use crate::git_commands::utils::{object_exists, read_raw_object, write_atomically};
+use crate::models::index::{Index, IndexEntry, IndexStat};
+use crate::models::tree::TreeEntryMode;
+
+/// Writes the files of `tree` into `work_dir` and replaces the index with them, as a checkout
+/// into an empty working tree does. Submodules become empty directories. A partial clone
+/// first fetches the blobs it is missing, all in one go.
+///
+/// The trees come from whoever we cloned from, so each must pass the fsck checks first: an entry
+/// named `.git` (in any case), `..` or with a `/` could otherwise write into the repository or
+/// outside the working tree. Nothing is written through a symbolic link, or over anything
+/// already there, so a link checked out earlier cannot redirect a later path either.
+pub fn checkout_tree(git_dir: &Path, work_dir: &Path, tree: &str) -> Result<(), String> {
+    let mut entries = Vec::new();
+
+    if promisor_remote(git_dir)?.is_some() {
+        let mut missing = Vec::new();
+
+        missing_blobs(git_dir, tree, &mut missing)?;
+
+        if !missing.is_empty() {
+            fetch_promised(git_dir, &missing)?;
+        }
+    }
+
+    write_tree_files(git_dir, work_dir, tree, "", &mut entries)?;
+
+    write_atomically(&git_dir.join("index"), &Index { entries }.to_bytes()?)
+}
+
+fn write_tree_files(
+    git_dir: &Path,
+    work_dir: &Path,
+    tree: &str,
+    prefix: &str,
+    entries: &mut Vec<IndexEntry>,
+) -> Result<(), String> {
+    let tree_object = read_tree(git_dir, tree)?;
+
+    tree_object
+        .check()
+        .map_err(|err| format!("invalid tree {}: {}", tree, err))?;
+
+    for entry in tree_object.tree_entries {
+        let path = format!("{}{}", prefix, entry.name);
+        let file_path = work_dir.join(&path);
+
+        check_no_symlink_in_path(work_dir, &path)?;
+
+        let mode = match entry.mode {
+            TreeEntryMode::Directory => {
+                create_dir(&file_path)?;
+                write_tree_files(
+                    git_dir,
+                    work_dir,
+                    &entry.sha,
+                    &format!("{}/", path),
+                    entries,
+                )?;
+                continue;
+            }
+            TreeEntryMode::Submodule => {
+                create_dir(&file_path)?;
+                entries.push(IndexEntry {
+                    mode: 0o160000,
+                    sha: entry.sha,
+                    path,
+                    stat: IndexStat::default(),
+                });
+                continue;
+            }
+            TreeEntryMode::RegularFile => 0o100644,
+            TreeEntryMode::ExecutableFile => 0o100755,
+            TreeEntryMode::SymbolicLink => 0o120000,
+        };
+        let (_, content) = read_raw_object(git_dir, &entry.sha)?;
+
+        write_file(&file_path, &content, mode)?;
+        entries.push(IndexEntry {
+            mode,
+            sha: entry.sha,
+            path,
+            stat: file_stat(&file_path)?,
+        });
+    }
+
+    Ok(())
+}
+
+fn missing_blobs(git_dir: &Path, tree: &str, missing: &mut Vec<String>) -> Result<(), String> {
+    for entry in read_tree(git_dir, tree)?.tree_entries {
+        match entry.mode {
+            TreeEntryMode::Directory => missing_blobs(git_dir, &entry.sha, missing)?,
+            TreeEntryMode::Submodule => {}
+            _ => {
+                if !object_exists(git_dir, &entry.sha)? && !missing.contains(&entry.sha) {
+                    missing.push(entry.sha);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuses `path` when the working tree already has a symbolic link at it or at one of the
+/// directories above it.
+fn check_no_symlink_in_path(work_dir: &Path, path: &str) -> Result<(), String> {
+    let mut current = work_dir.to_path_buf();
+
+    for component in path.split('/') {
+        current.push(component);
+
+        if fs::symlink_metadata(&current).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(format!(
+                "refusing to check out '{}' beyond a symbolic link",
+                path
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn create_dir(path: &Path) -> Result<(), String> {
+    fs::create_dir(path).map_err(|err| format!("error creating {:?}: {}", path, err))
+}
+
+#[cfg(unix)]
+fn write_file(path: &Path, content: &[u8], mode: u32) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::{symlink, OpenOptionsExt};
+
+    // a new file only: an existing one may be a link that would take the write elsewhere
+    let result = match mode {
+        0o120000 => symlink(String::from_utf8_lossy(content).as_ref(), path),
+        _ => fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(if mode == 0o100755 { 0o755 } else { 0o644 })
+            .open(path)
+            .and_then(|mut file| file.write_all(content)),
+    };
+
+    result.map_err(|err| format!("error writing {:?}: {}", path, err))
+}
+
+// without symlinks or an executable bit, links are checked out as files holding their target
+#[cfg(not(unix))]
+fn write_file(path: &Path, content: &[u8], _mode: u32) -> Result<(), String> {
+    use std::io::Write;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|err| format!("error writing {:?}: {}", path, err))
+}
+
+#[cfg(unix)]
+fn file_stat(path: &Path) -> Result<IndexStat, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata =
+        fs::symlink_metadata(path).map_err(|err| format!("error reading {:?}: {}", path, err))?;
+
+    // the index keeps only the low 32 bits of each field
+    Ok(IndexStat {
+        ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+        mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+        dev: metadata.dev() as u32,
+        ino: metadata.ino() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: metadata.size() as u32,
+    })
+}
+
+#[cfg(not(unix))]
+fn file_stat(path: &Path) -> Result<IndexStat, String> {
+    let metadata =
+        fs::symlink_metadata(path).map_err(|err| format!("error reading {:?}: {}", path, err))?;
+
+    Ok(IndexStat {
+        size: metadata.len() as u32,
+        ..IndexStat::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::object_walk::load_index;
+    use crate::git_commands::test_utils::{init_git_dir, write_blob, write_tree};
+
+    #[test]
+    fn checkout_tree_writes_files_and_the_index() {
+        let git_dir = init_git_dir();
+        let work_dir = tempfile::tempdir().unwrap();
+        let readme = write_blob(git_dir.path(), "hello\n");
+        let script = write_blob(git_dir.path(), "#!/bin/sh\n");
+        let bin = write_tree(git_dir.path(), &[("100755", "run.sh", &script)]);
+        let tree = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &readme), ("40000", "bin", &bin)],
+        );
+
+        checkout_tree(git_dir.path(), work_dir.path(), &tree).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(work_dir.path().join("README")).unwrap(),
+            "hello\n"
+        );
+
+        let index = load_index(git_dir.path()).unwrap().unwrap();
+        let paths: Vec<(&str, u32)> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.mode))
+            .collect();
+
+        assert_eq!(paths, vec![("README", 0o100644), ("bin/run.sh", 0o100755)]);
+        assert_eq!(index.entries[0].stat.size, 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checkout_tree_refuses_dot_git_entries_and_writing_through_links() {
+        let git_dir = init_git_dir();
+        let config = write_blob(git_dir.path(), "[core]\n\tsshCommand = touch pwned\n");
+        let fake_git = write_tree(git_dir.path(), &[("100644", "config", &config)]);
+
+        for name in [".git", ".GIT"] {
+            let work_dir = tempfile::tempdir().unwrap();
+            let tree = write_tree(git_dir.path(), &[("40000", name, &fake_git)]);
+            let err = checkout_tree(git_dir.path(), work_dir.path(), &tree).unwrap_err();
+
+            assert!(err.contains("hasDotgit"), "{}", err);
+            assert!(!work_dir.path().join(name).exists());
+        }
+
+        let work_dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let tree = write_tree(git_dir.path(), &[("40000", "link", &fake_git)]);
+
+        std::os::unix::fs::symlink(outside.path(), work_dir.path().join("link")).unwrap();
+
+        assert!(checkout_tree(git_dir.path(), work_dir.path(), &tree)
+            .unwrap_err()
+            .contains("beyond a symbolic link"));
+        assert!(!outside.path().join("config").exists());
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::bundle::is_bundle_file;
+use crate::git_commands::checkout::checkout_tree;
+use crate::git_commands::fetch_pack::{store_pack, Negotiator, RefAdvertisement};
+use crate::git_commands::object_walk::object_links;
+use crate::git_commands::promisor::mark_promisor_pack;
+use crate::git_commands::refs::{update_ref, update_symref};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::shallow::{read_shallow, update_shallow, ShallowOptions};
+use crate::git_commands::transport::{
+    find_git_dir, is_url, open_transport, transport_url, Service,
+};
+use crate::git_commands::upload_pack::ref_advertisement;
+use crate::git_commands::utils::{object_exists, read_object, read_raw_object};
+use crate::models::object::Object;
+use crate::models::object_filter::ObjectFilter;
+
+const USAGE: &str = "usage: git clone [--no-hardlinks] [--depth <depth>] \
+                     [--shallow-since <date>] [--shallow-exclude <ref>] [--filter <filter-spec>] \
+                     <repository> [<directory>]";
+const REMOTE: &str = "origin";
+
+/// Clones a repository into a new directory: fetches every branch and tag, records branches as
+/// `refs/remotes/origin/*`, creates a local branch for the remote's `HEAD` and checks it out.
+/// A plain path is cloned by hardlinking its object files (copying them with `--no-hardlinks`
+/// or across filesystems); URLs and bundle files go through a `Transport`. A failed clone
+/// leaves no directory behind. `--depth`, `--shallow-since` and `--shallow-exclude` make a
+/// shallow clone, whose history stops at the commits listed in `.git/shallow`. `--filter` makes
+/// a partial clone, which leaves out the objects the filter does and fetches them from `origin`
+/// when needed. Refs are only written once everything they reach has been received.
+pub fn clone<W: Write>(args: &[&str], writer: &mut W) -> Result<(), String> {
+    let mut hardlinks = true;
+    let mut shallow = ShallowOptions::default();
+    let mut filter = None;
+    let mut positional = Vec::new();
+    let mut args = args.iter().copied();
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "--no-hardlinks" => hardlinks = false,
+            "--filter" => {
+                let spec = args.next().ok_or_else(|| USAGE.to_string())?;
+
+                filter = Some(ObjectFilter::parse(spec)?);
+            }
+            _ if arg.starts_with("--filter=") => {
+                filter = Some(ObjectFilter::parse(&arg["--filter=".len()..])?)
+            }
+            _ if shallow.parse(arg, &mut args, false)? => {}
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (url, directory) = match positional[..] {
+        [url] => (url, directory_from_url(url)?),
+        [url, directory] => (url, directory.to_string()),
+        _ => return Err(USAGE.to_string()),
+    };
+    let source = if is_url(url) {
+        Source::Url {
+            url: url.to_string(),
+            shallow,
+            filter,
+        }
+    } else if is_bundle_file(Path::new(url)) {
+        let path = fs::canonicalize(url).map_err(|err| err.to_string())?;
+
+        Source::Url {
+            url: path.to_string_lossy().to_string(),
+            shallow,
+            filter,
+        }
+    } else {
+        let filter_given = filter.map(|_| "--filter");
+
+        for option in shallow.given().into_iter().chain(filter_given) {
+            writeln!(
+                writer,
+                "warning: {} is ignored in local clones; use file:// instead.",
+                option
+            )
+            .map_err(|err| err.to_string())?;
+        }
+
+        let git_dir = find_git_dir(Path::new(url))
+            .ok_or_else(|| format!("fatal: repository '{}' does not exist\n", url))?;
+        let path = fs::canonicalize(url).map_err(|err| err.to_string())?;
+
+        Source::Local {
+            path: path.to_string_lossy().to_string(),
+            git_dir,
+            hardlinks,
+        }
+    };
+    let work_dir = Path::new(&directory);
+    let existed = work_dir.exists();
+
+    if existed
+        && fs::read_dir(work_dir)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(true)
+    {
+        return Err(format!(
+            "fatal: destination path '{}' already exists and is not an empty directory.\n",
+            directory
+        ));
+    }
+
+    writeln!(writer, "Cloning into '{}'...", directory).map_err(|err| err.to_string())?;
+
+    let result = clone_into(&source, work_dir, writer);
+
+    if result.is_err() {
+        let _ = if existed {
+            fs::remove_dir_all(work_dir.join(".git"))
+        } else {
+            fs::remove_dir_all(work_dir)
+        };
+    }
+
+    result
+}
+
+/// Where a clone comes from: a repository on disk, whose objects are linked or copied, or a
+/// URL to fetch from, as much history as `shallow` asks for and without what `filter` leaves
+/// out.
+enum Source {
+    Local {
+        path: String,
+        git_dir: PathBuf,
+        hardlinks: bool,
+    },
+    Url {
+        url: String,
+        shallow: ShallowOptions,
+        filter: Option<ObjectFilter>,
+    },
+}
+
+fn clone_into<W: Write>(source: &Source, work_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let git_dir = work_dir.join(".git");
+    let advertisement = match source {
+        Source::Local {
+            path,
+            git_dir: source_dir,
+            hardlinks,
+        } => {
+            init_repository(&git_dir, path, None)?;
+            copy_objects(
+                &source_dir.join("objects"),
+                &git_dir.join("objects"),
+                *hardlinks,
+            )?;
+
+            ref_advertisement(source_dir)?
+        }
+        Source::Url {
+            url,
+            shallow,
+            filter,
+        } => {
+            init_repository(&git_dir, url, filter.as_ref())?;
+
+            let mut transport =
+                open_transport(&transport_url(url)?, &git_dir, Service::UploadPack)?;
+            let advertisement = transport.discover_refs(&["HEAD", "refs/heads/", "refs/tags/"])?;
+            advertisement.check_ref_names()?;
+
+            let mut wants: Vec<String> = Vec::new();
+
+            for (name, sha) in advertisement.direct_refs() {
+                if is_cloned_ref(name) && !wants.contains(sha) {
+                    wants.push(sha.clone());
+                }
+            }
+
+            if !wants.is_empty() {
+                let (pack, info) = transport.fetch_pack(
+                    &wants,
+                    &shallow.request(&git_dir)?,
+                    filter.as_ref(),
+                    &mut Negotiator::new(&git_dir, &[])?,
+                )?;
+
+                if let Some(name) = store_pack(&git_dir, pack)? {
+                    if filter.is_some() {
+                        mark_promisor_pack(&git_dir, &name)?;
+                    }
+                }
+
+                update_shallow(&git_dir, &info.shallow, &info.unshallow)?;
+                check_connected(&git_dir, wants, filter.is_some())?;
+            }
+
+            advertisement
+        }
+    };
+
+    if !advertisement
+        .direct_refs()
+        .any(|(name, _)| is_cloned_ref(name))
+    {
+        return writeln!(
+            writer,
+            "warning: You appear to have cloned an empty repository."
+        )
+        .map_err(|err| err.to_string());
+    }
+
+    match write_cloned_refs(&git_dir, &advertisement)? {
+        Some(head) => checkout_commit(&git_dir, work_dir, &head),
+        None => Ok(()),
+    }
+}
+
+/// Makes sure the fetched pack holds everything `tips` reach before refs point at them, as
+/// upstream's `check_connected` does. The parents of shallow commits are not followed, and a
+/// `partial` clone may lack the trees and blobs its filter left out, though never commits.
+fn check_connected(git_dir: &Path, tips: Vec<String>, partial: bool) -> Result<(), String> {
+    let shallow = read_shallow(git_dir)?;
+    // each object comes with whether it may be a commit: trees only lead to trees and blobs
+    let mut stack: Vec<(String, bool)> = tips.into_iter().map(|sha| (sha, true)).collect();
+    let mut seen = HashSet::new();
+
+    while let Some((sha, maybe_commit)) = stack.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+
+        if !object_exists(git_dir, &sha)? {
+            if partial && !maybe_commit {
+                continue;
+            }
+
+            return Err("fatal: remote did not send all necessary objects\n".to_string());
+        }
+
+        let (object_type, content) = read_raw_object(git_dir, &sha)?;
+        let mut links = object_links(&object_type, content)?;
+
+        if object_type == "commit" && shallow.contains(&sha) {
+            links.truncate(1);
+        }
+
+        stack.extend(links.into_iter().enumerate().map(|(idx, link)| {
+            // a commit's tree comes before its parents
+            let maybe_commit = match object_type.as_str() {
+                "commit" => idx > 0,
+                "tag" => true,
+                _ => false,
+            };
+
+            (link, maybe_commit)
+        }));
+    }
+
+    Ok(())
+}
+
+/// Hardlinks (or copies) every file under `source` into `destination`. Objects are never
+/// modified in place, so sharing them between repositories is safe.
+fn copy_objects(source: &Path, destination: &Path, hardlinks: bool) -> Result<(), String> {
+    let entries =
+        fs::read_dir(source).map_err(|err| format!("error reading {:?}: {}", source, err))?;
+
+    fs::create_dir_all(destination)
+        .map_err(|err| format!("error creating {:?}: {}", destination, err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let from = entry.path();
+        let to = destination.join(entry.file_name());
+
+        if from.is_dir() {
+            copy_objects(&from, &to, hardlinks)?;
+        } else if !hardlinks || fs::hard_link(&from, &to).is_err() {
+            // links fail across filesystems, where copying is the only option
+            fs::copy(&from, &to).map_err(|err| format!("error copying {:?}: {}", from, err))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Refs a clone copies: branches and tags. Others, like `refs/pull/*`, are left on the server.
+fn is_cloned_ref(name: &str) -> bool {
+    name.starts_with("refs/heads/") || name.starts_with("refs/tags/")
+}
+
+/// Picks the directory name the way upstream does: the last path component of the URL
+/// without a trailing `.git`.
+fn directory_from_url(url: &str) -> Result<String, String> {
+    let path = url.trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path);
+    let name = path.rsplit(['/', ':']).next().unwrap_or("");
+    let name = name
+        .strip_suffix(".git")
+        .or_else(|| name.strip_suffix(".bundle"))
+        .unwrap_or(name);
+
+    if name.is_empty() {
+        return Err(format!(
+            "fatal: could not guess a directory name from '{}'\n",
+            url
+        ));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Creates the repository with `url` as `origin`, which a partial clone with `filter` records
+/// as the promisor remote.
+fn init_repository(git_dir: &Path, url: &str, filter: Option<&ObjectFilter>) -> Result<(), String> {
+    for dir in ["objects/pack", "objects/info", "refs/heads", "refs/tags"] {
+        fs::create_dir_all(git_dir.join(dir))
+            .map_err(|err| format!("error creating {:?}: {}", git_dir.join(dir), err))?;
+    }
+
+    let mut config = format!(
+        "[core]\n\
+         \trepositoryformatversion = {version}\n\
+         \tfilemode = true\n\
+         \tbare = false\n\
+         \tlogallrefupdates = true\n\
+         [remote \"{remote}\"]\n\
+         \turl = {url}\n\
+         \tfetch = +refs/heads/*:refs/remotes/{remote}/*\n",
+        // extensions are only understood from version 1 on
+        version = if filter.is_some() { 1 } else { 0 },
+        remote = REMOTE,
+        url = url
+    );
+
+    if let Some(filter) = filter {
+        config.push_str(&format!(
+            "\tpromisor = true\n\
+             \tpartialclonefilter = {filter}\n\
+             [extensions]\n\
+             \tpartialclone = {remote}\n",
+            filter = filter,
+            remote = REMOTE
+        ));
+    }
+
+    fs::write(git_dir.join("config"), config).map_err(|err| err.to_string())?;
+    update_symref(git_dir, "HEAD", "refs/heads/main")
+}
+
+/// Writes the remote-tracking branches and tags, then sets up `HEAD`: a local branch tracking
+/// the remote's default branch, or a detached `HEAD` when the remote's is detached. Returns
+/// the commit to check out.
+fn write_cloned_refs(
+    git_dir: &Path,
+    advertisement: &RefAdvertisement,
+) -> Result<Option<String>, String> {
+    for (name, sha) in advertisement.direct_refs() {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            update_ref(git_dir, &format!("refs/remotes/{}/{}", REMOTE, branch), sha)?;
+        } else if name.starts_with("refs/tags/") {
+            update_ref(git_dir, name, sha)?;
+        }
+    }
+
+    let head_sha = match advertisement
+        .direct_refs()
+        .find(|(name, _)| name == "HEAD")
+        .map(|(_, sha)| sha.clone())
+    {
+        Some(sha) => sha,
+        None => return Ok(None),
+    };
+    // servers that do not advertise `symref` leave us to guess from the shas
+    let head_ref = advertisement
+        .symref_target("HEAD")
+        .map(|target| target.to_string())
+        .or_else(|| {
+            advertisement
+                .direct_refs()
+                .find(|(name, sha)| name.starts_with("refs/heads/") && *sha == head_sha)
+                .map(|(name, _)| name.clone())
+        });
+    let branch = match head_ref
+        .as_deref()
+        .and_then(|name| name.strip_prefix("refs/heads/"))
+    {
+        Some(branch) => branch,
+        None => {
+            update_ref(git_dir, "HEAD", &head_sha)?;
+            return Ok(Some(head_sha));
+        }
+    };
+
+    update_ref(git_dir, &format!("refs/heads/{}", branch), &head_sha)?;
+    update_symref(git_dir, "HEAD", &format!("refs/heads/{}", branch))?;
+    update_symref(
+        git_dir,
+        &format!("refs/remotes/{}/HEAD", REMOTE),
+        &format!("refs/remotes/{}/{}", REMOTE, branch),
+    )?;
+
+    let mut config = fs::OpenOptions::new()
+        .append(true)
+        .open(git_dir.join("config"))
+        .map_err(|err| err.to_string())?;
+
+    write!(
+        config,
+        "[branch \"{}\"]\n\tremote = {}\n\tmerge = refs/heads/{}\n",
+        branch, REMOTE, branch
+    )
+    .map_err(|err| err.to_string())?;
+
+    Ok(Some(head_sha))
+}
+
+fn checkout_commit(git_dir: &Path, work_dir: &Path, sha: &str) -> Result<(), String> {
+    let commit = RevWalk::peel(git_dir, sha)?;
+
+    match read_object(git_dir, &commit)?.object {
+        Object::Commit(commit) => checkout_tree(git_dir, work_dir, &commit.tree),
+        other => Err(format!(
+            "HEAD points at a {}, not a commit",
+            other.get_type()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::fsck::fsck;
+    use crate::git_commands::refs::resolve_ref;
+    use crate::git_commands::test_utils::{
+        init_git_dir, serve_http, write_blob, write_commit, write_object, write_ref, write_tree,
+    };
+    use crate::git_commands::utils::{get_object_path_in, loose_objects, read_raw_object};
+    use crate::models::pack::write_pack;
+    use crate::models::pkt_line::{PktLineWriter, SIDEBAND_DATA};
+
+    /// Serves `git_dir` the way a smart HTTP server would, always sending every object.
+    fn serve_repository(git_dir: &Path, refs: Vec<(String, String)>) -> String {
+        let objects: Vec<(String, Vec<u8>)> = loose_objects(git_dir)
+            .unwrap()
+            .into_iter()
+            .map(|(sha, _)| read_raw_object(git_dir, &sha).unwrap())
+            .collect();
+        let (pack, _) = write_pack(&objects).unwrap();
+
+        serve_http(move |method, path, _| {
+            let mut response = PktLineWriter::new(Vec::new());
+
+            if method == "GET" && path == "/repo.git/info/refs?service=git-upload-pack" {
+                response.write_line("# service=git-upload-pack").unwrap();
+                response.write_flush().unwrap();
+
+                for (idx, (name, sha)) in refs.iter().enumerate() {
+                    if idx == 0 {
+                        response
+                            .write_line(&format!(
+                                "{} {}\0side-band-64k ofs-delta symref=HEAD:refs/heads/main",
+                                sha, name
+                            ))
+                            .unwrap();
+                    } else {
+                        response.write_line(&format!("{} {}", sha, name)).unwrap();
+                    }
+                }
+
+                response.write_flush().unwrap();
+
+                (
+                    "application/x-git-upload-pack-advertisement",
+                    response.into_inner(),
+                )
+            } else {
+                response.write_line("NAK").unwrap();
+                response.write_sideband(SIDEBAND_DATA, &pack).unwrap();
+                response.write_flush().unwrap();
+
+                (
+                    "application/x-git-upload-pack-result",
+                    response.into_inner(),
+                )
+            }
+        })
+    }
+
+    #[test]
+    fn clone_fetches_refs_and_checks_out_head() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        let url = serve_repository(
+            remote.path(),
+            vec![
+                ("HEAD".to_string(), commit.clone()),
+                ("refs/heads/main".to_string(), commit.clone()),
+                ("refs/pull/1/head".to_string(), commit.clone()),
+            ],
+        );
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+        let mut output = Vec::new();
+
+        clone(
+            &[&format!("{}/repo.git", url), work_dir.to_str().unwrap()],
+            &mut output,
+        )
+        .unwrap();
+
+        let git_dir = work_dir.join(".git");
+
+        assert_eq!(
+            fs::read_to_string(work_dir.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            fs::read_to_string(git_dir.join("HEAD")).unwrap(),
+            "ref: refs/heads/main\n"
+        );
+        assert_eq!(
+            resolve_ref(&git_dir, "refs/remotes/origin/HEAD").unwrap(),
+            Some(commit.clone())
+        );
+        assert!(!git_dir.join("refs/remotes/origin/pull").exists());
+        assert!(fs::read_to_string(git_dir.join("config"))
+            .unwrap()
+            .contains("[branch \"main\"]\n\tremote = origin\n\tmerge = refs/heads/main\n"));
+        assert!(fsck(&[], &git_dir, &mut Vec::new()).is_ok());
+    }
+
+    /// Serves `git_dir` in protocol v2, honouring `ls-refs` prefixes and sending every object
+    /// to any `fetch`.
+    fn serve_repository_v2(git_dir: &Path, refs: Vec<(String, String)>) -> String {
+        let objects: Vec<(String, Vec<u8>)> = loose_objects(git_dir)
+            .unwrap()
+            .into_iter()
+            .map(|(sha, _)| read_raw_object(git_dir, &sha).unwrap())
+            .collect();
+        let (pack, _) = write_pack(&objects).unwrap();
+
+        serve_http(move |method, _, body| {
+            let mut response = PktLineWriter::new(Vec::new());
+
+            if method == "GET" {
+                for line in ["version 2", "ls-refs", "fetch=wait-for-done"] {
+                    response.write_line(line).unwrap();
+                }
+
+                response.write_flush().unwrap();
+
+                return (
+                    "application/x-git-upload-pack-advertisement",
+                    response.into_inner(),
+                );
+            }
+
+            let request = String::from_utf8_lossy(body);
+
+            if request.contains("command=ls-refs") {
+                let prefixes: Vec<&str> = request
+                    .lines()
+                    .filter_map(|line| line.split_once("ref-prefix "))
+                    .map(|(_, prefix)| prefix)
+                    .collect();
+
+                for (name, sha) in &refs {
+                    if prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+                        let symref = if name == "HEAD" {
+                            " symref-target:refs/heads/main"
+                        } else {
+                            ""
+                        };
+
+                        response
+                            .write_line(&format!("{} {}{}", sha, name, symref))
+                            .unwrap();
+                    }
+                }
+            } else {
+                assert!(request.contains("done"));
+                response.write_line("packfile").unwrap();
+                response.write_sideband(SIDEBAND_DATA, &pack).unwrap();
+            }
+
+            response.write_flush().unwrap();
+
+            (
+                "application/x-git-upload-pack-result",
+                response.into_inner(),
+            )
+        })
+    }
+
+    #[test]
+    fn clone_speaks_protocol_v2() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        let url = serve_repository_v2(
+            remote.path(),
+            vec![
+                ("HEAD".to_string(), commit.clone()),
+                ("refs/heads/main".to_string(), commit.clone()),
+                ("refs/pull/1/head".to_string(), "0".repeat(40)),
+            ],
+        );
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        clone(
+            &[&format!("{}/repo.git", url), work_dir.to_str().unwrap()],
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(work_dir.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            resolve_ref(&work_dir.join(".git"), "refs/heads/main").unwrap(),
+            Some(commit)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clone_from_a_path_hardlinks_objects() {
+        use std::os::unix::fs::MetadataExt;
+
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        clone(
+            &[remote.path().to_str().unwrap(), work_dir.to_str().unwrap()],
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let object_path = |git_dir: &Path| {
+            git_dir
+                .join("objects")
+                .join(&commit[..2])
+                .join(&commit[2..])
+        };
+
+        assert_eq!(
+            fs::metadata(object_path(remote.path())).unwrap().ino(),
+            fs::metadata(object_path(&work_dir.join(".git")))
+                .unwrap()
+                .ino()
+        );
+        assert_eq!(
+            fs::read_to_string(work_dir.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert!(fs::read_to_string(work_dir.join(".git/config"))
+            .unwrap()
+            .contains(&format!(
+                "url = {}\n",
+                fs::canonicalize(remote.path()).unwrap().display()
+            )));
+    }
+
+    #[test]
+    fn clone_from_a_file_url_runs_upload_pack() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        let tag = write_object(
+            remote.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                commit
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        write_ref(remote.path(), "refs/tags/v1", &tag);
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        clone(
+            &[
+                &format!("file://{}", remote.path().display()),
+                work_dir.to_str().unwrap(),
+            ],
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let git_dir = work_dir.join(".git");
+
+        assert!(fs::read_dir(git_dir.join("objects/pack"))
+            .unwrap()
+            .any(|entry| entry.unwrap().path().extension().unwrap() == "pack"));
+        assert_eq!(resolve_ref(&git_dir, "refs/tags/v1").unwrap(), Some(tag));
+        assert_eq!(
+            fs::read_to_string(git_dir.join("HEAD")).unwrap(),
+            "ref: refs/heads/main\n"
+        );
+        assert!(fsck(&[], &git_dir, &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn clone_with_a_filter_fetches_left_out_blobs_when_needed() {
+        let remote = init_git_dir();
+        let old = write_blob(remote.path(), "old\n");
+        let old_tree = write_tree(remote.path(), &[("100644", "README", &old)]);
+        let first = write_commit(remote.path(), &old_tree, &[], 100, "first");
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[&first], 200, "second");
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        clone(
+            &[
+                "--filter=blob:none",
+                &format!("file://{}", remote.path().display()),
+                work_dir.to_str().unwrap(),
+            ],
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let git_dir = work_dir.join(".git");
+        let config = fs::read_to_string(git_dir.join("config")).unwrap();
+        let promisor_packs = || {
+            fs::read_dir(git_dir.join("objects/pack"))
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension().unwrap() == "promisor")
+                .count()
+        };
+
+        assert!(config.contains("partialclonefilter = blob:none\n"));
+        assert!(config.contains("partialclone = origin\n"));
+        // the checkout fetched the one blob it needed
+        assert_eq!(
+            fs::read_to_string(work_dir.join("README")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(promisor_packs(), 2);
+        assert!(fsck(&[], &git_dir, &mut Vec::new()).is_ok());
+
+        assert_eq!(
+            read_raw_object(&git_dir, &old).unwrap(),
+            ("blob".to_string(), b"old\n".to_vec())
+        );
+        assert_eq!(promisor_packs(), 3);
+    }
+
+    #[test]
+    fn clone_returns_error_for_a_missing_path() {
+        let parent = tempfile::tempdir().unwrap();
+        let missing = parent.path().join("missing");
+
+        assert_eq!(
+            clone(&[missing.to_str().unwrap()], &mut Vec::new()).unwrap_err(),
+            format!("fatal: repository '{}' does not exist\n", missing.display())
+        );
+    }
+
+    #[test]
+    fn clone_removes_the_directory_when_it_fails() {
+        let url = serve_http(|_, _, _| ("text/plain", b"not git".to_vec()));
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        let result = clone(
+            &[&format!("{}/repo.git", url), work_dir.to_str().unwrap()],
+            &mut Vec::new(),
+        );
+
+        assert!(result.unwrap_err().contains("invalid info/refs line"));
+        assert!(!work_dir.exists());
+    }
+
+    #[test]
+    fn clone_refuses_a_pack_missing_objects_the_refs_reach() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        fs::remove_file(get_object_path_in(remote.path(), &readme).unwrap()).unwrap();
+        let url = serve_repository(
+            remote.path(),
+            vec![
+                ("HEAD".to_string(), commit.clone()),
+                ("refs/heads/main".to_string(), commit),
+            ],
+        );
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        let result = clone(
+            &[&format!("{}/repo.git", url), work_dir.to_str().unwrap()],
+            &mut Vec::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err("fatal: remote did not send all necessary objects\n".to_string())
+        );
+        assert!(!work_dir.exists());
+    }
+
+    #[test]
+    fn clone_refuses_an_advertisement_with_a_ref_outside_refs() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        let url = serve_repository(
+            remote.path(),
+            vec![
+                ("HEAD".to_string(), commit.clone()),
+                ("refs/heads/main".to_string(), commit.clone()),
+                ("refs/heads/../../../../evil".to_string(), commit),
+            ],
+        );
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        let result = clone(
+            &[&format!("{}/repo.git", url), work_dir.to_str().unwrap()],
+            &mut Vec::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(
+                "fatal: remote advertised an invalid ref name 'refs/heads/../../../../evil'\n"
+                    .to_string()
+            )
+        );
+        assert!(!work_dir.exists());
+        assert!(!parent.path().join("evil").exists());
+    }
+
+    #[test]
+    fn directory_from_url_strips_the_git_suffix() {
+        assert_eq!(
+            directory_from_url("https://example.com/org/repo.git/").unwrap(),
+            "repo"
+        );
+        assert_eq!(
+            directory_from_url("git@example.com:repo.git").unwrap(),
+            "repo"
+        );
+        assert_eq!(directory_from_url("/srv/repo/.git").unwrap(), "repo");
+        assert_eq!(directory_from_url("/tmp/repo.bundle").unwrap(), "repo");
+    }
+}
@@ -0,0 +1,489 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use std::collections::HashMap;
+
+use crate::git_commands::refs::ref_tips;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::tree_diff::changed_paths;
+use crate::git_commands::utils::write_atomically;
+use crate::models::bloom_filter::{bloom_keys, BloomFilter, BloomFilterSettings};
+use crate::models::commit_graph::{
+    CommitGraph, CommitGraphCommit, CommitGraphLayer, GENERATION_NUMBER_V1_MAX,
+};
+
+const USAGE: &str = "usage: git commit-graph (write [--reachable] [--split[=replace]] [--[no-]changed-paths] | verify)";
+
+fn commit_graph_file(git_dir: &Path) -> PathBuf {
+    git_dir.join("objects/info/commit-graph")
+}
+
+fn commit_graphs_dir(git_dir: &Path) -> PathBuf {
+    git_dir.join("objects/info/commit-graphs")
+}
+
+fn chain_file(git_dir: &Path) -> PathBuf {
+    commit_graphs_dir(git_dir).join("commit-graph-chain")
+}
+
+fn layer_file(git_dir: &Path, checksum: &str) -> PathBuf {
+    commit_graphs_dir(git_dir).join(format!("graph-{}.graph", checksum))
+}
+
+/// Loads `objects/info/commit-graph`, or the split chain when there is no single graph file.
+pub fn load_commit_graph(git_dir: &Path) -> Result<Option<CommitGraph>, String> {
+    let graph_path = commit_graph_file(git_dir);
+
+    if graph_path.exists() {
+        let data =
+            fs::read(&graph_path).map_err(|err| format!("error reading commit-graph: {}", err))?;
+
+        return Ok(Some(CommitGraph::new(vec![CommitGraphLayer::from_bytes(
+            &data,
+        )?])?));
+    }
+
+    load_commit_graph_chain(git_dir)
+}
+
+fn load_commit_graph_chain(git_dir: &Path) -> Result<Option<CommitGraph>, String> {
+    let chain_path = chain_file(git_dir);
+
+    if !chain_path.exists() {
+        return Ok(None);
+    }
+
+    let chain = fs::read_to_string(&chain_path)
+        .map_err(|err| format!("error reading commit-graph chain: {}", err))?;
+    let mut layers = Vec::new();
+
+    for checksum in chain.lines().filter(|line| !line.is_empty()) {
+        let data = fs::read(layer_file(git_dir, checksum))
+            .map_err(|err| format!("error reading commit-graph layer {}: {}", checksum, err))?;
+        let layer = CommitGraphLayer::from_bytes(&data)?;
+
+        if layer.checksum != checksum {
+            return Err(format!(
+                "commit-graph layer {} has the wrong checksum",
+                checksum
+            ));
+        }
+
+        layers.push(layer);
+    }
+
+    if layers.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(CommitGraph::new(layers)?))
+}
+
+pub fn commit_graph<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    match args.split_first() {
+        Some((&"write", options)) => write(options, git_dir),
+        Some((&"verify", [])) => verify(git_dir, writer),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+enum SplitMode {
+    None,
+    Append,
+    Replace,
+}
+
+fn write(options: &[&str], git_dir: &Path) -> Result<(), String> {
+    let mut split_mode = SplitMode::None;
+    let existing_graph = load_commit_graph(git_dir)?;
+    // like upstream, keep writing Bloom filters once a repository has them
+    let mut with_changed_paths = existing_graph
+        .as_ref()
+        .map(|graph| graph.has_bloom_filters())
+        .unwrap_or(false);
+
+    for option in options {
+        match *option {
+            "--reachable" => {}
+            "--split" => split_mode = SplitMode::Append,
+            "--split=replace" => split_mode = SplitMode::Replace,
+            "--changed-paths" => with_changed_paths = true,
+            "--no-changed-paths" => with_changed_paths = false,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut commits = reachable_commits(git_dir)?;
+
+    if with_changed_paths {
+        add_bloom_filters(git_dir, &mut commits, existing_graph.as_ref())?;
+    }
+
+    fs::create_dir_all(git_dir.join("objects/info")).map_err(|err| err.to_string())?;
+
+    match split_mode {
+        SplitMode::None => {
+            let data = CommitGraph::write_layer(&commits, None)?;
+
+            write_atomically(&commit_graph_file(git_dir), &data)?;
+            remove_chain(git_dir, &[])
+        }
+        SplitMode::Append => {
+            let base = load_commit_graph_chain(git_dir)?;
+            let new_commits: Vec<CommitGraphCommit> = commits
+                .into_iter()
+                .filter(|commit| {
+                    base.as_ref()
+                        .map(|graph| graph.position(&commit.sha).is_none())
+                        .unwrap_or(true)
+                })
+                .collect();
+
+            let mut chain: Vec<String> = base
+                .as_ref()
+                .map(|graph| graph.layers.iter().map(|l| l.checksum.clone()).collect())
+                .unwrap_or_default();
+
+            if !new_commits.is_empty() {
+                let data = CommitGraph::write_layer(&new_commits, base.as_ref())?;
+                chain.push(write_layer_file(git_dir, &data)?);
+            }
+
+            write_chain(git_dir, &chain)
+        }
+        SplitMode::Replace => {
+            let data = CommitGraph::write_layer(&commits, None)?;
+            let chain = vec![write_layer_file(git_dir, &data)?];
+
+            write_chain(git_dir, &chain)
+        }
+    }
+}
+
+fn reachable_commits(git_dir: &Path) -> Result<Vec<CommitGraphCommit>, String> {
+    let mut walk = RevWalk::new(git_dir)?;
+
+    for tip in ref_tips(git_dir)? {
+        // refs may point at trees or blobs, which have no place in the commit-graph
+        if RevWalk::peel(git_dir, &tip)
+            .and_then(|sha| walk.lookup(&sha))
+            .is_ok()
+        {
+            walk.push(&tip)?;
+        }
+    }
+
+    walk.map(|commit| {
+        commit.map(|commit| CommitGraphCommit {
+            sha: commit.sha,
+            tree: commit.tree,
+            parents: commit.parents,
+            commit_time: commit.timestamp.max(0) as u64,
+            bloom_filter: None,
+        })
+    })
+    .collect()
+}
+
+fn add_bloom_filters(
+    git_dir: &Path,
+    commits: &mut [CommitGraphCommit],
+    existing_graph: Option<&CommitGraph>,
+) -> Result<(), String> {
+    let settings = BloomFilterSettings::default();
+    let trees: HashMap<String, String> = commits
+        .iter()
+        .map(|commit| (commit.sha.clone(), commit.tree.clone()))
+        .collect();
+
+    for commit in commits.iter_mut() {
+        let reusable_filter = existing_graph
+            .and_then(|graph| graph.bloom_filter(&commit.sha))
+            .filter(|(_, existing_settings)| *existing_settings == settings)
+            .map(|(filter, _)| filter);
+
+        let filter = match reusable_filter {
+            Some(filter) => filter,
+            None => {
+                let parent_tree = commit.parents.first().and_then(|parent| trees.get(parent));
+                let paths = changed_paths(git_dir, parent_tree.map(|t| t.as_str()), &commit.tree)?;
+
+                BloomFilter::from_paths(&bloom_keys(&paths), &settings)
+            }
+        };
+
+        commit.bloom_filter = Some(filter);
+    }
+
+    Ok(())
+}
+
+fn write_layer_file(git_dir: &Path, data: &[u8]) -> Result<String, String> {
+    let checksum = hex::encode(&data[data.len() - 20..]);
+
+    fs::create_dir_all(commit_graphs_dir(git_dir)).map_err(|err| err.to_string())?;
+    write_atomically(&layer_file(git_dir, &checksum), data)?;
+
+    Ok(checksum)
+}
+
+fn write_chain(git_dir: &Path, chain: &[String]) -> Result<(), String> {
+    let content: String = chain
+        .iter()
+        .map(|checksum| format!("{}\n", checksum))
+        .collect();
+
+    fs::create_dir_all(commit_graphs_dir(git_dir)).map_err(|err| err.to_string())?;
+    write_atomically(&chain_file(git_dir), content.as_bytes())?;
+
+    let graph_path = commit_graph_file(git_dir);
+
+    if graph_path.exists() {
+        fs::remove_file(graph_path).map_err(|err| err.to_string())?;
+    }
+
+    remove_chain(git_dir, chain)
+}
+
+/// Removes split layers that are not part of `keep` (and the chain file itself when `keep` is empty).
+fn remove_chain(git_dir: &Path, keep: &[String]) -> Result<(), String> {
+    let graphs_dir = commit_graphs_dir(git_dir);
+
+    if !graphs_dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&graphs_dir).map_err(|err| err.to_string())? {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let is_kept_layer = keep
+            .iter()
+            .any(|checksum| file_name == format!("graph-{}.graph", checksum));
+        let is_live_chain = !keep.is_empty() && file_name == "commit-graph-chain";
+
+        if !is_kept_layer && !is_live_chain {
+            fs::remove_file(&path).map_err(|err| err.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn verify<W: Write>(git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let commit_graph = match load_commit_graph(git_dir)? {
+        Some(commit_graph) => commit_graph,
+        None => return Ok(()),
+    };
+
+    let mut errors = Vec::new();
+
+    for position in 0..commit_graph.num_commits() as u32 {
+        let entry = commit_graph.entry_at(position)?;
+        let commit = match RevWalk::lookup_object(git_dir, &entry.sha) {
+            Ok(commit) => commit,
+            Err(err) => {
+                errors.push(format!("commit {}: {}", entry.sha, err));
+                continue;
+            }
+        };
+
+        if commit.tree != entry.tree {
+            errors.push(format!("commit {}: root tree does not match", entry.sha));
+        }
+
+        if commit.parents != entry.parents {
+            errors.push(format!("commit {}: parents do not match", entry.sha));
+        }
+
+        if commit.timestamp.max(0) as u64 != entry.commit_time {
+            errors.push(format!("commit {}: commit date does not match", entry.sha));
+        }
+
+        for parent in &entry.parents {
+            let parent_entry = commit_graph
+                .lookup(parent)?
+                .ok_or_else(|| format!("parent {} is missing from the commit-graph", parent))?;
+
+            if parent_entry.topological_level >= entry.topological_level
+                && entry.topological_level != GENERATION_NUMBER_V1_MAX
+            {
+                errors.push(format!(
+                    "commit {}: generation number is too small",
+                    entry.sha
+                ));
+            }
+
+            if let (Some(parent_date), Some(date)) = (
+                parent_entry.corrected_commit_date,
+                entry.corrected_commit_date,
+            ) {
+                if parent_date >= date {
+                    errors.push(format!(
+                        "commit {}: corrected commit date is too small",
+                        entry.sha
+                    ));
+                }
+            }
+        }
+    }
+
+    for error in &errors {
+        writeln!(writer, "{}", error).map_err(|err| err.to_string())?;
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "commit-graph verification found {} problem(s)\n",
+            errors.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_history, write_ref, write_tree,
+    };
+
+    #[test]
+    fn write_creates_commit_graph_with_reachable_commits() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second"]);
+
+        commit_graph(&["write", "--reachable"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let graph = load_commit_graph(git_dir.path()).unwrap().unwrap();
+        let entry = graph.lookup(&commits[1]).unwrap().unwrap();
+
+        assert_eq!(graph.num_commits(), 2);
+        assert_eq!(entry.parents, vec![commits[0].clone()]);
+        assert!(graph.has_generation_v2());
+    }
+
+    #[test]
+    fn write_split_appends_layers_to_the_chain() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second"]);
+
+        commit_graph(&["write", "--split"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let tree = write_tree(git_dir.path(), &[]);
+        let third = write_commit(git_dir.path(), &tree, &[&commits[1]], 300, "third");
+        write_ref(git_dir.path(), "refs/heads/main", &third);
+
+        commit_graph(&["write", "--split"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let graph = load_commit_graph(git_dir.path()).unwrap().unwrap();
+
+        assert_eq!(graph.layers.len(), 2);
+        assert_eq!(graph.layers[1].num_commits(), 1);
+        assert_eq!(
+            graph.lookup(&third).unwrap().unwrap().parents,
+            vec![commits[1].clone()]
+        );
+
+        commit_graph(
+            &["write", "--split=replace"],
+            git_dir.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let graph = load_commit_graph(git_dir.path()).unwrap().unwrap();
+        let remaining_files = fs::read_dir(commit_graphs_dir(git_dir.path()))
+            .unwrap()
+            .count();
+
+        assert_eq!(graph.layers.len(), 1);
+        assert_eq!(graph.num_commits(), 3);
+        assert_eq!(remaining_files, 2);
+    }
+
+    #[test]
+    fn write_with_changed_paths_stores_bloom_filters() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "content");
+        let sub = write_tree(git_dir.path(), &[("100644", "lib.rs", &blob)]);
+        let first_tree = write_tree(git_dir.path(), &[("100644", "README", &blob)]);
+        let second_tree = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &blob), ("40000", "src", &sub)],
+        );
+        let first = write_commit(git_dir.path(), &first_tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &second_tree, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+
+        commit_graph(
+            &["write", "--changed-paths"],
+            git_dir.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let graph = load_commit_graph(git_dir.path()).unwrap().unwrap();
+        let (filter, settings) = graph.bloom_filter(&second).unwrap();
+
+        assert!(filter.contains("src/lib.rs", &settings));
+        assert!(filter.contains("src", &settings));
+        assert!(!filter.contains("README", &settings));
+
+        // a later write without the flag keeps the filters
+        commit_graph(&["write"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let graph = load_commit_graph(git_dir.path()).unwrap().unwrap();
+
+        assert!(graph.has_bloom_filters());
+    }
+
+    #[test]
+    fn rev_walk_uses_commit_graph_data() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second"]);
+
+        commit_graph(&["write"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        // remove the commit object: the walk must be answered from the commit-graph alone
+        let object_path =
+            crate::git_commands::utils::get_object_path_in(git_dir.path(), &commits[0]).unwrap();
+        fs::remove_file(object_path).unwrap();
+
+        let commit = RevWalk::new(git_dir.path())
+            .unwrap()
+            .lookup(&commits[0])
+            .unwrap();
+
+        assert_eq!(commit.timestamp, 100);
+    }
+
+    #[test]
+    fn verify_reports_mismatched_commits() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second"]);
+
+        commit_graph(&["write"], git_dir.path(), &mut Vec::new()).unwrap();
+        commit_graph(&["verify"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let object_path =
+            crate::git_commands::utils::get_object_path_in(git_dir.path(), &commits[1]).unwrap();
+        fs::remove_file(object_path).unwrap();
+
+        let mut output = Vec::new();
+        let result = commit_graph(&["verify"], git_dir.path(), &mut output);
+
+        assert!(result.is_err());
+        assert!(String::from_utf8(output).unwrap().contains(&commits[1]));
+    }
+
+    #[test]
+    fn commit_graph_fails_with_unknown_subcommand() {
+        let git_dir = init_git_dir();
+
+        let result = commit_graph(&["frobnicate"], git_dir.path(), &mut Vec::new());
+
+        assert_eq!(result.unwrap_err(), USAGE);
+    }
+}
@@ -0,0 +1,18 @@
+use std::fs;
+use std::path::Path;
+
+use crate::models::config::Config;
+
+/// Loads the repository's `config` file; a missing file is an empty config.
+pub fn load_config(git_dir: &Path) -> Result<Config, String> {
+    let path = git_dir.join("config");
+
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content =
+        fs::read_to_string(&path).map_err(|err| format!("error reading config: {}", err))?;
+
+    Config::parse(&content)
+}
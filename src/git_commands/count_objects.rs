@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::packs::{load_packs, pack_dir};
+
+const USAGE: &str = "usage: git count-objects [-v]";
+
+// files that may sit next to a pack; any of them without both the .pack and the .idx is garbage
+const PACK_EXTENSIONS: [&str; 7] = ["pack", "idx", "bitmap", "keep", "promisor", "rev", "mtimes"];
+
+/// Counts loose objects and the disk space they use. With `-v` it also reports the packs,
+/// loose objects that are already packed (`prune-packable`) and stray files (`garbage`).
+/// Sizes are in KiB; loose objects count the blocks they take up on disk, packs and garbage
+/// their file sizes, the same way upstream does.
+pub fn count_objects<W: Write>(
+    args: &[&str],
+    git_dir: &Path,
+    writer: &mut W,
+) -> Result<(), String> {
+    let verbose = match args {
+        [] => false,
+        ["-v"] | ["--verbose"] => true,
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let packs = load_packs(git_dir)?;
+    let packed: HashSet<&str> = packs
+        .iter()
+        .flat_map(|pack_file| {
+            pack_file
+                .index
+                .entries
+                .iter()
+                .map(|entry| entry.sha.as_str())
+        })
+        .collect();
+    let mut count = 0;
+    let mut size = 0;
+    let mut prune_packable = 0;
+    let mut garbage = Vec::new();
+
+    for fan_out in 0..=0xff {
+        let prefix = format!("{:02x}", fan_out);
+        let dir = git_dir.join("objects").join(&prefix);
+
+        for path in dir_files(&dir)? {
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+
+            if name.len() != 38 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+                garbage.push(path);
+                continue;
+            }
+
+            count += 1;
+            size += disk_usage(&path)?;
+
+            if packed.contains(format!("{}{}", prefix, name).as_str()) {
+                prune_packable += 1;
+            }
+        }
+    }
+
+    if !verbose {
+        return writeln!(writer, "{} objects, {} kilobytes", count, size / 1024)
+            .map_err(|err| err.to_string());
+    }
+
+    let mut size_pack = 0;
+
+    for pack_file in &packs {
+        for extension in ["pack", "idx"] {
+            let path = pack_dir(git_dir).join(format!("{}.{}", pack_file.name, extension));
+
+            size_pack += fs::metadata(&path)
+                .map_err(|err| format!("error reading {:?}: {}", path, err))?
+                .len();
+        }
+    }
+
+    garbage.extend(pack_garbage(git_dir)?);
+
+    let mut size_garbage = 0;
+
+    for path in &garbage {
+        size_garbage += fs::metadata(path)
+            .map_err(|err| format!("error reading {:?}: {}", path, err))?
+            .len();
+    }
+
+    let in_pack: usize = packs
+        .iter()
+        .map(|pack_file| pack_file.index.entries.len())
+        .sum();
+    let report = format!(
+        "count: {}\nsize: {}\nin-pack: {}\npacks: {}\nsize-pack: {}\nprune-packable: {}\n\
+         garbage: {}\nsize-garbage: {}\n",
+        count,
+        size / 1024,
+        in_pack,
+        packs.len(),
+        size_pack / 1024,
+        prune_packable,
+        garbage.len(),
+        size_garbage / 1024
+    );
+
+    writer
+        .write_all(report.as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+/// Files in `objects/pack` that do not belong to a complete pack: a `.pack` without its `.idx`
+/// (or the reverse), leftovers such as a `.bitmap` whose pack is gone, and unknown files.
+fn pack_garbage(git_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut groups: BTreeMap<String, Vec<(String, PathBuf)>> = BTreeMap::new();
+    let mut garbage = Vec::new();
+
+    for path in dir_files(&pack_dir(git_dir))? {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        if name == "multi-pack-index"
+            || (name.starts_with("multi-pack-index-") && name.ends_with(".bitmap"))
+        {
+            continue;
+        }
+
+        match name.rsplit_once('.') {
+            Some((stem, extension)) if PACK_EXTENSIONS.contains(&extension) => groups
+                .entry(stem.to_string())
+                .or_default()
+                .push((extension.to_string(), path)),
+            _ => garbage.push(path),
+        }
+    }
+
+    for files in groups.into_values() {
+        let complete = ["pack", "idx"]
+            .iter()
+            .all(|needed| files.iter().any(|(extension, _)| extension == needed));
+
+        if !complete {
+            garbage.extend(files.into_iter().map(|(_, path)| path));
+        }
+    }
+
+    Ok(garbage)
+}
+
+fn dir_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|err| format!("error reading {:?}: {}", dir, err))?;
+
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn disk_usage(path: &Path) -> Result<u64, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path)
+        .map(|metadata| metadata.blocks() * 512)
+        .map_err(|err| format!("error reading {:?}: {}", path, err))
+}
+
+#[cfg(not(unix))]
+fn disk_usage(path: &Path) -> Result<u64, String> {
+    fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|err| format!("error reading {:?}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::packs::write_pack_files;
+    use crate::git_commands::test_utils::{init_git_dir, write_blob};
+    use crate::models::pack::write_pack;
+
+    fn report_field(report: &str, field: &str) -> String {
+        report
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{}: ", field)))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn count_objects_verbose_reports_packs_and_garbage() {
+        let git_dir = init_git_dir();
+        write_blob(git_dir.path(), "one");
+        write_blob(git_dir.path(), "two");
+        let objects = vec![
+            ("blob".to_string(), b"one".to_vec()),
+            ("blob".to_string(), b"three".to_vec()),
+        ];
+        let (data, entries) = write_pack(&objects).unwrap();
+        write_pack_files(git_dir.path(), &data, entries).unwrap();
+        fs::create_dir_all(git_dir.path().join("objects/ab")).unwrap();
+        fs::write(git_dir.path().join("objects/ab/not-an-object"), "x").unwrap();
+        fs::write(pack_dir(git_dir.path()).join("pack-stale.bitmap"), "x").unwrap();
+        let mut output = Vec::new();
+
+        count_objects(&["-v"], git_dir.path(), &mut output).unwrap();
+
+        let report = String::from_utf8(output).unwrap();
+
+        assert_eq!(report_field(&report, "count"), "2");
+        assert_eq!(report_field(&report, "in-pack"), "2");
+        assert_eq!(report_field(&report, "packs"), "1");
+        assert_eq!(report_field(&report, "prune-packable"), "1");
+        assert_eq!(report_field(&report, "garbage"), "2");
+    }
+
+    #[test]
+    fn count_objects_without_verbose_prints_a_summary() {
+        let git_dir = init_git_dir();
+        let mut output = Vec::new();
+
+        count_objects(&[], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0 objects, 0 kilobytes\n"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashSet;
+use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use reqwest::blocking::Client;
+use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+
+use crate::git_commands::fetch_pack::{Negotiator, RefAdvertisement, ShallowInfo, ShallowRequest};
+use crate::git_commands::object_walk::object_links;
+use crate::git_commands::packs::write_pack_files;
+use crate::git_commands::smart_http::{HttpAuth, GIT_USER_AGENT};
+use crate::git_commands::transport::Transport;
+use crate::git_commands::utils::{is_sha, object_exists, parse_object_buffer, read_raw_object};
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pack::{object_sha, write_pack, Pack, RawObject};
+use crate::models::pack_index::PackIndex;
+
+/// The client side of the dumb HTTP protocol, for repositories on plain file servers: refs come
+/// from `info/refs` and `HEAD`, and objects are walked from the wanted refs, each downloaded
+/// from its loose path or, when the server has none, with the whole pack listed in
+/// `objects/info/packs` whose index holds it. Dumb servers cannot take pushes.
+pub struct DumbHttp {
+    url: String,
+    client: Client,
+    auth: HttpAuth,
+    git_dir: PathBuf,
+    /// The indexes of the server's packs we have not downloaded, loaded on first need.
+    remote_packs: Option<Vec<(String, PackIndex)>>,
+}
+
+impl DumbHttp {
+    pub fn new(url: &str, client: Client, auth: HttpAuth, git_dir: &Path) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client,
+            auth,
+            git_dir: git_dir.to_path_buf(),
+            remote_packs: None,
+        }
+    }
+
+    /// Builds the advertisement from the text of `info/refs`, `<sha>\t<name>` lines with peeled
+    /// tags as `^{}` refs, and `HEAD`, which becomes a `symref=` capability when it points at a
+    /// branch.
+    pub fn read_info_refs(&self, info_refs: &str) -> Result<RefAdvertisement, String> {
+        let mut advertisement = RefAdvertisement::default();
+
+        for line in info_refs.lines().filter(|line| !line.is_empty()) {
+            let (sha, name) = line
+                .split_once('\t')
+                .filter(|(sha, _)| is_sha(sha))
+                .ok_or_else(|| format!("invalid info/refs line '{}' from {}", line, self.url))?;
+
+            advertisement.refs.push((name.to_string(), sha.to_string()));
+        }
+
+        let head = match self.get("HEAD")? {
+            Some(head) => String::from_utf8_lossy(&head).trim().to_string(),
+            None => return Ok(advertisement),
+        };
+        let head_sha = match head.strip_prefix("ref: ") {
+            Some(target) => {
+                advertisement
+                    .capabilities
+                    .push(format!("symref=HEAD:{}", target));
+                advertisement
+                    .refs
+                    .iter()
+                    .find(|(name, _)| name == target)
+                    .map(|(_, sha)| sha.clone())
+            }
+            None if is_sha(&head) => Some(head),
+            None => return Err(format!("invalid HEAD '{}' from {}", head, self.url)),
+        };
+
+        if let Some(sha) = head_sha {
+            advertisement.refs.insert(0, ("HEAD".to_string(), sha));
+        }
+
+        Ok(advertisement)
+    }
+
+    /// Downloads a file under the repository URL, or `None` when the server does not have it.
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>, String> {
+        let request = self
+            .client
+            .get(format!("{}/{}", self.url, path))
+            .header(USER_AGENT, GIT_USER_AGENT);
+        let response = self
+            .auth
+            .authorize(request)
+            .send()
+            .map_err(|err| format!("unable to access '{}': {}", self.url, err))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "unable to access '{}/{}': The requested URL returned error: {}",
+                self.url,
+                path,
+                response.status().as_u16()
+            ));
+        }
+
+        response
+            .bytes()
+            .map(|bytes| Some(bytes.to_vec()))
+            .map_err(|err| format!("unable to access '{}': {}", self.url, err))
+    }
+
+    /// Downloads a loose object and checks it hashes to `sha`.
+    fn download_loose_object(&self, sha: &str) -> Result<Option<RawObject>, String> {
+        if !is_sha(sha) {
+            return Err(format!("invalid object name '{}'", sha));
+        }
+
+        let compressed = match self.get(&format!("objects/{}/{}", &sha[..2], &sha[2..]))? {
+            Some(compressed) => compressed,
+            None => return Ok(None),
+        };
+        let mut buffer = Vec::new();
+
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut buffer)
+            .map_err(|err| format!("error reading object {}: {}", sha, err))?;
+
+        let (object_type, content) = parse_object_buffer(sha, &buffer)?;
+
+        if object_sha(&object_type, &content) != sha {
+            return Err(format!("object {} from {} is corrupt", sha, self.url));
+        }
+
+        Ok(Some((object_type, content)))
+    }
+
+    /// Downloads the server's pack holding `sha` into the repository, returning the shas it
+    /// brought. The server's index only says which pack to download: the pack is indexed here,
+    /// like `store_pack` does, so that an index that does not match it cannot slip in objects
+    /// that were never hashed.
+    fn download_pack_with(&mut self, sha: &str) -> Result<Vec<String>, String> {
+        if self.remote_packs.is_none() {
+            self.remote_packs = Some(self.load_remote_packs()?);
+        }
+
+        let remote_packs = self
+            .remote_packs
+            .as_mut()
+            .ok_or("remote packs not loaded")?;
+        let position = remote_packs
+            .iter()
+            .position(|(_, index)| index.find_offset(sha).is_some())
+            .ok_or_else(|| format!("fatal: unable to find {} on {}\n", sha, self.url))?;
+        let (name, _) = remote_packs.remove(position);
+        let data = self
+            .get(&format!("objects/pack/{}.pack", name))?
+            .ok_or_else(|| {
+                format!(
+                    "fatal: {} lists {}.pack but has no such file\n",
+                    self.url, name
+                )
+            })?;
+        let pack = Pack::from_bytes(data)?;
+
+        pack.verify_checksum()?;
+
+        // a dumb server's packs are whole, so there are no outside bases to resolve
+        let entries = pack.index_entries(&|_| Ok(None))?;
+        let shas: Vec<String> = entries.iter().map(|entry| entry.sha.clone()).collect();
+
+        if !shas.iter().any(|packed| packed == sha) {
+            return Err(format!(
+                "fatal: {}.pack from {} does not have {}\n",
+                name, self.url, sha
+            ));
+        }
+
+        write_pack_files(&self.git_dir, pack.as_bytes(), entries)?;
+
+        Ok(shas)
+    }
+
+    /// Reads `objects/info/packs`, `P pack-<sha>.pack` lines, and downloads each pack's index.
+    /// Lines naming anything else are skipped, as upstream does.
+    fn load_remote_packs(&self) -> Result<Vec<(String, PackIndex)>, String> {
+        let listing = self.get("objects/info/packs")?.unwrap_or_default();
+        let mut packs = Vec::new();
+
+        for line in String::from_utf8_lossy(&listing).lines() {
+            let Some(name) = line
+                .strip_prefix("P ")
+                .and_then(|pack| pack.strip_suffix(".pack"))
+                .filter(|name| name.strip_prefix("pack-").is_some_and(is_sha))
+            else {
+                continue;
+            };
+            let index = self
+                .get(&format!("objects/pack/{}.idx", name))?
+                .ok_or_else(|| {
+                    format!("fatal: {} lists {}.pack but has no index\n", self.url, name)
+                })?;
+
+            packs.push((name.to_string(), PackIndex::from_bytes(&index)?));
+        }
+
+        Ok(packs)
+    }
+}
+
+impl Transport for DumbHttp {
+    fn discover_refs(&mut self, _prefixes: &[&str]) -> Result<RefAdvertisement, String> {
+        let info_refs = self
+            .get("info/refs")?
+            .ok_or_else(|| format!("fatal: {}/info/refs not found\n", self.url))?;
+
+        self.read_info_refs(&String::from_utf8_lossy(&info_refs))
+    }
+
+    /// Walks from `wants` down to the objects the repository already has, which are complete
+    /// since fetches only store what they got once the walk is over. Downloaded packs are
+    /// stored as they come, and their objects walked from the repository; the loose objects
+    /// come back as the pack.
+    fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        shallow: &ShallowRequest,
+        filter: Option<&ObjectFilter>,
+        _negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String> {
+        if shallow.is_deepening() {
+            return Err(
+                "fatal: dumb http transport does not support shallow capabilities\n".to_string(),
+            );
+        }
+
+        // files are fetched whole, so everything comes, as from a server without `filter`
+        if filter.is_some() {
+            let _ = writeln!(
+                stderr(),
+                "warning: filtering not recognized by server, ignoring"
+            );
+        }
+
+        let mut loose: Vec<RawObject> = Vec::new();
+        let mut from_packs: HashSet<String> = HashSet::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut queue: Vec<String> = wants.to_vec();
+
+        while let Some(sha) = queue.pop() {
+            if !seen.insert(sha.clone()) {
+                continue;
+            }
+
+            let (object_type, content) = if from_packs.contains(&sha) {
+                read_raw_object(&self.git_dir, &sha)?
+            } else if object_exists(&self.git_dir, &sha)? {
+                continue;
+            } else if let Some(object) = self.download_loose_object(&sha)? {
+                loose.push(object.clone());
+                object
+            } else {
+                from_packs.extend(self.download_pack_with(&sha)?);
+                read_raw_object(&self.git_dir, &sha)?
+            };
+
+            queue.extend(object_links(&object_type, content)?);
+        }
+
+        write_pack(&loose).map(|(pack, _)| (pack, ShallowInfo::default()))
+    }
+
+    fn send_pack(&mut self, _request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        Err(format!(
+            "fatal: {} is served over dumb HTTP, which does not support push\n",
+            self.url
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::git_commands::fetch_pack::store_pack;
+    use crate::git_commands::smart_http::SmartHttp;
+    use crate::git_commands::test_utils::{
+        init_git_dir, serve_files, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::transport::Service;
+    use crate::git_commands::update_server_info::write_server_info;
+    use crate::git_commands::utils::get_object_path_in;
+
+    #[test]
+    fn smart_http_falls_back_to_fetching_files() {
+        let remote = init_git_dir();
+        let old_blob = write_blob(remote.path(), "old\n");
+        let old_tree = write_tree(remote.path(), &[("100644", "file", &old_blob)]);
+        let old = write_commit(remote.path(), &old_tree, &[], 100, "old");
+        let packed: Vec<RawObject> = [&old_blob, &old_tree, &old]
+            .iter()
+            .map(|sha| read_raw_object(remote.path(), sha).unwrap())
+            .collect();
+        let (pack, entries) = write_pack(&packed).unwrap();
+
+        write_pack_files(remote.path(), &pack, entries).unwrap();
+
+        for sha in [&old_blob, &old_tree, &old] {
+            fs::remove_file(get_object_path_in(remote.path(), sha).unwrap()).unwrap();
+        }
+
+        let new_blob = write_blob(remote.path(), "new\n");
+        let new_tree = write_tree(remote.path(), &[("100644", "file", &new_blob)]);
+        let new = write_commit(remote.path(), &new_tree, &[&old], 200, "new");
+        write_ref(remote.path(), "refs/heads/main", &new);
+        write_server_info(remote.path()).unwrap();
+
+        let url = serve_files(remote.path().to_path_buf());
+        let local = init_git_dir();
+        let mut transport = SmartHttp::new(&url, Service::UploadPack, local.path());
+        let advertisement = transport.discover_refs(&[]).unwrap();
+
+        assert_eq!(
+            advertisement.refs,
+            vec![
+                ("HEAD".to_string(), new.clone()),
+                ("refs/heads/main".to_string(), new.clone()),
+            ]
+        );
+        assert_eq!(
+            advertisement.capabilities,
+            vec!["symref=HEAD:refs/heads/main"]
+        );
+
+        let (pack, _) = transport
+            .fetch_pack(
+                std::slice::from_ref(&new),
+                &ShallowRequest::default(),
+                None,
+                &mut Negotiator::new(local.path(), &[]).unwrap(),
+            )
+            .unwrap();
+
+        store_pack(local.path(), pack).unwrap();
+
+        for sha in [old_blob, old_tree, old, new_blob, new_tree, new] {
+            assert!(object_exists(local.path(), &sha).unwrap());
+        }
+    }
+
+    #[test]
+    fn info_refs_and_head_must_name_shas() {
+        let remote = init_git_dir();
+        let url = serve_files(remote.path().to_path_buf());
+        let local = init_git_dir();
+        // 40 bytes, but not 40 hex digits
+        let bad = format!("aé{}", "0".repeat(37));
+
+        fs::create_dir_all(remote.path().join("info")).unwrap();
+
+        for (info_refs, head) in [
+            (
+                format!("{}\trefs/heads/main\n", bad),
+                "ref: refs/heads/main\n".to_string(),
+            ),
+            (
+                format!("{}\trefs/heads/main\n", "1".repeat(40)),
+                bad.clone(),
+            ),
+        ] {
+            fs::write(remote.path().join("info/refs"), info_refs).unwrap();
+            fs::write(remote.path().join("HEAD"), head).unwrap();
+
+            let mut transport = SmartHttp::new(&url, Service::UploadPack, local.path());
+
+            assert!(transport
+                .discover_refs(&[])
+                .unwrap_err()
+                .contains("invalid"));
+        }
+    }
+
+    #[test]
+    fn packs_are_indexed_here_rather_than_trusting_the_servers_index() {
+        let remote = init_git_dir();
+        let blob = write_blob(remote.path(), "file\n");
+        let tree = write_tree(remote.path(), &[("100644", "file", &blob)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "commit");
+        let unrelated = write_blob(remote.path(), "unrelated\n");
+        let objects = |shas: &[&String]| -> Vec<RawObject> {
+            shas.iter()
+                .map(|sha| read_raw_object(remote.path(), sha).unwrap())
+                .collect()
+        };
+        let (_, entries) = write_pack(&objects(&[&blob, &tree, &commit])).unwrap();
+        let (pack, _) = write_pack(&objects(&[&unrelated])).unwrap();
+
+        // an index that claims the pack of one blob holds the whole commit
+        let name = write_pack_files(remote.path(), &pack, entries).unwrap();
+
+        for sha in [&blob, &tree, &commit, &unrelated] {
+            fs::remove_file(get_object_path_in(remote.path(), sha).unwrap()).unwrap();
+        }
+
+        // written by hand, as update-server-info would trip over the index
+        for (path, content) in [
+            ("info/refs", format!("{}\trefs/heads/main\n", commit)),
+            ("objects/info/packs", format!("P {}.pack\n", name)),
+        ] {
+            let path = remote.path().join(path);
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let url = serve_files(remote.path().to_path_buf());
+        let local = init_git_dir();
+        let mut transport = SmartHttp::new(&url, Service::UploadPack, local.path());
+
+        transport.discover_refs(&[]).unwrap();
+
+        let err = transport
+            .fetch_pack(
+                std::slice::from_ref(&commit),
+                &ShallowRequest::default(),
+                None,
+                &mut Negotiator::new(local.path(), &[]).unwrap(),
+            )
+            .unwrap_err();
+
+        assert!(err.contains(&format!("does not have {}", commit)));
+        assert!(!object_exists(local.path(), &commit).unwrap());
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::refs::{expand_ref_name, list_refs, or_head, resolve_revision};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::tree_diff::read_tree;
+use crate::git_commands::utils::{read_raw_object, write_atomically};
+use crate::models::commit::Commit;
+use crate::models::tag::Tag;
+use crate::models::tree::{TreeEntry, TreeEntryMode};
+
+const USAGE: &str = "usage: git fast-export [-M] [--all] [--import-marks=<file>] \
+                     [--import-marks-if-exists=<file>] [--export-marks=<file>] [<revision>...]";
+
+/// Writes the history the revisions reach as a fast-import stream: each commit's new blobs
+/// with marks, then the commit itself with its parents as `from` and `merge` marks and its
+/// changes from the first parent as `M` and `D` (with `-M`, exact renames as `R`), then the
+/// annotated tags and a `reset` for every ref whose commit went out under another name. A
+/// commit whose parents are all left out by the revisions lists its whole tree. Marks read with
+/// `--import-marks` stand for objects an earlier run exported, which are not exported again;
+/// `--export-marks` saves the commits' marks for the next run.
+pub fn fast_export<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut detect_renames = false;
+    let mut import_marks = None;
+    let mut export_marks = None;
+    let mut tips: Vec<(String, String)> = Vec::new();
+    let mut excluded = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "-M" | "--find-renames" => detect_renames = true,
+            "--all" => tips.extend(list_refs(git_dir)?),
+            _ if arg.starts_with("--import-marks=") => {
+                import_marks = Some((&arg["--import-marks=".len()..], false))
+            }
+            _ if arg.starts_with("--import-marks-if-exists=") => {
+                import_marks = Some((&arg["--import-marks-if-exists=".len()..], true))
+            }
+            _ if arg.starts_with("--export-marks=") => {
+                export_marks = Some(&arg["--export-marks=".len()..])
+            }
+            _ if arg.starts_with('^') => excluded.push(resolve_revision(git_dir, &arg[1..])?),
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => {
+                let revision = match arg.split_once("..") {
+                    Some((from, to)) => {
+                        excluded.push(resolve_revision(git_dir, or_head(from))?);
+                        or_head(to)
+                    }
+                    None => arg,
+                };
+
+                // revisions that are not refs are named as given, like upstream
+                tips.push(match expand_ref_name(git_dir, revision)? {
+                    Some(found) => found,
+                    None => (revision.to_string(), resolve_revision(git_dir, revision)?),
+                });
+            }
+        }
+    }
+
+    let mut marks = match import_marks {
+        Some((path, if_exists)) if !if_exists || Path::new(path).exists() => {
+            read_marks(Path::new(path))?
+        }
+        _ => Marks::default(),
+    };
+    let mut walk = RevWalk::new(git_dir)?;
+
+    for sha in &excluded {
+        walk.hide(sha)?;
+    }
+
+    let peeled = tips
+        .iter()
+        .map(|(_, sha)| RevWalk::peel(git_dir, sha))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for sha in &peeled {
+        if read_raw_object(git_dir, sha)?.0 == "commit" {
+            walk.push(sha)?;
+        }
+    }
+
+    let commits = walk
+        .by_ref()
+        .map(|commit| commit.map(|commit| (commit.sha, commit.parents)))
+        .collect::<Result<HashMap<_, _>, String>>()?;
+    let names = commit_names(&tips, &peeled, &commits);
+    let mut exporter = Exporter {
+        git_dir,
+        marks: &mut marks,
+        blobs: Vec::new(),
+        detect_renames,
+        writer,
+    };
+    let mut exported: HashMap<String, &str> = HashMap::new();
+
+    for sha in parents_first(&commits, &peeled) {
+        if exporter.marks.get(&sha).is_some() {
+            continue;
+        }
+
+        let name = names[&sha];
+
+        exporter.export_commit(&sha, name)?;
+        exported.insert(sha, name);
+    }
+
+    for (name, sha) in &tips {
+        let (object_type, content) = read_raw_object(git_dir, sha)?;
+
+        if object_type == "tag" {
+            exporter.export_tag(name, content)?;
+            continue;
+        }
+
+        let Some(mark) = exporter.marks.get(sha) else {
+            continue;
+        };
+
+        if exported.get(sha) != Some(&name.as_str()) {
+            exporter.write(format!("reset {}\nfrom :{}\n\n", name, mark).as_bytes())?;
+        }
+    }
+
+    // like upstream, only commits are saved, so blobs go out again with the commits that need them
+    for blob in std::mem::take(&mut exporter.blobs) {
+        marks.remove(&blob);
+    }
+
+    match export_marks {
+        Some(path) => write_marks(Path::new(path), &marks),
+        None => Ok(()),
+    }
+}
+
+/// Which ref each commit goes out under: the first of `tips` whose history holds it.
+fn commit_names<'a>(
+    tips: &'a [(String, String)],
+    peeled: &[String],
+    commits: &HashMap<String, Vec<String>>,
+) -> HashMap<String, &'a str> {
+    let mut names = HashMap::new();
+
+    for ((name, _), sha) in tips.iter().zip(peeled) {
+        let mut stack = vec![sha.clone()];
+
+        while let Some(sha) = stack.pop() {
+            if !commits.contains_key(&sha) || names.contains_key(&sha) {
+                continue;
+            }
+
+            stack.extend(commits[&sha].iter().cloned());
+            names.insert(sha, name.as_str());
+        }
+    }
+
+    names
+}
+
+/// Orders the commits so that every commit comes after its parents, as fast-import needs
+/// them, following the (peeled) tips in order.
+fn parents_first(commits: &HashMap<String, Vec<String>>, tips: &[String]) -> Vec<String> {
+    let mut ordered = Vec::new();
+    let mut done = HashSet::new();
+
+    for tip in tips {
+        let mut stack = vec![(tip.clone(), false)];
+
+        while let Some((sha, parents_done)) = stack.pop() {
+            if !commits.contains_key(&sha) || done.contains(&sha) {
+                continue;
+            }
+
+            if parents_done {
+                done.insert(sha.clone());
+                ordered.push(sha);
+                continue;
+            }
+
+            stack.push((sha.clone(), true));
+            stack.extend(
+                commits[&sha]
+                    .iter()
+                    .rev()
+                    .map(|parent| (parent.clone(), false)),
+            );
+        }
+    }
+
+    ordered
+}
+
+/// Marks as fast-import numbers them: `:<n>` for an object, handed out in order.
+#[derive(Debug, Default, PartialEq)]
+pub struct Marks {
+    marks: HashMap<String, usize>,
+    shas: HashMap<usize, String>,
+    last: usize,
+}
+
+impl Marks {
+    pub fn get(&self, sha: &str) -> Option<usize> {
+        self.marks.get(sha).copied()
+    }
+
+    /// The object a mark stands for.
+    pub fn sha(&self, mark: usize) -> Option<&str> {
+        self.shas.get(&mark).map(String::as_str)
+    }
+
+    /// Gives `sha` the next mark.
+    pub fn add(&mut self, sha: &str) -> usize {
+        self.insert(self.last + 1, sha);
+        self.last
+    }
+
+    pub fn remove(&mut self, sha: &str) {
+        if let Some(mark) = self.marks.remove(sha) {
+            self.shas.remove(&mark);
+        }
+    }
+
+    /// Records `sha` under a mark the stream chose, which replaces whatever it stood for.
+    pub fn insert(&mut self, mark: usize, sha: &str) {
+        if let Some(previous) = self.shas.insert(mark, sha.to_string()) {
+            if self.marks.get(&previous) == Some(&mark) {
+                self.marks.remove(&previous);
+            }
+        }
+
+        self.marks.insert(sha.to_string(), mark);
+        self.last = self.last.max(mark);
+    }
+
+    /// The marks in order, as `(mark, sha)` pairs.
+    pub fn sorted(&self) -> Vec<(usize, &str)> {
+        let mut marks: Vec<(usize, &str)> = self
+            .shas
+            .iter()
+            .map(|(mark, sha)| (*mark, sha.as_str()))
+            .collect();
+
+        marks.sort();
+        marks
+    }
+}
+
+/// Reads a marks file of `:<mark> <sha>` lines.
+pub fn read_marks(path: &Path) -> Result<Marks, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("fatal: cannot read '{}': {}\n", path.display(), err))?;
+    let mut marks = Marks::default();
+
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let parsed = line
+            .strip_prefix(':')
+            .and_then(|line| line.split_once(' '))
+            .and_then(|(mark, sha)| Some((mark.parse().ok()?, sha)))
+            .filter(|(_, sha)| sha.len() == 40);
+
+        match parsed {
+            Some((mark, sha)) => marks.insert(mark, sha),
+            None => return Err(format!("fatal: corrupt mark line: {}\n", line)),
+        }
+    }
+
+    Ok(marks)
+}
+
+pub fn write_marks(path: &Path, marks: &Marks) -> Result<(), String> {
+    let content: String = marks
+        .sorted()
+        .into_iter()
+        .map(|(mark, sha)| format!(":{} {}\n", mark, sha))
+        .collect();
+
+    write_atomically(path, content.as_bytes())
+}
+
+/// One change a commit makes to its first parent's tree, as the stream lists it.
+#[derive(Debug, PartialEq)]
+enum FileChange {
+    Delete {
+        path: String,
+        entry: TreeEntry,
+    },
+    Modify {
+        path: String,
+        entry: TreeEntry,
+        added: bool,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+}
+
+struct Exporter<'a, W: Write> {
+    git_dir: &'a Path,
+    marks: &'a mut Marks,
+    blobs: Vec<String>,
+    detect_renames: bool,
+    writer: &'a mut W,
+}
+
+impl<W: Write> Exporter<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.writer.write_all(bytes).map_err(|err| err.to_string())
+    }
+
+    fn export_blob(&mut self, sha: &str) -> Result<usize, String> {
+        if let Some(mark) = self.marks.get(sha) {
+            return Ok(mark);
+        }
+
+        let (_, content) = read_raw_object(self.git_dir, sha)?;
+        let mark = self.marks.add(sha);
+
+        self.blobs.push(sha.to_string());
+
+        self.write(format!("blob\nmark :{}\ndata {}\n", mark, content.len()).as_bytes())?;
+        self.write(&content)?;
+        self.write(b"\n")?;
+
+        Ok(mark)
+    }
+
+    fn export_commit(&mut self, sha: &str, name: &str) -> Result<(), String> {
+        let commit = Commit::new(read_raw_object(self.git_dir, sha)?.1)?;
+        let parent_marks: Vec<(usize, &String)> = commit
+            .parents
+            .iter()
+            .filter_map(|parent| Some((self.marks.get(parent)?, parent)))
+            .collect();
+        // changes are listed against the first parent, or the empty tree when it is left out
+        let base = match commit.parents.first() {
+            Some(first) if self.marks.get(first).is_some() => {
+                Some(Commit::new(read_raw_object(self.git_dir, first)?.1)?.tree)
+            }
+            _ => None,
+        };
+        let mut changes = Vec::new();
+
+        diff_trees(
+            self.git_dir,
+            base.as_deref(),
+            Some(&commit.tree),
+            "",
+            &mut changes,
+        )?;
+
+        if self.detect_renames {
+            find_renames(&mut changes);
+        }
+
+        let mut lines = Vec::new();
+
+        for change in &changes {
+            lines.push(match change {
+                FileChange::Delete { path, .. } => format!("D {}\n", quote_path(path, false)),
+                FileChange::Rename { from, to } => {
+                    format!("R {} {}\n", quote_path(from, true), quote_path(to, false))
+                }
+                FileChange::Modify { path, entry, .. }
+                    if entry.mode == TreeEntryMode::Submodule =>
+                {
+                    format!(
+                        "M {} {} {}\n",
+                        entry.mode,
+                        entry.sha,
+                        quote_path(path, false)
+                    )
+                }
+                FileChange::Modify { path, entry, .. } => format!(
+                    "M {} :{} {}\n",
+                    entry.mode,
+                    self.export_blob(&entry.sha)?,
+                    quote_path(path, false)
+                ),
+            });
+        }
+
+        if commit.parents.is_empty() {
+            self.write(format!("reset {}\n", name).as_bytes())?;
+        }
+
+        let mark = self.marks.add(sha);
+        let mut header = format!(
+            "commit {}\nmark :{}\nauthor {}\ncommitter {}\ndata {}\n{}",
+            name,
+            mark,
+            commit.author,
+            commit.committer,
+            commit.message.len(),
+            commit.message
+        );
+
+        for (idx, (parent_mark, _)) in parent_marks.iter().enumerate() {
+            let keyword = if idx == 0 { "from" } else { "merge" };
+
+            header.push_str(&format!("{} :{}\n", keyword, parent_mark));
+        }
+
+        self.write(header.as_bytes())?;
+        self.write(lines.concat().as_bytes())?;
+        self.write(b"\n")
+    }
+
+    /// Writes an annotated tag, unless what it points at was left out of the stream.
+    fn export_tag(&mut self, name: &str, content: Vec<u8>) -> Result<(), String> {
+        let tag = Tag::new(content)?;
+        let target = match tag.object_type.as_str() {
+            "blob" => self.export_blob(&tag.object)?,
+            _ => match self.marks.get(&tag.object) {
+                Some(mark) => mark,
+                None => return Ok(()),
+            },
+        };
+        let mut output = format!(
+            "tag {}\nfrom :{}\n",
+            name.strip_prefix("refs/tags/").unwrap_or(name),
+            target
+        );
+
+        if let Some(tagger) = &tag.tagger {
+            output.push_str(&format!("tagger {}\n", tagger));
+        }
+
+        output.push_str(&format!("data {}\n{}\n", tag.message.len(), tag.message));
+        self.write(output.as_bytes())
+    }
+}
+
+/// Lists what turns tree `old` into tree `new`, path by path, skipping subtrees that did not
+/// change. A missing tree is the empty tree.
+fn diff_trees(
+    git_dir: &Path,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    changes: &mut Vec<FileChange>,
+) -> Result<(), String> {
+    let entries = |sha: Option<&str>| -> Result<Vec<TreeEntry>, String> {
+        let mut entries = match sha {
+            Some(sha) => read_tree(git_dir, sha)?.tree_entries,
+            None => Vec::new(),
+        };
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    };
+    let old_entries = entries(old)?;
+    let new_entries = entries(new)?;
+    let mut names: Vec<&String> = old_entries
+        .iter()
+        .chain(&new_entries)
+        .map(|entry| &entry.name)
+        .collect();
+
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let old = old_entries.iter().find(|entry| entry.name == *name);
+        let new = new_entries.iter().find(|entry| entry.name == *name);
+
+        if old == new {
+            continue;
+        }
+
+        let path = format!("{}{}", prefix, name);
+        let subtree = |entry: Option<&TreeEntry>| {
+            entry
+                .filter(|entry| entry.mode == TreeEntryMode::Directory)
+                .map(|entry| entry.sha.clone())
+        };
+        let (old_tree, new_tree) = (subtree(old), subtree(new));
+
+        if let Some(old) = old.filter(|_| old_tree.is_none()) {
+            changes.push(FileChange::Delete {
+                path: path.clone(),
+                entry: old.clone(),
+            });
+        }
+
+        if old_tree.is_some() || new_tree.is_some() {
+            diff_trees(
+                git_dir,
+                old_tree.as_deref(),
+                new_tree.as_deref(),
+                &format!("{}/", path),
+                changes,
+            )?;
+        }
+
+        if let Some(new) = new.filter(|_| new_tree.is_none()) {
+            // a file that replaces a file is modified in place rather than deleted first
+            let replaced = matches!(changes.last(), Some(FileChange::Delete { path: deleted, .. }) if *deleted == path);
+
+            if replaced {
+                changes.pop();
+            }
+
+            changes.push(FileChange::Modify {
+                path,
+                entry: new.clone(),
+                added: !replaced,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns a deleted file and an added one with the same content and mode into a rename.
+fn find_renames(changes: &mut Vec<FileChange>) {
+    let mut renamed = HashSet::new();
+
+    for idx in 0..changes.len() {
+        let FileChange::Modify {
+            path,
+            entry,
+            added: true,
+        } = &changes[idx]
+        else {
+            continue;
+        };
+        let source = changes.iter().position(|change| {
+            matches!(change, FileChange::Delete { path: from, entry: old }
+                if old.sha == entry.sha && old.mode == entry.mode && !renamed.contains(from))
+        });
+
+        if let Some(source) = source {
+            let FileChange::Delete { path: from, .. } = &changes[source] else {
+                unreachable!()
+            };
+            let from = from.clone();
+
+            renamed.insert(from.clone());
+            changes[idx] = FileChange::Rename {
+                from,
+                to: path.clone(),
+            };
+        }
+    }
+
+    changes.retain(
+        |change| !matches!(change, FileChange::Delete { path, .. } if renamed.contains(path)),
+    );
+}
+
+/// Quotes a path the C way when the stream could not read it bare: with quotes, backslashes or
+/// newlines in it, or, for the first path of a rename, spaces.
+fn quote_path(path: &str, before_another: bool) -> String {
+    let needs_quotes = path.starts_with('"')
+        || path.contains(['\\', '\n'])
+        || (before_another && path.contains(' '));
+
+    if !needs_quotes {
+        return path.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+
+    for c in path.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_object, write_ref, write_tree,
+    };
+
+    #[test]
+    fn fast_export_writes_blobs_commits_and_tags() {
+        let git_dir = init_git_dir();
+        let hello = write_blob(git_dir.path(), "hello\n");
+        let run = write_blob(git_dir.path(), "#!/bin/sh\n");
+        let tree = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &hello), ("100755", "run", &run)],
+        );
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let tree2 = write_tree(git_dir.path(), &[("100644", "b", &hello)]);
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "second");
+        let tag = write_object(
+            git_dir.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                first
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+        write_ref(git_dir.path(), "refs/heads/old", &first);
+        write_ref(git_dir.path(), "refs/tags/v1", &tag);
+        let marks_dir = tempfile::tempdir().unwrap();
+        let marks = marks_dir.path().join("marks");
+        let mut output = Vec::new();
+
+        fast_export(
+            &[
+                "-M",
+                "--all",
+                &format!("--export-marks={}", marks.display()),
+            ],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+
+        let author = "A U Thor <author@example.com>";
+        let committer = "C O Mitter <committer@example.com>";
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "blob\nmark :1\ndata 6\nhello\n\nblob\nmark :2\ndata 10\n#!/bin/sh\n\n\
+                 reset refs/heads/main\ncommit refs/heads/main\nmark :3\nauthor {a} 100 +0000\n\
+                 committer {c} 100 +0000\ndata 6\nfirst\nM 100644 :1 a\nM 100755 :2 run\n\n\
+                 commit refs/heads/main\nmark :4\nauthor {a} 200 +0000\n\
+                 committer {c} 200 +0000\ndata 7\nsecond\nfrom :3\nR a b\nD run\n\n\
+                 reset refs/heads/old\nfrom :3\n\n\
+                 tag v1\nfrom :3\ntagger T <t@example.com> 100 +0000\ndata 3\nv1\n\n",
+                a = author,
+                c = committer
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(&marks).unwrap(),
+            format!(":3 {}\n:4 {}\n", first, second)
+        );
+
+        let mut output = Vec::new();
+
+        fast_export(
+            &[&format!("--import-marks={}", marks.display()), "main"],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+
+        // nothing new, but the ref still points at the right commit afterwards
+        assert_eq!(output, b"reset refs/heads/main\nfrom :4\n\n");
+    }
+
+    #[test]
+    fn quote_path_quotes_only_what_the_stream_cannot_read() {
+        assert_eq!(quote_path("dir/a b", false), "dir/a b");
+        assert_eq!(quote_path("a b", true), "\"a b\"");
+        assert_eq!(quote_path("a\"b\n", false), "\"a\\\"b\\n\"");
+    }
+}
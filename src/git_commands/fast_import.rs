@@ -0,0 +1,992 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{stderr, BufRead, Write};
+use std::path::Path;
+
+use crate::git_commands::fast_export::{read_marks, write_marks, Marks};
+use crate::git_commands::packs::write_pack_files;
+use crate::git_commands::refs::{delete_ref, resolve_ref, resolve_revision, update_ref};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::read_raw_object;
+use crate::models::commit::Commit;
+use crate::models::pack::{object_sha, write_pack};
+use crate::models::tree::{Tree, TreeEntryMode};
+
+const USAGE: &str = "usage: git fast-import [--quiet] [--force] [--done] [--import-marks=<file>] \
+                     [--import-marks-if-exists=<file>] [--export-marks=<file>]";
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// Reads a fast-import stream and builds the history it describes: `blob`, `commit`, `tag`,
+/// `reset`, `checkpoint`, `progress`, `feature` and `done` commands, with `#` comments. Each
+/// branch's tree is kept in memory and only the directories a commit touches are read or
+/// written again. The new objects go into one pack, and the refs (and the marks file, with
+/// `--export-marks`) are written at the end or at a `checkpoint`. A branch whose new tip does
+/// not contain its old one is left alone unless `--force` is given.
+pub fn fast_import<R: BufRead, W: Write>(
+    args: &[&str],
+    git_dir: &Path,
+    reader: R,
+    writer: &mut W,
+) -> Result<(), String> {
+    let mut options = Options::default();
+
+    for arg in args {
+        match *arg {
+            "--quiet" => options.quiet = true,
+            "--force" => options.force = true,
+            "--done" => options.done = true,
+            _ if arg.starts_with("--import-marks=") => {
+                options.import_marks = Some((arg["--import-marks=".len()..].to_string(), false))
+            }
+            _ if arg.starts_with("--import-marks-if-exists=") => {
+                options.import_marks =
+                    Some((arg["--import-marks-if-exists=".len()..].to_string(), true))
+            }
+            _ if arg.starts_with("--export-marks=") => {
+                options.export_marks = Some(arg["--export-marks=".len()..].to_string())
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut stream = Stream {
+        reader,
+        peeked: None,
+    };
+    let mut importer = Importer {
+        git_dir,
+        options,
+        started: false,
+        marks: Marks::default(),
+        objects: Vec::new(),
+        pending: HashMap::new(),
+        counts: BTreeMap::new(),
+        branches: BTreeMap::new(),
+        tags: BTreeMap::new(),
+        writer,
+    };
+    let mut done = false;
+
+    while let Some(line) = stream.next_line()? {
+        let (command, rest) = line.split_once(' ').unwrap_or((&line, ""));
+
+        if command != "feature" && !line.is_empty() && !line.starts_with('#') {
+            importer.start()?;
+        }
+
+        match command {
+            "" => {}
+            _ if command.starts_with('#') => {}
+            "feature" => importer.feature(rest)?,
+            "blob" => importer.blob(&mut stream)?,
+            "commit" => importer.commit(rest, &mut stream)?,
+            "tag" => importer.tag(rest, &mut stream)?,
+            "reset" => importer.reset(rest, &mut stream)?,
+            "checkpoint" => importer.checkpoint()?,
+            "progress" => importer.progress(rest)?,
+            "done" => {
+                done = true;
+                break;
+            }
+            _ => return Err(format!("fatal: Unsupported command: {}\n", line)),
+        }
+    }
+
+    if importer.options.done && !done {
+        return Err("fatal: stream ends early\n".to_string());
+    }
+
+    importer.checkpoint()?;
+
+    if !importer.options.quiet {
+        importer.print_statistics();
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    quiet: bool,
+    force: bool,
+    done: bool,
+    /// The file to read marks from, and whether it may be missing.
+    import_marks: Option<(String, bool)>,
+    export_marks: Option<String>,
+}
+
+/// The lines of the stream, with one line of lookahead for the optional parts of a command.
+struct Stream<R: BufRead> {
+    reader: R,
+    peeked: Option<Option<String>>,
+}
+
+impl<R: BufRead> Stream<R> {
+    fn next_line(&mut self) -> Result<Option<String>, String> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(line);
+        }
+
+        let mut line = Vec::new();
+
+        if self
+            .reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            return Ok(None);
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        Ok(Some(String::from_utf8_lossy(&line).to_string()))
+    }
+
+    fn peek_line(&mut self) -> Result<Option<&str>, String> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.next_line()?);
+        }
+
+        Ok(self.peeked.as_ref().and_then(|line| line.as_deref()))
+    }
+
+    /// Takes the next line if it starts with `prefix`, returning the rest of it.
+    fn take(&mut self, prefix: &str) -> Result<Option<String>, String> {
+        match self.peek_line()? {
+            Some(line) if line.starts_with(prefix) => Ok(self
+                .next_line()?
+                .map(|line| line[prefix.len()..].to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a `data <count>` or `data <<<delimiter>` command and what it carries.
+    fn data(&mut self) -> Result<Vec<u8>, String> {
+        let Some(header) = self.take("data ")? else {
+            return Err("fatal: Expected 'data n' command, found something else\n".to_string());
+        };
+
+        let data = match header.strip_prefix("<<") {
+            Some(delimiter) => {
+                let mut data = Vec::new();
+
+                loop {
+                    match self.next_line()? {
+                        Some(line) if line == delimiter => break,
+                        Some(line) => {
+                            data.extend_from_slice(line.as_bytes());
+                            data.push(b'\n');
+                        }
+                        None => {
+                            return Err(format!(
+                                "fatal: EOF in data (terminator '{}' not found)\n",
+                                delimiter
+                            ))
+                        }
+                    }
+                }
+
+                data
+            }
+            None => {
+                let count: usize = header
+                    .parse()
+                    .map_err(|_| format!("fatal: invalid data length: data {}\n", header))?;
+                let mut data = vec![0; count];
+
+                self.reader
+                    .read_exact(&mut data)
+                    .map_err(|_| format!("fatal: EOF in data ({} bytes remaining)\n", count))?;
+                data
+            }
+        };
+
+        // the data may be followed by a blank line
+        if self.peek_line()? == Some("") {
+            self.next_line()?;
+        }
+
+        Ok(data)
+    }
+}
+
+/// A directory of a branch's tree. It stays a stored tree until something under it changes,
+/// when its entries are read so they can be changed and it is written out again.
+#[derive(Clone, Debug, Default)]
+struct Directory {
+    /// The stored tree, while nothing under it has changed.
+    sha: Option<String>,
+    entries: Option<BTreeMap<String, Node>>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    File(TreeEntryMode, String),
+    Directory(Directory),
+}
+
+impl Directory {
+    fn stored(sha: String) -> Self {
+        Self {
+            sha: Some(sha),
+            entries: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.entries {
+            Some(entries) => entries.is_empty(),
+            None => self.sha.is_none(),
+        }
+    }
+
+    fn entries<F>(&mut self, read: &F) -> Result<&mut BTreeMap<String, Node>, String>
+    where
+        F: Fn(&str) -> Result<Vec<u8>, String>,
+    {
+        if let (None, Some(sha)) = (&self.entries, &self.sha) {
+            let entries = Tree::new(read(sha)?)?
+                .tree_entries
+                .into_iter()
+                .map(|entry| {
+                    let node = match entry.mode {
+                        TreeEntryMode::Directory => Node::Directory(Self::stored(entry.sha)),
+                        mode => Node::File(mode, entry.sha),
+                    };
+
+                    (entry.name, node)
+                })
+                .collect();
+
+            self.entries = Some(entries);
+        }
+
+        Ok(self.entries.get_or_insert_with(BTreeMap::new))
+    }
+
+    fn get<F>(&mut self, path: &[&str], read: &F) -> Result<Option<Node>, String>
+    where
+        F: Fn(&str) -> Result<Vec<u8>, String>,
+    {
+        let Some((name, rest)) = path.split_first() else {
+            return Ok(Some(Node::Directory(self.clone())));
+        };
+
+        match self.entries(read)?.get_mut(*name) {
+            Some(Node::Directory(directory)) => directory.get(rest, read),
+            Some(node) if rest.is_empty() => Ok(Some(node.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Puts `node` at `path`, making the directories on the way and replacing files in it.
+    fn set<F>(&mut self, path: &[&str], node: Node, read: &F) -> Result<(), String>
+    where
+        F: Fn(&str) -> Result<Vec<u8>, String>,
+    {
+        let Some((name, rest)) = path.split_first() else {
+            return Err("fatal: Empty path component found in input\n".to_string());
+        };
+        let entries = self.entries(read)?;
+
+        if rest.is_empty() {
+            entries.insert(name.to_string(), node);
+        } else {
+            let child = entries
+                .entry(name.to_string())
+                .or_insert_with(|| Node::Directory(Directory::default()));
+
+            if let Node::File(..) = child {
+                *child = Node::Directory(Directory::default());
+            }
+
+            if let Node::Directory(directory) = child {
+                directory.set(rest, node, read)?;
+            }
+        }
+
+        self.sha = None;
+        Ok(())
+    }
+
+    /// Takes what is at `path` out, dropping the directories that leaves empty.
+    fn remove<F>(&mut self, path: &[&str], read: &F) -> Result<Option<Node>, String>
+    where
+        F: Fn(&str) -> Result<Vec<u8>, String>,
+    {
+        let Some((name, rest)) = path.split_first() else {
+            return Ok(None);
+        };
+        let entries = self.entries(read)?;
+        let removed = match entries.get_mut(*name) {
+            Some(Node::Directory(directory)) if !rest.is_empty() => {
+                let removed = directory.remove(rest, read)?;
+
+                if directory.is_empty() {
+                    entries.remove(*name);
+                }
+
+                removed
+            }
+            Some(_) if rest.is_empty() => entries.remove(*name),
+            _ => None,
+        };
+
+        if removed.is_some() {
+            self.sha = None;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Branch {
+    head: Option<String>,
+    tree: Directory,
+    /// Set by a reset to the null sha, which deletes the ref unless a commit follows.
+    delete: bool,
+}
+
+struct Importer<'a, W: Write> {
+    git_dir: &'a Path,
+    options: Options,
+    /// Whether a command other than `feature` was seen, after which the marks are loaded.
+    started: bool,
+    marks: Marks,
+    /// The objects written since the last checkpoint, which go into the next pack.
+    objects: Vec<(String, Vec<u8>)>,
+    pending: HashMap<String, usize>,
+    counts: BTreeMap<String, usize>,
+    branches: BTreeMap<String, Branch>,
+    tags: BTreeMap<String, String>,
+    writer: &'a mut W,
+}
+
+impl<W: Write> Importer<'_, W> {
+    fn start(&mut self) -> Result<(), String> {
+        if self.started {
+            return Ok(());
+        }
+
+        self.started = true;
+
+        if let Some((path, if_exists)) = &self.options.import_marks {
+            if !if_exists || Path::new(path).exists() {
+                self.marks = read_marks(Path::new(path))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a `feature` command; options given on the command line win over the stream's.
+    fn feature(&mut self, feature: &str) -> Result<(), String> {
+        if self.started {
+            return Err(format!(
+                "fatal: Got feature command '{}' after data command\n",
+                feature
+            ));
+        }
+
+        match feature.split_once('=') {
+            Some(("import-marks", path)) => {
+                self.options
+                    .import_marks
+                    .get_or_insert((path.to_string(), false));
+            }
+            Some(("import-marks-if-exists", path)) => {
+                self.options
+                    .import_marks
+                    .get_or_insert((path.to_string(), true));
+            }
+            Some(("export-marks", path)) => {
+                self.options.export_marks.get_or_insert(path.to_string());
+            }
+            Some(("date-format", "raw")) => {}
+            None if feature == "done" => self.options.done = true,
+            None if feature == "force" => self.options.force = true,
+            _ => {
+                return Err(format!(
+                    "fatal: This version of fast-import does not support feature {}.\n",
+                    feature
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads an object written in this stream or already in the repository.
+    fn read(&self, sha: &str) -> Result<(String, Vec<u8>), String> {
+        match self.pending.get(sha) {
+            Some(&idx) => Ok(self.objects[idx].clone()),
+            None => read_raw_object(self.git_dir, sha),
+        }
+    }
+
+    fn store(&mut self, object_type: &str, content: Vec<u8>) -> String {
+        let sha = object_sha(object_type, &content);
+
+        if !self.pending.contains_key(&sha) {
+            *self.counts.entry(object_type.to_string()).or_default() += 1;
+            self.pending.insert(sha.clone(), self.objects.len());
+            self.objects.push((object_type.to_string(), content));
+        }
+
+        sha
+    }
+
+    /// The object a `from`, `merge` or data reference names: a mark, a branch of this stream,
+    /// a sha, or a revision of the repository (a trailing `^0` is ignored).
+    fn resolve(&self, reference: &str) -> Result<String, String> {
+        if let Some(mark) = reference.strip_prefix(':') {
+            let mark = parse_mark(mark)?;
+
+            return match self.marks.sha(mark) {
+                Some(sha) => Ok(sha.to_string()),
+                None => Err(format!("fatal: mark :{} not declared\n", mark)),
+            };
+        }
+
+        if let Some(sha) = self
+            .branches
+            .get(reference)
+            .and_then(|branch| branch.head.as_ref())
+        {
+            return Ok(sha.clone());
+        }
+
+        if reference.len() == 40 && reference.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(reference.to_lowercase());
+        }
+
+        let revision = reference.strip_suffix("^0").unwrap_or(reference);
+
+        resolve_revision(self.git_dir, revision)
+            .and_then(|sha| RevWalk::peel(self.git_dir, &sha))
+            .map_err(|_| {
+                format!(
+                    "fatal: Invalid ref name or SHA1 expression: {}\n",
+                    reference
+                )
+            })
+    }
+
+    /// A branch starting at the commit `reference` names, or nowhere for the null sha.
+    fn branch_at(&self, reference: &str) -> Result<Branch, String> {
+        if reference == NULL_SHA {
+            return Ok(Branch {
+                delete: true,
+                ..Branch::default()
+            });
+        }
+
+        let sha = self.resolve(reference)?;
+        let (object_type, content) = self.read(&sha)?;
+
+        if object_type != "commit" {
+            return Err(format!(
+                "fatal: Not a commit (actually a {}): {}\n",
+                object_type, sha
+            ));
+        }
+
+        Ok(Branch {
+            tree: Directory::stored(Commit::new(content)?.tree),
+            head: Some(sha),
+            delete: false,
+        })
+    }
+
+    fn blob<R: BufRead>(&mut self, stream: &mut Stream<R>) -> Result<(), String> {
+        let mark = stream.take("mark ")?;
+
+        stream.take("original-oid ")?;
+
+        let data = stream.data()?;
+        let sha = self.store("blob", data);
+
+        if let Some(mark) = mark {
+            self.marks
+                .insert(parse_mark(mark.trim_start_matches(':'))?, &sha);
+        }
+
+        Ok(())
+    }
+
+    fn commit<R: BufRead>(&mut self, name: &str, stream: &mut Stream<R>) -> Result<(), String> {
+        let mark = stream.take("mark ")?;
+
+        stream.take("original-oid ")?;
+
+        let author = stream.take("author ")?;
+        let Some(committer) = stream.take("committer ")? else {
+            return Err("fatal: Expected committer but didn't get one\n".to_string());
+        };
+        let encoding = stream.take("encoding ")?;
+        let message = stream.data()?;
+        let mut branch = match stream.take("from ")? {
+            Some(from) => self.branch_at(&from)?,
+            None => self.branches.remove(name).unwrap_or_default(),
+        };
+        let mut parents: Vec<String> = branch.head.iter().cloned().collect();
+
+        while let Some(merge) = stream.take("merge ")? {
+            parents.push(self.resolve(&merge)?);
+        }
+
+        while let Some(line) = stream.peek_line()? {
+            let line = line.to_string();
+
+            match line.split_once(' ') {
+                Some(("M", rest)) => {
+                    stream.next_line()?;
+                    self.file_modify(rest, &mut branch.tree, stream)?;
+                }
+                Some(("D", path)) => {
+                    stream.next_line()?;
+
+                    let path = parse_path(path, true)?.0;
+
+                    branch
+                        .tree
+                        .remove(&split_path(&path)?, &|sha| Ok(self.read(sha)?.1))?;
+                }
+                Some((command @ ("C" | "R"), rest)) => {
+                    stream.next_line()?;
+
+                    let (from, rest) = parse_path(rest, false)?;
+                    let to = parse_path(rest.trim_start_matches(' '), true)?.0;
+                    let read = |sha: &str| Ok(self.read(sha)?.1);
+                    let from = split_path(&from)?;
+                    let node = match command {
+                        "C" => branch.tree.get(&from, &read)?,
+                        _ => branch.tree.remove(&from, &read)?,
+                    };
+                    let Some(node) = node else {
+                        return Err(format!("fatal: Path {} not in branch\n", from.join("/")));
+                    };
+
+                    branch.tree.set(&split_path(&to)?, node, &read)?;
+                }
+                None if line == "deleteall" => {
+                    stream.next_line()?;
+                    branch.tree = Directory::default();
+                }
+                _ => break,
+            }
+        }
+
+        let tree = self.write_tree(&mut branch.tree)?;
+        let mut content = format!("tree {}\n", tree);
+
+        for parent in &parents {
+            content.push_str(&format!("parent {}\n", parent));
+        }
+
+        content.push_str(&format!(
+            "author {}\ncommitter {}\n",
+            author.as_ref().unwrap_or(&committer),
+            committer
+        ));
+
+        if let Some(encoding) = encoding {
+            content.push_str(&format!("encoding {}\n", encoding));
+        }
+
+        content.push('\n');
+
+        let mut content = content.into_bytes();
+
+        content.extend(message);
+
+        let sha = self.store("commit", content);
+
+        if let Some(mark) = mark {
+            self.marks
+                .insert(parse_mark(mark.trim_start_matches(':'))?, &sha);
+        }
+
+        branch.head = Some(sha);
+        branch.delete = false;
+        self.branches.insert(name.to_string(), branch);
+
+        Ok(())
+    }
+
+    /// Applies `M <mode> <dataref> <path>`, where the data is a mark, a sha or `inline`.
+    fn file_modify<R: BufRead>(
+        &mut self,
+        rest: &str,
+        tree: &mut Directory,
+        stream: &mut Stream<R>,
+    ) -> Result<(), String> {
+        let mut parts = rest.splitn(3, ' ');
+        let (Some(mode), Some(data), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("fatal: Missing space after SHA1: M {}\n", rest));
+        };
+        let mode = match mode {
+            "644" | "100644" => TreeEntryMode::RegularFile,
+            "755" | "100755" => TreeEntryMode::ExecutableFile,
+            "120000" => TreeEntryMode::SymbolicLink,
+            "160000" => TreeEntryMode::Submodule,
+            "40000" | "040000" => TreeEntryMode::Directory,
+            _ => return Err(format!("fatal: Corrupt mode: M {}\n", rest)),
+        };
+        let sha = match data {
+            "inline" => {
+                let data = stream.data()?;
+
+                self.store("blob", data)
+            }
+            _ => self.resolve(data)?,
+        };
+        let node = match mode {
+            TreeEntryMode::Directory => Node::Directory(Directory::stored(sha)),
+            mode => Node::File(mode, sha),
+        };
+        let path = parse_path(path, true)?.0;
+
+        tree.set(&split_path(&path)?, node, &|sha| Ok(self.read(sha)?.1))
+    }
+
+    /// Writes the directories that changed, deepest first, and returns the tree's sha. Empty
+    /// directories are left out.
+    fn write_tree(&mut self, directory: &mut Directory) -> Result<String, String> {
+        if let Some(sha) = &directory.sha {
+            return Ok(sha.clone());
+        }
+
+        let mut entries = Vec::new();
+
+        for (name, node) in directory.entries.iter_mut().flatten() {
+            let (mode, sha) = match node {
+                Node::File(mode, sha) => (mode.clone(), sha.clone()),
+                Node::Directory(directory) if directory.is_empty() => continue,
+                Node::Directory(directory) => {
+                    (TreeEntryMode::Directory, self.write_tree(directory)?)
+                }
+            };
+
+            entries.push((mode, name, sha));
+        }
+
+        // directories sort as if they ended in '/'
+        entries.sort_by_key(|(mode, name, _)| match mode {
+            TreeEntryMode::Directory => format!("{}/", name),
+            _ => name.to_string(),
+        });
+
+        let mut content = Vec::new();
+
+        for (mode, name, sha) in entries {
+            content.extend_from_slice(
+                format!("{} {}\0", mode.to_string().trim_start_matches('0'), name).as_bytes(),
+            );
+            content.extend(hex::decode(sha).map_err(|err| err.to_string())?);
+        }
+
+        let sha = self.store("tree", content);
+
+        directory.sha = Some(sha.clone());
+        Ok(sha)
+    }
+
+    fn tag<R: BufRead>(&mut self, name: &str, stream: &mut Stream<R>) -> Result<(), String> {
+        let mark = stream.take("mark ")?;
+        let Some(from) = stream.take("from ")? else {
+            return Err("fatal: Expected from command\n".to_string());
+        };
+
+        stream.take("original-oid ")?;
+
+        let tagger = stream.take("tagger ")?;
+        let message = stream.data()?;
+        let object = self.resolve(&from)?;
+        let object_type = self.read(&object)?.0;
+        let mut content = format!("object {}\ntype {}\ntag {}\n", object, object_type, name);
+
+        if let Some(tagger) = tagger {
+            content.push_str(&format!("tagger {}\n", tagger));
+        }
+
+        content.push('\n');
+
+        let mut content = content.into_bytes();
+
+        content.extend(message);
+
+        let sha = self.store("tag", content);
+
+        if let Some(mark) = mark {
+            self.marks
+                .insert(parse_mark(mark.trim_start_matches(':'))?, &sha);
+        }
+
+        self.tags.insert(name.to_string(), sha);
+        Ok(())
+    }
+
+    fn reset<R: BufRead>(&mut self, name: &str, stream: &mut Stream<R>) -> Result<(), String> {
+        let branch = match stream.take("from ")? {
+            Some(from) => self.branch_at(&from)?,
+            None => Branch::default(),
+        };
+
+        if stream.peek_line()? == Some("") {
+            stream.next_line()?;
+        }
+
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+
+    fn progress(&mut self, message: &str) -> Result<(), String> {
+        writeln!(self.writer, "progress {}", message).map_err(|err| err.to_string())?;
+        self.writer.flush().map_err(|err| err.to_string())
+    }
+
+    /// Stores the objects so far as a pack, then updates the refs and the marks file.
+    fn checkpoint(&mut self) -> Result<(), String> {
+        self.start()?;
+
+        if !self.objects.is_empty() {
+            let (data, entries) = write_pack(&self.objects)?;
+
+            write_pack_files(self.git_dir, &data, entries)?;
+            self.objects.clear();
+            self.pending.clear();
+        }
+
+        let mut refused = String::new();
+
+        for (name, branch) in &self.branches {
+            let old = resolve_ref(self.git_dir, name)?;
+
+            match &branch.head {
+                Some(sha) if old.as_ref() == Some(sha) => {}
+                Some(sha) => match old {
+                    Some(old)
+                        if !self.options.force
+                            && !RevWalk::is_ancestor(self.git_dir, &old, sha)? =>
+                    {
+                        refused.push_str(&format!(
+                            "warning: Not updating {} (new tip {} does not contain {})\n",
+                            name, sha, old
+                        ));
+                    }
+                    _ => update_ref(self.git_dir, name, sha)?,
+                },
+                None if branch.delete && old.is_some() => delete_ref(self.git_dir, name)?,
+                None => {}
+            }
+        }
+
+        for (name, sha) in &self.tags {
+            update_ref(self.git_dir, &format!("refs/tags/{}", name), sha)?;
+        }
+
+        if let Some(path) = &self.options.export_marks {
+            write_marks(Path::new(path), &self.marks)?;
+        }
+
+        if refused.is_empty() {
+            Ok(())
+        } else {
+            Err(refused)
+        }
+    }
+
+    fn print_statistics(&self) {
+        let count = |object_type: &str| self.counts.get(object_type).copied().unwrap_or(0);
+
+        let _ = write!(
+            stderr(),
+            "fast-import statistics:\n\
+             objects: {} (blobs: {}, trees: {}, commits: {}, tags: {})\n\
+             branches: {}, tags: {}, marks: {}\n",
+            self.counts.values().sum::<usize>(),
+            count("blob"),
+            count("tree"),
+            count("commit"),
+            count("tag"),
+            self.branches.len(),
+            self.tags.len(),
+            self.marks.sorted().len()
+        );
+    }
+}
+
+fn parse_mark(mark: &str) -> Result<usize, String> {
+    mark.parse()
+        .ok()
+        .filter(|&mark| mark > 0)
+        .ok_or_else(|| format!("fatal: Invalid mark: :{}\n", mark))
+}
+
+fn split_path(path: &str) -> Result<Vec<&str>, String> {
+    let components: Vec<&str> = path.split('/').collect();
+
+    if components.iter().any(|component| component.is_empty()) {
+        return Err(format!(
+            "fatal: Empty path component found in input: {}\n",
+            path
+        ));
+    }
+
+    Ok(components)
+}
+
+/// Reads a path at the start of `text`, returning it with what follows. A quoted path ends at
+/// its closing quote and may use C escapes; a bare one ends at the first space when another
+/// path follows it, and at the end of the line otherwise.
+fn parse_path(text: &str, last: bool) -> Result<(String, &str), String> {
+    let Some(quoted) = text.strip_prefix('"') else {
+        return Ok(match text.split_once(' ') {
+            Some((path, rest)) if !last => (path.to_string(), rest),
+            _ => (text.to_string(), ""),
+        });
+    };
+    let bytes = quoted.as_bytes();
+    let mut path = Vec::new();
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'"' => {
+                let path = String::from_utf8(path)
+                    .map_err(|_| format!("fatal: Invalid path: {}\n", text))?;
+
+                return Ok((path, &quoted[idx + 1..]));
+            }
+            b'\\' => {
+                let escaped = *bytes
+                    .get(idx + 1)
+                    .ok_or_else(|| format!("fatal: Invalid path: {}\n", text))?;
+
+                idx += 2;
+                path.push(match escaped {
+                    b'n' => b'\n',
+                    b't' => b'\t',
+                    b'r' => b'\r',
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b'f' => 0x0c,
+                    b'v' => 0x0b,
+                    b'0'..=b'3' => {
+                        let octal = quoted
+                            .get(idx - 1..idx + 2)
+                            .and_then(|octal| u8::from_str_radix(octal, 8).ok())
+                            .ok_or_else(|| format!("fatal: Invalid path: {}\n", text))?;
+
+                        idx += 2;
+                        octal
+                    }
+                    other => other,
+                });
+            }
+            byte => {
+                path.push(byte);
+                idx += 1;
+            }
+        }
+    }
+
+    Err(format!("fatal: Invalid path: {}\n", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{init_git_dir, write_blob, write_commit, write_tree};
+    use std::fs;
+
+    #[test]
+    fn fast_import_builds_commits_tags_and_refs() {
+        let git_dir = init_git_dir();
+        let marks_dir = tempfile::tempdir().unwrap();
+        let marks = marks_dir.path().join("marks");
+        let ident = |who: &str, timestamp: i64| match who {
+            "author" => format!("author A U Thor <author@example.com> {} +0000", timestamp),
+            _ => format!(
+                "committer C O Mitter <committer@example.com> {} +0000",
+                timestamp
+            ),
+        };
+        let stream = format!(
+            "feature done\n\
+             blob\nmark :1\ndata 6\nhello\n\n\
+             commit refs/heads/main\nmark :2\n{}\n{}\ndata 6\nfirst\n\
+             M 100644 :1 a\nM 100644 inline \"dir/b c\"\ndata <<EOF\nbye\nEOF\n\n\
+             progress one\n\
+             commit refs/heads/main\nmark :3\n{}\n{}\ndata 7\nsecond\n\
+             R a dir/a\nD \"dir/b c\"\n\n\
+             tag v1\nfrom :2\ntagger T <t@example.com> 100 +0000\ndata 3\nv1\n\
+             reset refs/heads/old\nfrom :2\n\n\
+             done\n",
+            ident("author", 100),
+            ident("committer", 100),
+            ident("author", 200),
+            ident("committer", 200)
+        );
+        let mut output = Vec::new();
+
+        fast_import(
+            &["--quiet", &format!("--export-marks={}", marks.display())],
+            git_dir.path(),
+            stream.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+
+        // the same history written object by object elsewhere has the same shas
+        let expected = init_git_dir();
+        let hello = write_blob(expected.path(), "hello\n");
+        let bye = write_blob(expected.path(), "bye\n");
+        let dir = write_tree(expected.path(), &[("100644", "b c", &bye)]);
+        let tree = write_tree(
+            expected.path(),
+            &[("100644", "a", &hello), ("40000", "dir", &dir)],
+        );
+        let first = write_commit(expected.path(), &tree, &[], 100, "first");
+        let dir2 = write_tree(expected.path(), &[("100644", "a", &hello)]);
+        let tree2 = write_tree(expected.path(), &[("40000", "dir", &dir2)]);
+        let second = write_commit(expected.path(), &tree2, &[&first], 200, "second");
+        let resolve = |name: &str| resolve_ref(git_dir.path(), name).unwrap().unwrap();
+
+        assert_eq!(output, b"progress one\n");
+        assert_eq!(resolve("refs/heads/main"), second);
+        assert_eq!(resolve("refs/heads/old"), first);
+        assert_eq!(
+            read_raw_object(git_dir.path(), &resolve("refs/tags/v1")).unwrap(),
+            (
+                "tag".to_string(),
+                format!(
+                    "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                    first
+                )
+                .into_bytes()
+            )
+        );
+        assert!(!git_dir.path().join("objects").join(&hello[..2]).exists());
+        assert_eq!(
+            fs::read_to_string(&marks).unwrap(),
+            format!(":1 {}\n:2 {}\n:3 {}\n", hello, first, second)
+        );
+    }
+
+    #[test]
+    fn parse_path_reads_quoted_and_bare_paths() {
+        assert_eq!(parse_path("a b", false).unwrap(), ("a".to_string(), "b"));
+        assert_eq!(parse_path("a b", true).unwrap(), ("a b".to_string(), ""));
+        assert_eq!(
+            parse_path("\"a \\\"b\\\"\\n\\303\\251\" c", false).unwrap(),
+            ("a \"b\"\né".to_string(), " c")
+        );
+        assert!(parse_path("\"a", true).is_err());
+    }
+}
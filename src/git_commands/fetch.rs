@@ -0,0 +1,719 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch_pack::{store_pack, Negotiator};
+use crate::git_commands::promisor::{mark_promisor_pack, partial_clone_filter, promisor_remote};
+use crate::git_commands::refs::{
+    abbrev, current_branch, delete_ref, list_refs, read_symref, ref_tips, resolve_ref, shorten_ref,
+    update_ref, SUMMARY_WIDTH,
+};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::shallow::{update_shallow, ShallowOptions};
+use crate::git_commands::transport::{open_transport, transport_url, Service};
+use crate::git_commands::utils::{object_exists, write_atomically};
+use crate::models::config::Config;
+use crate::models::refspec::Refspec;
+
+const USAGE: &str = "usage: git fetch [--prune] [--tags] [--depth <depth> | --deepen <depth> | \
+                     --unshallow] [--shallow-since <date>] [--shallow-exclude <ref>] \
+                     [<remote> [<refspec>...]]";
+const DEFAULT_REMOTE: &str = "origin";
+const TAGS_REFSPEC: &str = "refs/tags/*:refs/tags/*";
+
+/// Fetches from a remote (the current branch's, or `origin`) and updates refs as its refspecs
+/// say: those given on the command line, otherwise `remote.<name>.fetch`. Haves are offered
+/// from every local ref so the pack leaves out what we already have. A ref is only moved when
+/// the update is a fast-forward, unless its refspec starts with `+`, and tags are never moved
+/// without one. `--prune` deletes refs whose source is gone from the remote and `--tags` also
+/// fetches every tag. Every fetched ref is recorded in `FETCH_HEAD`. The shallow options cut
+/// the fetched history (`--deepen` counting from where it stops now) and `--unshallow` fetches
+/// all of it, `.git/shallow` keeping track of where it stops.
+pub fn fetch<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut prune = false;
+    let mut tags = false;
+    let mut shallow = ShallowOptions::default();
+    let mut positional = Vec::new();
+    let mut args = args.iter().copied();
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "-p" | "--prune" => prune = true,
+            "-t" | "--tags" => tags = true,
+            _ if shallow.parse(arg, &mut args, true)? => {}
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+
+    let shallow = shallow.request(git_dir)?;
+
+    let config = load_config(git_dir)?;
+    let remote = match positional.first() {
+        Some(remote) => remote.to_string(),
+        None => default_remote(git_dir, &config)?,
+    };
+    let (url, configured) = match config.get(&format!("remote.{}.url", remote)) {
+        Some(url) => (
+            url.to_string(),
+            config.get_all(&format!("remote.{}.fetch", remote)),
+        ),
+        None => (remote.clone(), Vec::new()),
+    };
+    // refs named on the command line, or the remote's HEAD when nothing says what to fetch,
+    // are the ones a later merge would take
+    let (specs, explicit) = if positional.len() > 1 {
+        (positional[1..].to_vec(), true)
+    } else if configured.is_empty() && !tags {
+        (vec!["HEAD"], true)
+    } else {
+        (configured, false)
+    };
+    let mut refspecs = specs
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<Result<Vec<_>, String>>()?;
+    let tags_start = refspecs.len();
+
+    if tags {
+        refspecs.push(Refspec::parse(TAGS_REFSPEC)?);
+    }
+
+    let merge_ref = if explicit {
+        None
+    } else {
+        merge_ref(git_dir, &config, &remote)?
+    };
+    let mut transport = open_transport(&transport_url(&url)?, git_dir, Service::UploadPack)?;
+    let prefixes: Vec<String> = refspecs
+        .iter()
+        .flat_map(|refspec| refspec.src_prefixes())
+        .collect();
+    let prefixes: Vec<&str> = prefixes.iter().map(|prefix| prefix.as_str()).collect();
+    let advertisement = transport.discover_refs(&prefixes)?;
+    advertisement.check_ref_names()?;
+
+    let remote_refs: Vec<(String, String)> = advertisement.direct_refs().cloned().collect();
+    let mut fetched: Vec<FetchedRef> = Vec::new();
+
+    for (idx, refspec) in refspecs.iter().enumerate() {
+        let matched: Vec<&(String, String)> = if refspec.is_glob() {
+            remote_refs
+                .iter()
+                .filter(|(name, _)| refspec.matches_src(name))
+                .collect()
+        } else {
+            // a short name takes the first ref it can stand for, like a revision would
+            let found = refspec
+                .src_prefixes()
+                .into_iter()
+                .find_map(|candidate| remote_refs.iter().find(|(name, _)| *name == candidate));
+
+            match found {
+                Some(found) => vec![found],
+                None => return Err(format!("fatal: couldn't find remote ref {}\n", refspec.src)),
+            }
+        };
+
+        for (name, sha) in matched {
+            let local = refspec.map_src(name);
+
+            if local.is_some() && fetched.iter().any(|other| other.local == local) {
+                continue;
+            }
+
+            fetched.push(FetchedRef {
+                remote: name.clone(),
+                local,
+                sha: sha.clone(),
+                force: refspec.force,
+                merge: idx < tags_start
+                    && (explicit || merge_ref.as_deref() == Some(name.as_str())),
+            });
+        }
+    }
+
+    check_current_branch(git_dir, &config, &fetched)?;
+
+    let mut wants: Vec<String> = Vec::new();
+
+    // deepening changes the history of commits we already have, so they are asked for too
+    for fetched_ref in &fetched {
+        if (shallow.is_deepening() || !object_exists(git_dir, &fetched_ref.sha)?)
+            && !wants.contains(&fetched_ref.sha)
+        {
+            wants.push(fetched_ref.sha.clone());
+        }
+    }
+
+    if !wants.is_empty() {
+        // a partial clone keeps filtering what it fetches from its promisor remote
+        let promisor = promisor_remote(git_dir)?.as_deref() == Some(remote.as_str());
+        let filter = if promisor {
+            partial_clone_filter(git_dir, &remote)?
+        } else {
+            None
+        };
+        let mut negotiator = Negotiator::new(git_dir, &ref_tips(git_dir)?)?;
+        let (pack, info) =
+            transport.fetch_pack(&wants, &shallow, filter.as_ref(), &mut negotiator)?;
+
+        if let Some(name) = store_pack(git_dir, pack)? {
+            if promisor {
+                mark_promisor_pack(git_dir, &name)?;
+            }
+        }
+
+        update_shallow(git_dir, &info.shallow, &info.unshallow)?;
+    }
+
+    let mut lines = Vec::new();
+
+    if prune {
+        for name in stale_refs(git_dir, &refspecs, &remote_refs)? {
+            delete_ref(git_dir, &name)?;
+            lines.push(SummaryLine {
+                flag: '-',
+                summary: "[deleted]".to_string(),
+                from: "(none)".to_string(),
+                to: shorten_ref(&name).to_string(),
+                suffix: "",
+            });
+        }
+    }
+
+    let display_url = display_url(&url);
+
+    write_fetch_head(git_dir, display_url, &fetched)?;
+
+    let mut rejected = false;
+
+    for fetched_ref in &fetched {
+        if let Some(line) = update_local_ref(git_dir, fetched_ref)? {
+            rejected |= line.flag == '!';
+            lines.push(line);
+        }
+    }
+
+    if !lines.is_empty() {
+        let width = lines
+            .iter()
+            .map(|line| line.from.len())
+            .max()
+            .unwrap_or(0)
+            .max(10);
+
+        writeln!(writer, "From {}", display_url).map_err(|err| err.to_string())?;
+
+        for line in lines {
+            writeln!(
+                writer,
+                " {} {:<summary_width$} {:<width$} -> {}{}",
+                line.flag,
+                line.summary,
+                line.from,
+                line.to,
+                line.suffix,
+                summary_width = SUMMARY_WIDTH,
+                width = width
+            )
+            .map_err(|err| err.to_string())?;
+        }
+    }
+
+    if rejected {
+        return Err("error: some local refs could not be updated\n".to_string());
+    }
+
+    Ok(())
+}
+
+/// A remote ref a refspec matched, and where it goes.
+struct FetchedRef {
+    remote: String,
+    local: Option<String>,
+    sha: String,
+    force: bool,
+    /// Whether `FETCH_HEAD` marks it as the one to merge.
+    merge: bool,
+}
+
+/// One line of the ref update summary: ` * [new branch]      main       -> origin/main`.
+struct SummaryLine {
+    flag: char,
+    summary: String,
+    from: String,
+    to: String,
+    suffix: &'static str,
+}
+
+/// The remote the current branch tracks, or `origin`.
+pub fn default_remote(git_dir: &Path, config: &Config) -> Result<String, String> {
+    let remote = current_branch(git_dir)?
+        .and_then(|branch| config.get(&format!("branch.{}.remote", branch)))
+        .unwrap_or(DEFAULT_REMOTE);
+
+    Ok(remote.to_string())
+}
+
+/// The remote ref the current branch merges from, when it tracks `remote`.
+fn merge_ref(git_dir: &Path, config: &Config, remote: &str) -> Result<Option<String>, String> {
+    let branch = match current_branch(git_dir)? {
+        Some(branch) => branch,
+        None => return Ok(None),
+    };
+
+    if config.get(&format!("branch.{}.remote", branch)) != Some(remote) {
+        return Ok(None);
+    }
+
+    Ok(config
+        .get(&format!("branch.{}.merge", branch))
+        .map(|merge| merge.to_string()))
+}
+
+/// Refuses to move the checked-out branch of a repository with a working tree, which would
+/// leave the index and files describing a different commit.
+fn check_current_branch(
+    git_dir: &Path,
+    config: &Config,
+    fetched: &[FetchedRef],
+) -> Result<(), String> {
+    if config.get_bool("core.bare")? == Some(true) {
+        return Ok(());
+    }
+
+    let head = match read_symref(git_dir, "HEAD")? {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+
+    if fetched
+        .iter()
+        .any(|fetched_ref| fetched_ref.local.as_deref() == Some(head.as_str()))
+    {
+        let work_dir = git_dir.parent().unwrap_or(git_dir);
+
+        return Err(format!(
+            "fatal: refusing to fetch into branch '{}' checked out at '{}'\n",
+            head,
+            fs::canonicalize(work_dir)
+                .map_err(|err| err.to_string())?
+                .display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Local refs a refspec stores into whose source the remote no longer has. Symbolic refs, like
+/// `refs/remotes/origin/HEAD`, are left alone.
+fn stale_refs(
+    git_dir: &Path,
+    refspecs: &[Refspec],
+    remote_refs: &[(String, String)],
+) -> Result<Vec<String>, String> {
+    let mut stale = Vec::new();
+
+    for (name, _) in list_refs(git_dir)? {
+        if read_symref(git_dir, &name)?.is_some() {
+            continue;
+        }
+
+        let is_stale = refspecs.iter().any(|refspec| {
+            refspec.map_dst(&name).is_some()
+                && !remote_refs.iter().any(|(remote_name, _)| {
+                    refspec.matches_src(remote_name)
+                        && refspec.map_src(remote_name).as_deref() == Some(name.as_str())
+                })
+        });
+
+        if is_stale {
+            stale.push(name);
+        }
+    }
+
+    Ok(stale)
+}
+
+/// Moves the local ref for `fetched_ref`, if it has one, and describes what happened. Returns
+/// `None` when the ref is already up to date.
+fn update_local_ref(
+    git_dir: &Path,
+    fetched_ref: &FetchedRef,
+) -> Result<Option<SummaryLine>, String> {
+    let from = shorten_ref(&fetched_ref.remote).to_string();
+    let (kind, _) = describe(&fetched_ref.remote);
+    let line = |flag, summary: String, to: &str, suffix| SummaryLine {
+        flag,
+        summary,
+        from: from.clone(),
+        to: to.to_string(),
+        suffix,
+    };
+    let local = match &fetched_ref.local {
+        Some(local) => local,
+        None => {
+            let kind = if kind.is_empty() { "branch" } else { kind };
+
+            return Ok(Some(line('*', kind.to_string(), "FETCH_HEAD", "")));
+        }
+    };
+    let to = shorten_ref(local);
+    let new = &fetched_ref.sha;
+    let old = match resolve_ref(git_dir, local)? {
+        Some(old) if old == *new => return Ok(None),
+        Some(old) => old,
+        None => {
+            let summary = match kind {
+                "branch" => "[new branch]",
+                "tag" => "[new tag]",
+                _ => "[new ref]",
+            };
+
+            update_ref(git_dir, local, new)?;
+
+            return Ok(Some(line('*', summary.to_string(), to, "")));
+        }
+    };
+
+    if local.starts_with("refs/tags/") {
+        if !fetched_ref.force {
+            return Ok(Some(line(
+                '!',
+                "[rejected]".to_string(),
+                to,
+                "  (would clobber existing tag)",
+            )));
+        }
+
+        update_ref(git_dir, local, new)?;
+
+        return Ok(Some(line('t', "[tag update]".to_string(), to, "")));
+    }
+
+    if RevWalk::is_ancestor(git_dir, &old, new)? {
+        update_ref(git_dir, local, new)?;
+
+        return Ok(Some(line(
+            ' ',
+            format!("{}..{}", abbrev(&old), abbrev(new)),
+            to,
+            "",
+        )));
+    }
+
+    if !fetched_ref.force {
+        return Ok(Some(line(
+            '!',
+            "[rejected]".to_string(),
+            to,
+            "  (non-fast-forward)",
+        )));
+    }
+
+    update_ref(git_dir, local, new)?;
+
+    Ok(Some(line(
+        '+',
+        format!("{}...{}", abbrev(&old), abbrev(new)),
+        to,
+        "  (forced update)",
+    )))
+}
+
+/// Writes `FETCH_HEAD`: one line per fetched ref, the ones to merge first, each saying where it
+/// came from the way `git merge FETCH_HEAD` puts it in its message.
+fn write_fetch_head(git_dir: &Path, url: &str, fetched: &[FetchedRef]) -> Result<(), String> {
+    let mut content = String::new();
+
+    for merge in [true, false] {
+        for fetched_ref in fetched
+            .iter()
+            .filter(|fetched_ref| fetched_ref.merge == merge)
+        {
+            let note = match describe(&fetched_ref.remote) {
+                (_, "") => String::new(),
+                ("", what) => format!("'{}' of ", what),
+                (kind, what) => format!("{} '{}' of ", kind, what),
+            };
+
+            content.push_str(&format!(
+                "{}\t{}\t{}{}\n",
+                fetched_ref.sha,
+                if merge { "" } else { "not-for-merge" },
+                note,
+                url
+            ));
+        }
+    }
+
+    write_atomically(&git_dir.join("FETCH_HEAD"), content.as_bytes())
+}
+
+/// The remote's URL as the summary and `FETCH_HEAD` show it, without a trailing `.git`.
+fn display_url(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+
+    url.strip_suffix(".git").unwrap_or(url)
+}
+
+/// What kind of ref `name` is and its name within that kind, as upstream words them:
+/// `("branch", "main")`, `("tag", "v1")`, or no kind and the full name for other refs. The
+/// remote's `HEAD` is neither.
+fn describe(name: &str) -> (&'static str, &str) {
+    if name == "HEAD" {
+        return ("", "");
+    }
+
+    for (prefix, kind) in [
+        ("refs/heads/", "branch"),
+        ("refs/tags/", "tag"),
+        ("refs/remotes/", "remote-tracking branch"),
+    ] {
+        if let Some(short) = name.strip_prefix(prefix) {
+            return (kind, short);
+        }
+    }
+
+    ("", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        add_commit, init_git_dir, write_blob, write_commit, write_object, write_ref, write_tree,
+    };
+    use crate::models::pack::Pack;
+    use tempfile::TempDir;
+
+    /// A remote with one commit on `main`, and a local repository set up to fetch from it.
+    fn remote_and_local() -> (TempDir, TempDir, String) {
+        let remote = init_git_dir();
+        let blob = write_blob(remote.path(), "one\n");
+        let tree = write_tree(remote.path(), &[("100644", "file", &blob)]);
+        let first = write_commit(remote.path(), &tree, &[], 100, "first");
+        write_ref(remote.path(), "refs/heads/main", &first);
+        write_ref(remote.path(), "refs/heads/topic", &first);
+
+        let local = init_git_dir();
+
+        fs::write(
+            local.path().join("config"),
+            format!(
+                "[remote \"origin\"]\n\
+                 \turl = file://{}\n\
+                 \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+                 [branch \"main\"]\n\
+                 \tremote = origin\n\
+                 \tmerge = refs/heads/main\n",
+                remote.path().display()
+            ),
+        )
+        .unwrap();
+        fetch(&[], local.path(), &mut Vec::new()).unwrap();
+
+        (remote, local, first)
+    }
+
+    fn pack_sizes(git_dir: &Path) -> Vec<usize> {
+        let mut sizes: Vec<usize> = fs::read_dir(git_dir.join("objects/pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().unwrap() == "pack")
+            .map(|path| {
+                let pack = Pack::from_bytes(fs::read(path).unwrap()).unwrap();
+
+                pack.index_entries(&|_| Ok(None)).unwrap().len()
+            })
+            .collect();
+
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn fetch_maintains_the_shallow_file() {
+        let remote = init_git_dir();
+        let first = add_commit(remote.path(), &[], "one\n", 100);
+        let second = add_commit(remote.path(), &[&first], "two\n", 200);
+        let third = add_commit(remote.path(), &[&second], "three\n", 300);
+        write_ref(remote.path(), "refs/heads/main", &third);
+
+        let local = init_git_dir();
+        let shallow = || fs::read_to_string(local.path().join("shallow")).unwrap();
+
+        fs::write(
+            local.path().join("config"),
+            format!(
+                "[remote \"origin\"]\n\
+                 \turl = file://{}\n\
+                 \tfetch = +refs/heads/*:refs/remotes/origin/*\n",
+                remote.path().display()
+            ),
+        )
+        .unwrap();
+
+        fetch(&["--depth", "1"], local.path(), &mut Vec::new()).unwrap();
+
+        assert_eq!(shallow(), format!("{}\n", third));
+        assert!(!object_exists(local.path(), &second).unwrap());
+        assert_eq!(
+            RevWalk::new(local.path())
+                .unwrap()
+                .lookup(&third)
+                .unwrap()
+                .parents,
+            Vec::<String>::new()
+        );
+
+        fetch(&["--deepen=1"], local.path(), &mut Vec::new()).unwrap();
+
+        assert_eq!(shallow(), format!("{}\n", second));
+
+        fetch(&["--unshallow"], local.path(), &mut Vec::new()).unwrap();
+
+        assert!(!local.path().join("shallow").exists());
+        assert!(object_exists(local.path(), &first).unwrap());
+        assert_eq!(
+            fetch(&["--unshallow"], local.path(), &mut Vec::new()),
+            Err("fatal: --unshallow on a complete repository does not make sense\n".to_string())
+        );
+    }
+
+    #[test]
+    fn fetch_fast_forwards_tracking_refs_and_prints_a_summary() {
+        let (remote, local, first) = remote_and_local();
+        let second = add_commit(remote.path(), &[&first], "two\n", 200);
+        write_ref(remote.path(), "refs/heads/main", &second);
+        write_ref(remote.path(), "refs/heads/feature", &second);
+
+        // enough local-only commits to take more than one round of haves
+        let mut tip = first.clone();
+
+        for idx in 0..40 {
+            tip = add_commit(
+                local.path(),
+                &[&tip],
+                &format!("local {}\n", idx),
+                300 + idx,
+            );
+        }
+
+        write_ref(local.path(), "refs/heads/main", &tip);
+
+        let mut output = Vec::new();
+
+        fetch(&[], local.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "From file://{}\n \
+                 * [new branch]      feature    -> origin/feature\n   \
+                 {}..{}  main       -> origin/main\n",
+                remote.path().display(),
+                &first[..7],
+                &second[..7]
+            )
+        );
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/main").unwrap(),
+            Some(second.clone())
+        );
+        // the second pack holds only the new commit, tree and blob
+        assert_eq!(pack_sizes(local.path()), vec![3, 3]);
+        assert!(fs::read_to_string(local.path().join("FETCH_HEAD"))
+            .unwrap()
+            .starts_with(&format!("{}\t\tbranch 'main' of file://", second)));
+    }
+
+    #[test]
+    fn fetch_rejects_non_fast_forwards_unless_forced() {
+        let (remote, local, first) = remote_and_local();
+        let rewritten = add_commit(remote.path(), &[], "rewritten\n", 200);
+        write_ref(remote.path(), "refs/heads/main", &rewritten);
+        let mut output = Vec::new();
+
+        let result = fetch(
+            &["origin", "refs/heads/main:refs/remotes/origin/main"],
+            local.path(),
+            &mut output,
+        );
+
+        assert!(result.is_err());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with(" ! [rejected]        main       -> origin/main  (non-fast-forward)\n"));
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/main").unwrap(),
+            Some(first.clone())
+        );
+
+        let mut output = Vec::new();
+
+        fetch(&["origin"], local.path(), &mut output).unwrap();
+
+        assert!(String::from_utf8(output).unwrap().ends_with(&format!(
+            " + {}...{} main       -> origin/main  (forced update)\n",
+            &first[..7],
+            &rewritten[..7]
+        )));
+    }
+
+    #[test]
+    fn fetch_refuses_an_advertisement_with_a_malformed_ref_name() {
+        let (remote, local, first) = remote_and_local();
+        let second = add_commit(remote.path(), &[&first], "two\n", 200);
+        write_ref(remote.path(), "refs/heads/main", &second);
+        write_ref(remote.path(), "refs/heads/bad..name", &second);
+
+        let result = fetch(&["origin"], local.path(), &mut Vec::new());
+
+        assert_eq!(
+            result,
+            Err(
+                "fatal: remote advertised an invalid ref name 'refs/heads/bad..name'\n".to_string()
+            )
+        );
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/main").unwrap(),
+            Some(first)
+        );
+    }
+
+    #[test]
+    fn fetch_prunes_stale_refs_and_fetches_tags() {
+        let (remote, local, first) = remote_and_local();
+        let tag = write_object(
+            remote.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                first
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(remote.path(), "refs/tags/v1", &tag);
+        fs::remove_file(remote.path().join("refs/heads/topic")).unwrap();
+        let mut output = Vec::new();
+
+        fetch(&["--prune", "--tags"], local.path(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(" - [deleted]         (none)     -> origin/topic\n"));
+        assert!(output.contains(" * [new tag]         v1         -> v1\n"));
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/topic").unwrap(),
+            None
+        );
+        assert_eq!(
+            resolve_ref(local.path(), "refs/tags/v1").unwrap(),
+            Some(tag)
+        );
+    }
+}
@@ -0,0 +1,1158 @@
+use std::io::{stderr, Read, Write};
+use std::path::Path;
+
+use crate::git_commands::packs::write_pack_files;
+use crate::git_commands::refs::check_refname_format;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::{is_sha, object_exists, read_raw_object};
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pack::Pack;
+use crate::models::pkt_line::{Packet, PktLineReader, PktLineWriter};
+
+const HAVES_PER_ROUND: usize = 32;
+const MAX_IN_VAIN: usize = 256;
+
+/// Sent to servers so that their logs show which client connected.
+pub const AGENT: &str = concat!("agent=git-starter-rust/", env!("CARGO_PKG_VERSION"));
+/// Stands for a ref that does not exist, in advertisements and ref update commands.
+pub const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// The refs and capabilities an upload-pack server lists when a protocol v0 conversation starts.
+#[derive(Debug, Default, PartialEq)]
+pub struct RefAdvertisement {
+    /// `(name, sha)` pairs in the order they were sent, including peeled `^{}` entries.
+    pub refs: Vec<(String, String)>,
+    pub capabilities: Vec<String>,
+}
+
+impl RefAdvertisement {
+    /// Reads the advertisement up to its flush packet, starting from `first_line`, which the
+    /// caller has already read to tell protocol versions apart. The first ref carries the
+    /// capability list after a NUL; an empty repository sends a placeholder `capabilities^{}`
+    /// ref instead.
+    pub fn read<R: Read>(first_line: &str, reader: &mut PktLineReader<R>) -> Result<Self, String> {
+        let mut advertisement = Self::default();
+
+        advertisement.add_line(first_line)?;
+
+        while let Some(line) = reader.read_line()? {
+            advertisement.add_line(&line)?;
+        }
+
+        Ok(advertisement)
+    }
+
+    /// Builds the same shape from a protocol v2 `ls-refs` response, whose lines look like
+    /// `<sha> <name> [symref-target:<target>] [peeled:<sha>]`. Peeled tags become `^{}` refs
+    /// and symrefs become `symref=` capabilities, as in v0.
+    pub fn from_ls_refs(lines: &[String]) -> Result<Self, String> {
+        let mut advertisement = Self::default();
+
+        for line in lines {
+            let mut fields = line.split(' ');
+            let (sha, name) = match (fields.next(), fields.next()) {
+                (Some(sha), Some(name)) if is_sha(sha) => (sha, name),
+                _ => return Err(format!("protocol error: unexpected ref line '{}'", line)),
+            };
+
+            advertisement.refs.push((name.to_string(), sha.to_string()));
+
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    advertisement
+                        .capabilities
+                        .push(format!("symref={}:{}", name, target));
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    if !is_sha(peeled) {
+                        return Err(format!("protocol error: unexpected ref line '{}'", line));
+                    }
+
+                    advertisement
+                        .refs
+                        .push((format!("{}^{{}}", name), peeled.to_string()));
+                }
+            }
+        }
+
+        Ok(advertisement)
+    }
+
+    fn add_line(&mut self, line: &str) -> Result<(), String> {
+        let (ref_line, capabilities) = match line.split_once('\0') {
+            Some((ref_line, capabilities)) => (ref_line, Some(capabilities)),
+            None => (line, None),
+        };
+
+        if let Some(capabilities) = capabilities {
+            self.capabilities = capabilities
+                .split(' ')
+                .filter(|capability| !capability.is_empty())
+                .map(|capability| capability.to_string())
+                .collect();
+        }
+
+        let (sha, name) = ref_line
+            .split_once(' ')
+            .filter(|(sha, _)| is_sha(sha))
+            .ok_or_else(|| format!("protocol error: unexpected ref line '{}'", ref_line))?;
+
+        if name != "capabilities^{}" {
+            self.refs.push((name.to_string(), sha.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|capability| capability == name || capability.starts_with(&format!("{}=", name)))
+    }
+
+    /// Looks up a `symref=<name>:<target>` capability, which is how servers say what `HEAD`
+    /// points at.
+    pub fn symref_target(&self, name: &str) -> Option<&str> {
+        self.capabilities.iter().find_map(|capability| {
+            capability
+                .strip_prefix("symref=")?
+                .strip_prefix(name)?
+                .strip_prefix(':')
+        })
+    }
+
+    /// Fails on the first ref, or `HEAD`'s symref target, whose name could not be a ref here,
+    /// so that nothing a hostile remote advertises gets written outside `refs/`.
+    pub fn check_ref_names(&self) -> Result<(), String> {
+        self.refs
+            .iter()
+            .map(|(name, _)| name.strip_suffix("^{}").unwrap_or(name))
+            .chain(self.symref_target("HEAD"))
+            .try_for_each(check_refname_format)
+            .map_err(|err| format!("fatal: remote advertised an {}\n", err))
+    }
+
+    /// The refs without their peeled `^{}` entries.
+    pub fn direct_refs(&self) -> impl Iterator<Item = &(String, String)> {
+        self.refs.iter().filter(|(name, _)| !name.ends_with("^{}"))
+    }
+
+    /// The capabilities to ask for: side-band for progress, ofs-delta for smaller packs and the
+    /// shallow ones for cutting history.
+    pub fn wanted_capabilities(&self) -> Vec<&'static str> {
+        let mut capabilities = Vec::new();
+
+        if self.has_capability("side-band-64k") {
+            capabilities.push("side-band-64k");
+        } else if self.has_capability("side-band") {
+            capabilities.push("side-band");
+        }
+
+        if self.has_capability("multi_ack_detailed") {
+            capabilities.push("multi_ack_detailed");
+        }
+
+        if self.has_capability("ofs-delta") {
+            capabilities.push("ofs-delta");
+        }
+
+        for capability in ["shallow", "deepen-since", "deepen-not"] {
+            if self.has_capability(capability) {
+                capabilities.push(capability);
+            }
+        }
+
+        capabilities.push(AGENT);
+        capabilities
+    }
+}
+
+/// How a fetch asks for shallow history: the commits our history already stops at, and how to
+/// cut the new history, at `depth` commits from the wants (or, when `relative`, below the
+/// shallow commits), at commits older than `since` or at commits reachable from `exclude`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShallowRequest {
+    pub shallow: Vec<String>,
+    pub depth: Option<usize>,
+    pub relative: bool,
+    pub since: Option<u64>,
+    pub exclude: Vec<String>,
+}
+
+impl ShallowRequest {
+    /// Whether the fetch changes where the history stops, in which case the server answers with
+    /// the commits that become shallow or complete.
+    pub fn is_deepening(&self) -> bool {
+        self.depth.is_some() || self.since.is_some() || !self.exclude.is_empty()
+    }
+
+    /// The `shallow` and `deepen` lines of a v0 request or v2 `fetch`.
+    fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .shallow
+            .iter()
+            .map(|sha| format!("shallow {}", sha))
+            .collect();
+
+        if let Some(depth) = self.depth {
+            lines.push(format!("deepen {}", depth));
+        }
+
+        if let Some(since) = self.since {
+            lines.push(format!("deepen-since {}", since));
+        }
+
+        lines.extend(
+            self.exclude
+                .iter()
+                .map(|name| format!("deepen-not {}", name)),
+        );
+        lines
+    }
+
+    /// Fails like upstream when the server cannot do what is asked, `supports` telling which of
+    /// `shallow`, `deepen-since`, `deepen-not` and `deepen-relative` it offers.
+    fn check_support(&self, supports: impl Fn(&str) -> bool) -> Result<(), String> {
+        if (!self.shallow.is_empty() || self.is_deepening()) && !supports("shallow") {
+            return Err("fatal: Server does not support shallow clients\n".to_string());
+        }
+
+        if self.since.is_some() && !supports("deepen-since") {
+            return Err("fatal: Server does not support --shallow-since\n".to_string());
+        }
+
+        if !self.exclude.is_empty() && !supports("deepen-not") {
+            return Err("fatal: Server does not support --shallow-exclude\n".to_string());
+        }
+
+        if self.relative && !supports("deepen-relative") {
+            return Err("fatal: Server does not support --deepen\n".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// The server's answer to a deepening fetch: the commits that became shallow and the ones whose
+/// history is now complete.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShallowInfo {
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
+}
+
+impl ShallowInfo {
+    fn add_line(&mut self, line: &str) -> Result<(), String> {
+        match line.split_once(' ') {
+            Some(("shallow", sha)) => self.shallow.push(sha.to_string()),
+            Some(("unshallow", sha)) => self.unshallow.push(sha.to_string()),
+            _ => {
+                return Err(format!(
+                    "protocol error: expected shallow or unshallow, got '{}'",
+                    line
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the v0 shallow lines a server sends ahead of its acknowledgments, up to a flush.
+    fn read<R: Read>(reader: &mut PktLineReader<R>) -> Result<Self, String> {
+        let mut info = Self::default();
+
+        for line in reader.read_lines()? {
+            info.add_line(&line)?;
+        }
+
+        Ok(info)
+    }
+}
+
+/// Writes the wants of a protocol v0 upload-pack request, the capabilities going on the first,
+/// then the `shallow` and `deepen` lines of `shallow`, the `filter` line and the flush that ends
+/// them.
+pub fn write_wants<W: Write>(
+    writer: &mut PktLineWriter<W>,
+    wants: &[String],
+    capabilities: &[&str],
+    shallow: &ShallowRequest,
+    filter: Option<&ObjectFilter>,
+) -> Result<(), String> {
+    for (idx, want) in wants.iter().enumerate() {
+        if idx == 0 {
+            writer.write_line(&format!("want {} {}", want, capabilities.join(" ")))?;
+        } else {
+            writer.write_line(&format!("want {}", want))?;
+        }
+    }
+
+    for line in shallow.lines() {
+        writer.write_line(&line)?;
+    }
+
+    if let Some(filter) = filter {
+        writer.write_line(&format!("filter {}", filter))?;
+    }
+
+    writer.write_flush()
+}
+
+fn write_haves<W: Write>(writer: &mut PktLineWriter<W>, haves: &[String]) -> Result<(), String> {
+    for have in haves {
+        writer.write_line(&format!("have {}", have))?;
+    }
+
+    Ok(())
+}
+
+/// Reads the server's answer to a round of haves up to its `NAK`: `ACK <sha> common` for each
+/// have it shares and `ACK <sha> ready` once it has enough. Returns whether it is ready.
+fn read_acknowledgments<R: Read>(
+    reader: &mut PktLineReader<R>,
+    negotiator: &mut Negotiator,
+    common: &mut Vec<String>,
+) -> Result<bool, String> {
+    let mut ready = false;
+
+    loop {
+        let line = reader
+            .read_line()?
+            .ok_or("protocol error: expected ACK or NAK, got a flush")?;
+
+        if line == "NAK" {
+            return Ok(ready);
+        }
+
+        let (sha, status) = line
+            .strip_prefix("ACK ")
+            .and_then(|ack| ack.split_once(' '))
+            .ok_or_else(|| format!("protocol error: expected ACK or NAK, got '{}'", line))?;
+
+        match status {
+            "common" | "continue" => {}
+            "ready" => ready = true,
+            _ => {
+                return Err(format!(
+                    "protocol error: unexpected acknowledgment '{}'",
+                    line
+                ))
+            }
+        }
+
+        if !common.iter().any(|known| known == sha) {
+            negotiator.acknowledge(sha)?;
+            common.push(sha.to_string());
+        }
+    }
+}
+
+/// Reads the server's answer to a request ending in `done`: any acknowledgments of the haves
+/// repeated in it, then a final `NAK` or `ACK <sha>`, then the pack, either multiplexed with
+/// progress on the side-band or raw. Progress goes to stderr like upstream's.
+pub fn read_pack_response<R: Read>(
+    mut reader: PktLineReader<R>,
+    sideband: bool,
+) -> Result<Vec<u8>, String> {
+    loop {
+        match reader.read_line()? {
+            Some(line) if line == "NAK" => break,
+            Some(line) if line.starts_with("ACK ") => {
+                // `ACK <sha> common` and the like come before the final `ACK <sha>`
+                if line.split(' ').count() == 2 {
+                    break;
+                }
+            }
+            Some(line) => return Err(format!("protocol error: expected NAK, got '{}'", line)),
+            None => return Err("protocol error: expected NAK, got a flush".to_string()),
+        }
+    }
+
+    let mut pack = Vec::new();
+
+    if sideband {
+        reader.read_sideband(&mut pack, &mut stderr())?;
+    } else {
+        reader
+            .into_inner()
+            .read_to_end(&mut pack)
+            .map_err(|err| format!("error reading pack: {}", err))?;
+    }
+
+    Ok(pack)
+}
+
+/// Picks the haves to offer while negotiating: local commits newest first. Once the server
+/// acknowledges a commit, its ancestors are known to be common and are not offered. Like
+/// upstream, negotiation gives up after 256 haves in a row that the server does not share.
+pub struct Negotiator {
+    walk: RevWalk,
+    in_vain: Option<usize>,
+}
+
+impl Negotiator {
+    /// Starts from `tips`, typically every local ref; tips that are not commits are skipped.
+    pub fn new(git_dir: &Path, tips: &[String]) -> Result<Self, String> {
+        let mut walk = RevWalk::new(git_dir)?;
+
+        for tip in tips {
+            if !object_exists(git_dir, tip)? {
+                continue;
+            }
+
+            let commit = RevWalk::peel(git_dir, tip)?;
+
+            if read_raw_object(git_dir, &commit)?.0 == "commit" {
+                walk.push(&commit)?;
+            }
+        }
+
+        Ok(Self {
+            walk,
+            in_vain: None,
+        })
+    }
+
+    fn next_batch(&mut self) -> Result<Vec<String>, String> {
+        if self.in_vain.is_some_and(|in_vain| in_vain >= MAX_IN_VAIN) {
+            return Ok(Vec::new());
+        }
+
+        let haves = self
+            .walk
+            .by_ref()
+            .take(HAVES_PER_ROUND)
+            .map(|commit| commit.map(|commit| commit.sha))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if let Some(in_vain) = &mut self.in_vain {
+            *in_vain += haves.len();
+        }
+
+        Ok(haves)
+    }
+
+    fn acknowledge(&mut self, sha: &str) -> Result<(), String> {
+        self.in_vain = Some(0);
+
+        if self.walk.is_hidden(sha) {
+            return Ok(());
+        }
+
+        self.walk.hide(sha)
+    }
+}
+
+/// What a protocol v2 server offers, one line per command or feature: `ls-refs=unborn`,
+/// `fetch=shallow wait-for-done`, `agent=git/2.39.5` and so on.
+#[derive(Debug, Default, PartialEq)]
+pub struct ServerCapabilities {
+    pub lines: Vec<String>,
+}
+
+impl ServerCapabilities {
+    /// Reads the capability advertisement that follows the `version 2` line.
+    pub fn read<R: Read>(reader: &mut PktLineReader<R>) -> Result<Self, String> {
+        Ok(Self {
+            lines: reader.read_lines()?,
+        })
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.value(name).is_some()
+    }
+
+    /// Whether `command` (e.g. `fetch`) lists `feature` (e.g. `wait-for-done`).
+    pub fn has_feature(&self, command: &str, feature: &str) -> bool {
+        self.value(command)
+            .is_some_and(|value| value.split(' ').any(|item| item == feature))
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| {
+            if line == name {
+                Some("")
+            } else {
+                line.strip_prefix(name)?.strip_prefix('=')
+            }
+        })
+    }
+
+    /// The capability lines every v2 command request starts with.
+    fn request_capabilities(&self) -> Vec<&str> {
+        let mut capabilities = vec![AGENT];
+
+        if self.has("object-format") {
+            capabilities.push("object-format=sha1");
+        }
+
+        capabilities
+    }
+}
+
+/// Writes a protocol v2 command: `command=<name>`, the capabilities, a delim packet, the
+/// arguments and a flush.
+fn write_command<W: Write>(
+    writer: &mut PktLineWriter<W>,
+    command: &str,
+    server: &ServerCapabilities,
+    arguments: &[String],
+) -> Result<(), String> {
+    writer.write_line(&format!("command={}", command))?;
+
+    for capability in server.request_capabilities() {
+        writer.write_line(capability)?;
+    }
+
+    writer.write_delim()?;
+
+    for argument in arguments {
+        writer.write_line(argument)?;
+    }
+
+    writer.write_flush()
+}
+
+/// The arguments of an `ls-refs` command that lists symrefs and peeled tags, limited to refs
+/// starting with one of `prefixes` so that big servers need not send every ref.
+fn ls_refs_arguments(prefixes: &[&str]) -> Vec<String> {
+    let mut arguments = vec!["peel".to_string(), "symrefs".to_string()];
+
+    arguments.extend(
+        prefixes
+            .iter()
+            .map(|prefix| format!("ref-prefix {}", prefix)),
+    );
+    arguments
+}
+
+/// The arguments of a v2 `fetch`. Without `done` the server only acknowledges common commits;
+/// `wait-for-done` (when supported) keeps it from sending a pack before we say `done`.
+fn fetch_arguments(
+    server: &ServerCapabilities,
+    wants: &[String],
+    shallow: &ShallowRequest,
+    filter: Option<&ObjectFilter>,
+    haves: &[String],
+    done: bool,
+) -> Vec<String> {
+    let mut arguments = vec!["ofs-delta".to_string()];
+
+    arguments.extend(wants.iter().map(|want| format!("want {}", want)));
+    arguments.extend(shallow.lines());
+
+    if shallow.relative {
+        arguments.push("deepen-relative".to_string());
+    }
+
+    if let Some(filter) = filter {
+        arguments.push(format!("filter {}", filter));
+    }
+
+    arguments.extend(haves.iter().map(|have| format!("have {}", have)));
+
+    if done {
+        arguments.push("done".to_string());
+    } else if server.has_feature("fetch", "wait-for-done") {
+        arguments.push("wait-for-done".to_string());
+    }
+
+    arguments
+}
+
+/// What upload-pack sends when a conversation starts.
+pub enum Greeting {
+    V0(RefAdvertisement),
+    V2(ServerCapabilities),
+}
+
+/// The protocol a conversation uses, and what later requests need from the greeting.
+pub enum Protocol {
+    V0 {
+        capabilities: Vec<&'static str>,
+        advertised: Vec<String>,
+    },
+    V2(ServerCapabilities),
+}
+
+impl Protocol {
+    fn supports_filter(&self) -> bool {
+        match self {
+            Protocol::V0 { advertised, .. } => advertised.iter().any(|name| name == "filter"),
+            Protocol::V2(server) => server.has_feature("fetch", "filter"),
+        }
+    }
+}
+
+/// Sends one request to upload-pack and hands back its response: a `POST` over HTTP, a write
+/// to and a read from the same pipes over SSH.
+pub trait Channel {
+    /// Whether the server forgets the conversation between requests, as over HTTP.
+    fn stateless(&self) -> bool {
+        true
+    }
+
+    fn request(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String>;
+}
+
+/// Reads the greeting, of which the caller has already read `first_line` (`None` for a flush):
+/// `version 2` and the capability advertisement, or a v0 ref advertisement.
+pub fn read_greeting<R: Read>(
+    first_line: Option<String>,
+    reader: &mut PktLineReader<R>,
+) -> Result<Greeting, String> {
+    match first_line {
+        Some(line) if line == "version 2" => {
+            let server = ServerCapabilities::read(reader)?;
+
+            if !server.has("ls-refs") || !server.has("fetch") {
+                return Err("protocol error: server does not support ls-refs and fetch".to_string());
+            }
+
+            Ok(Greeting::V2(server))
+        }
+        Some(line) if line.starts_with("version ") => {
+            Err(format!("protocol error: unknown protocol '{}'", line))
+        }
+        Some(line) => Ok(Greeting::V0(RefAdvertisement::read(&line, reader)?)),
+        None => Ok(Greeting::V0(RefAdvertisement::default())),
+    }
+}
+
+/// Lists the refs under `prefixes` after `greeting`: a v2 server is sent `ls-refs`, while a v0
+/// server has already listed all of its refs.
+pub fn list_refs<C: Channel>(
+    greeting: Greeting,
+    channel: &mut C,
+    prefixes: &[&str],
+) -> Result<(Protocol, RefAdvertisement), String> {
+    match greeting {
+        Greeting::V0(advertisement) => Ok((
+            Protocol::V0 {
+                capabilities: advertisement.wanted_capabilities(),
+                advertised: advertisement.capabilities.clone(),
+            },
+            advertisement,
+        )),
+        Greeting::V2(server) => {
+            let mut request = PktLineWriter::new(Vec::new());
+
+            write_command(
+                &mut request,
+                "ls-refs",
+                &server,
+                &ls_refs_arguments(prefixes),
+            )?;
+
+            let lines = PktLineReader::new(channel.request(request.into_inner())?).read_lines()?;
+
+            Ok((
+                Protocol::V2(server),
+                RefAdvertisement::from_ls_refs(&lines)?,
+            ))
+        }
+    }
+}
+
+/// Fetches a pack holding `wants` and everything they reach that the server and we do not
+/// both have, cutting the history as `shallow` asks and leaving out what `filter` does, when
+/// the server can filter. Haves go out in rounds of 32 until the
+/// server is ready or `negotiator` runs out; a v0 server without `multi_ack_detailed` gets all
+/// of them in the final request.
+pub fn fetch<C: Channel>(
+    protocol: &Protocol,
+    channel: &mut C,
+    wants: &[String],
+    shallow: &ShallowRequest,
+    filter: Option<&ObjectFilter>,
+    negotiator: &mut Negotiator,
+) -> Result<(Vec<u8>, ShallowInfo), String> {
+    let mut common = Vec::new();
+    let filter = match filter {
+        Some(_) if !protocol.supports_filter() => {
+            let _ = writeln!(
+                stderr(),
+                "warning: filtering not recognized by server, ignoring"
+            );
+            None
+        }
+        filter => filter,
+    };
+
+    match protocol {
+        Protocol::V0 {
+            capabilities,
+            advertised,
+        } => {
+            shallow.check_support(|name| advertised.iter().any(|capability| capability == name))?;
+
+            let mut capabilities = capabilities.clone();
+
+            if shallow.relative {
+                capabilities.push("deepen-relative");
+            }
+
+            if filter.is_some() {
+                capabilities.push("filter");
+            }
+
+            let sideband = capabilities
+                .iter()
+                .any(|capability| capability.starts_with("side-band"));
+            let stateless = channel.stateless();
+            let mut sent_wants = false;
+            let mut request = PktLineWriter::new(Vec::new());
+            let mut info = ShallowInfo::default();
+
+            let done_with_wants = if capabilities.contains(&"multi_ack_detailed") {
+                loop {
+                    let haves = negotiator.next_batch()?;
+
+                    if haves.is_empty() {
+                        break;
+                    }
+
+                    // a stateless server forgets everything between requests, so each one
+                    // repeats the wants and what is known to be common
+                    let with_wants = stateless || !sent_wants;
+
+                    if with_wants {
+                        write_wants(&mut request, wants, &capabilities, shallow, filter)?;
+                        sent_wants = true;
+                    }
+
+                    if stateless {
+                        write_haves(&mut request, &common)?;
+                    }
+
+                    write_haves(&mut request, &haves)?;
+                    request.write_flush()?;
+
+                    let round = std::mem::replace(&mut request, PktLineWriter::new(Vec::new()));
+                    let mut reader = PktLineReader::new(channel.request(round.into_inner())?);
+
+                    // a deepening request is answered with the shallow lines first
+                    if with_wants && shallow.is_deepening() {
+                        info = ShallowInfo::read(&mut reader)?;
+                    }
+
+                    if read_acknowledgments(&mut reader, negotiator, &mut common)? {
+                        break;
+                    }
+                }
+
+                let with_wants = stateless || !sent_wants;
+
+                if with_wants {
+                    write_wants(&mut request, wants, &capabilities, shallow, filter)?;
+                }
+
+                if stateless {
+                    write_haves(&mut request, &common)?;
+                }
+
+                with_wants
+            } else {
+                write_wants(&mut request, wants, &capabilities, shallow, filter)?;
+
+                loop {
+                    let haves = negotiator.next_batch()?;
+
+                    if haves.is_empty() {
+                        break;
+                    }
+
+                    write_haves(&mut request, &haves)?;
+                }
+
+                true
+            };
+
+            request.write_line("done")?;
+
+            let mut reader = PktLineReader::new(channel.request(request.into_inner())?);
+
+            if done_with_wants && shallow.is_deepening() {
+                info = ShallowInfo::read(&mut reader)?;
+            }
+
+            Ok((read_pack_response(reader, sideband)?, info))
+        }
+        Protocol::V2(server) => {
+            // v2 servers offering shallow fetches take every kind of deepen
+            shallow.check_support(|_| server.has_feature("fetch", "shallow"))?;
+
+            loop {
+                let haves = negotiator.next_batch()?;
+
+                if haves.is_empty() {
+                    break;
+                }
+
+                let haves = [common.clone(), haves].concat();
+                let response = fetch_v2(
+                    server,
+                    channel,
+                    &fetch_arguments(server, wants, shallow, filter, &haves, false),
+                )?;
+
+                if let Some(pack) = response.pack {
+                    return Ok((pack, response.shallow));
+                }
+
+                for sha in response.acknowledgments {
+                    if !common.contains(&sha) {
+                        negotiator.acknowledge(&sha)?;
+                        common.push(sha);
+                    }
+                }
+
+                if response.ready {
+                    break;
+                }
+            }
+
+            let response = fetch_v2(
+                server,
+                channel,
+                &fetch_arguments(server, wants, shallow, filter, &common, true),
+            )?;
+            let pack = response
+                .pack
+                .ok_or_else(|| "protocol error: expected a packfile section".to_string())?;
+
+            Ok((pack, response.shallow))
+        }
+    }
+}
+
+fn fetch_v2<C: Channel>(
+    server: &ServerCapabilities,
+    channel: &mut C,
+    arguments: &[String],
+) -> Result<FetchResponse, String> {
+    let mut request = PktLineWriter::new(Vec::new());
+
+    write_command(&mut request, "fetch", server, arguments)?;
+
+    read_fetch_response(&mut PktLineReader::new(
+        channel.request(request.into_inner())?,
+    ))
+}
+
+/// A v2 `fetch` response. `pack` is only set when the server sent a `packfile` section.
+#[derive(Debug, Default, PartialEq)]
+pub struct FetchResponse {
+    pub acknowledgments: Vec<String>,
+    pub ready: bool,
+    pub shallow: ShallowInfo,
+    pub pack: Option<Vec<u8>>,
+}
+
+/// Reads the sections of a v2 `fetch` response. Sections are separated by delim packets and
+/// the response ends with a flush; the `packfile` section is always last and multiplexed on
+/// the side-band.
+fn read_fetch_response<R: Read>(reader: &mut PktLineReader<R>) -> Result<FetchResponse, String> {
+    let mut response = FetchResponse::default();
+
+    loop {
+        let section = match reader.read_line()? {
+            Some(section) => section,
+            None => return Ok(response),
+        };
+
+        if section == "packfile" {
+            let mut pack = Vec::new();
+
+            reader.read_sideband(&mut pack, &mut stderr())?;
+            response.pack = Some(pack);
+
+            return Ok(response);
+        }
+
+        let (lines, end) = read_section(reader)?;
+
+        match section.as_str() {
+            "acknowledgments" => {
+                for line in lines {
+                    match line.as_str() {
+                        "ready" => response.ready = true,
+                        "NAK" => {}
+                        _ => response.acknowledgments.push(
+                            line.strip_prefix("ACK ")
+                                .ok_or_else(|| {
+                                    format!("protocol error: unexpected acknowledgment '{}'", line)
+                                })?
+                                .to_string(),
+                        ),
+                    }
+                }
+            }
+            "shallow-info" => {
+                for line in lines {
+                    response.shallow.add_line(&line)?;
+                }
+            }
+            // not asked for yet, so there is nothing to act on
+            "wanted-refs" | "packfile-uris" => {}
+            _ => return Err(format!("protocol error: unknown section '{}'", section)),
+        }
+
+        if end != Packet::Delim {
+            return Ok(response);
+        }
+    }
+}
+
+/// Reads the lines of one response section and the packet that ended it.
+pub fn read_section<R: Read>(
+    reader: &mut PktLineReader<R>,
+) -> Result<(Vec<String>, Packet), String> {
+    let mut lines = Vec::new();
+
+    loop {
+        match reader.read_packet()? {
+            Packet::Data(data) => {
+                let line = String::from_utf8_lossy(&data).to_string();
+
+                lines.push(line.strip_suffix('\n').unwrap_or(&line).to_string());
+            }
+            end => return Ok((lines, end)),
+        }
+    }
+}
+
+/// Verifies a received pack and stores it with its index, returning the pack name. A thin pack,
+/// with ref deltas against objects outside it (as bundles carry), is completed with those
+/// bases from the repository. An empty pack, as sent when every object is already there, is
+/// not kept.
+pub fn store_pack(git_dir: &Path, data: Vec<u8>) -> Result<Option<String>, String> {
+    let pack = Pack::from_bytes(data)?;
+
+    pack.verify_checksum()?;
+
+    if pack.num_objects() == 0 {
+        return Ok(None);
+    }
+
+    let (pack, entries) = pack.fix_thin(&|sha| match read_raw_object(git_dir, sha) {
+        Ok(object) => Ok(Some(object)),
+        Err(_) => Ok(None),
+    })?;
+
+    write_pack_files(git_dir, pack.as_bytes(), entries).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::pkt_line::SIDEBAND_DATA;
+
+    const SHA1: &str = "1111111111111111111111111111111111111111";
+    const SHA2: &str = "2222222222222222222222222222222222222222";
+
+    fn read_advertisement(bytes: Vec<u8>) -> RefAdvertisement {
+        let mut reader = PktLineReader::new(bytes.as_slice());
+        let first_line = reader.read_line().unwrap().unwrap();
+
+        RefAdvertisement::read(&first_line, &mut reader).unwrap()
+    }
+
+    #[test]
+    fn read_parses_refs_and_capabilities() {
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        writer
+            .write_line(&format!(
+                "{} HEAD\0multi_ack side-band-64k symref=HEAD:refs/heads/main",
+                SHA1
+            ))
+            .unwrap();
+        writer
+            .write_line(&format!("{} refs/heads/main", SHA1))
+            .unwrap();
+        writer
+            .write_line(&format!("{} refs/tags/v1", SHA2))
+            .unwrap();
+        writer
+            .write_line(&format!("{} refs/tags/v1^{{}}", SHA1))
+            .unwrap();
+        writer.write_flush().unwrap();
+
+        let advertisement = read_advertisement(writer.into_inner());
+
+        assert_eq!(advertisement.refs.len(), 4);
+        assert_eq!(advertisement.direct_refs().count(), 3);
+        assert_eq!(advertisement.symref_target("HEAD"), Some("refs/heads/main"));
+        assert_eq!(
+            advertisement.wanted_capabilities(),
+            vec!["side-band-64k", AGENT]
+        );
+    }
+
+    #[test]
+    fn read_skips_the_empty_repository_placeholder() {
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        writer
+            .write_line(&format!("{} capabilities^{{}}\0ofs-delta", "0".repeat(40)))
+            .unwrap();
+        writer.write_flush().unwrap();
+
+        let advertisement = read_advertisement(writer.into_inner());
+
+        assert!(advertisement.refs.is_empty());
+        assert!(advertisement.has_capability("ofs-delta"));
+    }
+
+    #[test]
+    fn read_refuses_shas_that_are_not_hex() {
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        // 40 bytes, but not 40 hex digits
+        writer
+            .write_line(&format!("aé{} refs/heads/main\0ofs-delta", "0".repeat(37)))
+            .unwrap();
+        writer.write_flush().unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+        let first_line = reader.read_line().unwrap().unwrap();
+
+        assert!(RefAdvertisement::read(&first_line, &mut reader)
+            .unwrap_err()
+            .starts_with("protocol error: unexpected ref line"));
+    }
+
+    #[test]
+    fn from_ls_refs_matches_the_v0_advertisement() {
+        let advertisement = RefAdvertisement::from_ls_refs(&[
+            format!("{} HEAD symref-target:refs/heads/main", SHA1),
+            format!("{} refs/heads/main", SHA1),
+            format!("{} refs/tags/v1 peeled:{}", SHA2, SHA1),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            advertisement.refs,
+            vec![
+                ("HEAD".to_string(), SHA1.to_string()),
+                ("refs/heads/main".to_string(), SHA1.to_string()),
+                ("refs/tags/v1".to_string(), SHA2.to_string()),
+                ("refs/tags/v1^{}".to_string(), SHA1.to_string()),
+            ]
+        );
+        assert_eq!(advertisement.symref_target("HEAD"), Some("refs/heads/main"));
+        assert!(RefAdvertisement::from_ls_refs(&["unborn HEAD".to_string()]).is_err());
+        assert!(RefAdvertisement::from_ls_refs(&[format!(
+            "{} refs/tags/v1 peeled:{}",
+            SHA2,
+            &SHA1[..39]
+        )])
+        .is_err());
+    }
+
+    #[test]
+    fn v2_requests_follow_the_server_capabilities() {
+        let server = ServerCapabilities {
+            lines: vec![
+                "agent=git/2.39.5".to_string(),
+                "ls-refs=unborn".to_string(),
+                "fetch=shallow wait-for-done".to_string(),
+                "server-option".to_string(),
+                "object-format=sha1".to_string(),
+            ],
+        };
+
+        assert!(server.has("server-option"));
+        assert!(server.has_feature("fetch", "wait-for-done"));
+        assert!(!server.has_feature("ls-refs", "wait-for-done"));
+        assert!(!server.has("ls"));
+
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        write_command(
+            &mut writer,
+            "ls-refs",
+            &server,
+            &ls_refs_arguments(&["refs/tags/"]),
+        )
+        .unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+
+        assert_eq!(
+            reader.read_lines().unwrap(),
+            vec!["command=ls-refs", AGENT, "object-format=sha1"]
+        );
+        assert_eq!(
+            reader.read_lines().unwrap(),
+            vec!["peel", "symrefs", "ref-prefix refs/tags/"]
+        );
+        assert_eq!(
+            fetch_arguments(
+                &server,
+                &[SHA1.to_string()],
+                &ShallowRequest {
+                    shallow: vec![SHA2.to_string()],
+                    depth: Some(3),
+                    relative: true,
+                    ..ShallowRequest::default()
+                },
+                Some(&ObjectFilter::BlobNone),
+                &[SHA2.to_string()],
+                false
+            ),
+            vec![
+                "ofs-delta".to_string(),
+                format!("want {}", SHA1),
+                format!("shallow {}", SHA2),
+                "deepen 3".to_string(),
+                "deepen-relative".to_string(),
+                "filter blob:none".to_string(),
+                format!("have {}", SHA2),
+                "wait-for-done".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_fetch_response_parses_sections() {
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        writer.write_line("acknowledgments").unwrap();
+        writer.write_line(&format!("ACK {}", SHA2)).unwrap();
+        writer.write_line("ready").unwrap();
+        writer.write_delim().unwrap();
+        writer.write_line("shallow-info").unwrap();
+        writer.write_line(&format!("shallow {}", SHA1)).unwrap();
+        writer.write_line(&format!("unshallow {}", SHA2)).unwrap();
+        writer.write_delim().unwrap();
+        writer.write_line("packfile").unwrap();
+        writer.write_sideband(SIDEBAND_DATA, b"PACK").unwrap();
+        writer.write_flush().unwrap();
+        writer.write_line("acknowledgments").unwrap();
+        writer.write_line("NAK").unwrap();
+        writer.write_flush().unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+
+        assert_eq!(
+            read_fetch_response(&mut reader).unwrap(),
+            FetchResponse {
+                acknowledgments: vec![SHA2.to_string()],
+                ready: true,
+                shallow: ShallowInfo {
+                    shallow: vec![SHA1.to_string()],
+                    unshallow: vec![SHA2.to_string()],
+                },
+                pack: Some(b"PACK".to_vec()),
+            }
+        );
+        assert_eq!(
+            read_fetch_response(&mut reader).unwrap(),
+            FetchResponse::default()
+        );
+    }
+}
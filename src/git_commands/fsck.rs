@@ -0,0 +1,367 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::object_walk::load_index;
+use crate::git_commands::packs::load_packs;
+use crate::git_commands::promisor::is_promisor_pack;
+use crate::git_commands::refs::{list_refs, reflog_shas, resolve_ref};
+use crate::git_commands::shallow::read_shallow;
+use crate::git_commands::utils::{loose_objects, read_raw_object};
+use crate::models::object::Object;
+use crate::models::pack::object_sha;
+use crate::models::tree::TreeEntryMode;
+
+const USAGE: &str = "usage: git fsck [--unreachable] [--no-dangling]";
+
+/// What fsck learned about one object: its type and the `(type, sha)` pairs it points at.
+struct CheckedObject {
+    object_type: String,
+    links: Vec<(String, String)>,
+}
+
+/// Verifies every loose and packed object, then walks from the refs, `HEAD`, the reflogs and
+/// the index and reports broken links, missing objects and objects nothing reaches. Problems
+/// are written to `writer`; the command fails if any were found. Dangling objects (unreachable
+/// and not pointed at by any other object) are reported but are not problems. The parents of
+/// the commits in `.git/shallow` are expected to be missing and are not followed, and so are
+/// the objects a partial clone was promised: those that objects in `.promisor` packs point at.
+pub fn fsck<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut show_unreachable = false;
+    let mut show_dangling = true;
+
+    for arg in args {
+        match *arg {
+            "--unreachable" => show_unreachable = true,
+            "--no-dangling" => show_dangling = false,
+            "--dangling" => show_dangling = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let mut errors = Vec::new();
+    let mut objects: HashMap<String, CheckedObject> = HashMap::new();
+    let mut promised: HashSet<String> = HashSet::new();
+
+    for (sha, path) in loose_objects(git_dir)? {
+        let (object_type, content) = match read_raw_object(git_dir, &sha) {
+            Ok(object) => object,
+            Err(_) => {
+                errors.push(format!(
+                    "error: {}: object corrupt or missing: {}",
+                    sha,
+                    path.display()
+                ));
+                continue;
+            }
+        };
+
+        let actual_sha = object_sha(&object_type, &content);
+
+        if actual_sha != sha {
+            errors.push(format!(
+                "error: {}: hash-path mismatch, found at: {}",
+                actual_sha,
+                path.display()
+            ));
+            continue;
+        }
+
+        objects.insert(
+            sha.clone(),
+            check_object(&sha, object_type, content, &mut errors),
+        );
+    }
+
+    for pack_file in load_packs(git_dir)? {
+        if let Err(err) = pack_file.pack.verify_checksum() {
+            errors.push(format!("error: {}.pack: {}", pack_file.name, err));
+        }
+
+        let promisor = is_promisor_pack(git_dir, &pack_file.name);
+
+        for entry in &pack_file.index.entries {
+            if objects.contains_key(&entry.sha) {
+                continue;
+            }
+
+            let object = pack_file
+                .pack
+                .read_object_at(entry.offset, &|base| read_raw_object(git_dir, base));
+            let (object_type, content) = match object {
+                Ok((object_type, content)) if object_sha(&object_type, &content) == entry.sha => {
+                    (object_type, content)
+                }
+                _ => {
+                    errors.push(format!(
+                        "error: {}: object corrupt or missing in {}.pack",
+                        entry.sha, pack_file.name
+                    ));
+                    continue;
+                }
+            };
+
+            let object = check_object(&entry.sha, object_type, content, &mut errors);
+
+            if promisor {
+                promised.extend(object.links.iter().map(|(_, link)| link.clone()));
+            }
+
+            objects.insert(entry.sha.clone(), object);
+        }
+    }
+
+    let roots = collect_roots(git_dir, &objects, &mut errors)?;
+    let shallow = read_shallow(git_dir)?;
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut missing: BTreeSet<(String, String)> = BTreeSet::new();
+    let mut stack = roots;
+
+    while let Some(sha) = stack.pop() {
+        if !reachable.insert(sha.clone()) {
+            continue;
+        }
+
+        // only index entries are pushed without checking that they exist, and those are blobs
+        let object = match objects.get(&sha) {
+            Some(object) => object,
+            None => {
+                missing.insert((sha, "blob".to_string()));
+                continue;
+            }
+        };
+
+        for (link_type, link) in &object.links {
+            if object.object_type == "commit" && link_type == "commit" && shallow.contains(&sha) {
+                continue;
+            }
+
+            match objects.get(link) {
+                Some(target) => {
+                    if target.object_type != *link_type {
+                        errors.push(format!(
+                            "error: object {} is a {}, not a {}",
+                            link, target.object_type, link_type
+                        ));
+                    }
+
+                    stack.push(link.clone());
+                }
+                None if promised.contains(link) => {}
+                None => {
+                    errors.push(format!(
+                        "broken link from {:>7} {}\n              to {:>7} {}",
+                        object.object_type, sha, link_type, link
+                    ));
+                    missing.insert((link.clone(), link_type.clone()));
+                }
+            }
+        }
+    }
+
+    for (sha, object_type) in &missing {
+        errors.push(format!("missing {} {}", object_type, sha));
+    }
+
+    let referenced: HashSet<&str> = objects
+        .values()
+        .flat_map(|object| object.links.iter().map(|(_, link)| link.as_str()))
+        .collect();
+    let mut unreachable: Vec<(&String, &CheckedObject)> = objects
+        .iter()
+        .filter(|(sha, _)| !reachable.contains(*sha))
+        .collect();
+
+    unreachable.sort_by_key(|(sha, _)| *sha);
+
+    let mut lines = errors.clone();
+
+    for (sha, object) in unreachable {
+        if show_unreachable {
+            lines.push(format!("unreachable {} {}", object.object_type, sha));
+        } else if show_dangling && !referenced.contains(sha.as_str()) {
+            lines.push(format!("dangling {} {}", object.object_type, sha));
+        }
+    }
+
+    for line in &lines {
+        writeln!(writer, "{}", line).map_err(|err| err.to_string())?;
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("fsck found {} problem(s)\n", errors.len()))
+    }
+}
+
+/// Parses an object strictly and records what it links to. Problems are reported as
+/// `error in <type> <sha>: <msg-id>: <message>` like upstream; an object that does not parse
+/// links to nothing.
+fn check_object(
+    sha: &str,
+    object_type: String,
+    content: Vec<u8>,
+    errors: &mut Vec<String>,
+) -> CheckedObject {
+    let mut report =
+        |err: String| errors.push(format!("error in {} {}: {}", object_type, sha, err));
+    let links = match Object::new(&object_type, content) {
+        Ok(Object::Commit(commit)) => {
+            if let Err(err) = commit.check() {
+                report(err);
+            }
+
+            [
+                vec![("tree".to_string(), commit.tree)],
+                commit
+                    .parents
+                    .into_iter()
+                    .map(|parent| ("commit".to_string(), parent))
+                    .collect(),
+            ]
+            .concat()
+        }
+        Ok(Object::Tree(tree)) => {
+            if let Err(err) = tree.check() {
+                report(err);
+            }
+
+            tree.tree_entries
+                .iter()
+                .filter(|entry| entry.mode != TreeEntryMode::Submodule)
+                .map(|entry| (entry.object_type(), entry.sha.clone()))
+                .collect()
+        }
+        Ok(Object::Tag(tag)) => vec![(tag.object_type, tag.object)],
+        Ok(Object::Blob(_)) => Vec::new(),
+        Err(err) => {
+            report(err);
+            Vec::new()
+        }
+    };
+
+    CheckedObject { object_type, links }
+}
+
+/// Returns the shas fsck walks from, reporting refs that point at objects that do not exist.
+/// Reflog entries may name long-pruned objects, so those are skipped quietly; index entries are
+/// all kept so that a missing blob shows up as missing.
+fn collect_roots(
+    git_dir: &Path,
+    objects: &HashMap<String, CheckedObject>,
+    errors: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut roots = Vec::new();
+    let mut refs = list_refs(git_dir)?;
+
+    if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+        refs.push(("HEAD".to_string(), head));
+    }
+
+    for (name, sha) in refs {
+        if objects.contains_key(&sha) {
+            roots.push(sha);
+        } else {
+            errors.push(format!("error: {}: invalid sha1 pointer {}", name, sha));
+        }
+    }
+
+    roots.extend(
+        reflog_shas(git_dir)?
+            .into_iter()
+            .filter(|sha| objects.contains_key(sha)),
+    );
+
+    if let Some(index) = load_index(git_dir)? {
+        roots.extend(
+            index
+                .entries
+                .into_iter()
+                .filter(|entry| !entry.is_gitlink())
+                .map(|entry| entry.sha),
+        );
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::utils::get_object_path_in;
+
+    fn run_fsck(args: &[&str], git_dir: &Path) -> (Result<(), String>, String) {
+        let mut output = Vec::new();
+        let result = fsck(args, git_dir, &mut output);
+
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn fsck_reports_dangling_and_unreachable_objects() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "kept");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &blob)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let orphan = write_blob(git_dir.path(), "orphan");
+        let orphan_tree = write_tree(git_dir.path(), &[("100644", "g", &orphan)]);
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+
+        let (result, output) = run_fsck(&[], git_dir.path());
+
+        assert!(result.is_ok());
+        assert_eq!(output, format!("dangling tree {}\n", orphan_tree));
+
+        let (_, output) = run_fsck(&["--unreachable"], git_dir.path());
+
+        // sorted by sha
+        assert_eq!(
+            output,
+            format!(
+                "unreachable tree {}\nunreachable blob {}\n",
+                orphan_tree, orphan
+            )
+        );
+        assert_eq!(run_fsck(&["--no-dangling"], git_dir.path()).1, "");
+    }
+
+    #[test]
+    fn fsck_reports_missing_and_corrupt_objects() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "gone");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &blob)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let other = write_blob(git_dir.path(), "other");
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+
+        let other_path = get_object_path_in(git_dir.path(), &other).unwrap();
+        let renamed = get_object_path_in(git_dir.path(), &blob).unwrap();
+
+        // the blob's file now holds a different object, so its content no longer matches
+        fs::remove_file(&renamed).unwrap();
+        fs::rename(&other_path, &renamed).unwrap();
+
+        let (result, output) = run_fsck(&[], git_dir.path());
+
+        assert!(result.is_err());
+        assert_eq!(
+            output,
+            format!(
+                "error: {}: hash-path mismatch, found at: {}\n\
+                 broken link from    tree {}\n              to    blob {}\n\
+                 missing blob {}\n",
+                other,
+                renamed.display(),
+                tree,
+                blob,
+                blob
+            )
+        );
+    }
+}
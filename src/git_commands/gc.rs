@@ -0,0 +1,212 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::packs::load_packs;
+use crate::git_commands::prune::{now, parse_expiry, prune_unreachable};
+use crate::git_commands::refs::pack_refs;
+use crate::git_commands::repack::repack;
+use crate::git_commands::utils::loose_objects;
+use crate::models::config::Config;
+
+const USAGE: &str = "usage: git gc [--auto] [--prune=<date> | --no-prune]";
+
+const DEFAULT_AUTO_THRESHOLD: i64 = 6700;
+const DEFAULT_AUTO_PACK_LIMIT: i64 = 50;
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Packs refs, repacks everything reachable into one pack (unreachable packed objects are
+/// loosened) and prunes unreachable loose objects older than `gc.pruneExpire`.
+pub fn gc<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut auto = false;
+    let mut prune_expire = None;
+
+    for arg in args {
+        match *arg {
+            "--auto" => auto = true,
+            "--no-prune" => prune_expire = Some("never".to_string()),
+            "--prune" => prune_expire = Some(DEFAULT_PRUNE_EXPIRE.to_string()),
+            _ if arg.starts_with("--prune=") => {
+                prune_expire = Some(arg["--prune=".len()..].to_string())
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let config = load_config(git_dir)?;
+    let prune_expire = match prune_expire {
+        Some(prune_expire) => prune_expire,
+        None => config
+            .get("gc.pruneExpire")
+            .unwrap_or(DEFAULT_PRUNE_EXPIRE)
+            .to_string(),
+    };
+    // parse before touching anything so that a typo does not leave a half-done gc
+    let expire = parse_expiry(&prune_expire, now())?;
+
+    if auto {
+        if !needs_auto_gc(git_dir, &config)? {
+            return Ok(());
+        }
+
+        writeln!(
+            writer,
+            "Auto packing the repository for optimum performance."
+        )
+        .map_err(|err| err.to_string())?;
+    }
+
+    if config.get_bool("gc.packRefs")?.unwrap_or(true) {
+        pack_refs(git_dir)?;
+    }
+
+    let repack_args: &[&str] = if config.get_bool("repack.writeBitmaps")?.unwrap_or(false) {
+        &["-A", "-d", "-b"]
+    } else {
+        &["-A", "-d"]
+    };
+
+    repack(repack_args, git_dir, &mut Vec::new())?;
+
+    if let Some(expire) = expire {
+        prune_unreachable(git_dir, expire, &[], false, false, writer)?;
+    }
+
+    Ok(())
+}
+
+/// Like upstream's `gc --auto`: work is only needed once there are more than `gc.auto` loose
+/// objects or more than `gc.autoPackLimit` packs. Either limit set to 0 (or below) turns its
+/// check off, and `gc.auto = 0` turns automatic gc off entirely. Unlike upstream, loose objects
+/// are counted exactly rather than estimated from one fan-out directory.
+fn needs_auto_gc(git_dir: &Path, config: &Config) -> Result<bool, String> {
+    let threshold = config.get_int("gc.auto")?.unwrap_or(DEFAULT_AUTO_THRESHOLD);
+
+    if threshold <= 0 {
+        return Ok(false);
+    }
+
+    if loose_objects(git_dir)?.len() as i64 > threshold {
+        return Ok(true);
+    }
+
+    let pack_limit = config
+        .get_int("gc.autoPackLimit")?
+        .unwrap_or(DEFAULT_AUTO_PACK_LIMIT);
+
+    Ok(pack_limit > 0 && load_packs(git_dir)?.len() as i64 > pack_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::git_commands::clone::clone;
+    use crate::git_commands::fsck::fsck;
+    use crate::git_commands::promisor::is_promisor_pack;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::utils::{get_object_path_in, object_exists, read_raw_object};
+
+    #[test]
+    fn gc_packs_refs_and_objects_and_keeps_recent_garbage() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let garbage = write_blob(git_dir.path(), "garbage");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &one)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+
+        gc(&[], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let packs = load_packs(git_dir.path()).unwrap();
+
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].index.entries.len(), 3);
+        assert!(!git_dir.path().join("refs/heads/main").exists());
+        assert!(fs::read_to_string(git_dir.path().join("packed-refs"))
+            .unwrap()
+            .contains(&format!("{} refs/heads/main\n", commit)));
+        // written just now, so still inside the default two week grace period
+        assert!(get_object_path_in(git_dir.path(), &garbage)
+            .unwrap()
+            .exists());
+
+        gc(&["--prune=now"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        assert!(!get_object_path_in(git_dir.path(), &garbage)
+            .unwrap()
+            .exists());
+        assert_eq!(loose_objects(git_dir.path()).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn gc_auto_does_nothing_below_the_thresholds() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "one");
+        let mut output = Vec::new();
+
+        fs::write(git_dir.path().join("config"), "[gc]\n\tauto = 1\n").unwrap();
+        gc(&["--auto"], git_dir.path(), &mut output).unwrap();
+
+        assert!(output.is_empty());
+        assert!(get_object_path_in(git_dir.path(), &blob).unwrap().exists());
+
+        write_blob(git_dir.path(), "two");
+        gc(&["--auto", "--prune=now"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Auto packing the repository for optimum performance.\n"
+        );
+        assert_eq!(loose_objects(git_dir.path()).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn gc_in_a_partial_clone_keeps_promised_objects_without_fetching_them() {
+        let remote = init_git_dir();
+        let old = write_blob(remote.path(), "old\n");
+        let old_tree = write_tree(remote.path(), &[("100644", "README", &old)]);
+        let first = write_commit(remote.path(), &old_tree, &[], 100, "first");
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[&first], 200, "second");
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        let parent = tempfile::tempdir().unwrap();
+        let work_dir = parent.path().join("repo");
+
+        clone(
+            &[
+                "--filter=blob:none",
+                &format!("file://{}", remote.path().display()),
+                work_dir.to_str().unwrap(),
+            ],
+            &mut Vec::new(),
+        )
+        .unwrap();
+        // any lazy fetch from here on fails
+        drop(remote);
+
+        let git_dir = work_dir.join(".git");
+
+        gc(&["--prune=now"], &git_dir, &mut Vec::new()).unwrap();
+
+        let packs = load_packs(&git_dir).unwrap();
+        let markers = fs::read_dir(git_dir.join("objects/pack"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().unwrap() == "promisor")
+            .count();
+
+        assert_eq!(packs.len(), 1);
+        assert!(is_promisor_pack(&git_dir, &packs[0].name));
+        assert_eq!(markers, 1);
+        assert!(!object_exists(&git_dir, &old).unwrap());
+        assert_eq!(
+            read_raw_object(&git_dir, &readme).unwrap(),
+            ("blob".to_string(), b"hello\n".to_vec())
+        );
+        assert!(fsck(&[], &git_dir, &mut Vec::new()).is_ok());
+    }
+}
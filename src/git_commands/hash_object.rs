@@ -26,7 +26,7 @@ pub fn hash_object<O: ObjectPathGetter, W: Write>(
 
     let git_object = GitObject::new(content_size as i32, Object::new("blob", contents)?);
     let object_file_buffer = [
-        format!("{} {}\0", git_object.object.get_type(), content_size).as_bytes(),
+        format!("{} {}\0", git_object.object.get_type(), git_object.size).as_bytes(),
         git_object.get_content(),
     ]
     .concat();
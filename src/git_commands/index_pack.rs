@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::utils::write_atomically;
+use crate::models::pack::Pack;
+use crate::models::pack_index::PackIndex;
+
+const USAGE: &str = "usage: git index-pack <pack-file>";
+
+/// Builds the `.idx` for a `.pack` next to it and prints the pack checksum, like upstream.
+/// Thin packs are refused: every delta base must be in the pack itself.
+pub fn index_pack<W: Write>(args: &[&str], writer: &mut W) -> Result<(), String> {
+    let pack_path = match args {
+        [pack_path] if pack_path.ends_with(".pack") => Path::new(pack_path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let pack = Pack::from_bytes(
+        fs::read(pack_path).map_err(|err| format!("error reading {:?}: {}", pack_path, err))?,
+    )?;
+
+    pack.verify_checksum()?;
+
+    let entries = pack.index_entries(&|_| Ok(None))?;
+
+    if entries.len() != pack.num_objects() as usize {
+        return Err("pack has duplicate objects".to_string());
+    }
+
+    let index = PackIndex::new(entries, &pack.checksum());
+
+    write_atomically(&pack_path.with_extension("idx"), &index.to_bytes()?)?;
+    writeln!(writer, "{}", pack.checksum()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::pack::write_pack;
+
+    #[test]
+    fn index_pack_writes_the_same_index_as_the_pack_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack_path = dir.path().join("test.pack");
+        let (data, entries) = write_pack(&[
+            ("blob".to_string(), b"one\n".to_vec()),
+            ("blob".to_string(), b"two\n".to_vec()),
+        ])
+        .unwrap();
+        let expected = PackIndex::new(entries, &hex::encode(&data[data.len() - 20..]));
+        let mut output = Vec::new();
+
+        fs::write(&pack_path, &data).unwrap();
+        index_pack(&[pack_path.to_str().unwrap()], &mut output).unwrap();
+
+        let index = fs::read(dir.path().join("test.idx")).unwrap();
+
+        assert_eq!(index, expected.to_bytes().unwrap());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{}\n", expected.pack_checksum)
+        );
+    }
+
+    #[test]
+    fn index_pack_fails_without_a_pack_file() {
+        let result = index_pack(&["objects"], &mut Vec::new());
+
+        assert_eq!(result.unwrap_err(), USAGE);
+    }
+}
@@ -0,0 +1,241 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::refs::resolve_revision;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::read_object;
+use crate::models::object::Object;
+
+const USAGE: &str = "usage: git log [--oneline] [-n <n>] [<revision>...] [-- <path>...]";
+
+pub fn log<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut walk = RevWalk::new(git_dir)?;
+    let mut oneline = false;
+    let mut max_count: Option<usize> = None;
+    let mut has_tips = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--" => {
+                walk.set_pathspec(&args.by_ref().copied().collect::<Vec<&str>>());
+            }
+            "--oneline" => oneline = true,
+            "-n" => max_count = Some(parse_count(args.next().ok_or(USAGE)?)?),
+            _ if arg.starts_with("--max-count=") => {
+                max_count = Some(parse_count(&arg["--max-count=".len()..])?)
+            }
+            _ if arg.starts_with('^') => walk.hide(&resolve_revision(git_dir, &arg[1..])?)?,
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => {
+                walk.push(&resolve_revision(git_dir, arg)?)?;
+                has_tips = true;
+            }
+        }
+    }
+
+    if !has_tips {
+        walk.push(&resolve_revision(git_dir, "HEAD")?)?;
+    }
+
+    for (idx, commit) in walk.take(max_count.unwrap_or(usize::MAX)).enumerate() {
+        let sha = commit?.sha;
+        let commit = match read_object(git_dir, &sha)?.object {
+            Object::Commit(commit) => commit,
+            _ => return Err(format!("object {} is not a commit", sha)),
+        };
+
+        let output = if oneline {
+            format!(
+                "{} {}\n",
+                &sha[..7],
+                commit.message.lines().next().unwrap_or_default()
+            )
+        } else {
+            let mut output = String::new();
+
+            if idx > 0 {
+                output.push('\n');
+            }
+
+            output.push_str(&format!("commit {}\n", sha));
+
+            if commit.parents.len() > 1 {
+                let abbreviated: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+                output.push_str(&format!("Merge: {}\n", abbreviated.join(" ")));
+            }
+
+            let (identity, date) = split_signature(&commit.author);
+            output.push_str(&format!("Author: {}\nDate:   {}\n\n", identity, date));
+
+            for line in commit.message.trim_end_matches('\n').lines() {
+                output.push_str(&format!("    {}\n", line));
+            }
+
+            output
+        };
+
+        writer
+            .write_all(output.as_bytes())
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|err| format!("invalid count {}: {}", value, err))
+}
+
+/// Splits `Name <email> 1700000000 +0100` into the identity and a formatted date.
+fn split_signature(signature: &str) -> (String, String) {
+    let email_end = signature
+        .rfind('>')
+        .map(|idx| idx + 1)
+        .unwrap_or(signature.len());
+    let identity = signature[..email_end].to_string();
+    let mut date_parts = signature[email_end..].split_whitespace();
+    let timestamp: i64 = date_parts.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+    let timezone = date_parts.next().unwrap_or("+0000");
+
+    (identity, format_date(timestamp, timezone))
+}
+
+/// Formats a timestamp like upstream's default date format: `Thu Apr 7 15:13:13 2005 -0700`.
+pub fn format_date(timestamp: i64, timezone: &str) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let local = timestamp + timezone_offset(timezone);
+    let days = local.div_euclid(86400);
+    let seconds_of_day = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60,
+        year,
+        timezone
+    )
+}
+
+/// The offset in seconds of a `+hhmm` or `-hhmm` timezone.
+pub fn timezone_offset(timezone: &str) -> i64 {
+    let offset_minutes = timezone
+        .get(1..)
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .map(|digits| (digits / 100) * 60 + digits % 100)
+        .unwrap_or(0);
+
+    if timezone.starts_with('-') {
+        -offset_minutes * 60
+    } else {
+        offset_minutes * 60
+    }
+}
+
+/// Converts days since the unix epoch into a `(year, month, day)` civil date.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+
+    #[test]
+    fn format_date_matches_upstream_default_format() {
+        assert_eq!(
+            format_date(1112911993, "-0700"),
+            "Thu Apr 7 15:13:13 2005 -0700"
+        );
+        assert_eq!(format_date(0, "+0000"), "Thu Jan 1 00:00:00 1970 +0000");
+    }
+
+    #[test]
+    fn log_prints_commits_with_author_and_message() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 0, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 60, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+        let mut output = Vec::new();
+
+        log(&[], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "commit {}\nAuthor: A U Thor <author@example.com>\nDate:   Thu Jan 1 00:01:00 1970 +0000\n\n    second\n\ncommit {}\nAuthor: A U Thor <author@example.com>\nDate:   Thu Jan 1 00:00:00 1970 +0000\n\n    first\n",
+                second, first
+            )
+        );
+    }
+
+    #[test]
+    fn log_with_pathspec_limits_commits() {
+        let git_dir = init_git_dir();
+        let v1 = write_blob(git_dir.path(), "v1");
+        let v2 = write_blob(git_dir.path(), "v2");
+        let tree1 = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &v1), ("100644", "b", &v1)],
+        );
+        let tree2 = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &v1), ("100644", "b", &v2)],
+        );
+        let first = write_commit(git_dir.path(), &tree1, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+        let mut output = Vec::new();
+
+        log(
+            &["--oneline", "main", "--", "a"],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{} first\n", &first[..7])
+        );
+    }
+
+    #[test]
+    fn log_fails_with_unknown_flag() {
+        let git_dir = init_git_dir();
+
+        let result = log(&["--graph"], git_dir.path(), &mut Vec::new());
+
+        assert_eq!(result.unwrap_err(), USAGE);
+    }
+}
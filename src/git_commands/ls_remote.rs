@@ -0,0 +1,187 @@
+use std::io::{stderr, Write};
+use std::path::Path;
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch::default_remote;
+use crate::git_commands::transport::{open_transport, transport_url, Service};
+
+const USAGE: &str =
+    "usage: git ls-remote [--heads] [--tags] [--refs] [--symref] [<remote> [<patterns>...]]";
+
+/// Lists the refs of a remote (the current branch's, or `origin`, when none is named) without
+/// fetching anything, one `<sha>\t<name>` line each in the order the server sent them, peeled
+/// tags included as `^{}` lines. `--heads` and `--tags` keep to branches and tags, which a v2
+/// server is asked for with `ref-prefix`es, and `--refs` drops `HEAD` and the peeled lines.
+/// `--symref` shows what symbolic refs point at. Patterns match the end of a ref name at a `/`,
+/// so `main` finds `refs/heads/main`, and may use `*` and `?`.
+pub fn ls_remote<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut heads = false;
+    let mut tags = false;
+    let mut refs_only = false;
+    let mut symref = false;
+    let mut positional = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "-h" | "--heads" => heads = true,
+            "-t" | "--tags" => tags = true,
+            "--refs" => refs_only = true,
+            "--symref" => symref = true,
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => positional.push(*arg),
+        }
+    }
+
+    let config = load_config(git_dir)?;
+    let (remote, patterns) = match positional.split_first() {
+        Some((remote, patterns)) => (remote.to_string(), patterns),
+        None => (default_remote(git_dir, &config)?, &[][..]),
+    };
+    let url = config
+        .get(&format!("remote.{}.url", remote))
+        .unwrap_or(&remote)
+        .to_string();
+
+    if positional.is_empty() {
+        let _ = writeln!(stderr(), "From {}", url);
+    }
+
+    let mut prefixes = Vec::new();
+
+    if tags {
+        prefixes.push("refs/tags/");
+    }
+
+    if heads {
+        prefixes.push("refs/heads/");
+    }
+
+    let mut transport = open_transport(&transport_url(&url)?, git_dir, Service::UploadPack)?;
+    let advertisement = transport.discover_refs(&prefixes)?;
+
+    for (name, sha) in &advertisement.refs {
+        if !is_listed(name, heads, tags, refs_only)
+            || !(patterns.is_empty() || patterns.iter().any(|pattern| tail_match(pattern, name)))
+        {
+            continue;
+        }
+
+        if symref {
+            if let Some(target) = advertisement.symref_target(name) {
+                writeln!(writer, "ref: {}\t{}", target, name).map_err(|err| err.to_string())?;
+            }
+        }
+
+        writeln!(writer, "{}\t{}", sha, name).map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Whether a ref of this name is of a kind the options ask for: with none of them, every ref.
+fn is_listed(name: &str, heads: bool, tags: bool, refs_only: bool) -> bool {
+    if !(heads || tags || refs_only) {
+        return true;
+    }
+
+    let Some(name) = name.strip_prefix("refs/") else {
+        return false;
+    };
+
+    if refs_only && name.ends_with("^{}") {
+        return false;
+    }
+
+    (heads && name.starts_with("heads/")) || (tags && name.starts_with("tags/")) || !(heads || tags)
+}
+
+/// Whether `pattern` matches `name` or the part of it after one of its `/`s.
+fn tail_match(pattern: &str, name: &str) -> bool {
+    wildcard_match(pattern.as_bytes(), name.as_bytes())
+        || name
+            .match_indices('/')
+            .any(|(idx, _)| wildcard_match(pattern.as_bytes(), &name.as_bytes()[idx + 1..]))
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters (`/` included)
+/// and `?` for any one character.
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| wildcard_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && wildcard_match(rest, &text[1..]),
+        Some((byte, rest)) => text.first() == Some(byte) && wildcard_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_object, write_ref, write_tree,
+    };
+
+    #[test]
+    fn ls_remote_lists_refs_by_kind_and_pattern() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        let tag = write_object(
+            remote.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                commit
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(remote.path(), "refs/heads/main", &commit);
+        write_ref(remote.path(), "refs/tags/v1", &tag);
+        let local = init_git_dir();
+        let url = format!("file://{}", remote.path().display());
+        let list = |options: &[&str], patterns: &[&str]| {
+            let mut output = Vec::new();
+            let mut args = options.to_vec();
+
+            args.push(&url);
+            args.extend(patterns);
+            ls_remote(&args, local.path(), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            list(&["--symref"], &[]),
+            format!(
+                "ref: refs/heads/main\tHEAD\n{c}\tHEAD\n{c}\trefs/heads/main\n{t}\trefs/tags/v1\n\
+                 {c}\trefs/tags/v1^{{}}\n",
+                c = commit,
+                t = tag
+            )
+        );
+        assert_eq!(
+            list(&["--tags", "--refs"], &[]),
+            format!("{}\trefs/tags/v1\n", tag)
+        );
+        assert_eq!(
+            list(&["--heads"], &[]),
+            format!("{}\trefs/heads/main\n", commit)
+        );
+        assert_eq!(
+            list(&[], &["main", "HEAD"]),
+            format!("{c}\tHEAD\n{c}\trefs/heads/main\n", c = commit)
+        );
+    }
+
+    #[test]
+    fn tail_match_matches_after_a_slash() {
+        assert!(tail_match("main", "refs/heads/main"));
+        assert!(tail_match("heads/main", "refs/heads/main"));
+        assert!(tail_match("HEAD", "HEAD"));
+        assert!(tail_match("v1?0", "refs/tags/v100"));
+        assert!(tail_match("m*", "refs/heads/main"));
+        assert!(!tail_match("ain", "refs/heads/main"));
+        assert!(!tail_match("EAD", "HEAD"));
+    }
+}
@@ -17,12 +17,11 @@ pub fn ls_tree<O: ObjectPathGetter, W: Write>(
     };
     let object_path = object_path_getter.get_object_path(sha)?;
     let decompressed_content =
-        read_and_decompress_file(&object_path.as_str()).map_err(|e| e.to_string())?;
+        read_and_decompress_file(object_path.as_str()).map_err(|e| e.to_string())?;
 
     let git_object = GitObject::from_object_file_buffer(&decompressed_content)?;
 
     let to_print: String = match git_object.object {
-        Object::Blob(_) => return Err("not a tree object".to_string()),
         Object::Tree(tree) => {
             if name_only {
                 tree.get_names()
@@ -30,6 +29,7 @@ pub fn ls_tree<O: ObjectPathGetter, W: Write>(
                 tree.get_content_string()?
             }
         }
+        _ => return Err("not a tree object".to_string()),
     };
 
     writer
@@ -1,19 +1,93 @@
-use std::io::stdout;
+use std::io::{stdin, stdout};
+use std::path::Path;
 
 use utils::ActualObjectPathGetter;
 
+use crate::git_commands::archive::archive;
+use crate::git_commands::bundle::bundle;
 use crate::git_commands::cat_file::cat_file;
-use crate::git_commands::GitCommand::{CatFile, HashObject, Init, LsTree};
+use crate::git_commands::clone::clone;
+use crate::git_commands::commit_graph::commit_graph;
+use crate::git_commands::credential::{
+    credential, credential_cache, credential_cache_daemon, credential_store,
+};
+use crate::git_commands::GitCommand::{
+    Archive, Bundle, CatFile, Clone, CommitGraph, CountObjects, Credential, CredentialCache,
+    CredentialCacheDaemon, CredentialStore, FastExport, FastImport, Fetch, Fsck, Gc, HashObject,
+    IndexPack, Init, Log, LsRemote, LsTree, MultiPackIndex, Prune, Push, ReceivePack, Repack,
+    RevList, Serve, UpdateServerInfo, UploadPack,
+};
+use crate::git_commands::count_objects::count_objects;
+use crate::git_commands::fast_export::fast_export;
+use crate::git_commands::fast_import::fast_import;
+use crate::git_commands::fetch::fetch;
+use crate::git_commands::fsck::fsck;
+use crate::git_commands::gc::gc;
 use crate::git_commands::hash_object::hash_object;
+use crate::git_commands::index_pack::index_pack;
 use crate::git_commands::init::init;
+use crate::git_commands::log::log;
+use crate::git_commands::ls_remote::ls_remote;
 use crate::git_commands::ls_tree::ls_tree;
+use crate::git_commands::multi_pack_index::multi_pack_index;
+use crate::git_commands::prune::prune;
+use crate::git_commands::push::push;
+use crate::git_commands::receive_pack::receive_pack;
+use crate::git_commands::repack::repack;
+use crate::git_commands::rev_list::rev_list;
+use crate::git_commands::serve::serve;
+use crate::git_commands::update_server_info::update_server_info;
+use crate::git_commands::upload_pack::upload_pack;
 
+mod archive;
+mod bitmaps;
+mod bundle;
 mod cat_file;
+mod checkout;
+mod clone;
+mod commit_graph;
+mod config;
+mod count_objects;
+mod credential;
+mod dumb_http;
+mod fast_export;
+mod fast_import;
+mod fetch;
+mod fetch_pack;
+mod fsck;
+mod gc;
 mod hash_object;
+mod index_pack;
 mod init;
+mod log;
+mod ls_remote;
 mod ls_tree;
+mod multi_pack_index;
+mod object_walk;
+mod packs;
+mod promisor;
+mod prune;
+mod push;
+mod receive_pack;
+mod refs;
+mod repack;
+mod rev_list;
+mod rev_walk;
+mod send_pack;
+mod serve;
+mod shallow;
+mod smart_http;
+mod ssh;
+#[cfg(test)]
+mod test_utils;
+mod transport;
+mod tree_diff;
+mod update_server_info;
+mod upload_pack;
 mod utils;
 
+const GIT_DIR: &str = ".git";
+
 pub enum GitCommand<'a> {
     CatFile {
         sha: &'a str,
@@ -27,11 +101,89 @@ pub enum GitCommand<'a> {
         sha: &'a str,
         flag: Option<&'a str>,
     },
+    CommitGraph {
+        args: Vec<&'a str>,
+    },
+    IndexPack {
+        args: Vec<&'a str>,
+    },
+    MultiPackIndex {
+        args: Vec<&'a str>,
+    },
+    Repack {
+        args: Vec<&'a str>,
+    },
+    Prune {
+        args: Vec<&'a str>,
+    },
+    Gc {
+        args: Vec<&'a str>,
+    },
+    Fsck {
+        args: Vec<&'a str>,
+    },
+    CountObjects {
+        args: Vec<&'a str>,
+    },
+    Clone {
+        args: Vec<&'a str>,
+    },
+    Fetch {
+        args: Vec<&'a str>,
+    },
+    Push {
+        args: Vec<&'a str>,
+    },
+    UploadPack {
+        args: Vec<&'a str>,
+    },
+    ReceivePack {
+        args: Vec<&'a str>,
+    },
+    Serve {
+        args: Vec<&'a str>,
+    },
+    UpdateServerInfo {
+        args: Vec<&'a str>,
+    },
+    RevList {
+        args: Vec<&'a str>,
+    },
+    Log {
+        args: Vec<&'a str>,
+    },
+    LsRemote {
+        args: Vec<&'a str>,
+    },
+    Bundle {
+        args: Vec<&'a str>,
+    },
+    FastExport {
+        args: Vec<&'a str>,
+    },
+    FastImport {
+        args: Vec<&'a str>,
+    },
+    Archive {
+        args: Vec<&'a str>,
+    },
+    Credential {
+        args: Vec<&'a str>,
+    },
+    CredentialStore {
+        args: Vec<&'a str>,
+    },
+    CredentialCache {
+        args: Vec<&'a str>,
+    },
+    CredentialCacheDaemon {
+        args: Vec<&'a str>,
+    },
     Init,
 }
 
 impl<'a> GitCommand<'a> {
-    pub fn from_args(args: &'a Vec<String>) -> Result<Self, String> {
+    pub fn from_args(args: &'a [String]) -> Result<Self, String> {
         match args[1].as_str() {
             "init" => Ok(Init {}),
             "cat-file" => {
@@ -47,7 +199,7 @@ impl<'a> GitCommand<'a> {
             "hash-object" => {
                 let arg_len = args.len();
 
-                if arg_len < 3 && arg_len > 4 {
+                if !(3..=4).contains(&arg_len) {
                     return Err("usage: git hash-object [-w] <file_path>".to_string());
                 }
 
@@ -66,7 +218,7 @@ impl<'a> GitCommand<'a> {
             "ls-tree" => {
                 let arg_len = args.len();
 
-                if arg_len < 3 && arg_len > 4 {
+                if !(3..=4).contains(&arg_len) {
                     return Err("usage: git ls-tree [--name-only] <tree_sha>".to_string());
                 }
 
@@ -82,6 +234,84 @@ impl<'a> GitCommand<'a> {
                     })
                 }
             }
+            "commit-graph" => Ok(CommitGraph {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "index-pack" => Ok(IndexPack {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "multi-pack-index" => Ok(MultiPackIndex {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "repack" => Ok(Repack {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "prune" => Ok(Prune {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "gc" => Ok(Gc {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "fsck" => Ok(Fsck {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "count-objects" => Ok(CountObjects {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "clone" => Ok(Clone {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "fetch" => Ok(Fetch {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "push" => Ok(Push {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "upload-pack" => Ok(UploadPack {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "receive-pack" => Ok(ReceivePack {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "serve" => Ok(Serve {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "update-server-info" => Ok(UpdateServerInfo {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "rev-list" => Ok(RevList {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "log" => Ok(Log {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "ls-remote" => Ok(LsRemote {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "bundle" => Ok(Bundle {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "fast-export" => Ok(FastExport {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "fast-import" => Ok(FastImport {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "archive" => Ok(Archive {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "credential" => Ok(Credential {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "credential-store" => Ok(CredentialStore {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "credential-cache" => Ok(CredentialCache {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
+            "credential-cache--daemon" => Ok(CredentialCacheDaemon {
+                args: args[2..].iter().map(|arg| arg.as_str()).collect(),
+            }),
             _ => Err("not a recognized git command".to_string()),
         }
     }
@@ -97,11 +327,42 @@ impl<'a> GitCommand<'a> {
                 hash_object(file_path, flag, ActualObjectPathGetter {}, &mut stdout())
             }
             LsTree { sha, flag } => ls_tree(sha, flag, ActualObjectPathGetter {}, &mut stdout()),
+            CommitGraph { args } => commit_graph(args, Path::new(GIT_DIR), &mut stdout()),
+            IndexPack { args } => index_pack(args, &mut stdout()),
+            MultiPackIndex { args } => multi_pack_index(args, Path::new(GIT_DIR), &mut stdout()),
+            Repack { args } => repack(args, Path::new(GIT_DIR), &mut stdout()),
+            Prune { args } => prune(args, Path::new(GIT_DIR), &mut stdout()),
+            Gc { args } => gc(args, Path::new(GIT_DIR), &mut stdout()),
+            Fsck { args } => fsck(args, Path::new(GIT_DIR), &mut stdout()),
+            CountObjects { args } => count_objects(args, Path::new(GIT_DIR), &mut stdout()),
+            Clone { args } => clone(args, &mut stdout()),
+            Fetch { args } => fetch(args, Path::new(GIT_DIR), &mut stdout()),
+            Push { args } => push(args, Path::new(GIT_DIR), &mut stdout()),
+            UploadPack { args } => upload_pack(args, stdin().lock(), &mut stdout()),
+            ReceivePack { args } => receive_pack(args, stdin().lock(), &mut stdout()),
+            Serve { args } => serve(args, &mut stdout()),
+            UpdateServerInfo { args } => {
+                update_server_info(args, Path::new(GIT_DIR), &mut stdout())
+            }
+            RevList { args } => rev_list(args, Path::new(GIT_DIR), &mut stdout()),
+            Log { args } => log(args, Path::new(GIT_DIR), &mut stdout()),
+            LsRemote { args } => ls_remote(args, Path::new(GIT_DIR), &mut stdout()),
+            Bundle { args } => bundle(args, Path::new(GIT_DIR), &mut stdout()),
+            FastExport { args } => fast_export(args, Path::new(GIT_DIR), &mut stdout()),
+            FastImport { args } => {
+                fast_import(args, Path::new(GIT_DIR), stdin().lock(), &mut stdout())
+            }
+            Archive { args } => archive(args, Path::new(GIT_DIR), &mut stdout()),
+            Credential { args } => {
+                credential(args, Path::new(GIT_DIR), stdin().lock(), &mut stdout())
+            }
+            CredentialStore { args } => credential_store(args, stdin().lock(), &mut stdout()),
+            CredentialCache { args } => credential_cache(args, stdin().lock(), &mut stdout()),
+            CredentialCacheDaemon { args } => credential_cache_daemon(args, &mut stdout()),
         };
 
-        match error {
-            Err(e) => print!("{}", e.to_string()),
-            _ => {}
+        if let Err(e) = error {
+            print!("{}", e);
         }
     }
 }
@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::git_commands::bitmaps::write_bitmap_index;
+use crate::git_commands::packs::{load_packs, pack_dir};
+use crate::git_commands::utils::write_atomically;
+use crate::models::multi_pack_index::{MultiPackIndex, MultiPackIndexEntry};
+
+const USAGE: &str =
+    "usage: git multi-pack-index (write [--bitmap] [--preferred-pack=<pack>] | verify)";
+
+fn multi_pack_index_file(git_dir: &Path) -> PathBuf {
+    pack_dir(git_dir).join("multi-pack-index")
+}
+
+pub fn multi_pack_index_bitmap_file(git_dir: &Path, checksum: &str) -> PathBuf {
+    pack_dir(git_dir).join(format!("multi-pack-index-{}.bitmap", checksum))
+}
+
+pub fn load_multi_pack_index(git_dir: &Path) -> Result<Option<MultiPackIndex>, String> {
+    let path = multi_pack_index_file(git_dir);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read(&path).map_err(|err| format!("error reading multi-pack-index: {}", err))?;
+
+    MultiPackIndex::from_bytes(&data).map(Some)
+}
+
+pub fn multi_pack_index<W: Write>(
+    args: &[&str],
+    git_dir: &Path,
+    writer: &mut W,
+) -> Result<(), String> {
+    match args.split_first() {
+        Some((&"write", options)) => write(options, git_dir),
+        Some((&"verify", [])) => verify(git_dir, writer),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn write(options: &[&str], git_dir: &Path) -> Result<(), String> {
+    let mut with_bitmap = false;
+    let mut preferred_pack_name = None;
+
+    for option in options {
+        match *option {
+            "--bitmap" => with_bitmap = true,
+            _ if option.starts_with("--preferred-pack=") => {
+                let name = &option["--preferred-pack=".len()..];
+
+                preferred_pack_name = Some(
+                    name.trim_end_matches(".pack")
+                        .trim_end_matches(".idx")
+                        .to_string(),
+                );
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let packs = load_packs(git_dir)?;
+
+    if packs.is_empty() {
+        return Err("no pack files to index".to_string());
+    }
+
+    let preferred_pack = match preferred_pack_name {
+        Some(name) => Some(
+            packs
+                .iter()
+                .position(|pack_file| pack_file.name == name)
+                .ok_or_else(|| format!("preferred pack {} not found", name))?,
+        ),
+        // like upstream, default to the oldest pack so bitmaps stay stable as new packs arrive
+        None => packs
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, pack_file)| pack_mtime(git_dir, &pack_file.name))
+            .map(|(idx, _)| idx),
+    };
+
+    // the preferred pack wins duplicate objects, then the most recently written pack
+    let mut pack_preference: Vec<usize> = (0..packs.len()).collect();
+
+    pack_preference.sort_by_key(|&idx| {
+        (
+            Some(idx) != preferred_pack,
+            std::cmp::Reverse(pack_mtime(git_dir, &packs[idx].name)),
+            idx,
+        )
+    });
+
+    let mut entries: Vec<MultiPackIndexEntry> = Vec::new();
+
+    for pack_id in pack_preference {
+        entries.extend(
+            packs[pack_id]
+                .index
+                .entries
+                .iter()
+                .map(|entry| MultiPackIndexEntry {
+                    sha: entry.sha.clone(),
+                    pack_id: pack_id as u32,
+                    offset: entry.offset,
+                }),
+        );
+    }
+
+    // a stable sort keeps the preferred copy of each object first
+    entries.sort_by(|a, b| a.sha.cmp(&b.sha));
+    entries.dedup_by(|later, earlier| later.sha == earlier.sha);
+
+    let pack_names: Vec<String> = packs
+        .iter()
+        .map(|pack_file| format!("{}.idx", pack_file.name))
+        .collect();
+    let data = MultiPackIndex::write(&pack_names, &entries, preferred_pack.map(|idx| idx as u32))?;
+    let midx = MultiPackIndex::from_bytes(&data)?;
+
+    remove_stale_bitmaps(git_dir)?;
+
+    if with_bitmap {
+        let objects: Vec<String> = midx
+            .pseudo_pack_order
+            .iter()
+            .map(|&position| midx.entries[position as usize].sha.clone())
+            .collect();
+        let bitmap = write_bitmap_index(git_dir, &objects, &midx.checksum)?;
+
+        write_atomically(
+            &multi_pack_index_bitmap_file(git_dir, &midx.checksum),
+            &bitmap,
+        )?;
+    }
+
+    write_atomically(&multi_pack_index_file(git_dir), &data)
+}
+
+fn pack_mtime(git_dir: &Path, name: &str) -> Option<SystemTime> {
+    fs::metadata(pack_dir(git_dir).join(format!("{}.pack", name)))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Drops the multi-pack-index and its bitmaps, for when the packs it covers are deleted.
+pub fn remove_multi_pack_index(git_dir: &Path) -> Result<(), String> {
+    let path = multi_pack_index_file(git_dir);
+
+    if path.exists() {
+        fs::remove_file(&path).map_err(|err| err.to_string())?;
+    }
+
+    remove_stale_bitmaps(git_dir)
+}
+
+fn remove_stale_bitmaps(git_dir: &Path) -> Result<(), String> {
+    for entry in fs::read_dir(pack_dir(git_dir)).map_err(|err| err.to_string())? {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        if file_name.starts_with("multi-pack-index-") && file_name.ends_with(".bitmap") {
+            fs::remove_file(&path).map_err(|err| err.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn verify<W: Write>(git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let midx = match load_multi_pack_index(git_dir)? {
+        Some(midx) => midx,
+        None => return Ok(()),
+    };
+    let packs = load_packs(git_dir)?;
+    let mut errors = Vec::new();
+
+    for entry in &midx.entries {
+        let pack_name = &midx.pack_names[entry.pack_id as usize];
+        let offset = packs
+            .iter()
+            .find(|pack_file| format!("{}.idx", pack_file.name) == *pack_name)
+            .and_then(|pack_file| pack_file.index.find_offset(&entry.sha));
+
+        if offset != Some(entry.offset) {
+            errors.push(format!(
+                "object {}: expected offset {} in {}, found {:?}",
+                entry.sha, entry.offset, pack_name, offset
+            ));
+        }
+    }
+
+    for pack_file in &packs {
+        if !midx.pack_names.contains(&format!("{}.idx", pack_file.name)) {
+            continue;
+        }
+
+        for entry in &pack_file.index.entries {
+            if midx.position(&entry.sha).is_none() {
+                errors.push(format!(
+                    "object {} in {} is missing from the multi-pack-index",
+                    entry.sha, pack_file.name
+                ));
+            }
+        }
+    }
+
+    for error in &errors {
+        writeln!(writer, "{}", error).map_err(|err| err.to_string())?;
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "multi-pack-index verification found {} problem(s)\n",
+            errors.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::packs::write_pack_files;
+    use crate::git_commands::test_utils::init_git_dir;
+    use crate::models::pack::{object_sha, write_pack};
+
+    fn write_blob_pack(git_dir: &Path, contents: &[&str]) -> String {
+        let objects: Vec<(String, Vec<u8>)> = contents
+            .iter()
+            .map(|content| ("blob".to_string(), content.as_bytes().to_vec()))
+            .collect();
+        let (data, entries) = write_pack(&objects).unwrap();
+
+        write_pack_files(git_dir, &data, entries).unwrap()
+    }
+
+    #[test]
+    fn write_indexes_objects_from_every_pack_once() {
+        let git_dir = init_git_dir();
+        let first = write_blob_pack(git_dir.path(), &["one", "shared"]);
+        write_blob_pack(git_dir.path(), &["two", "shared"]);
+
+        multi_pack_index(
+            &["write", &format!("--preferred-pack={}.pack", first)],
+            git_dir.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let midx = load_multi_pack_index(git_dir.path()).unwrap().unwrap();
+        let first_id = midx
+            .pack_names
+            .iter()
+            .position(|name| *name == format!("{}.idx", first))
+            .unwrap();
+        let shared = midx.position(&object_sha("blob", b"shared")).unwrap();
+
+        assert_eq!(midx.entries.len(), 3);
+        assert_eq!(midx.pack_names.len(), 2);
+        // the preferred pack wins duplicates and leads the pseudo-pack order
+        assert_eq!(midx.entries[shared].pack_id, first_id as u32);
+        assert_eq!(
+            midx.entries[midx.pseudo_pack_order[0] as usize].pack_id,
+            first_id as u32
+        );
+        multi_pack_index(&["verify"], git_dir.path(), &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn multi_pack_index_fails_with_unknown_subcommand() {
+        let git_dir = init_git_dir();
+
+        let result = multi_pack_index(&["expire"], git_dir.path(), &mut Vec::new());
+
+        assert_eq!(result.unwrap_err(), USAGE);
+    }
+}
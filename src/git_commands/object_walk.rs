@@ -0,0 +1,280 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::git_commands::promisor::promisor_remote;
+use crate::git_commands::refs::{ref_tips, reflog_shas};
+use crate::git_commands::rev_walk::{CommitInfo, RevWalk};
+use crate::git_commands::tree_diff::read_tree;
+use crate::git_commands::utils::{object_exists, read_raw_object};
+use crate::models::commit::Commit;
+use crate::models::index::Index;
+use crate::models::tree::{Tree, TreeEntryMode};
+
+/// Lists the non-commit objects that `rev-list --objects` prints after the commits, as
+/// `(sha, name)` pairs: the explicitly given `pending` objects (tags, trees, blobs) first, then
+/// each commit's tree depth first. Everything reachable from `uninteresting_trees` is left out.
+pub fn list_objects(
+    git_dir: &Path,
+    pending: &[(String, String, String)],
+    commits: &[CommitInfo],
+    uninteresting_trees: &[String],
+) -> Result<Vec<(String, String)>, String> {
+    walk_objects(git_dir, pending, commits, uninteresting_trees, false)
+}
+
+/// `list_objects`, leaving out the trees and blobs the repository does not have when
+/// `skip_missing` rather than failing on (or lazily fetching) them.
+fn walk_objects(
+    git_dir: &Path,
+    pending: &[(String, String, String)],
+    commits: &[CommitInfo],
+    uninteresting_trees: &[String],
+    skip_missing: bool,
+) -> Result<Vec<(String, String)>, String> {
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+
+    for tree in uninteresting_trees {
+        mark_tree_seen(git_dir, tree, &mut seen)?;
+    }
+
+    for (sha, object_type, name) in pending {
+        if object_type == "tree" {
+            walk_tree(git_dir, sha, name, skip_missing, &mut seen, &mut objects)?;
+        } else if seen.insert(sha.clone()) {
+            objects.push((sha.clone(), name.clone()));
+        }
+    }
+
+    for commit in commits {
+        walk_tree(
+            git_dir,
+            &commit.tree,
+            "",
+            skip_missing,
+            &mut seen,
+            &mut objects,
+        )?;
+    }
+
+    Ok(objects)
+}
+
+/// Every object reachable from the refs, `HEAD`, the reflogs and the index: everything that
+/// repacking and pruning must keep. Commits come first, newest first, then the other objects.
+/// A partial clone leaves out the trees and blobs it was promised but does not have, as
+/// fetching them just to repack would undo the filter.
+pub fn reachable_objects(git_dir: &Path) -> Result<Vec<String>, String> {
+    let skip_missing = promisor_remote(git_dir)?.is_some();
+    let mut walk = RevWalk::new(git_dir)?;
+    let mut pending = Vec::new();
+    let mut tips = ref_tips(git_dir)?;
+
+    // reflogs and the index may mention objects that are long gone, which is not an error
+    for sha in reflog_shas(git_dir)? {
+        if object_exists(git_dir, &sha)? {
+            tips.push(sha);
+        }
+    }
+
+    for tip in tips {
+        let mut sha = tip;
+
+        loop {
+            let (object_type, content) = read_raw_object(git_dir, &sha)?;
+
+            match object_type.as_str() {
+                "commit" => {
+                    walk.push(&sha)?;
+                    break;
+                }
+                "tag" => {
+                    let target = tag_target(&content)
+                        .ok_or_else(|| format!("tag {} has no object line", sha))?;
+
+                    pending.push((sha, object_type, String::new()));
+                    sha = target;
+                }
+                _ => {
+                    pending.push((sha, object_type, String::new()));
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(index) = load_index(git_dir)? {
+        for entry in index.entries {
+            if !entry.is_gitlink() && object_exists(git_dir, &entry.sha)? {
+                pending.push((entry.sha, "blob".to_string(), entry.path));
+            }
+        }
+    }
+
+    let commits = walk.collect::<Result<Vec<_>, String>>()?;
+    let mut objects: Vec<String> = commits.iter().map(|commit| commit.sha.clone()).collect();
+
+    objects.extend(
+        walk_objects(git_dir, &pending, &commits, &[], skip_missing)?
+            .into_iter()
+            .map(|(sha, _)| sha),
+    );
+
+    Ok(objects)
+}
+
+pub fn load_index(git_dir: &Path) -> Result<Option<Index>, String> {
+    let path = git_dir.join("index");
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read(&path).map_err(|err| format!("error reading index: {}", err))?;
+
+    Index::from_bytes(&data).map(Some)
+}
+
+/// Returns the shas an object points at: a commit's tree and parents, a tree's entries (except
+/// submodules) or a tag's target.
+pub fn referenced_objects(git_dir: &Path, sha: &str) -> Result<Vec<String>, String> {
+    let (object_type, content) = read_raw_object(git_dir, sha)?;
+
+    object_links(&object_type, content)
+}
+
+/// The shas in an object's content, as `referenced_objects` returns them.
+pub fn object_links(object_type: &str, content: Vec<u8>) -> Result<Vec<String>, String> {
+    match object_type {
+        "commit" => {
+            let commit = Commit::new(content)?;
+
+            Ok([vec![commit.tree], commit.parents].concat())
+        }
+        "tree" => Ok(Tree::new(content)?
+            .tree_entries
+            .into_iter()
+            .filter(|entry| entry.mode != TreeEntryMode::Submodule)
+            .map(|entry| entry.sha)
+            .collect()),
+        "tag" => Ok(tag_target(&content).into_iter().collect()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+pub fn tag_target(content: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(content)
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("object "))
+        .map(|sha| sha.to_string())
+}
+
+fn walk_tree(
+    git_dir: &Path,
+    sha: &str,
+    path: &str,
+    skip_missing: bool,
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<(String, String)>,
+) -> Result<(), String> {
+    if !seen.insert(sha.to_string()) || (skip_missing && !object_exists(git_dir, sha)?) {
+        return Ok(());
+    }
+
+    objects.push((sha.to_string(), path.to_string()));
+
+    for entry in read_tree(git_dir, sha)?.tree_entries {
+        let entry_path = if path.is_empty() {
+            entry.name.clone()
+        } else {
+            format!("{}/{}", path, entry.name)
+        };
+
+        match entry.mode {
+            TreeEntryMode::Directory => walk_tree(
+                git_dir,
+                &entry.sha,
+                &entry_path,
+                skip_missing,
+                seen,
+                objects,
+            )?,
+            TreeEntryMode::Submodule => {}
+            _ => {
+                if seen.insert(entry.sha.clone())
+                    && (!skip_missing || object_exists(git_dir, &entry.sha)?)
+                {
+                    objects.push((entry.sha, entry_path));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn mark_tree_seen(git_dir: &Path, sha: &str, seen: &mut HashSet<String>) -> Result<(), String> {
+    if !seen.insert(sha.to_string()) {
+        return Ok(());
+    }
+
+    for entry in read_tree(git_dir, sha)?.tree_entries {
+        match entry.mode {
+            TreeEntryMode::Directory => mark_tree_seen(git_dir, &entry.sha, seen)?,
+            TreeEntryMode::Submodule => {}
+            _ => {
+                seen.insert(entry.sha);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{init_git_dir, write_blob, write_tree};
+
+    fn commit_info(tree: &str) -> CommitInfo {
+        CommitInfo {
+            sha: String::new(),
+            tree: tree.to_string(),
+            parents: Vec::new(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn list_objects_walks_trees_depth_first_and_skips_uninteresting_ones() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let two = write_blob(git_dir.path(), "two");
+        let sub = write_tree(git_dir.path(), &[("100644", "a", &one)]);
+        let old_tree = write_tree(git_dir.path(), &[("40000", "src", &sub)]);
+        let new_tree = write_tree(
+            git_dir.path(),
+            &[("100644", "b", &two), ("40000", "src", &sub)],
+        );
+
+        let all = list_objects(git_dir.path(), &[], &[commit_info(&new_tree)], &[]).unwrap();
+        let new_only =
+            list_objects(git_dir.path(), &[], &[commit_info(&new_tree)], &[old_tree]).unwrap();
+
+        assert_eq!(
+            all,
+            vec![
+                (new_tree.clone(), "".to_string()),
+                (two.clone(), "b".to_string()),
+                (sub, "src".to_string()),
+                (one, "src/a".to_string()),
+            ]
+        );
+        assert_eq!(
+            new_only,
+            vec![(new_tree, "".to_string()), (two, "b".to_string())]
+        );
+    }
+}
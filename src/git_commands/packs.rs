@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::git_commands::utils::{read_raw_object, write_atomically};
+use crate::models::pack::Pack;
+use crate::models::pack_index::{PackIndex, PackIndexEntry};
+
+/// A pack and its index, named by their shared `pack-<checksum>` file stem.
+#[derive(Debug)]
+pub struct PackFile {
+    pub name: String,
+    pub index: PackIndex,
+    pub pack: Pack,
+}
+
+type PackCache = HashMap<PathBuf, (u64, Option<SystemTime>, Arc<PackFile>)>;
+
+// packs are immutable once written, so they are only re-read when the file changes underneath
+fn pack_cache() -> &'static Mutex<PackCache> {
+    static CACHE: OnceLock<Mutex<PackCache>> = OnceLock::new();
+
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn pack_dir(git_dir: &Path) -> PathBuf {
+    git_dir.join("objects/pack")
+}
+
+/// Loads every pack under `objects/pack` that has an index, sorted by name.
+pub fn load_packs(git_dir: &Path) -> Result<Vec<Arc<PackFile>>, String> {
+    let dir = pack_dir(git_dir);
+
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&dir)
+        .map_err(|err| format!("error reading {:?}: {}", dir, err))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_suffix(".idx")
+                .map(|name| name.to_string())
+        })
+        .filter(|name| dir.join(format!("{}.pack", name)).exists())
+        .collect();
+
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| load_pack(&dir, &name))
+        .collect()
+}
+
+fn load_pack(dir: &Path, name: &str) -> Result<Arc<PackFile>, String> {
+    let pack_path = dir.join(format!("{}.pack", name));
+    let metadata = fs::metadata(&pack_path)
+        .map_err(|err| format!("error reading {:?}: {}", pack_path, err))?;
+    let key = (metadata.len(), metadata.modified().ok());
+    let mut cache = pack_cache().lock().map_err(|err| err.to_string())?;
+
+    if let Some((len, modified, pack_file)) = cache.get(&pack_path) {
+        if (*len, *modified) == key {
+            return Ok(pack_file.clone());
+        }
+    }
+
+    let index_path = dir.join(format!("{}.idx", name));
+    let index = PackIndex::from_bytes(
+        &fs::read(&index_path).map_err(|err| format!("error reading {:?}: {}", index_path, err))?,
+    )?;
+    let pack = Pack::from_bytes(
+        fs::read(&pack_path).map_err(|err| format!("error reading {:?}: {}", pack_path, err))?,
+    )?;
+
+    if index.pack_checksum != pack.checksum() {
+        return Err(format!("pack index {} does not match its pack", name));
+    }
+
+    let pack_file = Arc::new(PackFile {
+        name: name.to_string(),
+        index,
+        pack,
+    });
+
+    cache.insert(pack_path, (key.0, key.1, pack_file.clone()));
+
+    Ok(pack_file)
+}
+
+/// Looks an object up in the packs, returning `None` when no pack has it.
+pub fn read_packed_object(git_dir: &Path, sha: &str) -> Result<Option<(String, Vec<u8>)>, String> {
+    for pack_file in load_packs(git_dir)? {
+        if let Some(offset) = pack_file.index.find_offset(sha) {
+            return pack_file
+                .pack
+                .read_object_at(offset, &|base| read_raw_object(git_dir, base))
+                .map(Some)
+                .map_err(|err| format!("error reading object {}: {}", sha, err));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Stores a pack and its index under `objects/pack`, returning the `pack-<checksum>` name.
+pub fn write_pack_files(
+    git_dir: &Path,
+    data: &[u8],
+    entries: Vec<PackIndexEntry>,
+) -> Result<String, String> {
+    write_pack_files_to(&pack_dir(git_dir), data, entries)
+}
+
+/// Stores a pack and its index in `dir`, which need not belong to a repository yet.
+pub fn write_pack_files_to(
+    dir: &Path,
+    data: &[u8],
+    entries: Vec<PackIndexEntry>,
+) -> Result<String, String> {
+    let checksum = hex::encode(&data[data.len() - 20..]);
+    let name = format!("pack-{}", checksum);
+    let index = PackIndex::new(entries, &checksum);
+
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    write_atomically(&dir.join(format!("{}.pack", name)), data)?;
+    write_atomically(&dir.join(format!("{}.idx", name)), &index.to_bytes()?)?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::init_git_dir;
+    use crate::git_commands::utils::read_object;
+    use crate::models::pack::write_pack;
+
+    #[test]
+    fn read_object_falls_back_to_packs() {
+        let git_dir = init_git_dir();
+        let (data, entries) = write_pack(&[("blob".to_string(), b"packed\n".to_vec())]).unwrap();
+        let sha = entries[0].sha.clone();
+
+        let name = write_pack_files(git_dir.path(), &data, entries).unwrap();
+        let git_object = read_object(git_dir.path(), &sha).unwrap();
+
+        assert_eq!(git_object.get_content(), b"packed\n");
+        assert_eq!(load_packs(git_dir.path()).unwrap()[0].name, name);
+        assert!(read_object(git_dir.path(), &"0".repeat(40)).is_err());
+    }
+}
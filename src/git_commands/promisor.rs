@@ -0,0 +1,101 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch_pack::{store_pack, Negotiator, ShallowRequest};
+use crate::git_commands::packs::pack_dir;
+use crate::git_commands::shallow::read_shallow;
+use crate::git_commands::transport::{open_transport, transport_url, Service};
+use crate::models::object_filter::ObjectFilter;
+
+thread_local! {
+    /// Set while promised objects are being fetched, so that objects found missing during that
+    /// fetch are not fetched in turn.
+    static FETCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The remote that promised the objects a partial clone left out, named by
+/// `extensions.partialClone`. Other repositories have none.
+pub fn promisor_remote(git_dir: &Path) -> Result<Option<String>, String> {
+    Ok(load_config(git_dir)?
+        .get("extensions.partialclone")
+        .map(|remote| remote.to_string()))
+}
+
+/// The filter fetches from the promisor remote keep using after a partial clone from it,
+/// `remote.<name>.partialCloneFilter`.
+pub fn partial_clone_filter(git_dir: &Path, remote: &str) -> Result<Option<ObjectFilter>, String> {
+    load_config(git_dir)?
+        .get(&format!("remote.{}.partialclonefilter", remote))
+        .map(ObjectFilter::parse)
+        .transpose()
+}
+
+/// Marks a pack fetched from the promisor remote with an empty `<name>.promisor` file: whatever
+/// its objects point at and the repository lacks is promised rather than missing.
+pub fn mark_promisor_pack(git_dir: &Path, name: &str) -> Result<(), String> {
+    let path = pack_dir(git_dir).join(format!("{}.promisor", name));
+
+    fs::write(&path, b"").map_err(|err| format!("error writing {:?}: {}", path, err))
+}
+
+pub fn is_promisor_pack(git_dir: &Path, name: &str) -> bool {
+    pack_dir(git_dir)
+        .join(format!("{}.promisor", name))
+        .exists()
+}
+
+/// Fetches objects a partial clone is missing from its promisor remote, with `blob:none` like
+/// upstream's lazy fetches so that a missing tree does not bring every blob under it. Returns
+/// false, fetching nothing, when the repository is not a partial clone or is already fetching
+/// promised objects.
+pub fn fetch_promised(git_dir: &Path, shas: &[String]) -> Result<bool, String> {
+    let Some(remote) = promisor_remote(git_dir)? else {
+        return Ok(false);
+    };
+
+    if FETCHING.with(|fetching| fetching.replace(true)) {
+        return Ok(false);
+    }
+
+    let result = fetch_objects(git_dir, &remote, shas);
+
+    FETCHING.with(|fetching| fetching.set(false));
+
+    result.map(|()| true).map_err(|err| {
+        format!(
+            "{}\nfatal: could not fetch {} from promisor remote\n",
+            err.trim_end(),
+            shas.join(" ")
+        )
+    })
+}
+
+fn fetch_objects(git_dir: &Path, remote: &str, shas: &[String]) -> Result<(), String> {
+    let url = load_config(git_dir)?
+        .get(&format!("remote.{}.url", remote))
+        .map(|url| url.to_string())
+        .ok_or_else(|| format!("fatal: promisor remote '{}' has no url\n", remote))?;
+    let mut transport = open_transport(&transport_url(&url)?, git_dir, Service::UploadPack)?;
+    let shallow = ShallowRequest {
+        shallow: read_shallow(git_dir)?.into_iter().collect(),
+        ..ShallowRequest::default()
+    };
+
+    // the refs do not matter, but the conversation starts with them
+    transport.discover_refs(&["HEAD"])?;
+
+    let (pack, _) = transport.fetch_pack(
+        shas,
+        &shallow,
+        Some(&ObjectFilter::BlobNone),
+        &mut Negotiator::new(git_dir, &[])?,
+    )?;
+
+    if let Some(name) = store_pack(git_dir, pack)? {
+        mark_promisor_pack(git_dir, &name)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git_commands::object_walk::{reachable_objects, referenced_objects};
+use crate::git_commands::packs::load_packs;
+use crate::git_commands::refs::resolve_revision;
+use crate::git_commands::utils::{loose_objects, object_exists, read_raw_object};
+
+const USAGE: &str = "usage: git prune [-n] [-v] [--expire <time>] [<head>...]";
+
+const SECONDS_PER_UNIT: [(&str, u64); 7] = [
+    ("second", 1),
+    ("minute", 60),
+    ("hour", 60 * 60),
+    ("day", 24 * 60 * 60),
+    ("week", 7 * 24 * 60 * 60),
+    ("month", 30 * 24 * 60 * 60),
+    ("year", 365 * 24 * 60 * 60),
+];
+
+pub fn prune<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut dry_run = false;
+    let mut verbose = false;
+    // like upstream, everything unreachable goes unless an expiry is given
+    let mut expire = Some(u64::MAX);
+    let mut heads = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "-n" | "--dry-run" => dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            "--expire" => expire = parse_expiry(args.next().ok_or(USAGE)?, now())?,
+            _ if arg.starts_with("--expire=") => {
+                expire = parse_expiry(&arg["--expire=".len()..], now())?
+            }
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => heads.push(resolve_revision(git_dir, arg)?),
+        }
+    }
+
+    let expire = match expire {
+        Some(expire) => expire,
+        None => return Ok(()),
+    };
+
+    prune_unreachable(git_dir, expire, &heads, dry_run, verbose, writer)?;
+
+    if !dry_run {
+        prune_packed(git_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Parses an expiry like upstream's `gc.pruneExpire`: `now`, `all`, `never`, a unix timestamp
+/// or `<n>.<unit>.ago` (also `<n> <unit>s ago`). Returns the cutoff in seconds since the epoch;
+/// objects modified at or before it expire. `None` means nothing ever expires.
+pub fn parse_expiry(value: &str, now: u64) -> Result<Option<u64>, String> {
+    let invalid = || format!("invalid expiry date '{}'", value);
+
+    match value {
+        "never" | "false" => return Ok(None),
+        "all" => return Ok(Some(u64::MAX)),
+        "now" => return Ok(Some(now)),
+        _ if value.bytes().all(|b| b.is_ascii_digit()) => {
+            return value.parse().map(Some).map_err(|_| invalid())
+        }
+        _ => {}
+    }
+
+    let parts: Vec<&str> = value.split(['.', ' ']).collect();
+    let (count, unit) = match parts[..] {
+        [count, unit, "ago"] => (count.parse::<u64>().map_err(|_| invalid())?, unit),
+        _ => return Err(invalid()),
+    };
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let seconds = SECONDS_PER_UNIT
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, seconds)| count * seconds)
+        .ok_or_else(invalid)?;
+
+    Ok(Some(now.saturating_sub(seconds)))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Deletes unreachable loose objects last modified at or before `expire`. Objects reachable
+/// from the refs, reflogs, index or `heads` are kept, and so is everything reachable from a
+/// loose object too young to expire, so a recent unreachable commit never loses its tree.
+pub fn prune_unreachable<W: Write>(
+    git_dir: &Path,
+    expire: u64,
+    heads: &[String],
+    dry_run: bool,
+    verbose: bool,
+    writer: &mut W,
+) -> Result<(), String> {
+    let mut keep: HashSet<String> = reachable_objects(git_dir)?.into_iter().collect();
+    let mut candidates = Vec::new();
+    let mut recent = heads.to_vec();
+
+    for (sha, path) in loose_objects(git_dir)? {
+        if keep.contains(&sha) {
+            continue;
+        }
+
+        if modified_secs(&path)? > expire {
+            recent.push(sha);
+        } else {
+            candidates.push((sha, path));
+        }
+    }
+
+    while let Some(sha) = recent.pop() {
+        if keep.insert(sha.clone()) && object_exists(git_dir, &sha)? {
+            recent.extend(referenced_objects(git_dir, &sha)?);
+        }
+    }
+
+    for (sha, path) in candidates {
+        if keep.contains(&sha) {
+            continue;
+        }
+
+        if dry_run || verbose {
+            let object_type = read_raw_object(git_dir, &sha)
+                .map(|(object_type, _)| object_type)
+                .unwrap_or_else(|_| "unknown".to_string());
+
+            writeln!(writer, "{} {}", sha, object_type).map_err(|err| err.to_string())?;
+        }
+
+        if !dry_run {
+            fs::remove_file(&path).map_err(|err| format!("error removing {:?}: {}", path, err))?;
+            remove_empty_parent(&path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes loose objects that are also in a pack.
+pub fn prune_packed(git_dir: &Path) -> Result<(), String> {
+    let packed: HashSet<String> = load_packs(git_dir)?
+        .iter()
+        .flat_map(|pack_file| {
+            pack_file
+                .index
+                .entries
+                .iter()
+                .map(|entry| entry.sha.clone())
+        })
+        .collect();
+
+    for (sha, path) in loose_objects(git_dir)? {
+        if packed.contains(&sha) {
+            fs::remove_file(&path).map_err(|err| format!("error removing {:?}: {}", path, err))?;
+            remove_empty_parent(&path);
+        }
+    }
+
+    Ok(())
+}
+
+fn modified_secs(path: &Path) -> Result<u64, String> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        })
+        .map_err(|err| format!("error reading {:?}: {}", path, err))
+}
+
+fn remove_empty_parent(path: &Path) {
+    if let Some(dir) = path.parent() {
+        // only succeeds once the fan-out directory is empty
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::utils::get_object_path_in;
+
+    #[test]
+    fn parse_expiry_understands_relative_dates() {
+        let now = 1_000_000_000;
+
+        assert_eq!(
+            parse_expiry("2.weeks.ago", now).unwrap(),
+            Some(now - 14 * 24 * 60 * 60)
+        );
+        assert_eq!(parse_expiry("1 hour ago", now).unwrap(), Some(now - 3600));
+        assert_eq!(parse_expiry("now", now).unwrap(), Some(now));
+        assert_eq!(parse_expiry("12345", now).unwrap(), Some(12345));
+        assert_eq!(parse_expiry("never", now).unwrap(), None);
+        assert!(parse_expiry("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn prune_removes_only_unreachable_objects() {
+        let git_dir = init_git_dir();
+        let kept = write_blob(git_dir.path(), "kept");
+        let reflog_only = write_blob(git_dir.path(), "reflog");
+        let garbage = write_blob(git_dir.path(), "garbage");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &kept)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let old_tree = write_tree(git_dir.path(), &[("100644", "f", &reflog_only)]);
+        let old_commit = write_commit(git_dir.path(), &old_tree, &[], 50, "amended");
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+        fs::create_dir_all(git_dir.path().join("logs")).unwrap();
+        fs::write(
+            git_dir.path().join("logs/HEAD"),
+            format!(
+                "{} {} A <a@example.com> 50 +0000\tcommit (amend): first\n",
+                old_commit, commit
+            ),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+
+        prune(&["-n"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{} blob\n", garbage)
+        );
+
+        prune(&["--expire", "1.day.ago"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        assert!(get_object_path_in(git_dir.path(), &garbage)
+            .unwrap()
+            .exists());
+
+        prune(&[], git_dir.path(), &mut Vec::new()).unwrap();
+
+        assert!(!get_object_path_in(git_dir.path(), &garbage)
+            .unwrap()
+            .exists());
+        assert!(get_object_path_in(git_dir.path(), &reflog_only)
+            .unwrap()
+            .exists());
+        assert!(get_object_path_in(git_dir.path(), &old_commit)
+            .unwrap()
+            .exists());
+    }
+}
@@ -0,0 +1,630 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch::default_remote;
+use crate::git_commands::fetch_pack::NULL_SHA;
+use crate::git_commands::receive_pack::RefUpdate;
+use crate::git_commands::refs::{
+    abbrev, current_branch, delete_ref, list_refs, resolve_ref, resolve_revision, shorten_ref,
+    update_ref, SUMMARY_WIDTH,
+};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::send_pack::send_pack;
+use crate::git_commands::transport::{open_transport, transport_url, Service};
+use crate::git_commands::utils::object_exists;
+use crate::models::config::Config;
+use crate::models::refspec::Refspec;
+
+const USAGE: &str = "usage: git push [--delete] [--tags] [-f | --force] \
+                     [--force-with-lease[=<ref>[:<expect>]]] [<remote> [<refspec>...]]";
+const TAGS_REFSPEC: &str = "refs/tags/*:refs/tags/*";
+
+/// Pushes local refs to a remote (the current branch's, or `origin`) as the refspecs say, or
+/// the current branch to the branch of the same name when none are given. An update that is
+/// not a fast-forward, or that moves an existing tag, is refused unless forced with `+`,
+/// `--force` or a lease: `--force-with-lease` only forces refs whose remote value is still the
+/// one expected, by default the value of their remote-tracking ref. `--delete` deletes the
+/// named remote refs and `--tags` also pushes every tag. Remote-tracking refs follow the
+/// updates the remote accepted.
+pub fn push<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut delete = false;
+    let mut tags = false;
+    let mut force = false;
+    let mut lease_all = false;
+    let mut leases = Vec::new();
+    let mut positional = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "-d" | "--delete" => delete = true,
+            "--tags" => tags = true,
+            "-f" | "--force" => force = true,
+            "--force-with-lease" => lease_all = true,
+            _ if arg.starts_with("--force-with-lease=") => {
+                let value = &arg["--force-with-lease=".len()..];
+
+                leases.push(match value.split_once(':') {
+                    // an empty expectation means the ref must not exist yet
+                    Some((name, "")) => (name.to_string(), Lease::Expect(None)),
+                    Some((name, expect)) => (
+                        name.to_string(),
+                        Lease::Expect(Some(resolve_revision(git_dir, expect)?)),
+                    ),
+                    None => (value.to_string(), Lease::Tracking),
+                });
+            }
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => positional.push(*arg),
+        }
+    }
+
+    let config = load_config(git_dir)?;
+    let remote = match positional.first() {
+        Some(remote) => remote.to_string(),
+        None => default_remote(git_dir, &config)?,
+    };
+    let url = config
+        .get(&format!("remote.{}.url", remote))
+        .unwrap_or(&remote)
+        .to_string();
+    let mut specs: Vec<String> = positional
+        .iter()
+        .skip(1)
+        .map(|spec| spec.to_string())
+        .collect();
+
+    if delete {
+        if specs.is_empty() {
+            return Err("fatal: --delete doesn't make sense without any refs\n".to_string());
+        }
+
+        if specs.iter().any(|spec| spec.contains(':')) {
+            return Err("fatal: --delete only accepts plain target ref names\n".to_string());
+        }
+
+        specs = specs.iter().map(|spec| format!(":{}", spec)).collect();
+    }
+
+    if specs.is_empty() && !tags {
+        let branch =
+            current_branch(git_dir)?.ok_or("fatal: You are not currently on a branch.\n")?;
+
+        specs.push(format!("refs/heads/{}", branch));
+    }
+
+    let mut refspecs = specs
+        .iter()
+        .map(|spec| Refspec::parse(spec))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if tags {
+        refspecs.push(Refspec::parse(TAGS_REFSPEC)?);
+    }
+
+    let mut transport = open_transport(&transport_url(&url)?, git_dir, Service::ReceivePack)?;
+    let advertisement = transport.discover_refs(&[])?;
+    let remote_refs: Vec<(String, String)> = advertisement
+        .direct_refs()
+        .filter(|(name, _)| name.starts_with("refs/"))
+        .cloned()
+        .collect();
+    let local_refs = list_refs(git_dir)?;
+    let mut push_refs: Vec<PushRef> = Vec::new();
+
+    for refspec in &refspecs {
+        for mut push_ref in match_refspec(git_dir, refspec, &local_refs, &remote_refs)? {
+            if push_refs.iter().any(|other| other.dst == push_ref.dst) {
+                continue;
+            }
+
+            push_ref.force |= force;
+            push_ref.old = remote_refs
+                .iter()
+                .find(|(name, _)| *name == push_ref.dst)
+                .map(|(_, sha)| sha.clone());
+            push_refs.push(push_ref);
+        }
+    }
+
+    let mut outcomes = Vec::new();
+
+    for push_ref in &push_refs {
+        let lease = match leases
+            .iter()
+            .find(|(name, _)| names_ref(name, &push_ref.dst))
+            .map(|(_, lease)| lease)
+            .or(lease_all.then_some(&Lease::Tracking))
+        {
+            Some(Lease::Tracking) => {
+                Some(tracking_value(git_dir, &config, &remote, &push_ref.dst)?)
+            }
+            Some(Lease::Expect(expected)) => Some(expected.clone()),
+            None => None,
+        };
+
+        outcomes.push(outcome(git_dir, push_ref, lease)?);
+    }
+
+    let updates: Vec<RefUpdate> = push_refs
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| outcome.sends_update())
+        .map(|(push_ref, _)| RefUpdate {
+            old: push_ref.old.clone().unwrap_or_else(|| NULL_SHA.to_string()),
+            new: push_ref.new.clone(),
+            name: push_ref.dst.clone(),
+        })
+        .collect();
+
+    if outcomes
+        .iter()
+        .all(|outcome| matches!(outcome, Outcome::UpToDate))
+    {
+        return writeln!(writer, "Everything up-to-date").map_err(|err| err.to_string());
+    }
+
+    let report = if updates.is_empty() {
+        None
+    } else {
+        Some(send_pack(
+            transport.as_mut(),
+            git_dir,
+            &advertisement,
+            &updates,
+        )?)
+    };
+    let mut failed = false;
+
+    writeln!(writer, "To {}", url).map_err(|err| err.to_string())?;
+
+    for (push_ref, outcome) in push_refs.iter().zip(&outcomes) {
+        let remote_rejection = match (&report, outcome.sends_update()) {
+            (Some(report), true) => report.rejection(&push_ref.dst),
+            _ => None,
+        };
+        let (flag, summary, message) = match (remote_rejection, outcome) {
+            (_, Outcome::UpToDate) => continue,
+            (Some(reason), _) => ('!', "[remote rejected]".to_string(), Some(reason)),
+            (None, Outcome::Rejected(reason)) => ('!', "[rejected]".to_string(), Some(*reason)),
+            (None, Outcome::Delete) => ('-', "[deleted]".to_string(), None),
+            (None, Outcome::New) => ('*', new_ref_summary(&push_ref.dst).to_string(), None),
+            (None, Outcome::FastForward) => (' ', push_ref.range(".."), None),
+            (None, Outcome::Forced) => ('+', push_ref.range("..."), Some("forced update")),
+        };
+
+        failed |= flag == '!';
+
+        let refs = if matches!(outcome, Outcome::Delete) {
+            shorten_ref(&push_ref.dst).to_string()
+        } else {
+            format!(
+                "{} -> {}",
+                shorten_ref(&push_ref.src),
+                shorten_ref(&push_ref.dst)
+            )
+        };
+        let message = message
+            .map(|message| format!(" ({})", message))
+            .unwrap_or_default();
+
+        writeln!(
+            writer,
+            " {} {:<width$} {}{}",
+            flag,
+            summary,
+            refs,
+            message,
+            width = SUMMARY_WIDTH
+        )
+        .map_err(|err| err.to_string())?;
+
+        if flag != '!' {
+            update_tracking_ref(git_dir, &config, &remote, push_ref)?;
+        }
+    }
+
+    if let Some(Err(error)) = report.as_ref().map(|report| &report.unpack) {
+        writeln!(writer, "error: remote unpack failed: {}", error)
+            .map_err(|err| err.to_string())?;
+    }
+
+    if failed {
+        return Err(format!("error: failed to push some refs to '{}'\n", url));
+    }
+
+    Ok(())
+}
+
+/// One remote ref a push wants to set: to `new`, or to nothing (the null sha) to delete it.
+/// `old` is its value on the remote, if it has one.
+struct PushRef {
+    src: String,
+    dst: String,
+    old: Option<String>,
+    new: String,
+    force: bool,
+}
+
+impl PushRef {
+    fn range(&self, dots: &str) -> String {
+        let old = self.old.as_deref().unwrap_or(NULL_SHA);
+
+        format!("{}{}{}", abbrev(old), dots, abbrev(&self.new))
+    }
+}
+
+/// What a lease expects a remote ref to be: whatever its remote-tracking ref says, or a value
+/// given on the command line (`None` for not existing).
+enum Lease {
+    Tracking,
+    Expect(Option<String>),
+}
+
+enum Outcome {
+    UpToDate,
+    New,
+    FastForward,
+    Forced,
+    Delete,
+    Rejected(&'static str),
+}
+
+impl Outcome {
+    fn sends_update(&self) -> bool {
+        matches!(
+            self,
+            Outcome::New | Outcome::FastForward | Outcome::Forced | Outcome::Delete
+        )
+    }
+}
+
+/// The remote refs `refspec` sets and their new values. A glob takes every local ref it
+/// matches; otherwise the source names one local ref (or sha), and a destination that is not
+/// a full ref name is taken to be of the same kind as the source, unless the remote already
+/// has a branch or tag by that name.
+fn match_refspec(
+    git_dir: &Path,
+    refspec: &Refspec,
+    local_refs: &[(String, String)],
+    remote_refs: &[(String, String)],
+) -> Result<Vec<PushRef>, String> {
+    let push_ref = |src: &str, dst: String, new: &str| PushRef {
+        src: src.to_string(),
+        dst,
+        old: None,
+        new: new.to_string(),
+        force: refspec.force,
+    };
+
+    if refspec.is_glob() {
+        return Ok(local_refs
+            .iter()
+            .filter(|(name, _)| refspec.matches_src(name))
+            .filter_map(|(name, sha)| Some(push_ref(name, refspec.map_src(name)?, sha)))
+            .collect());
+    }
+
+    let remote_ref = |dst: &str| {
+        [
+            dst.to_string(),
+            format!("refs/heads/{}", dst),
+            format!("refs/tags/{}", dst),
+        ]
+        .into_iter()
+        .find(|candidate| remote_refs.iter().any(|(name, _)| name == candidate))
+    };
+
+    if refspec.src.is_empty() {
+        let dst = refspec.dst.as_deref().unwrap_or_default();
+        let dst = remote_ref(dst).ok_or_else(|| {
+            format!(
+                "error: unable to delete '{}': remote ref does not exist\n",
+                dst
+            )
+        })?;
+
+        return Ok(vec![push_ref("", dst, NULL_SHA)]);
+    }
+
+    let (src, new) = resolve_src(git_dir, &refspec.src)?;
+    let dst = match refspec.dst.as_deref() {
+        Some(dst) if dst.starts_with("refs/") => Some(dst.to_string()),
+        Some(dst) => remote_ref(dst).or_else(|| {
+            ["refs/heads/", "refs/tags/"]
+                .iter()
+                .find(|prefix| src.starts_with(*prefix))
+                .map(|prefix| format!("{}{}", prefix, dst))
+        }),
+        None => src.starts_with("refs/").then(|| src.clone()),
+    };
+    let dst = dst.ok_or_else(|| {
+        format!(
+            "fatal: The destination you provided is not a full refname: '{}'\n",
+            refspec.dst.as_deref().unwrap_or(&refspec.src)
+        )
+    })?;
+
+    Ok(vec![push_ref(&src, dst, &new)])
+}
+
+/// Resolves the source side of a refspec to the full name of the local ref it stands for (a
+/// sha stays as it is) and its value. `HEAD` stands for the current branch.
+fn resolve_src(git_dir: &Path, src: &str) -> Result<(String, String), String> {
+    let not_found = || format!("error: src refspec {} does not match any\n", src);
+
+    if src == "HEAD" {
+        if let Some(branch) = current_branch(git_dir)? {
+            let name = format!("refs/heads/{}", branch);
+            let sha = resolve_ref(git_dir, &name)?.ok_or_else(not_found)?;
+
+            return Ok((name, sha));
+        }
+    }
+
+    for candidate in Refspec::parse(src)?.src_prefixes() {
+        if let Some(sha) = resolve_ref(git_dir, &candidate)? {
+            return Ok((candidate, sha));
+        }
+    }
+
+    if src.len() == 40 && object_exists(git_dir, src)? {
+        return Ok((src.to_string(), src.to_string()));
+    }
+
+    Err(not_found())
+}
+
+/// Decides what to do with one remote ref. A lease that holds allows what `--force` would.
+fn outcome(
+    git_dir: &Path,
+    push_ref: &PushRef,
+    lease: Option<Option<String>>,
+) -> Result<Outcome, String> {
+    if let Some(expected) = &lease {
+        if push_ref.old != *expected {
+            return Ok(Outcome::Rejected("stale info"));
+        }
+    }
+
+    let forced = push_ref.force || lease.is_some();
+    let old = match &push_ref.old {
+        _ if push_ref.new == NULL_SHA => return Ok(Outcome::Delete),
+        None => return Ok(Outcome::New),
+        Some(old) if *old == push_ref.new => return Ok(Outcome::UpToDate),
+        Some(old) => old,
+    };
+
+    if push_ref.dst.starts_with("refs/tags/") && !forced {
+        return Ok(Outcome::Rejected("already exists"));
+    }
+
+    // without the remote's commit we cannot tell whether it is an ancestor
+    if !object_exists(git_dir, old)? {
+        return Ok(if forced {
+            Outcome::Forced
+        } else {
+            Outcome::Rejected("fetch first")
+        });
+    }
+
+    if RevWalk::is_ancestor(git_dir, old, &push_ref.new)? {
+        Ok(Outcome::FastForward)
+    } else if forced {
+        Ok(Outcome::Forced)
+    } else {
+        Ok(Outcome::Rejected("non-fast-forward"))
+    }
+}
+
+fn new_ref_summary(name: &str) -> &'static str {
+    if name.starts_with("refs/heads/") {
+        "[new branch]"
+    } else if name.starts_with("refs/tags/") {
+        "[new tag]"
+    } else {
+        "[new reference]"
+    }
+}
+
+/// Whether `name`, as given to `--force-with-lease`, names the remote ref `full_name`.
+fn names_ref(name: &str, full_name: &str) -> bool {
+    name == full_name || shorten_ref(full_name) == name
+}
+
+/// The remote-tracking ref `remote`'s fetch refspecs store `name` in.
+fn tracking_ref(config: &Config, remote: &str, name: &str) -> Option<String> {
+    config
+        .get_all(&format!("remote.{}.fetch", remote))
+        .into_iter()
+        .filter_map(|spec| Refspec::parse(spec).ok())
+        .find(|refspec| refspec.matches_src(name))
+        .and_then(|refspec| refspec.map_src(name))
+}
+
+fn tracking_value(
+    git_dir: &Path,
+    config: &Config,
+    remote: &str,
+    name: &str,
+) -> Result<Option<String>, String> {
+    match tracking_ref(config, remote, name) {
+        Some(tracking) => resolve_ref(git_dir, &tracking),
+        None => Ok(None),
+    }
+}
+
+/// Makes the remote-tracking ref of a pushed ref match what the remote now has.
+fn update_tracking_ref(
+    git_dir: &Path,
+    config: &Config,
+    remote: &str,
+    push_ref: &PushRef,
+) -> Result<(), String> {
+    match tracking_ref(config, remote, &push_ref.dst) {
+        Some(tracking) if push_ref.new == NULL_SHA => delete_ref(git_dir, &tracking),
+        Some(tracking) => update_ref(git_dir, &tracking, &push_ref.new),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{add_commit, init_git_dir, write_object, write_ref};
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A bare remote with `main` at a first commit, and a local repository on `main` that
+    /// pushes to it through the in-process receive-pack.
+    fn remote_and_local() -> (TempDir, TempDir, String) {
+        let remote = init_git_dir();
+        let local = init_git_dir();
+        let first = add_commit(local.path(), &[], "one\n", 100);
+        write_ref(local.path(), "refs/heads/main", &first);
+
+        fs::write(remote.path().join("config"), "[core]\n\tbare = true\n").unwrap();
+        fs::write(
+            local.path().join("config"),
+            format!(
+                "[remote \"origin\"]\n\
+                 \turl = file://{}\n\
+                 \tfetch = +refs/heads/*:refs/remotes/origin/*\n",
+                remote.path().display()
+            ),
+        )
+        .unwrap();
+        push(&["origin"], local.path(), &mut Vec::new()).unwrap();
+
+        (remote, local, first)
+    }
+
+    fn output_of(args: &[&str], git_dir: &Path) -> (Result<(), String>, String) {
+        let mut output = Vec::new();
+        let result = push(args, git_dir, &mut output);
+
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn push_sends_missing_objects_and_updates_refs() {
+        let (remote, local, first) = remote_and_local();
+
+        assert_eq!(
+            resolve_ref(remote.path(), "refs/heads/main").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/main").unwrap(),
+            Some(first.clone())
+        );
+
+        let second = add_commit(local.path(), &[&first], "two\n", 200);
+        write_ref(local.path(), "refs/heads/main", &second);
+
+        let (result, output) = output_of(&["origin", "main", "main:topic"], local.path());
+
+        result.unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "To file://{}\n   {}..{}  main -> main\n * [new branch]      main -> topic\n",
+                remote.path().display(),
+                &first[..7],
+                &second[..7]
+            )
+        );
+        assert_eq!(
+            resolve_ref(remote.path(), "refs/heads/topic").unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            output_of(&["origin"], local.path()).1,
+            "Everything up-to-date\n"
+        );
+    }
+
+    #[test]
+    fn push_refuses_non_fast_forwards_unless_forced() {
+        let (remote, local, first) = remote_and_local();
+        let rewritten = add_commit(local.path(), &[], "rewritten\n", 200);
+        write_ref(local.path(), "refs/heads/main", &rewritten);
+
+        let (result, output) = output_of(&["origin", "main"], local.path());
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("error: failed to push some refs"));
+        assert!(output.ends_with(" ! [rejected]        main -> main (non-fast-forward)\n"));
+        assert_eq!(
+            resolve_ref(remote.path(), "refs/heads/main").unwrap(),
+            Some(first.clone())
+        );
+
+        let (result, output) = output_of(&["origin", "+main"], local.path());
+
+        result.unwrap();
+        assert!(output.ends_with(&format!(
+            " + {}...{} main -> main (forced update)\n",
+            &first[..7],
+            &rewritten[..7]
+        )));
+    }
+
+    #[test]
+    fn force_with_lease_refuses_when_the_remote_moved() {
+        let (remote, local, first) = remote_and_local();
+        let theirs = add_commit(remote.path(), &[&first], "theirs\n", 200);
+        write_ref(remote.path(), "refs/heads/main", &theirs);
+        let ours = add_commit(local.path(), &[], "ours\n", 300);
+        write_ref(local.path(), "refs/heads/main", &ours);
+
+        let (result, output) = output_of(&["--force-with-lease", "origin"], local.path());
+
+        assert!(result.is_err());
+        assert!(output.ends_with(" ! [rejected]        main -> main (stale info)\n"));
+
+        let lease = format!("--force-with-lease=main:{}", theirs);
+
+        output_of(&[&lease, "origin"], local.path()).0.unwrap();
+        assert_eq!(
+            resolve_ref(remote.path(), "refs/heads/main").unwrap(),
+            Some(ours)
+        );
+    }
+
+    #[test]
+    fn push_deletes_refs_and_pushes_tags() {
+        let (remote, local, first) = remote_and_local();
+        let tag = write_object(
+            local.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger T <t@example.com> 100 +0000\n\nv1\n",
+                first
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(local.path(), "refs/tags/v1", &tag);
+
+        let (result, output) = output_of(&["--tags", "origin"], local.path());
+
+        result.unwrap();
+        assert!(output.ends_with(" * [new tag]         v1 -> v1\n"));
+        assert_eq!(
+            resolve_ref(remote.path(), "refs/tags/v1").unwrap(),
+            Some(tag)
+        );
+
+        let (result, output) = output_of(&["--delete", "origin", "main"], local.path());
+
+        result.unwrap();
+        assert!(output.ends_with(" - [deleted]         main\n"));
+        assert_eq!(resolve_ref(remote.path(), "refs/heads/main").unwrap(), None);
+        assert_eq!(
+            resolve_ref(local.path(), "refs/remotes/origin/main").unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,966 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{stderr, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch_pack::{AGENT, NULL_SHA};
+use crate::git_commands::object_walk::object_links;
+use crate::git_commands::packs::{pack_dir, write_pack_files_to};
+use crate::git_commands::refs::{
+    check_refname_format, delete_ref, list_refs, lock_ref, read_symref, resolve_ref, update_ref,
+};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::transport::find_git_dir;
+use crate::git_commands::utils::{is_sha, object_exists, read_raw_object};
+use crate::models::config::Config;
+use crate::models::pack::{read_pack, Pack, RawObject};
+use crate::models::pkt_line::{PktLineReader, PktLineWriter, SIDEBAND_DATA, SIDEBAND_PROGRESS};
+
+const USAGE: &str = "usage: git receive-pack [--stateless-rpc] [--advertise-refs] <directory>";
+const CAPABILITIES: [&str; 6] = [
+    "report-status",
+    "report-status-v2",
+    "delete-refs",
+    "side-band-64k",
+    "atomic",
+    "ofs-delta",
+];
+
+/// One ref update a pusher asks for. `old` is the null sha to create the ref, `new` to delete
+/// it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefUpdate {
+    pub old: String,
+    pub new: String,
+    pub name: String,
+}
+
+impl RefUpdate {
+    pub fn is_delete(&self) -> bool {
+        self.new == NULL_SHA
+    }
+}
+
+/// Accepts a push over stdin and stdout, the way upstream's `git-receive-pack` does when a
+/// pusher runs it through ssh or `--receive-pack`: the ref advertisement, then one
+/// conversation. `--advertise-refs` stops after the advertisement and `--stateless-rpc` skips it
+/// and answers a single request, as a smart HTTP server needs. Once the pusher is listening,
+/// errors are sent to it as an `ERR` packet.
+pub fn receive_pack<R: Read, W: Write>(
+    args: &[&str],
+    reader: R,
+    writer: &mut W,
+) -> Result<(), String> {
+    let mut stateless = false;
+    let mut advertise_only = false;
+    let mut directory = None;
+
+    for arg in args {
+        match *arg {
+            "--stateless-rpc" => stateless = true,
+            "--advertise-refs" | "--http-backend-info-refs" => advertise_only = true,
+            _ if arg.starts_with('-') || directory.is_some() => return Err(USAGE.to_string()),
+            _ => directory = Some(*arg),
+        }
+    }
+
+    let directory = directory.ok_or_else(|| USAGE.to_string())?;
+    let mut writer = PktLineWriter::new(writer);
+    let served = find_git_dir(Path::new(directory))
+        .ok_or_else(|| format!("'{}' does not appear to be a git repository", directory))
+        .and_then(|git_dir| {
+            if advertise_only || !stateless {
+                advertise_refs(&git_dir, &mut writer)?;
+            }
+
+            if advertise_only {
+                return Ok(());
+            }
+
+            serve_receive_pack(
+                &git_dir,
+                PktLineReader::new(BufReader::new(reader)),
+                &mut writer,
+            )
+        });
+
+    match served {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            writer.write_line(&format!("ERR {}", err.trim_end()))?;
+            writer.flush()
+        }
+    }
+}
+
+/// Writes the receive-pack ref advertisement: every ref, without `HEAD` or peeled entries, and
+/// the capabilities after a NUL on the first line. A repository without refs advertises a
+/// `capabilities^{}` placeholder instead.
+pub fn advertise_refs<W: Write>(
+    git_dir: &Path,
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let capabilities = [CAPABILITIES.join(" "), AGENT.to_string()].join(" ");
+    let refs = list_refs(git_dir)?;
+
+    match refs.split_first() {
+        Some(((name, sha), rest)) => {
+            writer.write_line(&format!("{} {}\0{}", sha, name, capabilities))?;
+
+            for (name, sha) in rest {
+                writer.write_line(&format!("{} {}", sha, name))?;
+            }
+        }
+        None => writer.write_line(&format!("{} capabilities^{{}}\0{}", NULL_SHA, capabilities))?,
+    }
+
+    writer.write_flush()
+}
+
+/// Serves a receive-pack conversation the way upstream does:
+///
+/// 1. The ref update commands are read and, unless they all delete, the pack after them is
+///    indexed into a quarantine directory under `objects`.
+/// 2. The `pre-receive` hook sees every command and the quarantined objects, and may decline
+///    the whole push; otherwise the objects move into the repository.
+/// 3. Each ref must still be at the command's old value and pass the `update` hook. With
+///    `atomic`, one refusal refuses them all, and otherwise each ref moves on its own.
+/// 4. The `post-receive` hook is told what moved.
+///
+/// When the pusher asked for `report-status` it is told how the unpacking and every update
+/// went, and with `side-band-64k` the report and what the hooks print go on the side-band.
+pub fn serve_receive_pack<R: BufRead, W: Write>(
+    git_dir: &Path,
+    mut reader: PktLineReader<R>,
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let mut updates = Vec::new();
+    let mut capabilities = Vec::new();
+
+    while let Some(line) = reader.read_line()? {
+        let command = match line.split_once('\0') {
+            Some((command, requested)) => {
+                capabilities = requested.split(' ').map(|cap| cap.to_string()).collect();
+                command
+            }
+            None => line.as_str(),
+        };
+        let fields: Vec<&str> = command.split(' ').collect();
+
+        match fields[..] {
+            [old, new, name] => updates.push(RefUpdate {
+                old: old.to_string(),
+                new: new.to_string(),
+                name: name.to_string(),
+            }),
+            _ => {
+                return Err(format!(
+                    "protocol error: expected old/new/ref, got '{}'",
+                    line
+                ))
+            }
+        }
+    }
+
+    // a pusher with nothing to update just hangs up
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    let wants = |name: &str| capabilities.iter().any(|cap| cap == name);
+    let mut hooks = Hooks {
+        git_dir,
+        sideband: wants("side-band-64k"),
+        output: writer,
+    };
+    let received = if updates.iter().all(RefUpdate::is_delete) {
+        Ok(None)
+    } else {
+        read_pack(&mut reader.into_inner()).and_then(|pack| quarantine_pack(git_dir, pack))
+    };
+    let mut statuses: Vec<Result<(), String>> = match &received {
+        Ok(quarantine) => updates
+            .iter()
+            .map(|update| check_command(git_dir, quarantine.as_ref(), update))
+            .collect::<Result<_, String>>()?,
+        Err(_) => vec![Err("unpacker error".to_string()); updates.len()],
+    };
+
+    if let Ok(quarantine) = &received {
+        let pending = accepted(&updates, &statuses);
+        let declined = !pending.is_empty()
+            && !hooks.run(
+                "pre-receive",
+                &[],
+                &hook_input(&pending),
+                quarantine.as_ref(),
+            )?;
+
+        if declined {
+            for status in statuses.iter_mut().filter(|status| status.is_ok()) {
+                *status = Err("pre-receive hook declined".to_string());
+            }
+        }
+
+        if let Some(quarantine) = quarantine {
+            if declined {
+                quarantine.discard()?;
+            } else {
+                quarantine.migrate(git_dir)?;
+            }
+        }
+
+        if !declined {
+            apply_updates(&mut hooks, &updates, &mut statuses, wants("atomic"))?;
+
+            let updated = accepted(&updates, &statuses);
+
+            if !updated.is_empty() {
+                hooks.run("post-receive", &[], &hook_input(&updated), None)?;
+            }
+        }
+    }
+
+    let mut report = PktLineWriter::new(Vec::new());
+
+    match &received {
+        Ok(_) => report.write_line("unpack ok")?,
+        Err(err) => report.write_line(&format!("unpack {}", err.trim_end()))?,
+    }
+
+    for (update, status) in updates.iter().zip(&statuses) {
+        match status {
+            Ok(()) => report.write_line(&format!("ok {}", update.name))?,
+            Err(reason) => report.write_line(&format!("ng {} {}", update.name, reason))?,
+        }
+    }
+
+    report.write_flush()?;
+
+    if !wants("report-status") && !wants("report-status-v2") {
+        return hooks.output.flush();
+    }
+
+    if hooks.sideband {
+        hooks
+            .output
+            .write_sideband(SIDEBAND_DATA, &report.into_inner())?;
+        hooks.output.write_flush()
+    } else {
+        hooks.output.write_unframed(&report.into_inner())?;
+        hooks.output.flush()
+    }
+}
+
+/// Checks and runs the `update` hook for every command still standing, then locks and moves
+/// the refs. Under `atomic` a single refusal refuses every command, and a ref that fails to
+/// move puts back the ones already moved.
+fn apply_updates<W: Write>(
+    hooks: &mut Hooks<'_, W>,
+    updates: &[RefUpdate],
+    statuses: &mut [Result<(), String>],
+    atomic: bool,
+) -> Result<(), String> {
+    let config = load_config(hooks.git_dir)?;
+
+    for (update, status) in updates.iter().zip(statuses.iter_mut()) {
+        if status.is_ok() {
+            *status = check_update(hooks.git_dir, &config, update);
+        }
+
+        if status.is_ok()
+            && !hooks.run(
+                "update",
+                &[&update.name, &update.old, &update.new],
+                b"",
+                None,
+            )?
+        {
+            *status = Err("hook declined".to_string());
+        }
+    }
+
+    // the old value is checked again under the lock, so of two pushes racing for a ref one
+    // fails rather than silently undoing the other
+    let mut locks = Vec::new();
+
+    for (update, status) in updates.iter().zip(statuses.iter_mut()) {
+        if status.is_err() {
+            locks.push(None);
+            continue;
+        }
+
+        let lock = match lock_ref(hooks.git_dir, &update.name) {
+            Ok(lock)
+                if resolve_ref(hooks.git_dir, &update.name)?
+                    .as_deref()
+                    .unwrap_or(NULL_SHA)
+                    == update.old =>
+            {
+                Some(lock)
+            }
+            _ => {
+                *status = Err("failed to update ref".to_string());
+                None
+            }
+        };
+
+        locks.push(lock);
+    }
+
+    if atomic && statuses.iter().any(Result::is_err) {
+        fail_atomic_push(statuses);
+        return Ok(());
+    }
+
+    let mut applied = Vec::new();
+
+    for ((update, status), lock) in updates.iter().zip(statuses.iter_mut()).zip(locks) {
+        let Some(lock) = lock else {
+            continue;
+        };
+
+        let moved = if update.is_delete() {
+            lock.delete()
+        } else {
+            lock.update(&update.new)
+        };
+
+        match moved {
+            Ok(()) => applied.push(update),
+            Err(_) => *status = Err("failed to update ref".to_string()),
+        }
+
+        if atomic && status.is_err() {
+            for update in applied {
+                if update.old == NULL_SHA {
+                    delete_ref(hooks.git_dir, &update.name)?;
+                } else {
+                    update_ref(hooks.git_dir, &update.name, &update.old)?;
+                }
+            }
+
+            fail_atomic_push(statuses);
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Marks every command of an atomic push that was not refused itself as refused for its sake.
+fn fail_atomic_push(statuses: &mut [Result<(), String>]) {
+    for status in statuses.iter_mut().filter(|status| status.is_ok()) {
+        *status = Err("atomic push failure".to_string());
+    }
+}
+
+/// The commands nothing has refused so far.
+fn accepted<'a>(updates: &'a [RefUpdate], statuses: &[Result<(), String>]) -> Vec<&'a RefUpdate> {
+    updates
+        .iter()
+        .zip(statuses)
+        .filter(|(_, status)| status.is_ok())
+        .map(|(update, _)| update)
+        .collect()
+}
+
+/// The `<old> <new> <ref>` lines `pre-receive` and `post-receive` read on stdin.
+fn hook_input(updates: &[&RefUpdate]) -> Vec<u8> {
+    updates
+        .iter()
+        .map(|update| format!("{} {} {}\n", update.old, update.new, update.name))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Refuses a command whose old or new value is not a sha, for a malformed ref name or one
+/// outside `refs/`, or whose new value does not lead to complete history.
+fn check_command(
+    git_dir: &Path,
+    quarantine: Option<&Quarantine>,
+    update: &RefUpdate,
+) -> Result<Result<(), String>, String> {
+    if !is_sha(&update.old) || !is_sha(&update.new) {
+        return Ok(Err("malformed".to_string()));
+    }
+
+    if !update.name.starts_with("refs/") || check_refname_format(&update.name).is_err() {
+        return Ok(Err("funny refname".to_string()));
+    }
+
+    if update.is_delete() || is_connected(git_dir, quarantine, &update.new)? {
+        Ok(Ok(()))
+    } else {
+        Ok(Err("missing necessary objects".to_string()))
+    }
+}
+
+/// Whether everything `new` reaches is there, which upstream's `check_connected` makes sure of
+/// before a ref may point at pushed history. Pushed objects are read from the quarantine and
+/// followed. The walk stops at objects the repository already has, except that commits no ref
+/// reaches have their history walked, as nothing vouches for it.
+fn is_connected(
+    git_dir: &Path,
+    quarantine: Option<&Quarantine>,
+    new: &str,
+) -> Result<bool, String> {
+    // each object comes with whether it may be a commit: trees only lead to trees and blobs
+    let mut stack = vec![(new.to_string(), true)];
+    let mut seen = HashSet::new();
+    let mut boundary_commits = Vec::new();
+
+    while let Some((sha, maybe_commit)) = stack.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+
+        if let Some((object_type, content)) =
+            quarantine.map(|q| q.read(&sha)).transpose()?.flatten()
+        {
+            let links = object_links(&object_type, content)?;
+
+            stack.extend(links.into_iter().enumerate().map(|(idx, link)| {
+                // a commit's tree comes before its parents
+                let maybe_commit = match object_type.as_str() {
+                    "commit" => idx > 0,
+                    "tag" => true,
+                    _ => false,
+                };
+
+                (link, maybe_commit)
+            }));
+        } else if !object_exists(git_dir, &sha)? {
+            return Ok(false);
+        } else if maybe_commit && read_raw_object(git_dir, &sha)?.0 == "commit" {
+            boundary_commits.push(sha);
+        }
+    }
+
+    if boundary_commits.is_empty() {
+        return Ok(true);
+    }
+
+    let walked = (|| -> Result<bool, String> {
+        let mut walk = RevWalk::new(git_dir)?;
+
+        for sha in &boundary_commits {
+            walk.push(sha)?;
+        }
+
+        for (_, sha) in list_refs(git_dir)? {
+            walk.hide(&sha)?;
+        }
+
+        for commit in walk {
+            if !object_exists(git_dir, &commit?.tree)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    })();
+
+    // a commit missing from the history is a hole, not a failure of the push as a whole
+    Ok(walked.unwrap_or(false))
+}
+
+/// Whether one ref may move, or why not in the words `report-status` uses.
+fn check_update(git_dir: &Path, config: &Config, update: &RefUpdate) -> Result<(), String> {
+    let checked_out = config.get_bool("core.bare")? != Some(true)
+        && read_symref(git_dir, "HEAD")?.as_deref() == Some(update.name.as_str());
+    let current = resolve_ref(git_dir, &update.name)?;
+
+    // the pusher's idea of the old value is how a lease is enforced
+    if current.as_deref().unwrap_or(NULL_SHA) != update.old {
+        return Err("failed to update ref".to_string());
+    }
+
+    if update.is_delete() {
+        if checked_out {
+            return Err("deletion of the current branch prohibited".to_string());
+        }
+
+        return Ok(());
+    }
+
+    if checked_out {
+        return Err("branch is currently checked out".to_string());
+    }
+
+    if config.get_bool("receive.denyNonFastForwards")? == Some(true)
+        && update.old != NULL_SHA
+        && !RevWalk::is_ancestor(git_dir, &update.old, &update.new)?
+    {
+        return Err("non-fast-forward".to_string());
+    }
+
+    Ok(())
+}
+
+/// Objects received in a push, kept in their own object directory until the `pre-receive` hook
+/// has accepted them so a declined push leaves nothing behind in the repository.
+struct Quarantine {
+    dir: PathBuf,
+    pack: Pack,
+    /// Where each pushed object is in `pack`.
+    objects: HashMap<String, u64>,
+}
+
+impl Quarantine {
+    /// Reads a pushed object, or `None` when it was not in the push.
+    fn read(&self, sha: &str) -> Result<Option<RawObject>, String> {
+        let Some(&offset) = self.objects.get(sha) else {
+            return Ok(None);
+        };
+
+        // the pack was completed, so every delta base is in it
+        self.pack
+            .read_object_at(offset, &|base| {
+                self.read(base)?
+                    .ok_or_else(|| format!("pack has a delta with missing base {}", base))
+            })
+            .map(Some)
+    }
+
+    /// Moves the pack into `objects/pack`, the pack before its index so no reader finds an
+    /// index without its pack.
+    fn migrate(&self, git_dir: &Path) -> Result<(), String> {
+        let source = self.dir.join("pack");
+        let target = pack_dir(git_dir);
+        let mut names: Vec<PathBuf> = fs::read_dir(&source)
+            .map_err(|err| format!("error reading {:?}: {}", source, err))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+
+        names.sort_by_key(|path| path.extension().is_some_and(|ext| ext == "idx"));
+        fs::create_dir_all(&target).map_err(|err| err.to_string())?;
+
+        for path in names {
+            let destination = target.join(path.file_name().unwrap_or_default());
+
+            fs::rename(&path, &destination)
+                .map_err(|err| format!("error moving {:?}: {}", path, err))?;
+        }
+
+        self.discard()
+    }
+
+    fn discard(&self) -> Result<(), String> {
+        fs::remove_dir_all(&self.dir)
+            .map_err(|err| format!("error removing {:?}: {}", self.dir, err))
+    }
+}
+
+/// Verifies, completes and indexes a pushed pack into a new quarantine directory. An empty
+/// pack, as sent when every object is already there, needs no quarantine.
+fn quarantine_pack(git_dir: &Path, data: Vec<u8>) -> Result<Option<Quarantine>, String> {
+    let pack = Pack::from_bytes(data)?;
+
+    pack.verify_checksum()?;
+
+    if pack.num_objects() == 0 {
+        return Ok(None);
+    }
+
+    // pushers send thin packs whether or not they were offered
+    let (pack, entries) = pack.fix_thin(&|sha| match read_raw_object(git_dir, sha) {
+        Ok(object) => Ok(Some(object)),
+        Err(_) => Ok(None),
+    })?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| err.to_string())?
+        .subsec_nanos();
+    let dir = git_dir
+        .join("objects")
+        .join(format!("incoming-{}-{}", process::id(), nanos));
+    let objects = entries
+        .iter()
+        .map(|entry| (entry.sha.clone(), entry.offset))
+        .collect();
+
+    if let Err(err) = write_pack_files_to(&dir.join("pack"), pack.as_bytes(), entries) {
+        fs::remove_dir_all(&dir).map_err(|err| format!("error removing {:?}: {}", dir, err))?;
+        return Err(err);
+    }
+
+    let quarantine = Quarantine { dir, pack, objects };
+
+    Ok(Some(quarantine))
+}
+
+/// Runs the repository's hooks, passing what they print on to the pusher.
+struct Hooks<'a, W: Write> {
+    git_dir: &'a Path,
+    sideband: bool,
+    output: &'a mut PktLineWriter<W>,
+}
+
+impl<W: Write> Hooks<'_, W> {
+    /// Runs `hooks/<name>` if it is there and executable, returning whether it succeeded. A hook
+    /// that runs before the objects are migrated gets the quarantine as its object directory,
+    /// with the repository's objects as an alternate, as upstream hooks expect.
+    fn run(
+        &mut self,
+        name: &str,
+        args: &[&str],
+        input: &[u8],
+        quarantine: Option<&Quarantine>,
+    ) -> Result<bool, String> {
+        let path = self.git_dir.join("hooks").join(name);
+
+        if !is_executable(&path) {
+            return Ok(true);
+        }
+
+        let git_dir = fs::canonicalize(self.git_dir).map_err(|err| err.to_string())?;
+        let bare = load_config(&git_dir)?.get_bool("core.bare")? == Some(true);
+        let mut command = Command::new(&path);
+
+        command
+            .args(args)
+            .env("GIT_DIR", &git_dir)
+            .current_dir(match git_dir.parent() {
+                Some(work_tree) if !bare => work_tree,
+                _ => &git_dir,
+            })
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(quarantine) = quarantine {
+            let dir = fs::canonicalize(&quarantine.dir).map_err(|err| err.to_string())?;
+
+            command
+                .env("GIT_QUARANTINE_PATH", &dir)
+                .env("GIT_OBJECT_DIRECTORY", &dir)
+                .env("GIT_ALTERNATE_OBJECT_DIRECTORIES", git_dir.join("objects"));
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("error running {} hook: {}", name, err))?;
+
+        let stdin = child.stdin.take();
+        // the input goes in from its own thread, as a hook may print a lot before reading it
+        let result = thread::scope(|scope| {
+            if let Some(mut stdin) = stdin {
+                // a hook that ignores its input may exit before reading it
+                scope.spawn(move || {
+                    let _ = stdin.write_all(input);
+                });
+            }
+
+            child.wait_with_output()
+        })
+        .map_err(|err| format!("error running {} hook: {}", name, err))?;
+        let printed = [result.stdout, result.stderr].concat();
+
+        if !printed.is_empty() {
+            if self.sideband {
+                self.output.write_sideband(SIDEBAND_PROGRESS, &printed)?;
+            } else {
+                stderr()
+                    .write_all(&printed)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+
+        Ok(result.status.success())
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+// without an executable bit, any hook file counts
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::models::pack::write_pack;
+
+    fn serve(git_dir: &Path, commands: &[String], pack: &[u8]) -> Vec<String> {
+        let mut request = PktLineWriter::new(Vec::new());
+
+        for (idx, command) in commands.iter().enumerate() {
+            if idx == 0 {
+                request
+                    .write_line(&format!("{}\0report-status atomic", command))
+                    .unwrap();
+            } else {
+                request.write_line(command).unwrap();
+            }
+        }
+
+        request.write_flush().unwrap();
+        request.write_unframed(pack).unwrap();
+
+        let request = request.into_inner();
+        let mut response = PktLineWriter::new(Vec::new());
+
+        serve_receive_pack(
+            git_dir,
+            PktLineReader::new(request.as_slice()),
+            &mut response,
+        )
+        .unwrap();
+
+        let response = response.into_inner();
+
+        PktLineReader::new(response.as_slice())
+            .read_lines()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serve_receive_pack_keeps_objects_out_until_pre_receive_accepts() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let client = init_git_dir();
+        let blob = write_blob(client.path(), "pushed\n");
+        let tree = write_tree(client.path(), &[("100644", "file", &blob)]);
+        let commit = write_commit(client.path(), &tree, &[], 100, "pushed");
+        let objects = [&commit, &tree, &blob]
+            .iter()
+            .map(|sha| read_raw_object(client.path(), sha))
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap();
+        let (pack, _) = write_pack(&objects).unwrap();
+        let git_dir = init_git_dir();
+        let hook = git_dir.path().join("hooks/pre-receive");
+        let commands = [format!("{} {} refs/heads/topic", NULL_SHA, commit)];
+
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &pack),
+            vec!["unpack ok", "ng refs/heads/topic pre-receive hook declined"]
+        );
+        assert!(!object_exists(git_dir.path(), &commit).unwrap());
+        assert_eq!(
+            fs::read_dir(git_dir.path().join("objects"))
+                .unwrap()
+                .filter(|entry| entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("incoming-"))
+                .count(),
+            0
+        );
+
+        fs::write(&hook, "#!/bin/sh\nexit 0\n").unwrap();
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &pack),
+            vec!["unpack ok", "ok refs/heads/topic"]
+        );
+        assert!(object_exists(git_dir.path(), &blob).unwrap());
+        assert_eq!(
+            resolve_ref(git_dir.path(), "refs/heads/topic").unwrap(),
+            Some(commit)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_may_print_more_than_a_pipe_holds_before_reading_their_input() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let git_dir = init_git_dir();
+        let hook = git_dir.path().join("hooks/pre-receive");
+        let mut request = PktLineWriter::new(Vec::new());
+
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(
+            &hook,
+            "#!/bin/sh\nhead -c 200000 /dev/zero\ncat >/dev/null\nexit 1\n",
+        )
+        .unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // more than a pipe holds both ways, with the hook's output on the side-band
+        for idx in 0..1000 {
+            let command = format!("{} {} refs/heads/branch-{}", "1".repeat(40), NULL_SHA, idx);
+
+            if idx == 0 {
+                request
+                    .write_line(&format!("{}\0side-band-64k", command))
+                    .unwrap();
+            } else {
+                request.write_line(&command).unwrap();
+            }
+        }
+
+        request.write_flush().unwrap();
+
+        let request = request.into_inner();
+        let mut response = PktLineWriter::new(Vec::new());
+
+        serve_receive_pack(
+            git_dir.path(),
+            PktLineReader::new(request.as_slice()),
+            &mut response,
+        )
+        .unwrap();
+
+        assert!(response.into_inner().len() > 200000);
+    }
+
+    #[test]
+    fn serve_receive_pack_refuses_every_update_of_a_failed_atomic_push() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/a", &commit);
+        write_ref(git_dir.path(), "refs/heads/b", &commit);
+
+        let commands = [
+            format!("{} {} refs/heads/a", "1".repeat(40), NULL_SHA),
+            format!("{} {} refs/heads/b", commit, NULL_SHA),
+        ];
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &[]),
+            vec![
+                "unpack ok",
+                "ng refs/heads/a failed to update ref",
+                "ng refs/heads/b atomic push failure"
+            ]
+        );
+        assert_eq!(
+            resolve_ref(git_dir.path(), "refs/heads/b").unwrap(),
+            Some(commit)
+        );
+    }
+
+    #[test]
+    fn serve_receive_pack_refuses_values_that_are_not_shas() {
+        let git_dir = init_git_dir();
+        let (empty_pack, _) = write_pack(&[]).unwrap();
+        // 40 bytes, but not 40 hex digits
+        let commands = [format!("{} aé{} refs/heads/main", NULL_SHA, "0".repeat(37))];
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &empty_pack),
+            vec!["unpack ok", "ng refs/heads/main malformed"]
+        );
+    }
+
+    #[test]
+    fn serve_receive_pack_leaves_a_ref_someone_else_has_locked() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let lock = git_dir.path().join("refs/heads/topic.lock");
+        write_ref(git_dir.path(), "refs/heads/topic", &first);
+        fs::write(&lock, format!("{}\n", first)).unwrap();
+
+        let (empty_pack, _) = write_pack(&[]).unwrap();
+        let commands = [format!("{} {} refs/heads/topic", first, second)];
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &empty_pack),
+            vec!["unpack ok", "ng refs/heads/topic failed to update ref"]
+        );
+        assert!(lock.exists());
+
+        fs::remove_file(&lock).unwrap();
+
+        assert_eq!(
+            serve(git_dir.path(), &commands, &empty_pack),
+            vec!["unpack ok", "ok refs/heads/topic"]
+        );
+        assert_eq!(
+            resolve_ref(git_dir.path(), "refs/heads/topic").unwrap(),
+            Some(second)
+        );
+        assert!(!lock.exists());
+    }
+
+    #[test]
+    fn serve_receive_pack_refuses_funny_refnames_and_incomplete_history() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let base = write_commit(git_dir.path(), &tree, &[], 100, "base");
+        let orphan = write_commit(
+            git_dir.path(),
+            &tree,
+            &["1".repeat(40).as_str()],
+            200,
+            "orphan",
+        );
+        write_ref(git_dir.path(), "refs/heads/main", &base);
+
+        let (empty_pack, _) = write_pack(&[]).unwrap();
+        let escaped = [format!("{} {} refs/../../escaped", NULL_SHA, base)];
+
+        assert_eq!(
+            serve(git_dir.path(), &escaped, &empty_pack),
+            vec!["unpack ok", "ng refs/../../escaped funny refname"]
+        );
+        assert!(!git_dir.path().join("../escaped").exists());
+
+        let from_orphan = [format!("{} {} refs/heads/orphan", NULL_SHA, orphan)];
+
+        assert_eq!(
+            serve(git_dir.path(), &from_orphan, &empty_pack),
+            vec![
+                "unpack ok",
+                "ng refs/heads/orphan missing necessary objects"
+            ]
+        );
+
+        let client = init_git_dir();
+        let missing_blob = write_blob(client.path(), "never sent\n");
+        let holey_tree = write_tree(client.path(), &[("100644", "file", &missing_blob)]);
+        let holey = write_commit(client.path(), &holey_tree, &[&base], 300, "holey");
+        let complete = write_commit(client.path(), &tree, &[&base], 300, "complete");
+        let objects = [&holey, &holey_tree, &complete]
+            .iter()
+            .map(|sha| read_raw_object(client.path(), sha))
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap();
+        let (pack, _) = write_pack(&objects).unwrap();
+
+        assert_eq!(
+            serve(
+                git_dir.path(),
+                &[format!("{} {} refs/heads/holey", NULL_SHA, holey)],
+                &pack
+            ),
+            vec!["unpack ok", "ng refs/heads/holey missing necessary objects"]
+        );
+        assert_eq!(
+            serve(
+                git_dir.path(),
+                &[format!("{} {} refs/heads/complete", NULL_SHA, complete)],
+                &pack
+            ),
+            vec!["unpack ok", "ok refs/heads/complete"]
+        );
+    }
+}
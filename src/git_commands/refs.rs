@@ -0,0 +1,667 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::{is_sha, write_atomically};
+
+const MAX_SYMREF_DEPTH: usize = 5;
+/// Fetch and push pad their ref summary column, as upstream does, to fit two abbreviated shas
+/// and `...`.
+pub const SUMMARY_WIDTH: usize = 17;
+const ABBREV: usize = 7;
+
+/// Resolves a full ref name (or `HEAD`) to a sha, following symbolic refs.
+pub fn resolve_ref(git_dir: &Path, name: &str) -> Result<Option<String>, String> {
+    let mut name = name.to_string();
+
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let ref_path = git_dir.join(&name);
+
+        if ref_path.is_file() {
+            let content = fs::read_to_string(&ref_path)
+                .map_err(|err| format!("error reading ref {}: {}", name, err))?;
+            let content = content.trim();
+
+            match content.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => return Ok(Some(content.to_string())),
+            }
+
+            continue;
+        }
+
+        return Ok(read_packed_refs(git_dir)?
+            .into_iter()
+            .find(|(ref_name, _)| *ref_name == name)
+            .map(|(_, sha)| sha));
+    }
+
+    Err(format!("too many levels of symbolic refs at {}", name))
+}
+
+/// Returns `(name, sha)` pairs listed in `packed-refs`, skipping peeled `^` lines.
+pub fn read_packed_refs(git_dir: &Path) -> Result<Vec<(String, String)>, String> {
+    let packed_refs_path = git_dir.join("packed-refs");
+
+    if !packed_refs_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&packed_refs_path)
+        .map_err(|err| format!("error reading packed-refs: {}", err))?;
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(sha, name)| (name.to_string(), sha.to_string()))
+        .collect())
+}
+
+/// Lists every ref under `refs/`, loose refs taking precedence over packed ones, sorted by name.
+pub fn list_refs(git_dir: &Path) -> Result<Vec<(String, String)>, String> {
+    let mut refs = read_packed_refs(git_dir)?;
+    let mut loose_refs = Vec::new();
+
+    collect_loose_refs(git_dir, "refs", &mut loose_refs)?;
+
+    for (name, sha) in loose_refs {
+        refs.retain(|(ref_name, _)| *ref_name != name);
+        refs.push((name, sha));
+    }
+
+    refs.sort();
+
+    Ok(refs)
+}
+
+/// Returns the shas of every ref plus `HEAD`: the starting points for whole-repository walks.
+pub fn ref_tips(git_dir: &Path) -> Result<Vec<String>, String> {
+    let mut tips: Vec<String> = list_refs(git_dir)?
+        .into_iter()
+        .map(|(_, sha)| sha)
+        .collect();
+
+    if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+        tips.push(head);
+    }
+
+    Ok(tips)
+}
+
+/// Returns every sha recorded in the reflogs (`logs/HEAD` and `logs/refs/...`), old and new,
+/// skipping the null sha of ref creations and deletions.
+pub fn reflog_shas(git_dir: &Path) -> Result<Vec<String>, String> {
+    let mut log_files = Vec::new();
+    let mut dirs = vec![git_dir.join("logs")];
+    let mut shas = Vec::new();
+
+    while let Some(dir) = dirs.pop() {
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let entries =
+            fs::read_dir(&dir).map_err(|err| format!("error reading {:?}: {}", dir, err))?;
+
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                log_files.push(path);
+            }
+        }
+    }
+
+    for path in log_files {
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("error reading {:?}: {}", path, err))?;
+
+        for line in content.lines() {
+            let mut fields = line.split(' ');
+
+            for sha in [fields.next(), fields.next()].into_iter().flatten() {
+                if sha.len() == 40 && sha.bytes().any(|byte| byte != b'0') {
+                    shas.push(sha.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(shas)
+}
+
+/// Returns the target of the symbolic ref `name`, or `None` when it is a direct ref or missing.
+pub fn read_symref(git_dir: &Path, name: &str) -> Result<Option<String>, String> {
+    let path = git_dir.join(name);
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content =
+        fs::read_to_string(&path).map_err(|err| format!("error reading ref {}: {}", name, err))?;
+
+    Ok(content
+        .trim()
+        .strip_prefix("ref: ")
+        .map(|target| target.to_string()))
+}
+
+/// The branch `HEAD` points at, without `refs/heads/`; `None` when `HEAD` is detached.
+pub fn current_branch(git_dir: &Path) -> Result<Option<String>, String> {
+    Ok(read_symref(git_dir, "HEAD")?.and_then(|target| {
+        target
+            .strip_prefix("refs/heads/")
+            .map(|branch| branch.to_string())
+    }))
+}
+
+/// The first `ABBREV` characters of a sha for the ref summary, or the whole value when it is
+/// not a sha, so a ref file holding anything else cannot make the summary panic.
+pub fn abbrev(sha: &str) -> &str {
+    if is_sha(sha) {
+        &sha[..ABBREV]
+    } else {
+        sha
+    }
+}
+
+/// A ref name without its `refs/heads/`, `refs/tags/`, `refs/remotes/` or `refs/` prefix.
+pub fn shorten_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Checks `name` against upstream's `check_refname_format` rules, which also keep the ref's file
+/// inside the git directory once the name is joined onto it: no component may be empty, start with `.`
+/// or end in `.lock`, and the name may not contain `..`, `@{`, control characters, a space or
+/// any of `~^:?*[\`, nor end in `/` or `.`.
+pub fn check_refname_format(name: &str) -> Result<(), String> {
+    let bad_component = name.split('/').any(|component| {
+        component.is_empty() || component.starts_with('.') || component.ends_with(".lock")
+    });
+    let bad_char = name
+        .chars()
+        .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+
+    if name.is_empty()
+        || name == "@"
+        || bad_component
+        || bad_char
+        || name.contains("..")
+        || name.contains("@{")
+        || name.ends_with('.')
+    {
+        return Err(format!("invalid ref name '{}'", name));
+    }
+
+    Ok(())
+}
+
+/// Points the loose ref `name` (e.g. `refs/heads/main`) at `sha`.
+pub fn update_ref(git_dir: &Path, name: &str, sha: &str) -> Result<(), String> {
+    write_ref_file(git_dir, name, &format!("{}\n", sha))
+}
+
+/// Makes `name` a symbolic ref to `target`, like `HEAD` pointing at `refs/heads/main`.
+pub fn update_symref(git_dir: &Path, name: &str, target: &str) -> Result<(), String> {
+    write_ref_file(git_dir, name, &format!("ref: {}\n", target))
+}
+
+fn write_ref_file(git_dir: &Path, name: &str, content: &str) -> Result<(), String> {
+    check_refname_format(name)?;
+
+    let path = git_dir.join(name);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("error creating {:?}: {}", parent, err))?;
+    }
+
+    write_atomically(&path, content.as_bytes())
+}
+
+/// A ref held through its `<ref>.lock` file, which is created only if it is not there so two
+/// writers cannot both hold it. Dropping the lock without writing leaves the ref as it was.
+pub struct RefLock {
+    git_dir: PathBuf,
+    name: String,
+    lock_path: PathBuf,
+    file: File,
+    released: bool,
+}
+
+/// Locks the ref `name` (which need not exist yet) for writing.
+pub fn lock_ref(git_dir: &Path, name: &str) -> Result<RefLock, String> {
+    check_refname_format(name)?;
+
+    let path = git_dir.join(name);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("error creating {:?}: {}", parent, err))?;
+    }
+
+    let mut lock_path = path.into_os_string();
+
+    lock_path.push(".lock");
+
+    let lock_path = PathBuf::from(lock_path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .map_err(|err| format!("cannot lock ref '{}': {:?}: {}", name, lock_path, err))?;
+
+    Ok(RefLock {
+        git_dir: git_dir.to_path_buf(),
+        name: name.to_string(),
+        lock_path,
+        file,
+        released: false,
+    })
+}
+
+impl RefLock {
+    /// Points the ref at `sha` by moving the lock file over it.
+    pub fn update(mut self, sha: &str) -> Result<(), String> {
+        writeln!(self.file, "{}", sha)
+            .map_err(|err| format!("error writing {:?}: {}", self.lock_path, err))?;
+        fs::rename(&self.lock_path, self.git_dir.join(&self.name))
+            .map_err(|err| format!("error writing {}: {}", self.name, err))?;
+        self.released = true;
+
+        Ok(())
+    }
+
+    /// Deletes the ref, then lets go of the lock.
+    pub fn delete(self) -> Result<(), String> {
+        delete_ref(&self.git_dir, &self.name)
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Deletes the ref `name`, both its loose file and its `packed-refs` entry.
+pub fn delete_ref(git_dir: &Path, name: &str) -> Result<(), String> {
+    check_refname_format(name)?;
+
+    let path = git_dir.join(name);
+
+    if path.is_file() {
+        fs::remove_file(&path).map_err(|err| format!("error removing {}: {}", name, err))?;
+    }
+
+    let packed_refs_path = git_dir.join("packed-refs");
+
+    if !packed_refs_path.exists() {
+        return Ok(());
+    }
+
+    let content = fs::read_to_string(&packed_refs_path)
+        .map_err(|err| format!("error reading packed-refs: {}", err))?;
+    let mut kept = String::new();
+    let mut deleting = false;
+
+    for line in content.lines() {
+        // a peeled line belongs to the ref above it
+        if line.starts_with('^') && deleting {
+            continue;
+        }
+
+        deleting = line
+            .split_once(' ')
+            .is_some_and(|(_, ref_name)| ref_name == name);
+
+        if !deleting {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+
+    if kept != content {
+        write_atomically(&packed_refs_path, kept.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Moves every loose ref under `refs/` into `packed-refs`, recording the peeled target of
+/// annotated tags, and deletes the loose files. Symbolic refs stay loose.
+pub fn pack_refs(git_dir: &Path) -> Result<(), String> {
+    let mut loose_refs = Vec::new();
+
+    collect_direct_ref_files(git_dir, "refs", &mut loose_refs)?;
+
+    let mut refs = read_packed_refs(git_dir)?;
+
+    for (name, sha) in &loose_refs {
+        refs.retain(|(ref_name, _)| ref_name != name);
+        refs.push((name.clone(), sha.clone()));
+    }
+
+    refs.sort();
+
+    let mut content = "# pack-refs with: peeled fully-peeled sorted \n".to_string();
+
+    for (name, sha) in &refs {
+        content.push_str(&format!("{} {}\n", sha, name));
+
+        let peeled = RevWalk::peel(git_dir, sha)?;
+
+        if peeled != *sha {
+            content.push_str(&format!("^{}\n", peeled));
+        }
+    }
+
+    write_atomically(&git_dir.join("packed-refs"), content.as_bytes())?;
+
+    for (name, sha) in loose_refs {
+        let path = git_dir.join(&name);
+
+        // leave refs that were updated while packing
+        if fs::read_to_string(&path).is_ok_and(|content| content.trim() == sha) {
+            fs::remove_file(&path).map_err(|err| format!("error removing {}: {}", name, err))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_direct_ref_files(
+    git_dir: &Path,
+    prefix: &str,
+    refs: &mut Vec<(String, String)>,
+) -> Result<(), String> {
+    let dir = git_dir.join(prefix);
+
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(&dir).map_err(|err| format!("error reading {}: {}", prefix, err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+
+        if entry.path().is_dir() {
+            collect_direct_ref_files(git_dir, &name, refs)?;
+            continue;
+        }
+
+        let content = fs::read_to_string(entry.path())
+            .map_err(|err| format!("error reading ref {}: {}", name, err))?;
+
+        if !content.starts_with("ref: ") {
+            refs.push((name, content.trim().to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_loose_refs(
+    git_dir: &Path,
+    prefix: &str,
+    refs: &mut Vec<(String, String)>,
+) -> Result<(), String> {
+    let dir = git_dir.join(prefix);
+
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(&dir).map_err(|err| format!("error reading {}: {}", prefix, err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+
+        if entry.path().is_dir() {
+            collect_loose_refs(git_dir, &name, refs)?;
+        } else if let Some(sha) = resolve_ref(git_dir, &name)? {
+            refs.push((name, sha));
+        }
+    }
+
+    Ok(())
+}
+
+/// An empty side of `a..b` stands for `HEAD`.
+pub fn or_head(revision: &str) -> &str {
+    if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    }
+}
+
+/// Resolves a revision given on the command line: a full sha, `HEAD` or a (possibly short) ref name.
+pub fn resolve_revision(git_dir: &Path, revision: &str) -> Result<String, String> {
+    if revision.len() == 40 && revision.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(revision.to_lowercase());
+    }
+
+    match expand_ref_name(git_dir, revision)? {
+        Some((_, sha)) => Ok(sha),
+        None => Err(format!("unknown revision: {}", revision)),
+    }
+}
+
+/// The full name and sha of the ref a (possibly short) ref name stands for, trying the same
+/// places upstream does in order.
+pub fn expand_ref_name(git_dir: &Path, name: &str) -> Result<Option<(String, String)>, String> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+
+    for candidate in candidates {
+        if let Some(sha) = resolve_ref(git_dir, &candidate)? {
+            return Ok(Some((candidate, sha)));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_commit, write_object, write_ref, write_tree,
+    };
+
+    const SHA1: &str = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+    const SHA2: &str = "943a702d06f34599aee1f8da8ef9f7296031d699";
+
+    fn setup_git_dir() -> tempfile::TempDir {
+        let git_dir = tempfile::tempdir().unwrap();
+
+        fs::create_dir_all(git_dir.path().join("refs/heads")).unwrap();
+        fs::write(git_dir.path().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            git_dir.path().join("refs/heads/main"),
+            format!("{}\n", SHA1),
+        )
+        .unwrap();
+        fs::write(
+            git_dir.path().join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled\n{} refs/tags/v1\n^{}\n{} refs/heads/main\n",
+                SHA2, SHA1, SHA2
+            ),
+        )
+        .unwrap();
+
+        git_dir
+    }
+
+    #[test]
+    fn abbrev_shortens_only_shas() {
+        assert_eq!(abbrev(SHA1), "2aae6c3");
+        assert_eq!(abbrev("2aae6c"), "2aae6c");
+        assert_eq!(abbrev(&format!("aé{}", "0".repeat(37))).len(), 40);
+    }
+
+    #[test]
+    fn resolve_ref_follows_symbolic_head() {
+        let git_dir = setup_git_dir();
+
+        assert_eq!(
+            resolve_ref(git_dir.path(), "HEAD").unwrap(),
+            Some(SHA1.to_string())
+        );
+    }
+
+    #[test]
+    fn resolve_ref_falls_back_to_packed_refs() {
+        let git_dir = setup_git_dir();
+
+        assert_eq!(
+            resolve_ref(git_dir.path(), "refs/tags/v1").unwrap(),
+            Some(SHA2.to_string())
+        );
+        assert_eq!(resolve_ref(git_dir.path(), "refs/tags/v2").unwrap(), None);
+    }
+
+    #[test]
+    fn list_refs_prefers_loose_refs() {
+        let git_dir = setup_git_dir();
+
+        assert_eq!(
+            list_refs(git_dir.path()).unwrap(),
+            vec![
+                ("refs/heads/main".to_string(), SHA1.to_string()),
+                ("refs/tags/v1".to_string(), SHA2.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_revision_expands_short_names() {
+        let git_dir = setup_git_dir();
+
+        assert_eq!(resolve_revision(git_dir.path(), "main").unwrap(), SHA1);
+        assert_eq!(resolve_revision(git_dir.path(), "v1").unwrap(), SHA2);
+        assert!(resolve_revision(git_dir.path(), "missing").is_err());
+    }
+
+    #[test]
+    fn reflog_shas_skips_null_shas() {
+        let git_dir = setup_git_dir();
+        let null = "0".repeat(40);
+
+        fs::create_dir_all(git_dir.path().join("logs/refs/heads")).unwrap();
+        fs::write(
+            git_dir.path().join("logs/refs/heads/main"),
+            format!(
+                "{} {} A <a@example.com> 100 +0000\tcommit (initial): one\n\
+                 {} {} A <a@example.com> 200 +0000\tcommit: two\n",
+                null, SHA2, SHA2, SHA1
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            reflog_shas(git_dir.path()).unwrap(),
+            vec![SHA2.to_string(), SHA2.to_string(), SHA1.to_string()]
+        );
+    }
+
+    #[test]
+    fn check_refname_format_refuses_names_that_leave_refs() {
+        for name in [
+            "HEAD",
+            "refs/heads/main",
+            "refs/tags/v1.0",
+            "refs/heads/a-b_c@d",
+        ] {
+            assert!(check_refname_format(name).is_ok(), "{}", name);
+        }
+
+        for name in [
+            "refs/../../escaped",
+            "refs/heads/.hidden",
+            "refs/heads/main.lock",
+            "refs/heads//main",
+            "refs/heads/main/",
+            "refs/heads/main.",
+            "refs/heads/a b",
+            "refs/heads/a\tb",
+            "refs/heads/a~1",
+            "refs/heads/a^{}",
+            "refs/heads/a:b",
+            "refs/heads/a?",
+            "refs/heads/a*",
+            "refs/heads/a[b",
+            "refs/heads/a\\b",
+            "refs/heads/a@{1}",
+            "/refs/heads/main",
+            "@",
+            "",
+        ] {
+            assert!(check_refname_format(name).is_err(), "{}", name);
+        }
+
+        let git_dir = init_git_dir();
+
+        assert!(update_ref(git_dir.path(), "refs/../../escaped", SHA1).is_err());
+        assert!(!git_dir.path().join("../escaped").exists());
+    }
+
+    #[test]
+    fn pack_refs_moves_loose_refs_and_peels_tags() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let tag = write_object(
+            git_dir.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 100 +0000\n\nv1\n",
+                commit
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+        write_ref(git_dir.path(), "refs/tags/v1", &tag);
+        fs::create_dir_all(git_dir.path().join("refs/remotes/origin")).unwrap();
+        fs::write(
+            git_dir.path().join("refs/remotes/origin/HEAD"),
+            "ref: refs/heads/main\n",
+        )
+        .unwrap();
+
+        pack_refs(git_dir.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(git_dir.path().join("packed-refs")).unwrap(),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{} refs/heads/main\n{} refs/tags/v1\n^{}\n",
+                commit, tag, commit
+            )
+        );
+        assert!(!git_dir.path().join("refs/heads/main").exists());
+        assert!(git_dir.path().join("refs/remotes/origin/HEAD").exists());
+        assert_eq!(resolve_revision(git_dir.path(), "v1").unwrap(), tag);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::bitmaps::write_bitmap_index;
+use crate::git_commands::multi_pack_index::remove_multi_pack_index;
+use crate::git_commands::object_walk::reachable_objects;
+use crate::git_commands::packs::{load_packs, pack_dir, write_pack_files, PackFile};
+use crate::git_commands::promisor::{is_promisor_pack, mark_promisor_pack};
+use crate::git_commands::prune::prune_packed;
+use crate::git_commands::utils::{
+    get_object_path_in, read_raw_object, write_atomically, write_object,
+};
+use crate::models::pack::write_pack;
+
+const USAGE: &str = "usage: git repack [-a | -A] [-d] [-b]";
+
+/// Packs the reachable objects (see `reachable_objects`). Without `-a` only objects that are not
+/// packed yet go into the new pack; with `-a` everything does, and `-d` then deletes the old
+/// packs. `-A` is `-a`, except that unreachable objects from the deleted packs are written out
+/// loose so that `prune` can expire them later. `-d` also removes loose objects that ended up
+/// packed. `-b` writes a bitmap, which needs `-a` or `-A`. With `-a`, a partial clone keeps the
+/// objects of its `.promisor` packs apart, in a new pack marked `.promisor` too, so that what
+/// they point at stays promised.
+pub fn repack<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut all = false;
+    let mut unpack_unreachable = false;
+    let mut delete = false;
+    let mut write_bitmap = false;
+
+    for arg in args {
+        let flags = arg
+            .strip_prefix('-')
+            .filter(|flags| !flags.is_empty() && !flags.starts_with('-'))
+            .ok_or(USAGE)?;
+
+        for flag in flags.chars() {
+            match flag {
+                'a' => all = true,
+                'A' => {
+                    all = true;
+                    unpack_unreachable = true;
+                }
+                'd' => delete = true,
+                'b' => write_bitmap = true,
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+    }
+
+    if write_bitmap && !all {
+        return Err(
+            "repack: -b needs -a or -A, since a bitmap must cover everything reachable".to_string(),
+        );
+    }
+
+    let old_packs = load_packs(git_dir)?;
+    let mut promised: Vec<String> = Vec::new();
+    let mut promised_shas: HashSet<&str> = HashSet::new();
+
+    if all {
+        for pack_file in &old_packs {
+            if is_promisor_pack(git_dir, &pack_file.name) {
+                promised.extend(
+                    pack_file
+                        .index
+                        .entries
+                        .iter()
+                        .filter(|entry| promised_shas.insert(entry.sha.as_str()))
+                        .map(|entry| entry.sha.clone()),
+                );
+            }
+        }
+    }
+
+    let objects: Vec<String> = reachable_objects(git_dir)?
+        .into_iter()
+        .filter(|sha| {
+            if all {
+                !promised_shas.contains(sha.as_str())
+            } else {
+                old_packs
+                    .iter()
+                    .all(|pack_file| pack_file.index.position(sha).is_none())
+            }
+        })
+        .collect();
+
+    if objects.is_empty() && promised.is_empty() {
+        return writeln!(writer, "Nothing new to pack.").map_err(|err| err.to_string());
+    }
+
+    let mut new_packs = Vec::new();
+
+    if !promised.is_empty() {
+        let name = pack_objects(git_dir, &promised)?;
+
+        mark_promisor_pack(git_dir, &name)?;
+        new_packs.push(name);
+    }
+
+    if !objects.is_empty() {
+        let name = pack_objects(git_dir, &objects)?;
+
+        if write_bitmap && !promised.is_empty() {
+            // what the promised objects point at may be missing, so nothing could be closed
+            eprintln!("warning: disabling bitmap writing, as some objects are not being packed");
+        } else if write_bitmap {
+            let pack_file = load_packs(git_dir)?
+                .into_iter()
+                .find(|pack_file| pack_file.name == name)
+                .ok_or_else(|| format!("pack {} disappeared", name))?;
+            let pack_order: Vec<String> = pack_file
+                .index
+                .pack_order()
+                .into_iter()
+                .map(|idx| pack_file.index.entries[idx].sha.clone())
+                .collect();
+            let bitmap = write_bitmap_index(git_dir, &pack_order, &pack_file.pack.checksum())?;
+
+            write_atomically(&pack_dir(git_dir).join(format!("{}.bitmap", name)), &bitmap)?;
+        }
+
+        new_packs.push(name);
+    }
+
+    if delete {
+        if all {
+            let packed: HashSet<&str> = objects
+                .iter()
+                .chain(&promised)
+                .map(|sha| sha.as_str())
+                .collect();
+            let redundant: Vec<&PackFile> = old_packs
+                .iter()
+                .map(|pack_file| pack_file.as_ref())
+                .filter(|pack_file| !new_packs.contains(&pack_file.name))
+                .collect();
+
+            if !redundant.is_empty() {
+                remove_multi_pack_index(git_dir)?;
+            }
+
+            for pack_file in redundant {
+                if unpack_unreachable {
+                    unpack_objects(git_dir, pack_file, &packed)?;
+                }
+
+                remove_pack(git_dir, &pack_file.name)?;
+            }
+        }
+
+        prune_packed(git_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Writes `shas` into a new pack, returning its name.
+fn pack_objects(git_dir: &Path, shas: &[String]) -> Result<String, String> {
+    let contents = shas
+        .iter()
+        .map(|sha| read_raw_object(git_dir, sha))
+        .collect::<Result<Vec<_>, String>>()?;
+    let (data, entries) = write_pack(&contents)?;
+
+    write_pack_files(git_dir, &data, entries)
+}
+
+/// Deletes a pack with its index and everything kept beside it, `.keep` and `.promisor`
+/// markers included, so that no marker outlives its pack.
+fn remove_pack(git_dir: &Path, name: &str) -> Result<(), String> {
+    for extension in ["bitmap", "idx", "pack", "keep", "promisor"] {
+        let path = pack_dir(git_dir).join(format!("{}.{}", name, extension));
+
+        if path.exists() {
+            fs::remove_file(&path).map_err(|err| format!("error removing {:?}: {}", path, err))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the objects of `pack_file` that are not in `packed` as loose objects, dated like the
+/// pack so that their prune grace period does not start over.
+fn unpack_objects(
+    git_dir: &Path,
+    pack_file: &PackFile,
+    packed: &HashSet<&str>,
+) -> Result<(), String> {
+    let pack_path = pack_dir(git_dir).join(format!("{}.pack", pack_file.name));
+    let modified = fs::metadata(&pack_path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| format!("error reading {:?}: {}", pack_path, err))?;
+
+    for entry in &pack_file.index.entries {
+        let path = get_object_path_in(git_dir, &entry.sha)?;
+
+        if packed.contains(entry.sha.as_str()) || path.exists() {
+            continue;
+        }
+
+        let (object_type, content) = pack_file
+            .pack
+            .read_object_at(entry.offset, &|base| read_raw_object(git_dir, base))?;
+
+        write_object(git_dir, &object_type, &content)?;
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(modified))
+            .map_err(|err| format!("error updating {:?}: {}", path, err))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::bitmaps::load_bitmaps;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::utils::get_object_path_in;
+
+    #[test]
+    fn repack_packs_reachable_objects_and_writes_a_bitmap() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let unreachable = write_blob(git_dir.path(), "unreachable");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &one)]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &first);
+
+        repack(&["-a"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let two = write_blob(git_dir.path(), "two");
+        let tree2 = write_tree(git_dir.path(), &[("100644", "f", &two)]);
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+
+        repack(&["-adb"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let packs = load_packs(git_dir.path()).unwrap();
+        let bitmaps = load_bitmaps(git_dir.path()).unwrap().unwrap();
+        let (reachable, extra_objects) = bitmaps
+            .reachable(git_dir.path(), std::slice::from_ref(&second))
+            .unwrap();
+
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].index.entries.len(), 6);
+        assert_eq!(reachable.count(), 6);
+        assert!(extra_objects.is_empty());
+        assert!(!get_object_path_in(git_dir.path(), &second)
+            .unwrap()
+            .exists());
+        // unreachable objects stay loose for prune to decide about
+        assert!(get_object_path_in(git_dir.path(), &unreachable)
+            .unwrap()
+            .exists());
+    }
+
+    #[test]
+    fn repack_only_packs_new_objects_without_all() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let tree = write_tree(git_dir.path(), &[("100644", "f", &one)]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &first);
+        repack(&["-d"], git_dir.path(), &mut Vec::new()).unwrap();
+        let mut output = Vec::new();
+
+        repack(&["-d"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "Nothing new to pack.\n");
+        assert_eq!(
+            repack(&["-b"], git_dir.path(), &mut Vec::new()).unwrap_err(),
+            "repack: -b needs -a or -A, since a bitmap must cover everything reachable"
+        );
+    }
+
+    #[test]
+    fn repack_unpacks_unreachable_objects_with_capital_a() {
+        let git_dir = init_git_dir();
+        let old = write_blob(git_dir.path(), "old");
+        let new = write_blob(git_dir.path(), "new");
+        let old_tree = write_tree(git_dir.path(), &[("100644", "f", &old)]);
+        let new_tree = write_tree(git_dir.path(), &[("100644", "f", &new)]);
+        let first = write_commit(git_dir.path(), &old_tree, &[], 100, "first");
+        let rewritten = write_commit(git_dir.path(), &new_tree, &[], 200, "rewritten");
+        write_ref(git_dir.path(), "refs/heads/main", &first);
+        repack(&["-ad"], git_dir.path(), &mut Vec::new()).unwrap();
+        write_ref(git_dir.path(), "refs/heads/main", &rewritten);
+
+        repack(&["-Ad"], git_dir.path(), &mut Vec::new()).unwrap();
+
+        let packs = load_packs(git_dir.path()).unwrap();
+
+        assert_eq!(packs.len(), 1);
+        assert!(packs[0].index.position(&new).is_some());
+        assert!(packs[0].index.position(&old).is_none());
+        assert!(get_object_path_in(git_dir.path(), &old).unwrap().exists());
+        assert!(get_object_path_in(git_dir.path(), &first).unwrap().exists());
+    }
+}
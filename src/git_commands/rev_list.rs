@@ -0,0 +1,289 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::bitmaps::{load_bitmaps, ReachabilityBitmaps};
+use crate::git_commands::object_walk::list_objects;
+use crate::git_commands::refs::{list_refs, resolve_revision};
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::read_raw_object;
+use crate::models::ewah::Bitmap;
+
+const USAGE: &str = "usage: git rev-list [--all] [--count] [--objects] [--use-bitmap-index] [--max-count=<n>] <commit>... [^<commit>...]";
+
+pub fn rev_list<W: Write>(args: &[&str], git_dir: &Path, writer: &mut W) -> Result<(), String> {
+    let mut count_only = false;
+    let mut with_objects = false;
+    let mut use_bitmap_index = false;
+    let mut max_count: Option<usize> = None;
+    let mut pathspec: Vec<&str> = Vec::new();
+    let mut tips: Vec<(String, String)> = Vec::new();
+    let mut excluded: Vec<String> = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--" => pathspec = args.by_ref().copied().collect(),
+            "--all" => tips.extend(
+                list_refs(git_dir)?
+                    .into_iter()
+                    .map(|(name, sha)| (sha, short_ref_name(&name))),
+            ),
+            "--count" => count_only = true,
+            "--objects" => with_objects = true,
+            "--use-bitmap-index" => use_bitmap_index = true,
+            "-n" => max_count = Some(parse_count(args.next().ok_or(USAGE)?)?),
+            _ if arg.starts_with("--max-count=") => {
+                max_count = Some(parse_count(&arg["--max-count=".len()..])?)
+            }
+            _ if arg.starts_with('^') => excluded.push(resolve_revision(git_dir, &arg[1..])?),
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => tips.push((resolve_revision(git_dir, arg)?, arg.to_string())),
+        }
+    }
+
+    if tips.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    // bitmaps answer whole-history queries; limited walks fall back to walking commits
+    if use_bitmap_index && max_count.is_none() && pathspec.is_empty() {
+        if let Some(bitmaps) = load_bitmaps(git_dir)? {
+            let (wanted, extra_objects) =
+                reachable_with_bitmaps(git_dir, &bitmaps, &tips, &excluded)?;
+
+            if count_only && with_objects {
+                return writeln!(writer, "{}", wanted.count() + extra_objects.len())
+                    .map_err(|err| err.to_string());
+            }
+
+            let mut shas = bitmaps.objects(&wanted, !with_objects);
+
+            for sha in extra_objects {
+                if with_objects || read_raw_object(git_dir, &sha)?.0 == "commit" {
+                    shas.push(sha);
+                }
+            }
+
+            return print_lines(writer, &shas, count_only);
+        }
+    }
+
+    let mut walk = RevWalk::new(git_dir)?;
+    let mut pending = Vec::new();
+
+    walk.set_pathspec(&pathspec);
+
+    for (sha, name) in &tips {
+        let object_type = read_raw_object(git_dir, sha)?.0;
+
+        if object_type == "tag" && with_objects {
+            pending.push((sha.clone(), object_type, name.clone()));
+        }
+
+        let peeled = RevWalk::peel(git_dir, sha)?;
+        let peeled_type = read_raw_object(git_dir, &peeled)?.0;
+
+        if peeled_type == "commit" || !with_objects {
+            walk.push(&peeled)?;
+        } else {
+            pending.push((peeled, peeled_type, name.clone()));
+        }
+    }
+
+    for sha in &excluded {
+        walk.hide(sha)?;
+    }
+
+    let commits = walk
+        .by_ref()
+        .take(max_count.unwrap_or(usize::MAX))
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut lines: Vec<String> = commits.iter().map(|commit| commit.sha.clone()).collect();
+
+    if with_objects {
+        let mut uninteresting_trees = Vec::new();
+
+        for parent in commits.iter().flat_map(|commit| &commit.parents) {
+            if walk.is_hidden(parent) {
+                uninteresting_trees.push(walk.lookup(parent)?.tree);
+            }
+        }
+
+        lines.extend(
+            list_objects(git_dir, &pending, &commits, &uninteresting_trees)?
+                .into_iter()
+                .map(|(sha, name)| format!("{} {}", sha, name)),
+        );
+    }
+
+    print_lines(writer, &lines, count_only)
+}
+
+/// Everything reachable from the tips minus everything reachable from the exclusions, as a
+/// bitmap plus the objects the bitmaps do not cover. Like upstream, objects come without names.
+fn reachable_with_bitmaps(
+    git_dir: &Path,
+    bitmaps: &ReachabilityBitmaps,
+    tips: &[(String, String)],
+    excluded: &[String],
+) -> Result<(Bitmap, Vec<String>), String> {
+    let tip_shas: Vec<String> = tips.iter().map(|(sha, _)| sha.clone()).collect();
+    let (mut wanted, mut extra_objects) = bitmaps.reachable(git_dir, &tip_shas)?;
+
+    if !excluded.is_empty() {
+        let (unwanted, unwanted_extra_objects) = bitmaps.reachable(git_dir, excluded)?;
+
+        wanted.and_not(&unwanted);
+        extra_objects.retain(|sha| !unwanted_extra_objects.contains(sha));
+    }
+
+    Ok((wanted, extra_objects))
+}
+
+fn print_lines<W: Write>(writer: &mut W, lines: &[String], count_only: bool) -> Result<(), String> {
+    if count_only {
+        return writeln!(writer, "{}", lines.len()).map_err(|err| err.to_string());
+    }
+
+    for line in lines {
+        writeln!(writer, "{}", line).map_err(|err| err.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Shortens `refs/tags/v1.0` to `v1.0`, the name upstream shows next to tag objects.
+fn short_ref_name(name: &str) -> String {
+    ["refs/tags/", "refs/heads/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+        .to_string()
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|err| format!("invalid count {}: {}", value, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::repack::repack;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_history, write_ref, write_tree,
+    };
+
+    #[test]
+    fn rev_list_prints_commits_newest_first() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second", "third"]);
+        let mut output = Vec::new();
+
+        rev_list(&["main"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{}\n{}\n{}\n", commits[2], commits[1], commits[0])
+        );
+    }
+
+    #[test]
+    fn rev_list_honors_exclusions_and_max_count() {
+        let git_dir = init_git_dir();
+        let commits = write_history(git_dir.path(), &["first", "second", "third"]);
+        let exclude = format!("^{}", commits[0]);
+        let mut output = Vec::new();
+
+        rev_list(
+            &["--all", &exclude, "--max-count=1"],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{}\n", commits[2])
+        );
+    }
+
+    #[test]
+    fn rev_list_counts_commits() {
+        let git_dir = init_git_dir();
+        write_history(git_dir.path(), &["first", "second", "third"]);
+        let mut output = Vec::new();
+
+        rev_list(&["--count", "HEAD"], git_dir.path(), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "3\n");
+    }
+
+    #[test]
+    fn rev_list_objects_lists_new_objects_with_and_without_bitmaps() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let two = write_blob(git_dir.path(), "two");
+        let old_tree = write_tree(git_dir.path(), &[("100644", "a", &one)]);
+        let new_tree = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &one), ("100644", "b", &two)],
+        );
+        let first = write_commit(git_dir.path(), &old_tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &new_tree, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+        let exclude = format!("^{}", first);
+        let mut output = Vec::new();
+
+        rev_list(
+            &["--objects", "main", &exclude],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{}\n{} \n{} b\n", second, new_tree, two)
+        );
+
+        repack(&["-adb"], git_dir.path(), &mut Vec::new()).unwrap();
+        let mut output = Vec::new();
+        let mut count = Vec::new();
+
+        rev_list(
+            &["--objects", "--use-bitmap-index", "main", &exclude],
+            git_dir.path(),
+            &mut output,
+        )
+        .unwrap();
+        rev_list(
+            &["--objects", "--use-bitmap-index", "--count", "--all"],
+            git_dir.path(),
+            &mut count,
+        )
+        .unwrap();
+
+        let mut shas: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        shas.sort();
+        let mut expected = vec![second, new_tree, two];
+        expected.sort();
+
+        assert_eq!(shas, expected);
+        assert_eq!(String::from_utf8(count).unwrap(), "6\n");
+    }
+
+    #[test]
+    fn rev_list_fails_without_revisions() {
+        let git_dir = init_git_dir();
+
+        let result = rev_list(&["--count"], git_dir.path(), &mut Vec::new());
+
+        assert_eq!(result.unwrap_err(), USAGE);
+    }
+}
@@ -0,0 +1,506 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::commit_graph::load_commit_graph;
+use crate::git_commands::shallow::read_shallow;
+use crate::git_commands::tree_diff::lookup_path;
+use crate::git_commands::utils::{read_object, read_raw_object};
+use crate::models::commit_graph::CommitGraph;
+use crate::models::object::Object;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub timestamp: i64,
+}
+
+/// Walks commits newest first (by committer date), consulting the commit-graph for parents,
+/// trees and dates before falling back to parsing commit objects.
+///
+/// With a pathspec, history is simplified like upstream's default: commits that leave the paths
+/// untouched are skipped, and a merge that is TREESAME to one parent only follows that parent.
+///
+/// In a shallow repository the commits listed in `.git/shallow` are walked as if they had no
+/// parents, since their parents are not there.
+///
+/// Hidden commits are walked too, passing their hiddenness on to their parents as they go, and
+/// the walk ends once only hidden commits are left queued, like upstream's `still_interesting`:
+/// the history behind them is never read.
+pub struct RevWalk {
+    git_dir: PathBuf,
+    commit_graph: Option<CommitGraph>,
+    shallow: BTreeSet<String>,
+    // ties on the commit date are broken by insertion order, like upstream git
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    insertion_counter: usize,
+    pending: HashMap<String, CommitInfo>,
+    seen: HashSet<String>,
+    hidden: HashSet<String>,
+    /// How many of the queued commits are not hidden.
+    interesting: usize,
+    pathspec: Vec<String>,
+}
+
+impl RevWalk {
+    pub fn new(git_dir: &Path) -> Result<Self, String> {
+        Ok(Self {
+            git_dir: git_dir.to_path_buf(),
+            commit_graph: load_commit_graph(git_dir)?,
+            shallow: read_shallow(git_dir)?,
+            queue: BinaryHeap::new(),
+            insertion_counter: 0,
+            pending: HashMap::new(),
+            seen: HashSet::new(),
+            hidden: HashSet::new(),
+            interesting: 0,
+            pathspec: Vec::new(),
+        })
+    }
+
+    pub fn lookup(&self, sha: &str) -> Result<CommitInfo, String> {
+        let mut commit = match &self.commit_graph {
+            Some(commit_graph) => match commit_graph.lookup(sha)? {
+                Some(entry) => CommitInfo {
+                    sha: entry.sha,
+                    tree: entry.tree,
+                    parents: entry.parents,
+                    timestamp: entry.commit_time as i64,
+                },
+                None => Self::lookup_object(&self.git_dir, sha)?,
+            },
+            None => Self::lookup_object(&self.git_dir, sha)?,
+        };
+
+        if self.shallow.contains(&commit.sha) {
+            commit.parents.clear();
+        }
+
+        Ok(commit)
+    }
+
+    /// Reads a commit by parsing its object, bypassing the commit-graph.
+    pub fn lookup_object(git_dir: &Path, sha: &str) -> Result<CommitInfo, String> {
+        match read_object(git_dir, sha)?.object {
+            Object::Commit(commit) => Ok(CommitInfo {
+                sha: sha.to_string(),
+                timestamp: commit.committer_timestamp()?,
+                tree: commit.tree,
+                parents: commit.parents,
+            }),
+            other => Err(format!(
+                "object {} is a {}, not a commit",
+                sha,
+                other.get_type()
+            )),
+        }
+    }
+
+    /// Follows annotated tags until reaching a non-tag object.
+    pub fn peel(git_dir: &Path, sha: &str) -> Result<String, String> {
+        let mut sha = sha.to_string();
+
+        loop {
+            let (object_type, content) = read_raw_object(git_dir, &sha)?;
+
+            if object_type != "tag" {
+                return Ok(sha);
+            }
+
+            sha = String::from_utf8_lossy(&content)
+                .lines()
+                .next()
+                .and_then(|line| line.strip_prefix("object "))
+                .ok_or_else(|| format!("tag {} has no object line", sha))?
+                .to_string();
+        }
+    }
+
+    /// Whether the commit `ancestor` is reachable from `descendant`. Anything that is not a
+    /// commit is no one's ancestor.
+    pub fn is_ancestor(git_dir: &Path, ancestor: &str, descendant: &str) -> Result<bool, String> {
+        for sha in [ancestor, descendant] {
+            if read_raw_object(git_dir, sha)?.0 != "commit" {
+                return Ok(false);
+            }
+        }
+
+        let mut walk = Self::new(git_dir)?;
+
+        walk.push(descendant)?;
+
+        for commit in walk {
+            if commit?.sha == ancestor {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    pub fn push(&mut self, sha: &str) -> Result<(), String> {
+        let sha = Self::peel(&self.git_dir, sha)?;
+
+        if self.seen.insert(sha.clone()) {
+            let commit = self.lookup(&sha)?;
+
+            self.enqueue(commit);
+        }
+
+        Ok(())
+    }
+
+    fn enqueue(&mut self, commit: CommitInfo) {
+        self.insertion_counter += 1;
+        self.queue.push((
+            commit.timestamp,
+            Reverse(self.insertion_counter),
+            commit.sha.clone(),
+        ));
+
+        if !self.hidden.contains(&commit.sha) {
+            self.interesting += 1;
+        }
+
+        self.pending.insert(commit.sha.clone(), commit);
+    }
+
+    pub fn set_pathspec(&mut self, paths: &[&str]) {
+        self.pathspec = paths
+            .iter()
+            .map(|path| path.trim_matches('/').to_string())
+            .collect();
+    }
+
+    /// Decides whether a commit is shown under the pathspec and which parents to keep walking.
+    fn simplify(&self, commit: &CommitInfo) -> Result<(bool, Vec<String>), String> {
+        if commit.parents.is_empty() {
+            return Ok((!self.is_treesame(None, &commit.tree)?, Vec::new()));
+        }
+
+        for (idx, parent) in commit.parents.iter().enumerate() {
+            // Bloom filters describe the diff against the first parent only
+            let treesame = if idx == 0 && !self.maybe_changed(&commit.sha) {
+                true
+            } else {
+                self.is_treesame(Some(&self.lookup(parent)?.tree), &commit.tree)?
+            };
+
+            if treesame {
+                return Ok((false, vec![parent.clone()]));
+            }
+        }
+
+        Ok((true, commit.parents.clone()))
+    }
+
+    fn is_treesame(&self, old_tree: Option<&str>, new_tree: &str) -> Result<bool, String> {
+        for path in &self.pathspec {
+            let old_entry = match old_tree {
+                Some(old_tree) => lookup_path(&self.git_dir, old_tree, path)?,
+                None => None,
+            };
+            let new_entry = lookup_path(&self.git_dir, new_tree, path)?;
+
+            if old_entry != new_entry {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Consults the changed-path Bloom filter: false means no path in the pathspec was touched.
+    fn maybe_changed(&self, sha: &str) -> bool {
+        let (filter, settings) = match self
+            .commit_graph
+            .as_ref()
+            .and_then(|graph| graph.bloom_filter(sha))
+        {
+            Some(filter) => filter,
+            None => return true,
+        };
+
+        self.pathspec.iter().any(|path| {
+            let mut prefix_end = Some(path.len());
+
+            while let Some(end) = prefix_end {
+                if !filter.contains(&path[..end], &settings) {
+                    return false;
+                }
+
+                prefix_end = path[..end].rfind('/');
+            }
+
+            true
+        })
+    }
+
+    pub fn is_hidden(&self, sha: &str) -> bool {
+        self.hidden.contains(sha)
+    }
+
+    /// Excludes `sha` and all of its ancestors from the walk. Only `sha` is marked now, and
+    /// queued for the walk to mark its ancestors as it reaches them, so `is_hidden` knows of
+    /// an ancestor once the walk has passed its children.
+    pub fn hide(&mut self, sha: &str) -> Result<(), String> {
+        let sha = Self::peel(&self.git_dir, sha)?;
+
+        self.mark_hidden(&sha)?;
+
+        if self.seen.insert(sha.clone()) {
+            let commit = self.lookup(&sha)?;
+
+            self.enqueue(commit);
+        }
+
+        Ok(())
+    }
+
+    /// Marks a commit hidden. Queued and unseen commits pass it on once the walk reaches
+    /// them, but those it has gone past already do so now, down to the commits still queued.
+    fn mark_hidden(&mut self, sha: &str) -> Result<(), String> {
+        let mut stack = vec![sha.to_string()];
+
+        while let Some(sha) = stack.pop() {
+            if !self.hidden.insert(sha.clone()) {
+                continue;
+            }
+
+            if self.pending.contains_key(&sha) {
+                self.interesting -= 1;
+            } else if self.seen.contains(&sha) {
+                stack.extend(self.lookup(&sha)?.parents);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for RevWalk {
+    type Item = Result<CommitInfo, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.interesting > 0 {
+            let (_, _, sha) = self.queue.pop()?;
+            let commit = self.pending.remove(&sha)?;
+
+            if self.hidden.contains(&sha) {
+                for parent in &commit.parents {
+                    if let Err(err) = self.mark_hidden(parent) {
+                        return Some(Err(err));
+                    }
+
+                    if self.seen.insert(parent.clone()) {
+                        match self.lookup(parent) {
+                            Ok(parent_commit) => self.enqueue(parent_commit),
+                            Err(err) => return Some(Err(err)),
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            self.interesting -= 1;
+
+            let (show, parents) = if self.pathspec.is_empty() {
+                (true, commit.parents.clone())
+            } else {
+                match self.simplify(&commit) {
+                    Ok(simplified) => simplified,
+                    Err(err) => return Some(Err(err)),
+                }
+            };
+
+            for parent in &parents {
+                if self.seen.insert(parent.clone()) {
+                    match self.lookup(parent) {
+                        Ok(parent_commit) => self.enqueue(parent_commit),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
+
+            if show {
+                return Some(Ok(commit));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+
+    #[test]
+    fn rev_walk_yields_commits_newest_first() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let side = write_commit(git_dir.path(), &tree, &[&first], 150, "side");
+        let merge = write_commit(git_dir.path(), &tree, &[&second, &side], 300, "merge");
+
+        let mut walk = RevWalk::new(git_dir.path()).unwrap();
+        walk.push(&merge).unwrap();
+        let shas: Vec<String> = walk.map(|commit| commit.unwrap().sha).collect();
+
+        assert_eq!(shas, vec![merge, second, side, first]);
+    }
+
+    #[test]
+    fn rev_walk_skips_hidden_commits() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let third = write_commit(git_dir.path(), &tree, &[&second], 300, "third");
+
+        let mut walk = RevWalk::new(git_dir.path()).unwrap();
+        walk.push(&third).unwrap();
+        walk.hide(&first).unwrap();
+        let shas: Vec<String> = walk.map(|commit| commit.unwrap().sha).collect();
+
+        assert_eq!(shas, vec![third, second]);
+    }
+
+    #[test]
+    fn rev_walk_stops_before_the_history_behind_hidden_commits() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let side = write_commit(git_dir.path(), &tree, &[&second], 250, "side");
+        let third = write_commit(git_dir.path(), &tree, &[&second], 300, "third");
+        let fourth = write_commit(git_dir.path(), &tree, &[&third, &side], 400, "fourth");
+
+        // hiding used to read all of this
+        std::fs::remove_file(
+            crate::git_commands::utils::get_object_path_in(git_dir.path(), &first).unwrap(),
+        )
+        .unwrap();
+
+        let mut walk = RevWalk::new(git_dir.path()).unwrap();
+        walk.push(&fourth).unwrap();
+        walk.hide(&third).unwrap();
+
+        assert_eq!(walk.next().unwrap().unwrap().sha, fourth);
+
+        // hiding a commit already walked past reaches the parents it queued, and with only
+        // hidden commits left the walk ends without going further
+        walk.hide(&fourth).unwrap();
+
+        assert!(walk.next().is_none());
+        assert!(walk.is_hidden(&side));
+        assert!(!walk.is_hidden(&second));
+    }
+
+    fn walk_paths(git_dir: &Path, tip: &str, paths: &[&str]) -> Vec<String> {
+        let mut walk = RevWalk::new(git_dir).unwrap();
+        walk.set_pathspec(paths);
+        walk.push(tip).unwrap();
+
+        walk.map(|commit| commit.unwrap().sha).collect()
+    }
+
+    #[test]
+    fn rev_walk_with_pathspec_only_yields_commits_touching_the_path() {
+        let git_dir = init_git_dir();
+        let v1 = write_blob(git_dir.path(), "v1");
+        let v2 = write_blob(git_dir.path(), "v2");
+        let sub1 = write_tree(git_dir.path(), &[("100644", "lib.rs", &v1)]);
+        let sub2 = write_tree(git_dir.path(), &[("100644", "lib.rs", &v2)]);
+        let tree1 = write_tree(git_dir.path(), &[("100644", "README", &v1)]);
+        let tree2 = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &v1), ("40000", "src", &sub1)],
+        );
+        let tree3 = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &v2), ("40000", "src", &sub1)],
+        );
+        let tree4 = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &v2), ("40000", "src", &sub2)],
+        );
+        let first = write_commit(git_dir.path(), &tree1, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "add src");
+        let third = write_commit(git_dir.path(), &tree3, &[&second], 300, "readme");
+        let fourth = write_commit(git_dir.path(), &tree4, &[&third], 400, "src again");
+
+        assert_eq!(
+            walk_paths(git_dir.path(), &fourth, &["src/lib.rs"]),
+            vec![fourth.clone(), second.clone()]
+        );
+        assert_eq!(
+            walk_paths(git_dir.path(), &fourth, &["README"]),
+            vec![third, first]
+        );
+    }
+
+    #[test]
+    fn rev_walk_with_pathspec_follows_treesame_merge_parent() {
+        let git_dir = init_git_dir();
+        let v1 = write_blob(git_dir.path(), "v1");
+        let v2 = write_blob(git_dir.path(), "v2");
+        let tree1 = write_tree(git_dir.path(), &[("100644", "a", &v1)]);
+        let tree2 = write_tree(git_dir.path(), &[("100644", "a", &v2)]);
+        let root = write_commit(git_dir.path(), &tree1, &[], 100, "root");
+        let side = write_commit(git_dir.path(), &tree2, &[&root], 200, "side");
+        let main = write_commit(git_dir.path(), &tree1, &[&root], 300, "main");
+        let merge = write_commit(git_dir.path(), &tree2, &[&main, &side], 400, "merge");
+
+        assert_eq!(walk_paths(git_dir.path(), &merge, &["a"]), vec![side, root]);
+    }
+
+    #[test]
+    fn rev_walk_with_pathspec_skips_tree_reads_when_bloom_filter_rules_commit_out() {
+        let git_dir = init_git_dir();
+        let v1 = write_blob(git_dir.path(), "v1");
+        let v2 = write_blob(git_dir.path(), "v2");
+        let tree1 = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &v1), ("100644", "b", &v1)],
+        );
+        let tree2 = write_tree(
+            git_dir.path(),
+            &[("100644", "a", &v1), ("100644", "b", &v2)],
+        );
+        let first = write_commit(git_dir.path(), &tree1, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree2, &[&first], 200, "second");
+        write_ref(git_dir.path(), "refs/heads/main", &second);
+
+        crate::git_commands::commit_graph::commit_graph(
+            &["write", "--changed-paths"],
+            git_dir.path(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        // without the Bloom filter, deciding on `second` would need this tree
+        let tree_path =
+            crate::git_commands::utils::get_object_path_in(git_dir.path(), &tree2).unwrap();
+        std::fs::remove_file(tree_path).unwrap();
+
+        assert_eq!(walk_paths(git_dir.path(), &second, &["a"]), vec![first]);
+    }
+
+    #[test]
+    fn push_returns_error_for_non_commit() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+
+        let mut walk = RevWalk::new(git_dir.path()).unwrap();
+
+        assert!(walk.push(&tree).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::{stderr, Read};
+use std::path::Path;
+
+use crate::git_commands::fetch_pack::{RefAdvertisement, AGENT};
+use crate::git_commands::receive_pack::RefUpdate;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::transport::Transport;
+use crate::git_commands::upload_pack::objects_to_send;
+use crate::git_commands::utils::{object_exists, read_raw_object};
+use crate::models::pack::write_pack;
+use crate::models::pkt_line::{PktLineReader, PktLineWriter};
+
+/// What receive-pack reported about a push: whether the pack went in, and for each ref either
+/// `None` for success or the reason it was refused.
+#[derive(Debug, PartialEq)]
+pub struct PushReport {
+    pub unpack: Result<(), String>,
+    pub statuses: Vec<(String, Option<String>)>,
+}
+
+impl PushReport {
+    /// The reason receive-pack gave for refusing `name`, if it did.
+    pub fn rejection(&self, name: &str) -> Option<&str> {
+        self.statuses
+            .iter()
+            .find(|(status_name, _)| status_name == name)
+            .and_then(|(_, reason)| reason.as_deref())
+    }
+}
+
+/// Sends `updates` to receive-pack with a pack of what the remote lacks, which is everything
+/// the new values reach minus the history of the refs it advertised that we have too.
+pub fn send_pack(
+    transport: &mut dyn Transport,
+    git_dir: &Path,
+    advertisement: &RefAdvertisement,
+    updates: &[RefUpdate],
+) -> Result<PushReport, String> {
+    let capabilities = push_capabilities(advertisement);
+    let mut request = PktLineWriter::new(Vec::new());
+
+    for (idx, update) in updates.iter().enumerate() {
+        let command = format!("{} {} {}", update.old, update.new, update.name);
+
+        if idx == 0 {
+            request.write_line(&format!("{}\0{}", command, capabilities.join(" ")))?;
+        } else {
+            request.write_line(&command)?;
+        }
+    }
+
+    request.write_flush()?;
+
+    // receive-pack expects no pack when it is only asked to delete
+    if !updates.iter().all(RefUpdate::is_delete) {
+        request.write_unframed(&build_pack(git_dir, advertisement, updates)?)?;
+    }
+
+    let response = transport.send_pack(request.into_inner())?;
+    let mut reader = PktLineReader::new(response);
+
+    if !capabilities
+        .iter()
+        .any(|cap| cap.starts_with("report-status"))
+    {
+        // without a report, all we can do is assume it worked
+        return Ok(PushReport {
+            unpack: Ok(()),
+            statuses: updates
+                .iter()
+                .map(|update| (update.name.clone(), None))
+                .collect(),
+        });
+    }
+
+    if capabilities.contains(&"side-band-64k") {
+        let mut report = Vec::new();
+
+        reader.read_sideband(&mut report, &mut stderr())?;
+
+        read_report(&mut PktLineReader::new(report.as_slice()))
+    } else {
+        read_report(&mut reader)
+    }
+}
+
+/// The capabilities to ask for: a report, in its v2 form if possible, on the side-band.
+fn push_capabilities(advertisement: &RefAdvertisement) -> Vec<&'static str> {
+    let mut capabilities = Vec::new();
+
+    if advertisement.has_capability("report-status-v2") {
+        capabilities.push("report-status-v2");
+    } else if advertisement.has_capability("report-status") {
+        capabilities.push("report-status");
+    }
+
+    if advertisement.has_capability("side-band-64k") {
+        capabilities.push("side-band-64k");
+    }
+
+    capabilities.push(AGENT);
+    capabilities
+}
+
+fn build_pack(
+    git_dir: &Path,
+    advertisement: &RefAdvertisement,
+    updates: &[RefUpdate],
+) -> Result<Vec<u8>, String> {
+    let wants: Vec<String> = updates
+        .iter()
+        .filter(|update| !update.is_delete())
+        .map(|update| update.new.clone())
+        .collect();
+    let mut common = Vec::new();
+
+    for (_, sha) in advertisement.direct_refs() {
+        if !object_exists(git_dir, sha)? {
+            continue;
+        }
+
+        let commit = RevWalk::peel(git_dir, sha)?;
+
+        if read_raw_object(git_dir, &commit)?.0 == "commit" && !common.contains(&commit) {
+            common.push(commit);
+        }
+    }
+
+    let objects = objects_to_send(git_dir, &wants, &common)?
+        .iter()
+        .map(|sha| read_raw_object(git_dir, sha))
+        .collect::<Result<Vec<_>, String>>()?;
+    let (pack, _) = write_pack(&objects)?;
+
+    Ok(pack)
+}
+
+/// Reads a `report-status` or `report-status-v2` report: `unpack ok` or `unpack <error>`, then
+/// `ok <ref>` or `ng <ref> <reason>` for each command. The `option` lines v2 adds after an `ok`
+/// say how the server rewrote the update, which nothing here needs.
+fn read_report<R: Read>(reader: &mut PktLineReader<R>) -> Result<PushReport, String> {
+    let unpack = match reader.read_line()? {
+        Some(line) => match line.strip_prefix("unpack ") {
+            Some("ok") => Ok(()),
+            Some(error) => Err(error.to_string()),
+            None => {
+                return Err(format!(
+                    "protocol error: expected unpack status, got '{}'",
+                    line
+                ))
+            }
+        },
+        None => return Err("protocol error: expected unpack status, got a flush".to_string()),
+    };
+    let mut statuses = Vec::new();
+
+    while let Some(line) = reader.read_line()? {
+        if let Some(name) = line.strip_prefix("ok ") {
+            statuses.push((name.to_string(), None));
+        } else if let Some(rejection) = line.strip_prefix("ng ") {
+            let (name, reason) = rejection.split_once(' ').unwrap_or((rejection, "failed"));
+
+            statuses.push((name.to_string(), Some(reason.to_string())));
+        } else if !line.starts_with("option ") {
+            return Err(format!("protocol error: unexpected report line '{}'", line));
+        }
+    }
+
+    Ok(PushReport { unpack, statuses })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_report_skips_v2_options() {
+        let mut response = PktLineWriter::new(Vec::new());
+
+        for line in [
+            "unpack ok",
+            "ok refs/heads/main",
+            "option refname refs/heads/main",
+            "option forced-update",
+            "ng refs/heads/topic non-fast-forward",
+        ] {
+            response.write_line(line).unwrap();
+        }
+
+        response.write_flush().unwrap();
+
+        let response = response.into_inner();
+        let report = read_report(&mut PktLineReader::new(response.as_slice())).unwrap();
+
+        assert_eq!(report.unpack, Ok(()));
+        assert_eq!(report.rejection("refs/heads/main"), None);
+        assert_eq!(
+            report.rejection("refs/heads/topic"),
+            Some("non-fast-forward")
+        );
+        assert_eq!(report.statuses.len(), 2);
+    }
+}
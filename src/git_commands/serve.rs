@@ -0,0 +1,702 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+use crate::git_commands::receive_pack::{self, serve_receive_pack};
+use crate::git_commands::transport::find_git_dir;
+use crate::git_commands::upload_pack::{self, advertise_v2, serve_upload_pack, serve_v2};
+use crate::models::pkt_line::{PktLineReader, PktLineWriter};
+
+const USAGE: &str = "usage: git serve [--read-only] [--listen=<address>] <repository>...";
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+/// The most a request body may take, as sent and again once gunzipped. Bodies are held in
+/// memory whole, so their size cannot be left to the client.
+const MAX_REQUEST_BODY: usize = 256 * 1024 * 1024;
+/// The longest request or header line, without its CRLF, and the most headers a request may
+/// have, which bound what a client can make us hold before the body.
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// Hosts repositories over smart HTTP until killed, each under the name of its directory:
+/// `serve /srv/app.git` answers `http://127.0.0.1:8080/app.git`. `info/refs` advertises
+/// upload-pack (in protocol v2 when the client's `Git-Protocol` header asks for it) or
+/// receive-pack, and posts to either service are answered statelessly. Request bodies may be
+/// chunked and gzipped, as upstream clients send large ones, and are answered with 413 beyond
+/// `MAX_REQUEST_BODY`, as are headers with 431 beyond `MAX_HEADER_LINE` or `MAX_HEADERS`.
+/// `--read-only` refuses pushes and
+/// `--listen` picks the address; port 0 picks a free port, which is printed with the URLs.
+pub fn serve<W: Write>(args: &[&str], writer: &mut W) -> Result<(), String> {
+    let mut read_only = false;
+    let mut address = DEFAULT_ADDRESS;
+    let mut paths = Vec::new();
+
+    for arg in args {
+        match *arg {
+            "--read-only" => read_only = true,
+            _ if arg.starts_with("--listen=") => address = &arg["--listen=".len()..],
+            _ if arg.starts_with('-') => return Err(USAGE.to_string()),
+            _ => paths.push(*arg),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut repositories = HashMap::new();
+
+    for path in paths {
+        let git_dir = find_git_dir(Path::new(path))
+            .ok_or_else(|| format!("fatal: '{}' does not appear to be a git repository\n", path))?;
+        let canonical = fs::canonicalize(path).map_err(|err| err.to_string())?;
+        let name = canonical
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| format!("fatal: '{}' has no name to serve it under\n", path))?;
+
+        if repositories.insert(name.clone(), git_dir).is_some() {
+            return Err(format!("fatal: two repositories are named '{}'\n", name));
+        }
+    }
+
+    let host = Arc::new(Host {
+        repositories,
+        read_only,
+    });
+    let runtime = Runtime::new().map_err(|err| err.to_string())?;
+
+    runtime.block_on(async {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|err| format!("fatal: unable to listen on {}: {}\n", address, err))?;
+        let address = listener.local_addr().map_err(|err| err.to_string())?;
+        let mut names: Vec<&String> = host.repositories.keys().collect();
+
+        names.sort();
+
+        for name in names {
+            writeln!(writer, "Serving http://{}/{}", address, name)
+                .map_err(|err| err.to_string())?;
+        }
+
+        writer.flush().map_err(|err| err.to_string())?;
+
+        loop {
+            let (stream, _) = listener.accept().await.map_err(|err| err.to_string())?;
+            let host = host.clone();
+
+            // one client's broken connection is no reason to stop serving the others
+            tokio::spawn(async move {
+                let _ = serve_connection(stream, host).await;
+            });
+        }
+    })
+}
+
+/// The repositories being served, by the name their URLs start with.
+struct Host {
+    repositories: HashMap<String, PathBuf>,
+    read_only: bool,
+}
+
+/// An HTTP request with its body de-chunked but still in its content encoding.
+#[derive(Debug, Default)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    fn wants_protocol_v2(&self) -> bool {
+        self.header("Git-Protocol")
+            .is_some_and(|protocol| protocol.split(':').any(|item| item == "version=2"))
+    }
+
+    /// The body with its `Content-Encoding` undone, or the error response when it cannot be
+    /// decoded or decodes to more than `max_size` bytes.
+    fn decoded_body(&self, max_size: usize) -> Result<Vec<u8>, HttpResponse> {
+        match self.header("Content-Encoding") {
+            None | Some("identity") => Ok(self.body.clone()),
+            Some("gzip") | Some("x-gzip") => {
+                let mut body = Vec::new();
+
+                GzDecoder::new(self.body.as_slice())
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut body)
+                    .map_err(|_| HttpResponse::error("400 Bad Request"))?;
+
+                if body.len() > max_size {
+                    return Err(HttpResponse::error("413 Payload Too Large"));
+                }
+
+                Ok(body)
+            }
+            Some(_) => Err(HttpResponse::error("415 Unsupported Media Type")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct HttpResponse {
+    status: &'static str,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn ok(content_type: String, body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain".to_string(),
+            body: format!("{}\n", status).into_bytes(),
+        }
+    }
+}
+
+impl Host {
+    /// Answers one request. Everything git-related happens here, synchronously, so the
+    /// connection handling around it stays plain I/O.
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let Some((git_dir, route)) = self.route(&request.path) else {
+            return HttpResponse::error("404 Not Found");
+        };
+
+        match (request.method.as_str(), route) {
+            ("GET", "info/refs") => match request.query_param("service") {
+                Some(UPLOAD_PACK) => self.advertise(git_dir, UPLOAD_PACK, request),
+                Some(RECEIVE_PACK) if self.read_only => HttpResponse::error("403 Forbidden"),
+                Some(RECEIVE_PACK) => self.advertise(git_dir, RECEIVE_PACK, request),
+                // the dumb protocol's plain info/refs file is not served
+                _ => HttpResponse::error("403 Forbidden"),
+            },
+            ("POST", UPLOAD_PACK) => self.post(git_dir, UPLOAD_PACK, request),
+            ("POST", RECEIVE_PACK) if self.read_only => HttpResponse::error("403 Forbidden"),
+            ("POST", RECEIVE_PACK) => self.post(git_dir, RECEIVE_PACK, request),
+            (_, "info/refs" | UPLOAD_PACK | RECEIVE_PACK) => {
+                HttpResponse::error("405 Method Not Allowed")
+            }
+            _ => HttpResponse::error("404 Not Found"),
+        }
+    }
+
+    /// Finds the repository a path starts with and what the rest of the path asks of it.
+    fn route<'a>(&self, path: &'a str) -> Option<(&Path, &'a str)> {
+        let path = path.strip_prefix('/')?;
+
+        self.repositories.iter().find_map(|(name, git_dir)| {
+            let route = path.strip_prefix(name.as_str())?.strip_prefix('/')?;
+
+            Some((git_dir.as_path(), route))
+        })
+    }
+
+    /// The `info/refs` response: a v0 advertisement behind the `# service=` banner smart
+    /// clients look for, or the v2 capabilities, which go without one.
+    fn advertise(&self, git_dir: &Path, service: &str, request: &HttpRequest) -> HttpResponse {
+        let mut body = PktLineWriter::new(Vec::new());
+        let advertised = if service == UPLOAD_PACK && request.wants_protocol_v2() {
+            advertise_v2(&mut body)
+        } else {
+            body.write_line(&format!("# service={}", service))
+                .and_then(|_| body.write_flush())
+                .and_then(|_| match service {
+                    UPLOAD_PACK => upload_pack::advertise_refs(git_dir, &mut body),
+                    _ => receive_pack::advertise_refs(git_dir, &mut body),
+                })
+        };
+
+        match advertised {
+            Ok(()) => HttpResponse::ok(
+                format!("application/x-{}-advertisement", service),
+                body.into_inner(),
+            ),
+            Err(_) => HttpResponse::error("500 Internal Server Error"),
+        }
+    }
+
+    /// Serves one stateless request. Errors reach the client as an `ERR` packet in place of
+    /// whatever was answered so far.
+    fn post(&self, git_dir: &Path, service: &str, request: &HttpRequest) -> HttpResponse {
+        let body = match request.decoded_body(MAX_REQUEST_BODY) {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let mut reader = PktLineReader::new(body.as_slice());
+        let mut response = PktLineWriter::new(Vec::new());
+        let served = match service {
+            UPLOAD_PACK if request.wants_protocol_v2() => {
+                serve_v2(git_dir, &mut reader, &mut response, true)
+            }
+            UPLOAD_PACK => serve_upload_pack(git_dir, &mut reader, &mut response, true),
+            _ => serve_receive_pack(git_dir, reader, &mut response),
+        };
+        let body = match served {
+            Ok(()) => response.into_inner(),
+            Err(err) => {
+                let mut response = PktLineWriter::new(Vec::new());
+
+                match response.write_line(&format!("ERR {}", err.trim_end())) {
+                    Ok(()) => response.into_inner(),
+                    Err(_) => return HttpResponse::error("500 Internal Server Error"),
+                }
+            }
+        };
+
+        HttpResponse::ok(format!("application/x-{}-result", service), body)
+    }
+}
+
+/// Answers requests on one keep-alive connection until the client closes it.
+async fn serve_connection(stream: TcpStream, host: Arc<Host>) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    while let Some(request) = read_request(&mut reader, &mut writer, MAX_REQUEST_BODY).await? {
+        let close = request
+            .header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+        let host = host.clone();
+        // serving reads and writes repositories and may run hooks, none of which is async
+        let response = tokio::task::spawn_blocking(move || host.handle(&request))
+            .await
+            .map_err(|err| err.to_string())?;
+
+        write_response(&mut writer, &response).await?;
+
+        if close {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads one request, or `None` when the client has closed the connection. A client waiting
+/// for `100 Continue` before it sends a big body is told to go ahead. A body over `max_size`
+/// is answered with 413 and also gives `None`, as the rest of it is never read.
+async fn read_request<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    max_size: usize,
+) -> Result<Option<HttpRequest>, String>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let request_line = match read_http_line(reader).await? {
+        HttpLine::Line(line) => line,
+        HttpLine::TooLong => return refuse(writer, "414 URI Too Long").await,
+        HttpLine::Closed => return Ok(None),
+    };
+    let mut parts = request_line.split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(format!("malformed request line '{}'", request_line)),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        ..HttpRequest::default()
+    };
+
+    loop {
+        let line = match read_http_line(reader).await? {
+            HttpLine::Line(line) => line,
+            HttpLine::TooLong => {
+                return refuse(writer, "431 Request Header Fields Too Large").await
+            }
+            HttpLine::Closed => return Err("connection closed in the request headers".to_string()),
+        };
+
+        if line.is_empty() {
+            break;
+        }
+
+        if request.headers.len() == MAX_HEADERS {
+            return refuse(writer, "431 Request Header Fields Too Large").await;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let chunked = request
+        .header("Transfer-Encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+    let length = request
+        .header("Content-Length")
+        .map(|length| {
+            length
+                .parse::<usize>()
+                .map_err(|_| format!("invalid Content-Length '{}'", length))
+        })
+        .transpose()?;
+
+    if !chunked && length.is_some_and(|length| length > max_size) {
+        return refuse(writer, "413 Payload Too Large").await;
+    }
+
+    if request
+        .header("Expect")
+        .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"))
+    {
+        writer
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    if chunked {
+        match read_chunked_body(reader, max_size).await? {
+            Some(body) => request.body = body,
+            None => return refuse(writer, "413 Payload Too Large").await,
+        }
+    } else if let Some(length) = length {
+        let mut body = vec![0; length];
+
+        reader
+            .read_exact(&mut body)
+            .await
+            .map_err(|err| err.to_string())?;
+        request.body = body;
+    }
+
+    Ok(Some(request))
+}
+
+/// Answers a request that is too big with `status`, leaving the connection to be closed.
+async fn refuse<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: &'static str,
+) -> Result<Option<HttpRequest>, String> {
+    write_response(writer, &HttpResponse::error(status)).await?;
+
+    Ok(None)
+}
+
+/// Reads a chunked body: hex sizes each followed by that many bytes, up to a zero size and the
+/// (ignored) trailers. Gives `None` as soon as the chunks add up to more than `max_size`.
+async fn read_chunked_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<Vec<u8>>, String> {
+    let mut body = Vec::new();
+
+    loop {
+        let line = match read_http_line(reader).await? {
+            HttpLine::Line(line) => line,
+            HttpLine::TooLong => return Err("chunk size line too long".to_string()),
+            HttpLine::Closed => return Err("connection closed in a chunked body".to_string()),
+        };
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| format!("invalid chunk size '{}'", size))?;
+
+        if size == 0 {
+            while matches!(read_http_line(reader).await?, HttpLine::Line(line) if !line.is_empty())
+            {
+            }
+
+            return Ok(Some(body));
+        }
+
+        if size > max_size - body.len() {
+            return Ok(None);
+        }
+
+        let start = body.len();
+
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .await
+            .map_err(|err| err.to_string())?;
+        read_http_line(reader).await?;
+    }
+}
+
+/// What reading a line off a connection gave.
+enum HttpLine {
+    /// The line without its CRLF.
+    Line(String),
+    /// A line longer than `MAX_HEADER_LINE`, which is not read any further.
+    TooLong,
+    /// The end of the stream.
+    Closed,
+}
+
+async fn read_http_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<HttpLine, String> {
+    let mut line = Vec::new();
+    // room for the CRLF, and one byte more to tell the line is too long
+    let limit = MAX_HEADER_LINE as u64 + 3;
+
+    if (&mut *reader)
+        .take(limit)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|err| err.to_string())?
+        == 0
+    {
+        return Ok(HttpLine::Closed);
+    }
+
+    let line = String::from_utf8(line).map_err(|err| err.to_string())?;
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    if line.len() > MAX_HEADER_LINE {
+        return Ok(HttpLine::TooLong);
+    }
+
+    Ok(HttpLine::Line(line.to_string()))
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &HttpResponse,
+) -> Result<(), String> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+
+    writer
+        .write_all(head.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_all(&response.body)
+        .await
+        .map_err(|err| err.to_string())?;
+    writer.flush().await.map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn host(git_dir: &Path, read_only: bool) -> Host {
+        Host {
+            repositories: HashMap::from([("repo.git".to_string(), git_dir.to_path_buf())]),
+            read_only,
+        }
+    }
+
+    fn get(path: &str) -> HttpRequest {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+        HttpRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            ..HttpRequest::default()
+        }
+    }
+
+    #[test]
+    fn info_refs_opens_with_the_service_banner() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "content\n");
+        let tree = write_tree(git_dir.path(), &[("100644", "file", &blob)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+
+        let response =
+            host(git_dir.path(), false).handle(&get("/repo.git/info/refs?service=git-upload-pack"));
+        let mut reader = PktLineReader::new(response.body.as_slice());
+
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(
+            response.content_type,
+            "application/x-git-upload-pack-advertisement"
+        );
+        assert_eq!(
+            reader.read_line().unwrap(),
+            Some("# service=git-upload-pack".to_string())
+        );
+        assert_eq!(reader.read_line().unwrap(), None);
+        assert!(reader
+            .read_line()
+            .unwrap()
+            .unwrap()
+            .starts_with(&format!("{} HEAD\0", commit)));
+    }
+
+    #[test]
+    fn read_only_host_refuses_receive_pack() {
+        let git_dir = init_git_dir();
+        let host = host(git_dir.path(), true);
+        let post = HttpRequest {
+            method: "POST".to_string(),
+            path: "/repo.git/git-receive-pack".to_string(),
+            ..HttpRequest::default()
+        };
+
+        assert_eq!(
+            host.handle(&get("/repo.git/info/refs?service=git-receive-pack"))
+                .status,
+            "403 Forbidden"
+        );
+        assert_eq!(host.handle(&post).status, "403 Forbidden");
+        assert_eq!(
+            host.handle(&get("/other.git/info/refs?service=git-upload-pack"))
+                .status,
+            "404 Not Found"
+        );
+    }
+
+    #[test]
+    fn read_request_decodes_a_chunked_gzip_body() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"0009done\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let (first, rest) = gzipped.split_at(4);
+        let mut raw = b"POST /repo.git/git-upload-pack HTTP/1.1\r\n\
+            Content-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n"
+            .to_vec();
+
+        for chunk in [first, rest] {
+            raw.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            raw.extend(chunk);
+            raw.extend(b"\r\n");
+        }
+
+        raw.extend(b"0\r\n\r\n");
+
+        let runtime = Runtime::new().unwrap();
+        let request = runtime
+            .block_on(read_request(&mut raw.as_slice(), &mut Vec::new(), 100))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/repo.git/git-upload-pack");
+        assert_eq!(request.decoded_body(100).unwrap(), b"0009done\n");
+    }
+
+    #[test]
+    fn read_request_refuses_bodies_over_the_limit() {
+        let runtime = Runtime::new().unwrap();
+        let read = |raw: &[u8]| {
+            let mut response = Vec::new();
+            let request = runtime
+                .block_on(read_request(&mut &raw[..], &mut response, 8))
+                .unwrap();
+
+            (request.is_none(), String::from_utf8(response).unwrap())
+        };
+
+        for raw in [
+            &b"POST /repo.git/git-upload-pack HTTP/1.1\r\nContent-Length: 999999999999\r\n\r\n"[..],
+            b"POST /repo.git/git-upload-pack HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n",
+        ] {
+            let (closed, response) = read(raw);
+
+            assert!(closed);
+            assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 1000]).unwrap();
+        let request = HttpRequest {
+            headers: vec![("Content-Encoding".to_string(), "gzip".to_string())],
+            body: encoder.finish().unwrap(),
+            ..HttpRequest::default()
+        };
+
+        assert_eq!(
+            request.decoded_body(100),
+            Err(HttpResponse::error("413 Payload Too Large"))
+        );
+    }
+
+    #[test]
+    fn read_request_refuses_headers_over_the_limits() {
+        let runtime = Runtime::new().unwrap();
+        let read = |raw: Vec<u8>| {
+            let mut response = Vec::new();
+            let request = runtime
+                .block_on(read_request(&mut raw.as_slice(), &mut response, 8))
+                .unwrap();
+
+            (request.is_none(), String::from_utf8(response).unwrap())
+        };
+        let long_header = format!(
+            "GET /repo.git/info/refs HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_LINE)
+        );
+        let many_headers = format!(
+            "GET /repo.git/info/refs HTTP/1.1\r\n{}\r\n",
+            "X-Many: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEADER_LINE));
+
+        for (raw, status) in [
+            (long_header, "431 Request Header Fields Too Large"),
+            (many_headers, "431 Request Header Fields Too Large"),
+            (long_target, "414 URI Too Long"),
+        ] {
+            let (closed, response) = read(raw.into_bytes());
+
+            assert!(closed);
+            assert!(response.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+        }
+
+        let (closed, _) = read(
+            format!(
+                "GET /repo.git/info/refs HTTP/1.1\r\n{}\r\n",
+                "X-Many: a\r\n".repeat(MAX_HEADERS)
+            )
+            .into_bytes(),
+        );
+
+        assert!(!closed);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::git_commands::fetch_pack::ShallowRequest;
+use crate::git_commands::prune::{now, parse_expiry};
+use crate::git_commands::utils::write_atomically;
+
+/// Upstream's stand-in for a depth without limit, which is how `--unshallow` asks for all of
+/// the history.
+const INFINITE_DEPTH: usize = 0x7fffffff;
+
+/// The shallow options of clone and fetch: `--depth`, `--shallow-since` and `--shallow-exclude`,
+/// and for fetch, `--deepen` and `--unshallow`.
+#[derive(Debug, Default)]
+pub struct ShallowOptions {
+    depth: Option<usize>,
+    deepen: Option<usize>,
+    unshallow: bool,
+    since: Option<u64>,
+    exclude: Vec<String>,
+}
+
+impl ShallowOptions {
+    /// Takes `arg` when it is one of the options, its value either after `=` or the next of
+    /// `args`. Returns false for any other argument, so that the caller can handle it.
+    pub fn parse<'a>(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = &'a str>,
+        fetch: bool,
+    ) -> Result<bool, String> {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+
+        if fetch && name == "--unshallow" && inline.is_none() {
+            self.unshallow = true;
+            return Ok(true);
+        }
+
+        let takes_value = match name {
+            "--depth" | "--shallow-since" | "--shallow-exclude" => true,
+            "--deepen" => fetch,
+            _ => false,
+        };
+
+        if !takes_value {
+            return Ok(false);
+        }
+
+        let value = inline
+            .or_else(|| args.next())
+            .ok_or_else(|| format!("fatal: option '{}' requires a value\n", name))?;
+
+        match name {
+            "--depth" => self.depth = Some(parse_depth(value)?),
+            "--deepen" => self.deepen = Some(parse_depth(value)?),
+            "--shallow-since" => {
+                self.since = Some(
+                    parse_expiry(value, now())?
+                        .ok_or_else(|| format!("fatal: invalid date '{}'\n", value))?,
+                )
+            }
+            _ => self.exclude.push(value.to_string()),
+        }
+
+        Ok(true)
+    }
+
+    /// The options given, by name, for the warning that a local clone ignores them.
+    pub fn given(&self) -> Vec<&'static str> {
+        let mut given = Vec::new();
+
+        if self.depth.is_some() {
+            given.push("--depth");
+        }
+
+        if self.since.is_some() {
+            given.push("--shallow-since");
+        }
+
+        if !self.exclude.is_empty() {
+            given.push("--shallow-exclude");
+        }
+
+        given
+    }
+
+    /// Builds the request for a fetch into `git_dir`, which sends the commits its history
+    /// already stops at.
+    pub fn request(&self, git_dir: &Path) -> Result<ShallowRequest, String> {
+        let shallow: Vec<String> = read_shallow(git_dir)?.into_iter().collect();
+
+        if self.depth.is_some() && self.deepen.is_some() {
+            return Err(
+                "fatal: options '--deepen' and '--depth' cannot be used together\n".to_string(),
+            );
+        }
+
+        if self.unshallow && (self.depth.is_some() || self.deepen.is_some()) {
+            return Err(
+                "fatal: options '--depth' and '--unshallow' cannot be used together\n".to_string(),
+            );
+        }
+
+        if self.unshallow && shallow.is_empty() {
+            return Err(
+                "fatal: --unshallow on a complete repository does not make sense\n".to_string(),
+            );
+        }
+
+        let depth = if self.unshallow {
+            Some(INFINITE_DEPTH)
+        } else {
+            self.depth.or(self.deepen)
+        };
+
+        Ok(ShallowRequest {
+            shallow,
+            depth,
+            relative: self.deepen.is_some(),
+            since: self.since,
+            exclude: self.exclude.clone(),
+        })
+    }
+}
+
+fn parse_depth(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(depth) if depth > 0 => Ok(depth),
+        _ => Err(format!("fatal: depth {} is not a positive number\n", value)),
+    }
+}
+
+/// Reads `.git/shallow`: the commits whose parents a shallow repository does not have, one sha
+/// per line. A complete repository has no such file.
+pub fn read_shallow(git_dir: &Path) -> Result<BTreeSet<String>, String> {
+    let path = git_dir.join("shallow");
+
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+
+    let content =
+        fs::read_to_string(&path).map_err(|err| format!("error reading {:?}: {}", path, err))?;
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Adds the commits a fetch made `shallow` and drops the ones it `unshallow`ed, removing the file
+/// once the history is complete again.
+pub fn update_shallow(
+    git_dir: &Path,
+    shallow: &[String],
+    unshallow: &[String],
+) -> Result<(), String> {
+    let mut commits = read_shallow(git_dir)?;
+
+    commits.extend(shallow.iter().cloned());
+
+    for sha in unshallow {
+        commits.remove(sha);
+    }
+
+    let path = git_dir.join("shallow");
+
+    if commits.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).map_err(|err| format!("error removing {:?}: {}", path, err))?;
+        }
+
+        return Ok(());
+    }
+
+    let content: String = commits.iter().map(|sha| format!("{}\n", sha)).collect();
+
+    write_atomically(&path, content.as_bytes())
+}
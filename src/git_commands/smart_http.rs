@@ -0,0 +1,291 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use reqwest::StatusCode;
+
+use crate::git_commands::credential::{approve, fill, reject};
+use crate::git_commands::dumb_http::DumbHttp;
+use crate::git_commands::fetch_pack::{
+    fetch, list_refs, read_greeting, Channel, Greeting, Negotiator, Protocol, RefAdvertisement,
+    ShallowInfo, ShallowRequest,
+};
+use crate::git_commands::transport::{Service, Transport};
+use crate::models::credential::Credential;
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pkt_line::PktLineReader;
+
+const GIT_PROTOCOL: &str = "Git-Protocol";
+const PROTOCOL_V2: &str = "version=2";
+// some hosts only speak the smart protocol to user agents that look like git
+pub const GIT_USER_AGENT: &str =
+    concat!("git/2.0 (git-starter-rust/", env!("CARGO_PKG_VERSION"), ")");
+
+/// The client side of the smart HTTP protocol: a `GET` of `info/refs` to discover refs, then a
+/// `POST` to the service for each request. Protocol v2 is asked for with the `Git-Protocol`
+/// header; servers that ignore it answer in v0. A plain file server, which answers `info/refs`
+/// with the file of that name, is fetched from over the dumb protocol instead.
+pub struct SmartHttp {
+    endpoint: Endpoint,
+    protocol: Option<Protocol>,
+    dumb: Option<DumbHttp>,
+    git_dir: PathBuf,
+}
+
+/// Where requests are posted, and whether they belong to a v2 conversation.
+struct Endpoint {
+    url: String,
+    service: Service,
+    client: Client,
+    auth: HttpAuth,
+    protocol_v2: bool,
+}
+
+/// Basic authentication for an HTTP remote. Requests go out without credentials until the
+/// server answers `401 Unauthorized`; then the credentials are filled in by the helpers or a
+/// prompt, the request is sent again, and the helpers are told whether the server took them.
+/// Later requests carry them from the start.
+#[derive(Clone)]
+pub struct HttpAuth {
+    url: String,
+    git_dir: PathBuf,
+    credential: Option<Credential>,
+}
+
+impl SmartHttp {
+    pub fn new(url: &str, service: Service, git_dir: &Path) -> Self {
+        let url = url.trim_end_matches('/');
+
+        Self {
+            endpoint: Endpoint {
+                url: url.to_string(),
+                service,
+                client: Client::new(),
+                auth: HttpAuth::new(url, git_dir),
+                protocol_v2: false,
+            },
+            protocol: None,
+            dumb: None,
+            git_dir: git_dir.to_path_buf(),
+        }
+    }
+}
+
+impl Transport for SmartHttp {
+    /// Fetches `info/refs?service=<service>` and lists the server's refs. A v2 server answers
+    /// with its capabilities, so the refs under `prefixes` are asked for with `ls-refs`; a v0
+    /// server sends all of its refs behind a `# service=` banner. Only upload-pack has a v2, so
+    /// receive-pack is not asked for it.
+    fn discover_refs(&mut self, prefixes: &[&str]) -> Result<RefAdvertisement, String> {
+        let endpoint = &mut self.endpoint;
+        let service = endpoint.service.name();
+        let mut get = endpoint
+            .client
+            .get(format!("{}/info/refs?service={}", endpoint.url, service))
+            .header(USER_AGENT, GIT_USER_AGENT);
+
+        if endpoint.service == Service::UploadPack {
+            get = get.header(GIT_PROTOCOL, PROTOCOL_V2);
+        }
+
+        let content_type = format!("application/x-{}-advertisement", service);
+        let response = endpoint.auth.send(get)?;
+        let response = endpoint.check_status(response)?;
+
+        if response_type(&response) != content_type && endpoint.service == Service::UploadPack {
+            let dumb = DumbHttp::new(
+                &endpoint.url,
+                endpoint.client.clone(),
+                endpoint.auth.clone(),
+                &self.git_dir,
+            );
+            let info_refs = response
+                .text()
+                .map_err(|err| format!("unable to access '{}': {}", endpoint.url, err))?;
+            let advertisement = dumb.read_info_refs(&info_refs)?;
+
+            self.dumb = Some(dumb);
+
+            return Ok(advertisement);
+        }
+
+        let response = endpoint.check_type(response, &content_type)?;
+        let mut reader = PktLineReader::new(response);
+        let mut first_line = reader.read_line()?;
+
+        // v2 servers may leave the banner out
+        if first_line.as_deref() == Some(&format!("# service={}", service)) {
+            // the banner is followed by a flush before the advertisement proper
+            if reader.read_line()?.is_some() {
+                return Err(format!("invalid server response from {}", endpoint.url));
+            }
+
+            first_line = reader.read_line()?;
+        }
+
+        let greeting = read_greeting(first_line, &mut reader)?;
+
+        endpoint.protocol_v2 = matches!(greeting, Greeting::V2(_));
+
+        let (protocol, advertisement) = list_refs(greeting, endpoint, prefixes)?;
+
+        self.protocol = Some(protocol);
+
+        Ok(advertisement)
+    }
+
+    fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        shallow: &ShallowRequest,
+        filter: Option<&ObjectFilter>,
+        negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String> {
+        if let Some(dumb) = &mut self.dumb {
+            return dumb.fetch_pack(wants, shallow, filter, negotiator);
+        }
+
+        let protocol = self
+            .protocol
+            .as_ref()
+            .ok_or("fetch_pack called before discover_refs")?;
+
+        fetch(
+            protocol,
+            &mut self.endpoint,
+            wants,
+            shallow,
+            filter,
+            negotiator,
+        )
+    }
+
+    fn send_pack(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        self.endpoint.request(request)
+    }
+}
+
+impl Channel for Endpoint {
+    /// Posts a pkt-line request to the service and returns the response body.
+    fn request(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        let service = self.service.name();
+        let mut post = self
+            .client
+            .post(format!("{}/{}", self.url, service))
+            .header(USER_AGENT, GIT_USER_AGENT)
+            .header(CONTENT_TYPE, format!("application/x-{}-request", service));
+
+        if self.protocol_v2 {
+            post = post.header(GIT_PROTOCOL, PROTOCOL_V2);
+        }
+
+        let response = self.auth.send(post.body(request))?;
+        let response =
+            self.check_response(response, &format!("application/x-{}-result", service))?;
+
+        Ok(Box::new(response))
+    }
+}
+
+impl Endpoint {
+    fn check_response(&self, response: Response, content_type: &str) -> Result<Response, String> {
+        self.check_type(self.check_status(response)?, content_type)
+    }
+
+    fn check_status(&self, response: Response) -> Result<Response, String> {
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(format!("Authentication failed for '{}/'", self.url));
+        }
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "unable to access '{}': The requested URL returned error: {}",
+                self.url,
+                response.status().as_u16()
+            ));
+        }
+
+        Ok(response)
+    }
+
+    fn check_type(&self, response: Response, content_type: &str) -> Result<Response, String> {
+        let actual_type = response_type(&response);
+
+        if actual_type != content_type {
+            return Err(format!(
+                "{} is not a smart HTTP server (content type '{}')",
+                self.url, actual_type
+            ));
+        }
+
+        Ok(response)
+    }
+}
+
+impl HttpAuth {
+    pub fn new(url: &str, git_dir: &Path) -> Self {
+        Self {
+            url: url.to_string(),
+            git_dir: git_dir.to_path_buf(),
+            credential: None,
+        }
+    }
+
+    /// Adds the credentials to `request`, once there are some.
+    pub fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.credential {
+            Some(Credential {
+                username: Some(username),
+                password,
+                ..
+            }) => request.basic_auth(username, password.as_ref()),
+            _ => request,
+        }
+    }
+
+    /// Sends `request`, and sends it again with credentials if the server wants some and we
+    /// have not tried any yet. A `401` that is left is for the caller to report.
+    pub fn send(&mut self, request: RequestBuilder) -> Result<Response, String> {
+        let retry = request.try_clone();
+        let response = self
+            .authorize(request)
+            .send()
+            .map_err(|err| format!("unable to access '{}': {}", self.url, err))?;
+
+        let retry = match retry {
+            Some(retry)
+                if response.status() == StatusCode::UNAUTHORIZED && self.credential.is_none() =>
+            {
+                retry
+            }
+            _ => return Ok(response),
+        };
+        let wanted = Credential::from_url(&self.url)
+            .map_err(|err| format!("unable to access '{}': {}", self.url, err))?;
+        let credential = fill(&self.git_dir, wanted)?;
+
+        self.credential = Some(credential.clone());
+
+        let response = self
+            .authorize(retry)
+            .send()
+            .map_err(|err| format!("unable to access '{}': {}", self.url, err))?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            reject(&self.git_dir, &credential)?;
+        } else if response.status().is_success() {
+            approve(&self.git_dir, &credential)?;
+        }
+
+        Ok(response)
+    }
+}
+
+fn response_type(response: &Response) -> &str {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
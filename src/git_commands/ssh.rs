@@ -0,0 +1,404 @@
+use std::env;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::git_commands::config::load_config;
+use crate::git_commands::fetch_pack::{
+    fetch, list_refs, read_greeting, Channel, Negotiator, Protocol, RefAdvertisement, ShallowInfo,
+    ShallowRequest,
+};
+use crate::git_commands::transport::{Service, Transport};
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pkt_line::PktLineReader;
+
+/// Where an `ssh://[user@]host[:port]/path` or scp-style `[user@]host:path` URL points.
+#[derive(Debug, PartialEq)]
+pub struct SshUrl {
+    /// The host, with the user in front when the URL has one.
+    pub host: String,
+    pub port: Option<String>,
+    pub path: String,
+}
+
+impl SshUrl {
+    /// Parses `url`, returning `None` for anything that is not an SSH URL. As with upstream, a
+    /// colon only makes an scp-style URL when it comes before the first slash.
+    pub fn parse(url: &str) -> Option<Self> {
+        if let Some(rest) = url
+            .strip_prefix("ssh://")
+            .or_else(|| url.strip_prefix("git+ssh://"))
+        {
+            let (authority, path) = rest.split_at(rest.find('/')?);
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.to_string())),
+                None => (authority, None),
+            };
+            // `ssh://host/~user/repo` is relative to a home directory, like `host:~user/repo`
+            let path = match path.strip_prefix("/~") {
+                Some(home_path) => format!("~{}", home_path),
+                None => path.to_string(),
+            };
+
+            return Some(Self {
+                host: host.to_string(),
+                port,
+                path,
+            });
+        }
+
+        if url.contains("://") {
+            return None;
+        }
+
+        let colon = url.find(':')?;
+
+        if url[..colon].contains('/') || colon == 0 {
+            return None;
+        }
+
+        Some(Self {
+            host: url[..colon].to_string(),
+            port: None,
+            path: url[colon + 1..].to_string(),
+        })
+    }
+}
+
+/// Runs `git-upload-pack` or `git-receive-pack` on the remote through ssh and speaks the
+/// protocol over the ssh process's stdin and stdout. The command comes from `GIT_SSH_COMMAND` (run by the shell),
+/// `GIT_SSH` (a program), the `core.sshCommand` config or plain `ssh`, in that order.
+pub struct Ssh {
+    connection: Connection,
+    protocol: Option<Protocol>,
+}
+
+struct Connection {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl Ssh {
+    /// Refuses hosts, ports and paths that start with `-`, which ssh or the remote shell would
+    /// take as options (CVE-2017-1000117), and ends OpenSSH's options with `--` as well.
+    pub fn connect(url: &SshUrl, service: Service, git_dir: &Path) -> Result<Self, String> {
+        if url.host.starts_with('-') {
+            return Err(format!("fatal: strange hostname '{}' blocked\n", url.host));
+        }
+
+        if let Some(port) = url.port.as_ref().filter(|port| port.starts_with('-')) {
+            return Err(format!("fatal: strange port '{}' blocked\n", port));
+        }
+
+        if url.path.starts_with('-') {
+            return Err(format!("fatal: strange pathname '{}' blocked\n", url.path));
+        }
+
+        let (command, use_shell) = match (env::var("GIT_SSH_COMMAND"), env::var("GIT_SSH")) {
+            (Ok(command), _) => (command, true),
+            (_, Ok(program)) => (program, false),
+            _ => match load_config(git_dir)?.get("core.sshCommand") {
+                Some(command) => (command.to_string(), true),
+                None => ("ssh".to_string(), false),
+            },
+        };
+        let program_name = command.split_whitespace().next().unwrap_or("");
+        let openssh = Path::new(program_name).file_name() == Some("ssh".as_ref());
+        let mut args = Vec::new();
+
+        // only OpenSSH is known to pass the variable on; other wrappers just get v0
+        if openssh {
+            args.extend(["-o".to_string(), "SendEnv=GIT_PROTOCOL".to_string()]);
+        }
+
+        if let Some(port) = &url.port {
+            args.extend(["-p".to_string(), port.clone()]);
+        }
+
+        if openssh {
+            args.push("--".to_string());
+        }
+
+        args.push(url.host.clone());
+        args.push(format!("{} {}", service.name(), shell_quote(&url.path)));
+
+        let mut process = if use_shell {
+            let mut process = Command::new("sh");
+
+            process
+                .arg("-c")
+                .arg(format!("{} \"$@\"", command))
+                .arg(&command);
+            process
+        } else {
+            Command::new(&command)
+        };
+        let mut child = process
+            .args(&args)
+            .env("GIT_PROTOCOL", "version=2")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("fatal: cannot run {}: {}\n", program_name, err))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().ok_or("ssh has no stdout")?;
+
+        Ok(Self {
+            connection: Connection {
+                child,
+                stdin,
+                stdout,
+            },
+            protocol: None,
+        })
+    }
+}
+
+impl Transport for Ssh {
+    fn discover_refs(&mut self, prefixes: &[&str]) -> Result<RefAdvertisement, String> {
+        let mut reader = PktLineReader::new(&mut self.connection.stdout);
+        let first_line = reader.read_line()?;
+        let greeting = read_greeting(first_line, &mut reader)?;
+        let (protocol, advertisement) = list_refs(greeting, &mut self.connection, prefixes)?;
+
+        self.protocol = Some(protocol);
+
+        Ok(advertisement)
+    }
+
+    fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        shallow: &ShallowRequest,
+        filter: Option<&ObjectFilter>,
+        negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String> {
+        let protocol = self
+            .protocol
+            .as_ref()
+            .ok_or("fetch_pack called before discover_refs")?;
+
+        fetch(
+            protocol,
+            &mut self.connection,
+            wants,
+            shallow,
+            filter,
+            negotiator,
+        )
+    }
+
+    fn send_pack(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        self.connection.request(request)
+    }
+}
+
+impl Channel for Connection {
+    fn stateless(&self) -> bool {
+        false
+    }
+
+    fn request(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or("the remote end hung up unexpectedly")?;
+
+        stdin
+            .write_all(&request)
+            .and_then(|_| stdin.flush())
+            .map_err(|_| "the remote end hung up unexpectedly".to_string())?;
+
+        Ok(Box::new(&mut self.stdout))
+    }
+}
+
+impl Drop for Connection {
+    /// Ends the conversation with a flush, which upload-pack takes as a goodbye whether or not
+    /// anything was fetched, and waits for ssh to exit.
+    fn drop(&mut self) {
+        if let Some(mut stdin) = self.stdin.take() {
+            let _ = stdin.write_all(b"0000");
+        }
+
+        let _ = self.child.wait();
+    }
+}
+
+/// Quotes `value` for the remote shell, which is what runs the service command.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::git_commands::upload_pack::{advertise_refs, serve_upload_pack};
+    use crate::models::pack::Pack;
+    use crate::models::pkt_line::PktLineWriter;
+    use std::fs;
+
+    /// Connects through a fake ssh that plays back what upload-pack on `remote` would say,
+    /// recording its arguments in `args` next to it.
+    #[cfg(unix)]
+    fn connect_to_fake_ssh(remote: &Path, url: &str) -> (Ssh, tempfile::TempDir) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut advertisement = PktLineWriter::new(Vec::new());
+        let mut response = PktLineWriter::new(Vec::new());
+
+        advertise_refs(remote, &mut advertisement).unwrap();
+        fs::write(dir.path().join("advertisement"), advertisement.into_inner()).unwrap();
+
+        let head = fs::read_to_string(remote.join("refs/heads/main")).unwrap();
+        let mut request = PktLineWriter::new(Vec::new());
+
+        request
+            .write_line(&format!("want {} side-band-64k", head.trim()))
+            .unwrap();
+        request.write_flush().unwrap();
+        request.write_line("done").unwrap();
+
+        let request = request.into_inner();
+
+        serve_upload_pack(
+            remote,
+            &mut PktLineReader::new(request.as_slice()),
+            &mut response,
+            false,
+        )
+        .unwrap();
+        fs::write(dir.path().join("response"), response.into_inner()).unwrap();
+
+        // named like OpenSSH, so it gets OpenSSH's arguments
+        let script = dir.path().join("ssh");
+        let d = dir.path().display();
+
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 echo \"$@\" > {d}/args\n\
+                 cat {d}/advertisement\n\
+                 while read -r line; do case \"$line\" in *done) break;; esac; done\n\
+                 cat {d}/response\n"
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let local = init_git_dir();
+
+        fs::write(
+            local.path().join("config"),
+            format!("[core]\n\tsshCommand = {}\n", script.display()),
+        )
+        .unwrap();
+
+        let ssh = Ssh::connect(
+            &SshUrl::parse(url).unwrap(),
+            Service::UploadPack,
+            local.path(),
+        )
+        .unwrap();
+
+        (ssh, dir)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ssh_runs_upload_pack_through_the_configured_command() {
+        let remote = init_git_dir();
+        let readme = write_blob(remote.path(), "hello\n");
+        let tree = write_tree(remote.path(), &[("100644", "README", &readme)]);
+        let commit = write_commit(remote.path(), &tree, &[], 100, "first");
+        write_ref(remote.path(), "refs/heads/main", &commit);
+
+        let (mut ssh, dir) = connect_to_fake_ssh(remote.path(), "git@example.com:srv/it's.git");
+        let advertisement = ssh.discover_refs(&["refs/heads/"]).unwrap();
+
+        assert_eq!(advertisement.symref_target("HEAD"), Some("refs/heads/main"));
+
+        let (pack, _) = ssh
+            .fetch_pack(
+                &[commit],
+                &ShallowRequest::default(),
+                None,
+                &mut Negotiator::new(remote.path(), &[]).unwrap(),
+            )
+            .unwrap();
+        let pack = Pack::from_bytes(pack).unwrap();
+
+        assert_eq!(pack.index_entries(&|_| Ok(None)).unwrap().len(), 3);
+
+        drop(ssh);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("args")).unwrap(),
+            "-o SendEnv=GIT_PROTOCOL -- git@example.com git-upload-pack 'srv/it'\\''s.git'\n"
+        );
+    }
+
+    #[test]
+    fn connect_refuses_urls_that_would_pass_options_to_ssh() {
+        let local = init_git_dir();
+        let connect = |url: &str| {
+            Ssh::connect(
+                &SshUrl::parse(url).unwrap(),
+                Service::UploadPack,
+                local.path(),
+            )
+            .err()
+            .unwrap()
+        };
+
+        assert_eq!(
+            connect("ssh://-oProxyCommand=touch%20pwned/repo.git"),
+            "fatal: strange hostname '-oProxyCommand=touch%20pwned' blocked\n"
+        );
+        assert_eq!(
+            connect("ssh://example.com:-oProxyCommand=x/repo.git"),
+            "fatal: strange port '-oProxyCommand=x' blocked\n"
+        );
+        assert_eq!(
+            connect("example.com:--upload-pack=touch pwned"),
+            "fatal: strange pathname '--upload-pack=touch pwned' blocked\n"
+        );
+    }
+
+    #[test]
+    fn parse_recognizes_ssh_and_scp_style_urls() {
+        let url = |host: &str, port: Option<&str>, path: &str| {
+            Some(SshUrl {
+                host: host.to_string(),
+                port: port.map(|port| port.to_string()),
+                path: path.to_string(),
+            })
+        };
+
+        assert_eq!(
+            SshUrl::parse("ssh://git@example.com:2222/srv/repo.git"),
+            url("git@example.com", Some("2222"), "/srv/repo.git")
+        );
+        assert_eq!(
+            SshUrl::parse("ssh://example.com/~alice/repo.git"),
+            url("example.com", None, "~alice/repo.git")
+        );
+        assert_eq!(
+            SshUrl::parse("git@example.com:org/repo.git"),
+            url("git@example.com", None, "org/repo.git")
+        );
+        assert_eq!(SshUrl::parse("./dir:with/colon"), None);
+        assert_eq!(SshUrl::parse("https://example.com/repo.git"), None);
+        assert_eq!(SshUrl::parse("/srv/repo.git"), None);
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("/srv/it's.git"), "'/srv/it'\\''s.git'");
+    }
+}
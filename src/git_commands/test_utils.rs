@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use tempfile::TempDir;
+
+pub use crate::git_commands::utils::write_object;
+
+pub fn init_git_dir() -> TempDir {
+    let git_dir = tempfile::tempdir().unwrap();
+
+    fs::create_dir_all(git_dir.path().join("objects")).unwrap();
+    fs::create_dir_all(git_dir.path().join("refs/heads")).unwrap();
+    fs::write(git_dir.path().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+    git_dir
+}
+
+pub fn write_blob(git_dir: &Path, content: &str) -> String {
+    write_object(git_dir, "blob", content.as_bytes()).unwrap()
+}
+
+/// Writes a tree from `(mode, name, sha)` entries, which must already be sorted.
+pub fn write_tree(git_dir: &Path, entries: &[(&str, &str, &str)]) -> String {
+    let mut content = Vec::new();
+
+    for (mode, name, sha) in entries {
+        content.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+        content.extend(hex::decode(sha).unwrap());
+    }
+
+    write_object(git_dir, "tree", &content).unwrap()
+}
+
+pub fn write_commit(
+    git_dir: &Path,
+    tree: &str,
+    parents: &[&str],
+    timestamp: i64,
+    message: &str,
+) -> String {
+    let mut content = format!("tree {}\n", tree);
+
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", parent));
+    }
+
+    content.push_str(&format!(
+        "author A U Thor <author@example.com> {} +0000\ncommitter C O Mitter <committer@example.com> {} +0000\n\n{}\n",
+        timestamp, timestamp, message
+    ));
+
+    write_object(git_dir, "commit", content.as_bytes()).unwrap()
+}
+
+/// Commits a tree holding only `file`, with `content` as both its text and the message.
+pub fn add_commit(git_dir: &Path, parents: &[&str], content: &str, timestamp: i64) -> String {
+    let blob = write_blob(git_dir, content);
+    let tree = write_tree(git_dir, &[("100644", "file", &blob)]);
+
+    write_commit(git_dir, &tree, parents, timestamp, content)
+}
+
+/// Commits a line of empty trees, one per message and a hundred seconds apart, with `main` at
+/// the last. Returns them oldest first.
+pub fn write_history(git_dir: &Path, messages: &[&str]) -> Vec<String> {
+    let tree = write_tree(git_dir, &[]);
+    let mut commits: Vec<String> = Vec::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let parents: Vec<&str> = commits.last().map(String::as_str).into_iter().collect();
+        let commit = write_commit(git_dir, &tree, &parents, 100 * (i as i64 + 1), message);
+
+        commits.push(commit);
+    }
+
+    write_ref(git_dir, "refs/heads/main", commits.last().unwrap());
+
+    commits
+}
+
+pub fn write_ref(git_dir: &Path, name: &str, sha: &str) {
+    let ref_path = git_dir.join(name);
+
+    fs::create_dir_all(ref_path.parent().unwrap()).unwrap();
+    fs::write(ref_path, format!("{}\n", sha)).unwrap();
+}
+
+/// Starts a minimal HTTP/1.1 server on a free local port and returns its base URL. Each
+/// request is answered with the `(content type, body)` that `handler` returns for its method,
+/// path (with query) and body.
+pub fn serve_http<F>(handler: F) -> String
+where
+    F: Fn(&str, &str, &[u8]) -> (&'static str, Vec<u8>) + Send + Sync + 'static,
+{
+    serve_http_or_404(None, move |method, path, body| {
+        Some(handler(method, path, body))
+    })
+}
+
+/// Serves the files under `root` the way a static file server would, for dumb HTTP clients.
+pub fn serve_files(root: PathBuf) -> String {
+    serve_files_with_login(root, None)
+}
+
+/// Like `serve_files`, answering `401 Unauthorized` to requests whose `Authorization` header is
+/// not `login`, when there is one.
+pub fn serve_files_with_login(root: PathBuf, login: Option<&'static str>) -> String {
+    serve_http_or_404(login, move |_, path, _| {
+        let path = path.split('?').next().unwrap_or(path);
+
+        fs::read(root.join(path.trim_start_matches('/')))
+            .ok()
+            .map(|content| ("application/octet-stream", content))
+    })
+}
+
+/// Like `serve_http`, answering `404 Not Found` where `handler` returns `None`.
+fn serve_http_or_404<F>(login: Option<&'static str>, handler: F) -> String
+where
+    F: Fn(&str, &str, &[u8]) -> Option<(&'static str, Vec<u8>)> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handler = handler.clone();
+
+            thread::spawn(move || serve_connection(stream, login, handler.as_ref()));
+        }
+    });
+
+    url
+}
+
+fn serve_connection<F>(stream: TcpStream, login: Option<&str>, handler: &F)
+where
+    F: Fn(&str, &str, &[u8]) -> Option<(&'static str, Vec<u8>)>,
+{
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    // keep-alive: serve requests until the client closes the connection
+    loop {
+        let mut request_line = String::new();
+
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let path = parts.next().unwrap_or("").to_string();
+        let mut content_length = 0;
+        let mut authorization = None;
+
+        loop {
+            let mut header = String::new();
+
+            reader.read_line(&mut header).unwrap();
+
+            if header.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+
+        reader.read_exact(&mut body).unwrap();
+
+        let authorized = login.is_none() || login == authorization.as_deref();
+        let (status, (content_type, response)) = match handler(&method, &path, &body) {
+            _ if !authorized => ("401 Unauthorized", ("text/plain", Vec::new())),
+            Some(answer) => ("200 OK", answer),
+            None => ("404 Not Found", ("text/plain", Vec::new())),
+        };
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            status,
+            content_type,
+            response.len()
+        );
+
+        writer.write_all(head.as_bytes()).unwrap();
+        writer.write_all(&response).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use crate::git_commands::bundle::{is_bundle_file, BundleTransport};
+use crate::git_commands::fetch_pack::{
+    fetch, list_refs, read_greeting, Channel, Negotiator, Protocol, RefAdvertisement, ShallowInfo,
+    ShallowRequest,
+};
+use crate::git_commands::receive_pack::{self, serve_receive_pack};
+use crate::git_commands::smart_http::SmartHttp;
+use crate::git_commands::ssh::{Ssh, SshUrl};
+use crate::git_commands::upload_pack::{self, serve_upload_pack};
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pkt_line::{PktLineReader, PktLineWriter};
+
+/// The program a transport talks to on the remote: upload-pack to fetch, receive-pack to push.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Service {
+    UploadPack,
+    ReceivePack,
+}
+
+impl Service {
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::UploadPack => "git-upload-pack",
+            Service::ReceivePack => "git-receive-pack",
+        }
+    }
+}
+
+/// A way of talking to a service on a remote: list its refs, then fetch a pack from
+/// upload-pack or send one to receive-pack.
+pub trait Transport {
+    /// Lists the remote's refs. `prefixes` is a hint for servers that can filter refs; others
+    /// list them all.
+    fn discover_refs(&mut self, prefixes: &[&str]) -> Result<RefAdvertisement, String>;
+
+    /// Fetches a pack holding `wants` and everything they reach, leaving out what negotiation
+    /// finds we already have and what `filter` leaves to be fetched later. The history is cut
+    /// as `shallow` asks, and the commits that became shallow come back with the pack. Must be
+    /// called after `discover_refs`.
+    fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        shallow: &ShallowRequest,
+        filter: Option<&ObjectFilter>,
+        negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String>;
+
+    /// Sends receive-pack a request of ref update commands and a pack, returning its answer.
+    /// Must be called after `discover_refs`.
+    fn send_pack(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String>;
+}
+
+/// A `file://` remote, served by running upload-pack or receive-pack in-process on the
+/// repository.
+pub struct FileTransport {
+    server: InProcessServer,
+    protocol: Option<Protocol>,
+}
+
+struct InProcessServer {
+    git_dir: PathBuf,
+    service: Service,
+}
+
+impl Transport for FileTransport {
+    fn discover_refs(&mut self, prefixes: &[&str]) -> Result<RefAdvertisement, String> {
+        let mut greeting = PktLineWriter::new(Vec::new());
+
+        match self.server.service {
+            Service::UploadPack => {
+                upload_pack::advertise_refs(&self.server.git_dir, &mut greeting)?
+            }
+            Service::ReceivePack => {
+                receive_pack::advertise_refs(&self.server.git_dir, &mut greeting)?
+            }
+        }
+
+        let bytes = greeting.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+        let first_line = reader.read_line()?;
+        let greeting = read_greeting(first_line, &mut reader)?;
+        let (protocol, advertisement) = list_refs(greeting, &mut self.server, prefixes)?;
+
+        self.protocol = Some(protocol);
+
+        Ok(advertisement)
+    }
+
+    fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        shallow: &ShallowRequest,
+        filter: Option<&ObjectFilter>,
+        negotiator: &mut Negotiator,
+    ) -> Result<(Vec<u8>, ShallowInfo), String> {
+        let protocol = self
+            .protocol
+            .as_ref()
+            .ok_or("fetch_pack called before discover_refs")?;
+
+        fetch(
+            protocol,
+            &mut self.server,
+            wants,
+            shallow,
+            filter,
+            negotiator,
+        )
+    }
+
+    fn send_pack(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        self.server.request(request)
+    }
+}
+
+impl Channel for InProcessServer {
+    fn request(&mut self, request: Vec<u8>) -> Result<Box<dyn Read + '_>, String> {
+        let mut response = PktLineWriter::new(Vec::new());
+        let mut reader = PktLineReader::new(request.as_slice());
+
+        match self.service {
+            Service::UploadPack => {
+                serve_upload_pack(&self.git_dir, &mut reader, &mut response, true)?
+            }
+            Service::ReceivePack => serve_receive_pack(&self.git_dir, reader, &mut response)?,
+        }
+
+        Ok(Box::new(Cursor::new(response.into_inner())))
+    }
+}
+
+/// Picks the transport for talking to `service` at `url`: smart HTTP (or dumb, if that is all the
+/// server speaks) for `http://` and `https://`, an in-process server for `file://` (or the file
+/// itself, for a bundle) and ssh for `ssh://` and scp-style `host:path` URLs. `git_dir` is the local repository, whose config may say how to run ssh.
+pub fn open_transport(
+    url: &str,
+    git_dir: &Path,
+    service: Service,
+) -> Result<Box<dyn Transport>, String> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(Box::new(SmartHttp::new(url, service, git_dir)));
+    }
+
+    if let Some(path) = url.strip_prefix("file://") {
+        if is_bundle_file(Path::new(path)) {
+            return Ok(Box::new(BundleTransport::open(Path::new(path), git_dir)?));
+        }
+
+        let git_dir = find_git_dir(Path::new(path))
+            .ok_or_else(|| format!("fatal: '{}' does not appear to be a git repository\n", path))?;
+
+        return Ok(Box::new(FileTransport {
+            server: InProcessServer { git_dir, service },
+            protocol: None,
+        }));
+    }
+
+    if let Some(ssh_url) = SshUrl::parse(url) {
+        return Ok(Box::new(Ssh::connect(&ssh_url, service, git_dir)?));
+    }
+
+    Err(format!(
+        "fatal: unable to find remote helper for '{}'\n",
+        url
+    ))
+}
+
+/// The URL to open for a remote: URLs as they are, paths to repositories or bundles on disk as
+/// `file://` URLs.
+pub fn transport_url(url: &str) -> Result<String, String> {
+    if is_url(url) {
+        return Ok(url.to_string());
+    }
+
+    if find_git_dir(Path::new(url)).is_none() && !is_bundle_file(Path::new(url)) {
+        return Err(format!(
+            "fatal: '{}' does not appear to be a git repository\n",
+            url
+        ));
+    }
+
+    let path = fs::canonicalize(url).map_err(|err| err.to_string())?;
+
+    Ok(format!("file://{}", path.display()))
+}
+
+/// Whether `url` names a remote rather than a path on disk.
+pub fn is_url(url: &str) -> bool {
+    url.contains("://") || SshUrl::parse(url).is_some()
+}
+
+/// The git directory of the repository at `path`: `path/.git` for a working tree, or `path`
+/// itself for a bare repository.
+pub fn find_git_dir(path: &Path) -> Option<PathBuf> {
+    let dot_git = path.join(".git");
+
+    if dot_git.join("objects").is_dir() {
+        Some(dot_git)
+    } else if path.join("objects").is_dir() && path.join("HEAD").is_file() {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
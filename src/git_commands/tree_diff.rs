@@ -0,0 +1,241 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::git_commands::utils::read_object;
+use crate::models::object::Object;
+use crate::models::tree::{Tree, TreeEntry, TreeEntryMode};
+
+pub fn read_tree(git_dir: &Path, sha: &str) -> Result<Tree, String> {
+    match read_object(git_dir, sha)?.object {
+        Object::Tree(tree) => Ok(tree),
+        other => Err(format!(
+            "object {} is a {}, not a tree",
+            sha,
+            other.get_type()
+        )),
+    }
+}
+
+/// Lists the file paths that differ between two trees, recursing into subdirectories.
+/// A missing `old_tree` is treated as the empty tree.
+pub fn changed_paths(
+    git_dir: &Path,
+    old_tree: Option<&str>,
+    new_tree: &str,
+) -> Result<Vec<String>, String> {
+    let mut paths = Vec::new();
+
+    diff_trees(git_dir, old_tree, Some(new_tree), "", &mut paths)?;
+
+    Ok(paths)
+}
+
+fn diff_trees(
+    git_dir: &Path,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    prefix: &str,
+    paths: &mut Vec<String>,
+) -> Result<(), String> {
+    let old_entries = tree_entries(git_dir, old_tree)?;
+    let new_entries = tree_entries(git_dir, new_tree)?;
+    let mut old_iter = old_entries.iter().peekable();
+    let mut new_iter = new_entries.iter().peekable();
+
+    loop {
+        let ordering = match (old_iter.peek(), new_iter.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(old), Some(new)) => old.name.cmp(&new.name),
+        };
+
+        match ordering {
+            Ordering::Less => diff_entries(git_dir, old_iter.next(), None, prefix, paths)?,
+            Ordering::Greater => diff_entries(git_dir, None, new_iter.next(), prefix, paths)?,
+            Ordering::Equal => {
+                diff_entries(git_dir, old_iter.next(), new_iter.next(), prefix, paths)?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_entries(
+    git_dir: &Path,
+    old: Option<&TreeEntry>,
+    new: Option<&TreeEntry>,
+    prefix: &str,
+    paths: &mut Vec<String>,
+) -> Result<(), String> {
+    if let (Some(old), Some(new)) = (old, new) {
+        if old.sha == new.sha && old.mode == new.mode {
+            return Ok(());
+        }
+    }
+
+    let name = old
+        .or(new)
+        .map(|entry| entry.name.as_str())
+        .unwrap_or_default();
+    let path = format!("{}{}", prefix, name);
+    let subtree = |entry: Option<&TreeEntry>| {
+        entry
+            .filter(|entry| entry.mode == TreeEntryMode::Directory)
+            .map(|entry| entry.sha.clone())
+    };
+    let old_subtree = subtree(old);
+    let new_subtree = subtree(new);
+    let old_is_file = old.is_some() && old_subtree.is_none();
+    let new_is_file = new.is_some() && new_subtree.is_none();
+
+    if old_is_file || new_is_file {
+        paths.push(path.clone());
+    }
+
+    if old_subtree.is_some() || new_subtree.is_some() {
+        diff_trees(
+            git_dir,
+            old_subtree.as_deref(),
+            new_subtree.as_deref(),
+            &format!("{}/", path),
+            paths,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the tree's entries sorted by name (a file and a directory never share a name within
+/// one tree, so plain name order lines both sides up for the merge in `diff_trees`).
+fn tree_entries(git_dir: &Path, sha: Option<&str>) -> Result<Vec<TreeEntry>, String> {
+    let mut entries = match sha {
+        Some(sha) => read_tree(git_dir, sha)?.tree_entries,
+        None => Vec::new(),
+    };
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(entries)
+}
+
+/// Finds the entry at a slash-separated `path` inside a tree.
+pub fn lookup_path(git_dir: &Path, tree: &str, path: &str) -> Result<Option<TreeEntry>, String> {
+    let mut current_tree = tree.to_string();
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+
+    while let Some(component) = components.next() {
+        let entry = match read_tree(git_dir, &current_tree)?
+            .tree_entries
+            .into_iter()
+            .find(|entry| entry.name == component)
+        {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+
+        if entry.mode != TreeEntryMode::Directory {
+            return Ok(None);
+        }
+
+        current_tree = entry.sha;
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{init_git_dir, write_blob, write_tree};
+
+    #[test]
+    fn changed_paths_lists_added_modified_and_removed_files() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let two = write_blob(git_dir.path(), "two");
+        let old_sub = write_tree(git_dir.path(), &[("100644", "a.txt", &one)]);
+        let new_sub = write_tree(
+            git_dir.path(),
+            &[("100644", "a.txt", &two), ("100644", "b.txt", &one)],
+        );
+        let old_tree = write_tree(
+            git_dir.path(),
+            &[
+                ("100644", "gone.txt", &one),
+                ("100644", "same.txt", &one),
+                ("40000", "src", &old_sub),
+            ],
+        );
+        let new_tree = write_tree(
+            git_dir.path(),
+            &[("100644", "same.txt", &one), ("40000", "src", &new_sub)],
+        );
+
+        let paths = changed_paths(git_dir.path(), Some(&old_tree), &new_tree).unwrap();
+
+        assert_eq!(paths, vec!["gone.txt", "src/a.txt", "src/b.txt"]);
+    }
+
+    #[test]
+    fn changed_paths_against_empty_tree_lists_every_file() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let sub = write_tree(git_dir.path(), &[("100644", "a.txt", &one)]);
+        let tree = write_tree(
+            git_dir.path(),
+            &[("100644", "README", &one), ("40000", "src", &sub)],
+        );
+
+        let paths = changed_paths(git_dir.path(), None, &tree).unwrap();
+
+        assert_eq!(paths, vec!["README", "src/a.txt"]);
+    }
+
+    #[test]
+    fn changed_paths_handles_file_replaced_by_directory() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let sub = write_tree(git_dir.path(), &[("100644", "inner", &one)]);
+        let old_tree = write_tree(git_dir.path(), &[("100644", "thing", &one)]);
+        let new_tree = write_tree(git_dir.path(), &[("40000", "thing", &sub)]);
+
+        let paths = changed_paths(git_dir.path(), Some(&old_tree), &new_tree).unwrap();
+
+        assert_eq!(paths, vec!["thing", "thing/inner"]);
+    }
+
+    #[test]
+    fn lookup_path_finds_nested_entries() {
+        let git_dir = init_git_dir();
+        let one = write_blob(git_dir.path(), "one");
+        let sub = write_tree(git_dir.path(), &[("100644", "a.txt", &one)]);
+        let tree = write_tree(git_dir.path(), &[("40000", "src", &sub)]);
+
+        let entry = lookup_path(git_dir.path(), &tree, "src/a.txt")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(entry.sha, one);
+        assert_eq!(
+            lookup_path(git_dir.path(), &tree, "src")
+                .unwrap()
+                .unwrap()
+                .sha,
+            sub
+        );
+        assert_eq!(
+            lookup_path(git_dir.path(), &tree, "src/missing").unwrap(),
+            None
+        );
+        assert_eq!(
+            lookup_path(git_dir.path(), &tree, "src/a.txt/x").unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::git_commands::packs::load_packs;
+use crate::git_commands::refs::list_refs;
+use crate::git_commands::rev_walk::RevWalk;
+use crate::git_commands::utils::write_atomically;
+
+const USAGE: &str = "usage: git update-server-info";
+
+/// Writes the files a dumb HTTP client reads in place of a ref advertisement: `info/refs`, every
+/// ref as `<sha>\t<name>` followed by its peeled target as `<name>^{}` when it is a tag, and
+/// `objects/info/packs`, a `P <name>.pack` line for each pack. Run it after every update of a
+/// repository served from a plain file server.
+pub fn update_server_info<W: Write>(
+    args: &[&str],
+    git_dir: &Path,
+    _writer: &mut W,
+) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    write_server_info(git_dir)
+}
+
+pub fn write_server_info(git_dir: &Path) -> Result<(), String> {
+    let mut info_refs = String::new();
+
+    for (name, sha) in list_refs(git_dir)? {
+        let peeled = RevWalk::peel(git_dir, &sha)?;
+
+        info_refs.push_str(&format!("{}\t{}\n", sha, name));
+
+        if peeled != sha {
+            info_refs.push_str(&format!("{}\t{}^{{}}\n", peeled, name));
+        }
+    }
+
+    let mut packs = String::new();
+
+    for pack_file in load_packs(git_dir)? {
+        packs.push_str(&format!("P {}.pack\n", pack_file.name));
+    }
+
+    // upstream ends the list with an empty line
+    packs.push('\n');
+
+    for dir in [git_dir.join("info"), git_dir.join("objects/info")] {
+        fs::create_dir_all(&dir).map_err(|err| format!("error creating {:?}: {}", dir, err))?;
+    }
+
+    write_atomically(&git_dir.join("info/refs"), info_refs.as_bytes())?;
+    write_atomically(&git_dir.join("objects/info/packs"), packs.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_object, write_ref, write_tree,
+    };
+
+    #[test]
+    fn update_server_info_lists_refs_with_peeled_tags() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "content\n");
+        let tree = write_tree(git_dir.path(), &[("100644", "file", &blob)]);
+        let commit = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let tag = write_object(
+            git_dir.path(),
+            "tag",
+            format!(
+                "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 100 +0000\n\nv1\n",
+                commit
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_ref(git_dir.path(), "refs/heads/main", &commit);
+        write_ref(git_dir.path(), "refs/tags/v1", &tag);
+
+        update_server_info(&[], git_dir.path(), &mut Vec::new()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(git_dir.path().join("info/refs")).unwrap(),
+            format!(
+                "{}\trefs/heads/main\n{}\trefs/tags/v1\n{}\trefs/tags/v1^{{}}\n",
+                commit, tag, commit
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(git_dir.path().join("objects/info/packs")).unwrap(),
+            "\n"
+        );
+    }
+}
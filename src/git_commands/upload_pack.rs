@@ -0,0 +1,1199 @@
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::git_commands::fetch_pack::{read_section, RefAdvertisement, AGENT, NULL_SHA};
+use crate::git_commands::object_walk::{list_objects, tag_target};
+use crate::git_commands::refs::{list_refs, read_symref, resolve_ref, resolve_revision};
+use crate::git_commands::rev_walk::{CommitInfo, RevWalk};
+use crate::git_commands::transport::find_git_dir;
+use crate::git_commands::utils::{is_sha, object_exists, read_raw_object};
+use crate::models::object_filter::ObjectFilter;
+use crate::models::pack::write_pack;
+use crate::models::pkt_line::{
+    Packet, PktLineReader, PktLineWriter, MAX_PACKET_SIZE, SIDEBAND_DATA, SIDEBAND_PROGRESS,
+};
+
+const USAGE: &str = "usage: git upload-pack [--stateless-rpc] [--advertise-refs] <directory>";
+const CAPABILITIES: [&str; 12] = [
+    "multi_ack_detailed",
+    "side-band-64k",
+    "side-band",
+    "ofs-delta",
+    "shallow",
+    "deepen-since",
+    "deepen-not",
+    "deepen-relative",
+    "no-progress",
+    "filter",
+    // partial clones rely on these to fetch what they left out, as long as a ref reaches it
+    "allow-tip-sha1-in-want",
+    "allow-reachable-sha1-in-want",
+];
+/// Plain `side-band` predates 64k packets and keeps to upstream's old 1000-byte limit.
+const SMALL_PACKET_SIZE: usize = 1000;
+
+/// Serves a repository to a fetching client over stdin and stdout, the way upstream's
+/// `git-upload-pack` does when a client runs it through ssh or `--upload-pack`: the ref
+/// advertisement (or the v2 capabilities when `GIT_PROTOCOL` asks for `version=2`), then one
+/// conversation. `--advertise-refs` stops after the advertisement and `--stateless-rpc` skips
+/// it and answers a single request, as a smart HTTP server needs. Once the client is
+/// listening, errors are sent to it as an `ERR` packet.
+pub fn upload_pack<R: Read, W: Write>(
+    args: &[&str],
+    reader: R,
+    writer: &mut W,
+) -> Result<(), String> {
+    let mut stateless = false;
+    let mut advertise_only = false;
+    let mut directory = None;
+
+    for arg in args {
+        match *arg {
+            "--stateless-rpc" => stateless = true,
+            "--advertise-refs" | "--http-backend-info-refs" => advertise_only = true,
+            _ if arg.starts_with('-') || directory.is_some() => return Err(USAGE.to_string()),
+            _ => directory = Some(*arg),
+        }
+    }
+
+    let directory = directory.ok_or_else(|| USAGE.to_string())?;
+    // clients ask for protocol v2 through the environment ssh and `--upload-pack` pass on
+    let v2 = env::var("GIT_PROTOCOL")
+        .is_ok_and(|protocol| protocol.split(':').any(|item| item == "version=2"));
+    let mut writer = PktLineWriter::new(writer);
+    let served = find_git_dir(Path::new(directory))
+        .ok_or_else(|| format!("'{}' does not appear to be a git repository", directory))
+        .and_then(|git_dir| {
+            let mut reader = PktLineReader::new(reader);
+
+            if v2 {
+                if advertise_only || !stateless {
+                    advertise_v2(&mut writer)?;
+                }
+
+                if advertise_only {
+                    return Ok(());
+                }
+
+                return serve_v2(&git_dir, &mut reader, &mut writer, stateless);
+            }
+
+            if advertise_only || !stateless {
+                advertise_refs(&git_dir, &mut writer)?;
+            }
+
+            if advertise_only {
+                return Ok(());
+            }
+
+            serve_upload_pack(&git_dir, &mut reader, &mut writer, stateless)
+        });
+
+    match served {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            writer.write_line(&format!("ERR {}", err.trim_end()))?;
+            writer.flush()
+        }
+    }
+}
+
+/// The refs upload-pack offers: `HEAD` first, then every ref with annotated tags followed by
+/// their peeled `^{}` entries, and the capabilities including where `HEAD` points.
+pub fn ref_advertisement(git_dir: &Path) -> Result<RefAdvertisement, String> {
+    let mut advertisement = RefAdvertisement {
+        capabilities: CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+        ..RefAdvertisement::default()
+    };
+
+    if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+        advertisement.refs.push(("HEAD".to_string(), head));
+
+        if let Some(target) = read_symref(git_dir, "HEAD")? {
+            advertisement
+                .capabilities
+                .push(format!("symref=HEAD:{}", target));
+        }
+    }
+
+    advertisement.capabilities.push(AGENT.to_string());
+
+    for (name, sha) in list_refs(git_dir)? {
+        let peeled = RevWalk::peel(git_dir, &sha)?;
+
+        advertisement.refs.push((name.clone(), sha.clone()));
+
+        if peeled != sha {
+            advertisement.refs.push((format!("{}^{{}}", name), peeled));
+        }
+    }
+
+    Ok(advertisement)
+}
+
+/// Writes the protocol v0 ref advertisement, with the capabilities after a NUL on the first
+/// line. A repository without refs advertises a `capabilities^{}` placeholder instead.
+pub fn advertise_refs<W: Write>(
+    git_dir: &Path,
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let advertisement = ref_advertisement(git_dir)?;
+    let capabilities = advertisement.capabilities.join(" ");
+
+    match advertisement.refs.split_first() {
+        Some(((name, sha), rest)) => {
+            writer.write_line(&format!("{} {}\0{}", sha, name, capabilities))?;
+
+            for (name, sha) in rest {
+                writer.write_line(&format!("{} {}", sha, name))?;
+            }
+        }
+        None => writer.write_line(&format!("{} capabilities^{{}}\0{}", NULL_SHA, capabilities))?,
+    }
+
+    writer.write_flush()
+}
+
+/// What a client asks for before the haves: the objects it wants, the capabilities it picked,
+/// the commits its shallow history already stops at, and where it wants the history under the
+/// wants cut: with `deepen`, how many commits deep, with `deepen-since`, at commits older than
+/// a timestamp, and with `deepen-not`, at commits reachable from the named refs. A partial
+/// clone also sends the `filter` of objects to leave out.
+#[derive(Default)]
+struct UploadRequest {
+    wants: Vec<String>,
+    capabilities: Vec<String>,
+    client_shallow: HashSet<String>,
+    depth: Option<usize>,
+    since: Option<i64>,
+    exclude: Vec<String>,
+    filter: Option<ObjectFilter>,
+}
+
+impl UploadRequest {
+    fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|cap| cap == name)
+    }
+
+    /// Takes a `want` (with the capabilities v0 puts after the first one), `shallow`,
+    /// `deepen*` or `filter` line, returning false for anything else.
+    fn parse_line(&mut self, git_dir: &Path, line: &str) -> Result<bool, String> {
+        let mut fields = line.split(' ');
+
+        match (fields.next(), fields.next()) {
+            (Some("want"), Some(sha)) if is_sha(sha) => {
+                if !object_exists(git_dir, sha)? {
+                    return Err(format!("upload-pack: not our ref {}", sha));
+                }
+
+                self.capabilities.extend(fields.map(|cap| cap.to_string()));
+                self.wants.push(sha.to_string());
+            }
+            (Some("shallow"), Some(sha)) if is_sha(sha) => {
+                self.client_shallow.insert(sha.to_string());
+            }
+            (Some("deepen"), Some(depth)) => match depth.parse() {
+                Ok(depth) if depth > 0 => self.depth = Some(depth),
+                _ => return Err(format!("protocol error: invalid depth '{}'", depth)),
+            },
+            (Some("deepen-since"), Some(since)) => match since.parse() {
+                Ok(since) => self.since = Some(since),
+                _ => return Err(format!("protocol error: invalid date '{}'", since)),
+            },
+            (Some("deepen-not"), Some(name)) => self.exclude.push(name.to_string()),
+            (Some("filter"), Some(spec)) => self.filter = Some(ObjectFilter::parse(spec)?),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Refuses wants that no advertised ref reaches, so objects left behind by deleted or
+    /// rewound refs are not handed out.
+    fn check_wants(&self, git_dir: &Path) -> Result<(), String> {
+        let mut tips: Vec<String> = ref_advertisement(git_dir)?
+            .refs
+            .into_iter()
+            .map(|(_, sha)| sha)
+            .collect();
+
+        tips.sort();
+        tips.dedup();
+
+        let others: Vec<&String> = self
+            .wants
+            .iter()
+            .filter(|want| !tips.contains(want))
+            .collect();
+
+        if others.is_empty() {
+            return Ok(());
+        }
+
+        let reachable: HashSet<String> =
+            objects_within(git_dir, &tips, &[], &HashSet::new(), &HashSet::new())?
+                .into_iter()
+                .map(|(sha, _)| sha)
+                .collect();
+
+        match others.into_iter().find(|want| !reachable.contains(*want)) {
+            Some(want) => Err(format!("upload-pack: not our ref {}", want)),
+            None => Ok(()),
+        }
+    }
+
+    /// Works out how the client's history moves when it asked to deepen it, one way or another.
+    fn shallow_update(
+        &self,
+        git_dir: &Path,
+        walk: &RevWalk,
+    ) -> Result<Option<ShallowUpdate>, String> {
+        let by_rev_list = self.since.is_some() || !self.exclude.is_empty();
+
+        match self.depth {
+            Some(_) if by_rev_list => {
+                Err("deepen and deepen-since (or deepen-not) cannot be used together".to_string())
+            }
+            Some(depth) => deepen(git_dir, walk, self, depth).map(Some),
+            None if by_rev_list => deepen_by_rev_list(git_dir, walk, self).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// How a `deepen` moves a shallow client's history: the commits that become its new shallow
+/// ends, the ones it used to stop at that now get their parents, and every commit the pack
+/// stops at.
+struct ShallowUpdate {
+    shallow: Vec<String>,
+    unshallow: Vec<String>,
+    boundary: HashSet<String>,
+}
+
+impl ShallowUpdate {
+    fn write_lines<W: Write>(&self, writer: &mut PktLineWriter<W>) -> Result<(), String> {
+        for sha in &self.shallow {
+            writer.write_line(&format!("shallow {}", sha))?;
+        }
+
+        for sha in &self.unshallow {
+            writer.write_line(&format!("unshallow {}", sha))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serves an upload-pack conversation: reads the wants, answers a `deepen` with the commits
+/// the client's history will now stop at, then reads the haves, acknowledging the ones we
+/// share (each with `ACK <sha> common` under `multi_ack_detailed`, otherwise just the first),
+/// and finally sends a pack of everything the wants reach that the common haves do not, on the
+/// side-band with progress when the client asked for it. A `stateless` server (as over HTTP)
+/// answers a flush among the haves with `NAK` and stops, since the client repeats itself in the
+/// next request.
+pub fn serve_upload_pack<R: Read, W: Write>(
+    git_dir: &Path,
+    reader: &mut PktLineReader<R>,
+    writer: &mut PktLineWriter<W>,
+    stateless: bool,
+) -> Result<(), String> {
+    let mut request = UploadRequest::default();
+
+    while let Some(line) = reader.read_line()? {
+        if !request.parse_line(git_dir, &line)? {
+            return Err(format!("protocol error: expected want, got '{}'", line));
+        }
+    }
+
+    // a client that wants nothing closes the connection after the flush
+    if request.wants.is_empty() {
+        return Ok(());
+    }
+
+    request.check_wants(git_dir)?;
+
+    let walk = RevWalk::new(git_dir)?;
+    let shallow = request.shallow_update(git_dir, &walk)?;
+
+    if let Some(update) = &shallow {
+        update.write_lines(writer)?;
+        writer.write_flush()?;
+    }
+
+    let multi_ack = request.has_capability("multi_ack_detailed");
+    let mut common: Vec<String> = Vec::new();
+
+    loop {
+        let line = match reader.read_line()? {
+            Some(line) => line,
+            None => {
+                if common.is_empty() || multi_ack {
+                    writer.write_line("NAK")?;
+                }
+
+                if stateless {
+                    return writer.flush();
+                }
+
+                // the client waits for our answer before sending more haves
+                writer.flush()?;
+                continue;
+            }
+        };
+
+        if line == "done" {
+            break;
+        }
+
+        let sha = line
+            .strip_prefix("have ")
+            .ok_or_else(|| format!("protocol error: expected have, got '{}'", line))?;
+
+        if !is_common(git_dir, sha)? {
+            continue;
+        }
+
+        if multi_ack {
+            writer.write_line(&format!("ACK {} common", sha))?;
+        } else if common.is_empty() {
+            writer.write_line(&format!("ACK {}", sha))?;
+        }
+
+        if !common.iter().any(|known| known == sha) {
+            common.push(sha.to_string());
+        }
+    }
+
+    match common.last() {
+        Some(last) if multi_ack => writer.write_line(&format!("ACK {}", last))?,
+        Some(_) => {}
+        None => writer.write_line("NAK")?,
+    }
+
+    let packet_size = if request.has_capability("side-band-64k") {
+        Some(MAX_PACKET_SIZE)
+    } else if request.has_capability("side-band") {
+        Some(SMALL_PACKET_SIZE)
+    } else {
+        None
+    };
+
+    send_pack_data(
+        git_dir,
+        &walk,
+        &request,
+        shallow.as_ref(),
+        &common,
+        packet_size,
+        writer,
+    )
+}
+
+/// Writes the protocol v2 capability advertisement upload-pack opens with.
+pub fn advertise_v2<W: Write>(writer: &mut PktLineWriter<W>) -> Result<(), String> {
+    for line in [
+        "version 2",
+        AGENT,
+        "ls-refs=unborn",
+        "fetch=shallow filter",
+        "server-option",
+        "object-format=sha1",
+    ] {
+        writer.write_line(line)?;
+    }
+
+    writer.write_flush()
+}
+
+/// Serves protocol v2 command requests: `command=<name>` and capabilities, a delim packet, the
+/// arguments and a flush. A client ends the conversation with a flush in place of a command;
+/// a `stateless` server answers just one.
+pub fn serve_v2<R: Read, W: Write>(
+    git_dir: &Path,
+    reader: &mut PktLineReader<R>,
+    writer: &mut PktLineWriter<W>,
+    stateless: bool,
+) -> Result<(), String> {
+    loop {
+        let capabilities = match read_section(reader)? {
+            (lines, _) if lines.is_empty() => return Ok(()),
+            (lines, Packet::Delim) => lines,
+            (lines, _) => {
+                return Err(format!(
+                    "protocol error: expected arguments after '{}'",
+                    lines[0]
+                ))
+            }
+        };
+        let (arguments, _) = read_section(reader)?;
+
+        match capabilities
+            .iter()
+            .find_map(|line| line.strip_prefix("command="))
+        {
+            Some("ls-refs") => ls_refs(git_dir, &arguments, writer)?,
+            Some("fetch") => fetch_v2(git_dir, &arguments, writer)?,
+            Some(command) => return Err(format!("unknown command '{}'", command)),
+            None => return Err("protocol error: no command requested".to_string()),
+        }
+
+        if stateless {
+            return Ok(());
+        }
+    }
+}
+
+/// Answers `ls-refs`: `HEAD` and every ref starting with one of the `ref-prefix` arguments (all
+/// of them without any), with `symref-target:` under `symrefs`, `peeled:` under `peel`, and an
+/// unborn `HEAD` under `unborn`.
+fn ls_refs<W: Write>(
+    git_dir: &Path,
+    arguments: &[String],
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let has = |name: &str| arguments.iter().any(|argument| argument == name);
+    let prefixes: Vec<&str> = arguments
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("ref-prefix "))
+        .collect();
+    let wanted =
+        |name: &str| prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix));
+    let symref_target = |name: &str| -> Result<String, String> {
+        Ok(match read_symref(git_dir, name)? {
+            Some(target) if has("symrefs") => format!(" symref-target:{}", target),
+            _ => String::new(),
+        })
+    };
+
+    if wanted("HEAD") {
+        match resolve_ref(git_dir, "HEAD")? {
+            Some(sha) => writer.write_line(&format!("{} HEAD{}", sha, symref_target("HEAD")?))?,
+            None if has("unborn") && read_symref(git_dir, "HEAD")?.is_some() => {
+                writer.write_line(&format!("unborn HEAD{}", symref_target("HEAD")?))?
+            }
+            None => {}
+        }
+    }
+
+    for (name, sha) in list_refs(git_dir)? {
+        if !wanted(&name) {
+            continue;
+        }
+
+        let mut line = format!("{} {}{}", sha, name, symref_target(&name)?);
+        let peeled = RevWalk::peel(git_dir, &sha)?;
+
+        if has("peel") && peeled != sha {
+            line.push_str(&format!(" peeled:{}", peeled));
+        }
+
+        writer.write_line(&line)?;
+    }
+
+    writer.write_flush()
+}
+
+/// Answers a v2 `fetch`. Until the client says `done` it only gets the `acknowledgments`
+/// section; then the `shallow-info` section when it deepened, and the `packfile`, which v2
+/// always sends on the side-band.
+fn fetch_v2<W: Write>(
+    git_dir: &Path,
+    arguments: &[String],
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let mut request = UploadRequest::default();
+    let mut common: Vec<String> = Vec::new();
+    let mut done = false;
+
+    for argument in arguments {
+        if request.parse_line(git_dir, argument)? {
+            continue;
+        }
+
+        match argument.strip_prefix("have ") {
+            Some(sha) => {
+                if is_common(git_dir, sha)? && !common.iter().any(|known| known == sha) {
+                    common.push(sha.to_string());
+                }
+            }
+            None if argument == "done" => done = true,
+            // the rest are flags such as `no-progress` or `deepen-relative`
+            None => request.capabilities.push(argument.clone()),
+        }
+    }
+
+    request.check_wants(git_dir)?;
+
+    if !done {
+        writer.write_line("acknowledgments")?;
+
+        if common.is_empty() {
+            writer.write_line("NAK")?;
+        }
+
+        for sha in &common {
+            writer.write_line(&format!("ACK {}", sha))?;
+        }
+
+        return writer.write_flush();
+    }
+
+    let walk = RevWalk::new(git_dir)?;
+    let shallow = request.shallow_update(git_dir, &walk)?;
+
+    if let Some(update) = &shallow {
+        writer.write_line("shallow-info")?;
+        update.write_lines(writer)?;
+        writer.write_delim()?;
+    }
+
+    writer.write_line("packfile")?;
+    send_pack_data(
+        git_dir,
+        &walk,
+        &request,
+        shallow.as_ref(),
+        &common,
+        Some(MAX_PACKET_SIZE),
+        writer,
+    )
+}
+
+/// Whether a client's `have` is a commit we share, which is all that negotiation counts.
+fn is_common(git_dir: &Path, sha: &str) -> Result<bool, String> {
+    if !is_sha(sha) {
+        return Err(format!("protocol error: expected sha, got '{}'", sha));
+    }
+
+    Ok(object_exists(git_dir, sha)? && read_raw_object(git_dir, sha)?.0 == "commit")
+}
+
+/// Sends the pack for a request, raw or on the side-band in packets of `packet_size` with
+/// progress unless the client asked for `no-progress`, and ends with a flush. After a
+/// `deepen`, the client also needs what is under the commits it no longer stops at. Objects
+/// the request's filter leaves out are not sent unless they were wanted by name.
+fn send_pack_data<W: Write>(
+    git_dir: &Path,
+    walk: &RevWalk,
+    request: &UploadRequest,
+    shallow: Option<&ShallowUpdate>,
+    common: &[String],
+    packet_size: Option<usize>,
+    writer: &mut PktLineWriter<W>,
+) -> Result<(), String> {
+    let mut wants = request.wants.clone();
+
+    for sha in shallow.iter().flat_map(|update| &update.unshallow) {
+        // the client has the commit but none of what is under it yet
+        wants.extend(walk.lookup(sha)?.parents);
+    }
+
+    let boundary = shallow.map_or(&request.client_shallow, |update| &update.boundary);
+    let progress = packet_size.is_some() && !request.has_capability("no-progress");
+    let mut objects = Vec::new();
+
+    for (sha, name) in objects_within(git_dir, &wants, common, &request.client_shallow, boundary)? {
+        let (object_type, content) = read_raw_object(git_dir, &sha)?;
+        // the root tree is level 0, and a name like `dir/file` is two levels below it
+        let depth = if name.is_empty() {
+            0
+        } else {
+            name.split('/').count()
+        };
+        let omitted = request.filter.is_some_and(|filter| {
+            !request.wants.contains(&sha) && filter.omits(&object_type, content.len(), depth)
+        });
+
+        if !omitted {
+            objects.push((object_type, content));
+        }
+    }
+
+    if progress {
+        writer.write_sideband(
+            SIDEBAND_PROGRESS,
+            format!("Enumerating objects: {}, done.\n", objects.len()).as_bytes(),
+        )?;
+    }
+
+    let (pack, _) = write_pack(&objects)?;
+
+    let Some(packet_size) = packet_size else {
+        writer.write_unframed(&pack)?;
+        return writer.flush();
+    };
+
+    // the band byte and the length take five bytes of every packet
+    for chunk in pack.chunks(packet_size - 5) {
+        writer.write_sideband(SIDEBAND_DATA, chunk)?;
+    }
+
+    if progress {
+        writer.write_sideband(
+            SIDEBAND_PROGRESS,
+            format!(
+                "Total {} (delta 0), reused 0 (delta 0), pack-reused 0\n",
+                objects.len()
+            )
+            .as_bytes(),
+        )?;
+    }
+
+    writer.write_flush()
+}
+
+/// Walks `depth` commits down from the wants, or under `deepen-relative` from the commits the
+/// client's history stops at, breadth first so every commit is seen at its shallowest. Commits
+/// at the limit that have parents become shallow, unless the client's history already stops
+/// there, and client shallow commits above the limit are unshallowed.
+fn deepen(
+    git_dir: &Path,
+    walk: &RevWalk,
+    request: &UploadRequest,
+    depth: usize,
+) -> Result<ShallowUpdate, String> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut update = ShallowUpdate {
+        shallow: Vec::new(),
+        unshallow: Vec::new(),
+        boundary: HashSet::new(),
+    };
+
+    let (starts, depth) = if request.has_capability("deepen-relative") {
+        // the shallow commits themselves are the first level, so one more reaches `depth` below
+        let starts = request
+            .client_shallow
+            .iter()
+            .filter(|sha| object_exists(git_dir, sha).unwrap_or(false))
+            .cloned()
+            .collect();
+
+        (starts, depth + 1)
+    } else {
+        (request.wants.clone(), depth)
+    };
+
+    for start in starts {
+        let commit = RevWalk::peel(git_dir, &start)?;
+
+        if read_raw_object(git_dir, &commit)?.0 == "commit" && seen.insert(commit.clone()) {
+            queue.push_back((commit, 1));
+        }
+    }
+
+    while let Some((sha, commit_depth)) = queue.pop_front() {
+        let parents = walk.lookup(&sha)?.parents;
+        let client_shallow = request.client_shallow.contains(&sha);
+
+        if commit_depth >= depth {
+            if !parents.is_empty() {
+                if !client_shallow {
+                    update.shallow.push(sha.clone());
+                }
+
+                update.boundary.insert(sha);
+            }
+
+            continue;
+        }
+
+        if client_shallow {
+            update.unshallow.push(sha);
+        }
+
+        for parent in parents {
+            if seen.insert(parent.clone()) {
+                queue.push_back((parent, commit_depth + 1));
+            }
+        }
+    }
+
+    // client shallow commits the walk never reached stay where they are
+    update.boundary.extend(
+        request
+            .client_shallow
+            .iter()
+            .filter(|sha| !seen.contains(*sha))
+            .cloned(),
+    );
+
+    Ok(update)
+}
+
+/// Cuts the history under the wants at commits older than `deepen-since` and at commits
+/// reachable from the `deepen-not` refs, like upstream's rev-list based deepening: the commits
+/// kept that have a parent left out become shallow, and client shallow commits whose parents
+/// are all kept are unshallowed.
+fn deepen_by_rev_list(
+    git_dir: &Path,
+    walk: &RevWalk,
+    request: &UploadRequest,
+) -> Result<ShallowUpdate, String> {
+    let mut excluded_tips = Vec::new();
+
+    for name in &request.exclude {
+        let sha = resolve_revision(git_dir, name)
+            .map_err(|_| format!("git upload-pack: ambiguous deepen-not: {}", name))?;
+
+        excluded_tips.push(RevWalk::peel(git_dir, &sha)?);
+    }
+
+    let excluded: HashSet<String> =
+        reachable(walk, &excluded_tips, &HashSet::new(), &HashSet::new())?
+            .into_iter()
+            .map(|commit| commit.sha)
+            .collect();
+    let is_kept = |commit: &CommitInfo| {
+        !excluded.contains(&commit.sha)
+            && request.since.is_none_or(|since| commit.timestamp >= since)
+    };
+    let mut kept = HashSet::new();
+    let mut stack = Vec::new();
+
+    for want in &request.wants {
+        let commit = RevWalk::peel(git_dir, want)?;
+
+        if read_raw_object(git_dir, &commit)?.0 == "commit" {
+            stack.push(commit);
+        }
+    }
+
+    while let Some(sha) = stack.pop() {
+        if kept.contains(&sha) {
+            continue;
+        }
+
+        let commit = walk.lookup(&sha)?;
+
+        if is_kept(&commit) {
+            kept.insert(sha);
+            stack.extend(commit.parents);
+        }
+    }
+
+    if kept.is_empty() {
+        return Err("no commits selected for shallow requests".to_string());
+    }
+
+    let mut update = ShallowUpdate {
+        shallow: Vec::new(),
+        unshallow: Vec::new(),
+        boundary: HashSet::new(),
+    };
+
+    for sha in &kept {
+        let complete = walk
+            .lookup(sha)?
+            .parents
+            .iter()
+            .all(|parent| kept.contains(parent));
+        let client_shallow = request.client_shallow.contains(sha);
+
+        if !complete {
+            if !client_shallow {
+                update.shallow.push(sha.clone());
+            }
+
+            update.boundary.insert(sha.clone());
+        } else if client_shallow {
+            update.unshallow.push(sha.clone());
+        }
+    }
+
+    // client shallow commits the walk never reached stay where they are
+    update.boundary.extend(
+        request
+            .client_shallow
+            .iter()
+            .filter(|sha| !kept.contains(*sha))
+            .cloned(),
+    );
+    update.shallow.sort();
+    update.unshallow.sort();
+
+    Ok(update)
+}
+
+/// Everything reachable from `wants` minus the history of `common`, commits first.
+pub fn objects_to_send(
+    git_dir: &Path,
+    wants: &[String],
+    common: &[String],
+) -> Result<Vec<String>, String> {
+    Ok(
+        objects_within(git_dir, wants, common, &HashSet::new(), &HashSet::new())?
+            .into_iter()
+            .map(|(sha, _)| sha)
+            .collect(),
+    )
+}
+
+/// Like [`objects_to_send`] for a shallow client: the history of `common` stops at the
+/// client's shallow commits, since it has nothing beneath them, and the walk from `wants` stops
+/// at the `boundary` commits, whose parents it will not get either. Objects come with their
+/// paths, as [`list_objects`] names them.
+fn objects_within(
+    git_dir: &Path,
+    wants: &[String],
+    common: &[String],
+    client_shallow: &HashSet<String>,
+    boundary: &HashSet<String>,
+) -> Result<Vec<(String, String)>, String> {
+    let walk = RevWalk::new(git_dir)?;
+    let mut pending = Vec::new();
+    let mut tips = Vec::new();
+
+    for want in wants {
+        let mut sha = want.clone();
+
+        loop {
+            let (object_type, content) = read_raw_object(git_dir, &sha)?;
+
+            match object_type.as_str() {
+                "commit" => {
+                    tips.push(sha);
+                    break;
+                }
+                "tag" => {
+                    let target = tag_target(&content)
+                        .ok_or_else(|| format!("tag {} has no object line", sha))?;
+
+                    pending.push((sha, object_type, String::new()));
+                    sha = target;
+                }
+                _ => {
+                    pending.push((sha, object_type, String::new()));
+                    break;
+                }
+            }
+        }
+    }
+
+    let hidden: HashSet<String> = reachable(&walk, common, client_shallow, &HashSet::new())?
+        .into_iter()
+        .map(|commit| commit.sha)
+        .collect();
+    let mut commits = reachable(&walk, &tips, boundary, &hidden)?;
+    let mut uninteresting_trees = Vec::new();
+
+    commits.sort_by_key(|commit| Reverse(commit.timestamp));
+
+    for commit in commits
+        .iter()
+        .filter(|commit| !boundary.contains(&commit.sha))
+    {
+        for parent in commit
+            .parents
+            .iter()
+            .filter(|parent| hidden.contains(*parent))
+        {
+            uninteresting_trees.push(walk.lookup(parent)?.tree);
+        }
+    }
+
+    let mut objects: Vec<(String, String)> = commits
+        .iter()
+        .map(|commit| (commit.sha.clone(), String::new()))
+        .collect();
+
+    objects.extend(list_objects(
+        git_dir,
+        &pending,
+        &commits,
+        &uninteresting_trees,
+    )?);
+
+    Ok(objects)
+}
+
+/// The commits reachable from `tips` without going into `excluded` or past `boundary` commits.
+fn reachable(
+    walk: &RevWalk,
+    tips: &[String],
+    boundary: &HashSet<String>,
+    excluded: &HashSet<String>,
+) -> Result<Vec<CommitInfo>, String> {
+    let mut seen = HashSet::new();
+    let mut stack = tips.to_vec();
+    let mut commits = Vec::new();
+
+    while let Some(sha) = stack.pop() {
+        if excluded.contains(&sha) || !seen.insert(sha.clone()) {
+            continue;
+        }
+
+        let commit = walk.lookup(&sha)?;
+
+        if !boundary.contains(&sha) {
+            stack.extend(commit.parents.iter().cloned());
+        }
+
+        commits.push(commit);
+    }
+
+    Ok(commits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_commands::fetch_pack::{read_pack_response, write_wants, ShallowRequest};
+    use crate::git_commands::test_utils::{
+        init_git_dir, write_blob, write_commit, write_ref, write_tree,
+    };
+    use crate::models::pack::Pack;
+
+    #[test]
+    fn advertise_refs_sends_a_placeholder_for_an_empty_repository() {
+        let git_dir = init_git_dir();
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        advertise_refs(git_dir.path(), &mut writer).unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+        let first_line = reader.read_line().unwrap().unwrap();
+
+        assert!(first_line.starts_with(&format!(
+            "{} capabilities^{{}}\0multi_ack_detailed",
+            NULL_SHA
+        )));
+        assert_eq!(reader.read_line().unwrap(), None);
+    }
+
+    #[test]
+    fn serve_upload_pack_leaves_out_what_the_client_has() {
+        let git_dir = init_git_dir();
+        let old_blob = write_blob(git_dir.path(), "old\n");
+        let old_tree = write_tree(git_dir.path(), &[("100644", "file", &old_blob)]);
+        let old = write_commit(git_dir.path(), &old_tree, &[], 100, "old");
+        let new_blob = write_blob(git_dir.path(), "new\n");
+        let new_tree = write_tree(git_dir.path(), &[("100644", "file", &new_blob)]);
+        let new = write_commit(git_dir.path(), &new_tree, &[&old], 200, "new");
+        write_ref(git_dir.path(), "refs/heads/main", &new);
+        let mut request = PktLineWriter::new(Vec::new());
+        let mut response = PktLineWriter::new(Vec::new());
+
+        write_wants(
+            &mut request,
+            std::slice::from_ref(&new),
+            &["side-band-64k"],
+            &ShallowRequest::default(),
+            None,
+        )
+        .unwrap();
+
+        for have in ["1".repeat(40), old.clone()] {
+            request.write_line(&format!("have {}", have)).unwrap();
+        }
+
+        request.write_line("done").unwrap();
+
+        let request = request.into_inner();
+
+        serve_upload_pack(
+            git_dir.path(),
+            &mut PktLineReader::new(request.as_slice()),
+            &mut response,
+            true,
+        )
+        .unwrap();
+
+        let response = response.into_inner();
+
+        assert!(response.starts_with(format!("0031ACK {}\n", old).as_bytes()));
+
+        let pack = Pack::from_bytes(
+            read_pack_response(PktLineReader::new(response.as_slice()), true).unwrap(),
+        )
+        .unwrap();
+        let mut shas: Vec<String> = pack
+            .index_entries(&|_| Ok(None))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.sha)
+            .collect();
+        let mut expected = vec![new, new_tree, new_blob];
+
+        shas.sort();
+        expected.sort();
+        assert_eq!(shas, expected);
+    }
+
+    #[test]
+    fn serve_upload_pack_cuts_history_at_the_requested_depth() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let third = write_commit(git_dir.path(), &tree, &[&second], 300, "third");
+        write_ref(git_dir.path(), "refs/heads/main", &third);
+        let mut request = PktLineWriter::new(Vec::new());
+        let mut response = PktLineWriter::new(Vec::new());
+
+        request
+            .write_line(&format!("want {} shallow", third))
+            .unwrap();
+        request.write_line(&format!("shallow {}", third)).unwrap();
+        request.write_line("deepen 2").unwrap();
+        request.write_flush().unwrap();
+        request.write_line(&format!("have {}", third)).unwrap();
+        request.write_line("done").unwrap();
+
+        let request = request.into_inner();
+
+        serve_upload_pack(
+            git_dir.path(),
+            &mut PktLineReader::new(request.as_slice()),
+            &mut response,
+            false,
+        )
+        .unwrap();
+
+        let response = response.into_inner();
+        let mut reader = PktLineReader::new(response.as_slice());
+
+        assert_eq!(
+            reader.read_lines().unwrap(),
+            vec![
+                format!("shallow {}", second),
+                format!("unshallow {}", third)
+            ]
+        );
+        assert_eq!(reader.read_line().unwrap(), Some(format!("ACK {}", third)));
+
+        let mut pack = Vec::new();
+
+        reader.into_inner().read_to_end(&mut pack).unwrap();
+
+        let mut shas: Vec<String> = Pack::from_bytes(pack)
+            .unwrap()
+            .index_entries(&|_| Ok(None))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.sha)
+            .collect();
+
+        let mut expected = vec![second, tree];
+
+        shas.sort();
+        expected.sort();
+        assert_eq!(shas, expected);
+    }
+
+    #[test]
+    fn serve_upload_pack_cuts_history_by_date_and_excluded_refs() {
+        let git_dir = init_git_dir();
+        let tree = write_tree(git_dir.path(), &[]);
+        let first = write_commit(git_dir.path(), &tree, &[], 100, "first");
+        let second = write_commit(git_dir.path(), &tree, &[&first], 200, "second");
+        let third = write_commit(git_dir.path(), &tree, &[&second], 300, "third");
+        let fourth = write_commit(git_dir.path(), &tree, &[&third], 400, "fourth");
+        write_ref(git_dir.path(), "refs/tags/v2", &second);
+        write_ref(git_dir.path(), "refs/heads/main", &fourth);
+
+        for (deepen, shallow) in [("deepen-since 200", &second), ("deepen-not v2", &third)] {
+            let mut request = PktLineWriter::new(Vec::new());
+            let mut response = PktLineWriter::new(Vec::new());
+
+            request
+                .write_line(&format!("want {} shallow deepen-since deepen-not", fourth))
+                .unwrap();
+            request.write_line(deepen).unwrap();
+            request.write_flush().unwrap();
+            request.write_line("done").unwrap();
+
+            let request = request.into_inner();
+
+            serve_upload_pack(
+                git_dir.path(),
+                &mut PktLineReader::new(request.as_slice()),
+                &mut response,
+                false,
+            )
+            .unwrap();
+
+            let response = response.into_inner();
+            let mut reader = PktLineReader::new(response.as_slice());
+
+            assert_eq!(
+                reader.read_lines().unwrap(),
+                vec![format!("shallow {}", shallow)]
+            );
+        }
+    }
+
+    #[test]
+    fn serve_upload_pack_refuses_wants_and_haves_that_are_not_shas() {
+        let git_dir = init_git_dir();
+        // 40 bytes, but not 40 hex digits
+        let bad = format!("aé{}", "0".repeat(37));
+
+        for line in [format!("want {}", bad), format!("shallow {}", bad)] {
+            let mut request = PktLineWriter::new(Vec::new());
+            request.write_line(&line).unwrap();
+            request.write_flush().unwrap();
+            let request = request.into_inner();
+
+            assert!(serve_upload_pack(
+                git_dir.path(),
+                &mut PktLineReader::new(request.as_slice()),
+                &mut PktLineWriter::new(Vec::new()),
+                true,
+            )
+            .unwrap_err()
+            .starts_with("protocol error: expected want"));
+        }
+
+        assert!(is_common(git_dir.path(), &bad).is_err());
+    }
+
+    #[test]
+    fn serve_upload_pack_only_hands_out_what_a_ref_reaches() {
+        let git_dir = init_git_dir();
+        let blob = write_blob(git_dir.path(), "kept\n");
+        let tree = write_tree(git_dir.path(), &[("100644", "file", &blob)]);
+        let kept = write_commit(git_dir.path(), &tree, &[], 100, "kept");
+        let deleted = write_commit(git_dir.path(), &tree, &[&kept], 200, "deleted");
+        write_ref(git_dir.path(), "refs/heads/main", &kept);
+
+        let serve = |want: &str| {
+            let mut request = PktLineWriter::new(Vec::new());
+            request.write_line(&format!("want {}", want)).unwrap();
+            request.write_flush().unwrap();
+            request.write_line("done").unwrap();
+            let request = request.into_inner();
+
+            serve_upload_pack(
+                git_dir.path(),
+                &mut PktLineReader::new(request.as_slice()),
+                &mut PktLineWriter::new(Vec::new()),
+                true,
+            )
+        };
+
+        assert!(serve(&blob).is_ok());
+        assert_eq!(
+            serve(&deleted).unwrap_err(),
+            format!("upload-pack: not our ref {}", deleted)
+        );
+    }
+
+    #[test]
+    fn upload_pack_reports_a_missing_repository_to_the_client() {
+        let mut output = Vec::new();
+
+        upload_pack(&["/nonexistent"], &[][..], &mut output).unwrap();
+
+        assert_eq!(
+            PktLineReader::new(output.as_slice())
+                .read_line()
+                .unwrap_err(),
+            "remote error: '/nonexistent' does not appear to be a git repository"
+        );
+    }
+}
@@ -2,12 +2,16 @@ use std::error::Error;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use flate2::Compression;
 use flate2::read::{ZlibDecoder, ZlibEncoder};
+use flate2::Compression;
 use sha1::{Digest, Sha1};
 
+use crate::git_commands::packs::{load_packs, read_packed_object};
+use crate::git_commands::promisor::fetch_promised;
+use crate::models::git_object::GitObject;
+
 pub trait ShaGetter {
     fn get_sha(&self) -> Result<String, Box<dyn Error>>;
 }
@@ -40,17 +44,166 @@ impl ShaGetter for String {
 }
 
 pub fn get_object_path(sha: &str) -> Result<String, &'static str> {
-    if sha.len() != 40 {
-        return Err("file sha is invalid. Needs to be 40 char");
+    get_object_path_in(Path::new(".git"), sha).map(|path| path.to_string_lossy().to_string())
+}
+
+/// Whether `value` is a full sha: 40 hex digits.
+pub fn is_sha(value: &str) -> bool {
+    value.len() == 40 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+pub fn get_object_path_in(git_dir: &Path, sha: &str) -> Result<PathBuf, &'static str> {
+    if !is_sha(sha) {
+        return Err("file sha is invalid. Needs to be 40 hex digits");
     }
 
-    let bas_dir = ".git/objects/";
     let first_two_chars = &sha[0..2];
     let file_name = &sha[2..];
 
-    let object_path = format!("{}{}/{}", bas_dir, first_two_chars, file_name);
+    Ok(git_dir
+        .join("objects")
+        .join(first_two_chars)
+        .join(file_name))
+}
+
+/// Builds the `<type> <size>\0<content>` buffer that is hashed and stored for an object.
+pub fn build_object_buffer(object_type: &str, content: &[u8]) -> Vec<u8> {
+    [
+        format!("{} {}\0", object_type, content.len()).as_bytes(),
+        content,
+    ]
+    .concat()
+}
+
+pub fn read_raw_object(git_dir: &Path, sha: &str) -> Result<(String, Vec<u8>), String> {
+    let object_path = get_object_path_in(git_dir, sha)?;
+
+    if !object_path.exists() {
+        if let Some(object) = read_packed_object(git_dir, sha)? {
+            return Ok(object);
+        }
 
-    Ok(object_path)
+        // a partial clone fetches what its promisor remote left out once it is needed
+        if fetch_promised(git_dir, &[sha.to_string()])? {
+            if let Some(object) = read_packed_object(git_dir, sha)? {
+                return Ok(object);
+            }
+        }
+    }
+
+    let decompressed_content = read_and_decompress_file(&object_path.to_string_lossy())
+        .map_err(|e| format!("error reading object {}: {}", sha, e))?;
+
+    parse_object_buffer(sha, &decompressed_content)
+}
+
+/// Splits a decompressed loose object into its type and content, checking the size header.
+pub fn parse_object_buffer(
+    sha: &str,
+    decompressed_content: &[u8],
+) -> Result<(String, Vec<u8>), String> {
+    let null_position = decompressed_content
+        .iter()
+        .position(|&x| x == 0)
+        .ok_or_else(|| format!("object {} has no header", sha))?;
+    let header = String::from_utf8_lossy(&decompressed_content[..null_position]).to_string();
+    let (object_type, size) = header
+        .split_once(' ')
+        .ok_or_else(|| format!("object {} has an invalid header", sha))?;
+    let content = decompressed_content[null_position + 1..].to_vec();
+
+    if size.parse::<usize>().ok() != Some(content.len()) {
+        return Err(format!("object {} has a corrupt size header", sha));
+    }
+
+    Ok((object_type.to_string(), content))
+}
+
+/// Writes an object into the loose object store unless it already exists, returning its sha.
+pub fn write_object(git_dir: &Path, object_type: &str, content: &[u8]) -> Result<String, String> {
+    let object_file_buffer = build_object_buffer(object_type, content);
+    let sha = object_file_buffer
+        .get_sha()
+        .map_err(|err| format!("error constructing sha from contents: {}", err))?;
+    let object_path = get_object_path_in(git_dir, &sha)?;
+
+    if !object_path.exists() {
+        compress_and_write_file(&object_path.to_string_lossy(), &object_file_buffer)
+            .map_err(|err| format!("error writing object {}: {}", sha, err))?;
+    }
+
+    Ok(sha)
+}
+
+/// Lists the loose objects as `(sha, path)` pairs, skipping anything that is not named like one.
+pub fn loose_objects(git_dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let objects_dir = git_dir.join("objects");
+    let mut objects = Vec::new();
+
+    if !objects_dir.is_dir() {
+        return Ok(objects);
+    }
+
+    let dirs =
+        fs::read_dir(&objects_dir).map_err(|err| format!("error reading objects: {}", err))?;
+
+    for dir in dirs {
+        let dir = dir.map_err(|err| err.to_string())?.path();
+        let prefix = dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        if prefix.len() != 2 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) || !dir.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(&dir).map_err(|err| format!("error reading {:?}: {}", dir, err))? {
+            let path = file.map_err(|err| err.to_string())?.path();
+            let sha = format!(
+                "{}{}",
+                prefix,
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+
+            if is_sha(&sha) {
+                objects.push((sha, path));
+            }
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Checks the loose objects and the packs for `sha` without reading it.
+pub fn object_exists(git_dir: &Path, sha: &str) -> Result<bool, String> {
+    if get_object_path_in(git_dir, sha)?.exists() {
+        return Ok(true);
+    }
+
+    Ok(load_packs(git_dir)?
+        .iter()
+        .any(|pack_file| pack_file.index.position(sha).is_some()))
+}
+
+pub fn read_object(git_dir: &Path, sha: &str) -> Result<GitObject, String> {
+    let (object_type, content) = read_raw_object(git_dir, sha)?;
+
+    GitObject::from_object_file_buffer(&build_object_buffer(&object_type, &content))
+}
+
+/// Writes to a `.lock` file first and renames it into place, so readers never see a partial file.
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    // appended rather than swapped in, so `refs/tags/v1.0` locks as `v1.0.lock`
+    let mut lock_path = path.as_os_str().to_owned();
+
+    lock_path.push(".lock");
+
+    let lock_path = PathBuf::from(lock_path);
+
+    fs::write(&lock_path, data).map_err(|err| format!("error writing {:?}: {}", lock_path, err))?;
+    fs::rename(&lock_path, path).map_err(|err| format!("error writing {:?}: {}", path, err))
 }
 
 pub fn read_and_decompress_file(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -81,12 +234,36 @@ pub fn compress_and_write_file(path: &str, content: &Vec<u8>) -> Result<(), Box<
 
 pub trait ObjectPathGetter {
     fn get_object_path(&self, sha: &str) -> Result<String, &'static str>;
+
+    /// Reads the decompressed `<type> <size>\0<content>` buffer of an object, by default from
+    /// its loose file.
+    fn read_object_buffer(&self, sha: &str) -> Result<Vec<u8>, String> {
+        let object_path = self.get_object_path(sha)?;
+
+        read_and_decompress_file(object_path.as_str())
+            .map_err(|e| format!("Error reading and decompressing file: {}", e))
+    }
 }
 
 impl ObjectPathGetter for ActualObjectPathGetter {
     fn get_object_path(&self, sha: &str) -> Result<String, &'static str> {
         get_object_path(sha)
     }
+
+    /// Falls back to the packs, and in a partial clone to the promisor remote, when there is
+    /// no loose file.
+    fn read_object_buffer(&self, sha: &str) -> Result<Vec<u8>, String> {
+        let object_path = self.get_object_path(sha)?;
+
+        if Path::new(&object_path).exists() {
+            return read_and_decompress_file(object_path.as_str())
+                .map_err(|e| format!("Error reading and decompressing file: {}", e));
+        }
+
+        let (object_type, content) = read_raw_object(Path::new(".git"), sha)?;
+
+        Ok(build_object_buffer(&object_type, &content))
+    }
 }
 
 pub struct ActualObjectPathGetter {}
@@ -130,6 +307,37 @@ mod tests {
         let sha = "invalid_sha";
         let result = get_object_path(sha);
         assert!(result.is_err());
+        // 40 bytes, but not hex: slicing it by bytes would panic
+        assert!(get_object_path(&format!("aé{}", "0".repeat(37))).is_err());
+    }
+
+    #[test]
+    fn read_object_parses_written_object() {
+        let git_dir = tempfile::tempdir().unwrap();
+        let sha = "5dd01c177f5d7d1be5346a5bc18a569a7410c2ef";
+        let object_path = get_object_path_in(git_dir.path(), sha).unwrap();
+
+        compress_and_write_file(
+            &object_path.to_string_lossy(),
+            &b"blob 13\0Hello, world!".to_vec(),
+        )
+        .unwrap();
+
+        let git_object = read_object(git_dir.path(), sha).unwrap();
+
+        assert_eq!(git_object.get_content(), b"Hello, world!");
+    }
+
+    #[test]
+    fn read_raw_object_returns_error_for_corrupt_size_header() {
+        let git_dir = tempfile::tempdir().unwrap();
+        let sha = "5dd01c177f5d7d1be5346a5bc18a569a7410c2ef";
+        let object_path = get_object_path_in(git_dir.path(), sha).unwrap();
+
+        compress_and_write_file(&object_path.to_string_lossy(), &b"blob 3\0Hello".to_vec())
+            .unwrap();
+
+        assert!(read_raw_object(git_dir.path(), sha).is_err());
     }
 
     #[test]
@@ -0,0 +1,235 @@
+/// The state a `.gitattributes` line gives an attribute: `name` sets it, `-name` unsets it,
+/// `name=value` gives it a value and `!name` returns it to unspecified.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+#[derive(Debug, PartialEq)]
+struct AttributeRule {
+    /// The directory of the file the rule came from: empty, or ending in `/`.
+    base: String,
+    pattern: String,
+    attributes: Vec<(String, AttributeValue)>,
+}
+
+/// The rules of the attribute files that apply to a path, in increasing precedence: a file
+/// deeper in the tree wins over those above it, and within a file later lines win.
+#[derive(Debug, Default, PartialEq)]
+pub struct Attributes {
+    rules: Vec<AttributeRule>,
+}
+
+impl Attributes {
+    /// Adds the rules of the attribute file in directory `base`, ahead of those added before.
+    pub fn add(&mut self, base: &str, content: &str) {
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with('#')) else {
+                continue;
+            };
+            let attributes = fields
+                .map(|field| {
+                    if let Some(name) = field.strip_prefix('-') {
+                        (name.to_string(), AttributeValue::Unset)
+                    } else if let Some(name) = field.strip_prefix('!') {
+                        (name.to_string(), AttributeValue::Unspecified)
+                    } else if let Some((name, value)) = field.split_once('=') {
+                        (name.to_string(), AttributeValue::Value(value.to_string()))
+                    } else {
+                        (field.to_string(), AttributeValue::Set)
+                    }
+                })
+                .collect();
+
+            self.rules.push(AttributeRule {
+                base: base.to_string(),
+                pattern: pattern.to_string(),
+                attributes,
+            });
+        }
+    }
+
+    /// Drops the rules that came from the attribute file in directory `base`.
+    pub fn remove(&mut self, base: &str) {
+        self.rules.retain(|rule| rule.base != base);
+    }
+
+    /// The state of attribute `name` for `path`, from the rule with the highest precedence
+    /// that mentions it, or `None` when no rule does.
+    pub fn get(&self, path: &str, is_dir: bool, name: &str) -> Option<&AttributeValue> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| rule.matches(path, is_dir))
+            .find_map(|rule| {
+                rule.attributes
+                    .iter()
+                    .rev()
+                    .find(|(attribute, _)| attribute == name)
+                    .map(|(_, value)| value)
+            })
+    }
+
+    /// Whether attribute `name` is set (or has a value) for `path`.
+    pub fn is_set(&self, path: &str, is_dir: bool, name: &str) -> bool {
+        matches!(
+            self.get(path, is_dir, name),
+            Some(AttributeValue::Set | AttributeValue::Value(_))
+        )
+    }
+}
+
+impl AttributeRule {
+    /// Matches like a `.gitignore` pattern: one without a `/` matches the name at any depth
+    /// below the rule's directory, one with a `/` the path relative to it, and one ending in
+    /// `/` only directories.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let Some(relative) = path.strip_prefix(self.base.as_str()) else {
+            return false;
+        };
+        let pattern = match self.pattern.strip_suffix('/') {
+            Some(_) if !is_dir => return false,
+            Some(pattern) => pattern,
+            None => &self.pattern,
+        };
+
+        match pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored.as_bytes(), relative.as_bytes()),
+            None if pattern.contains('/') => wildmatch(pattern.as_bytes(), relative.as_bytes()),
+            None => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+
+                wildmatch(pattern.as_bytes(), name.as_bytes())
+            }
+        }
+    }
+}
+
+/// Matches `text` against a glob where `*` and `?` stop at `/`, `**` does not, and `[...]`
+/// matches one character from a set.
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` may also match no directories at all
+            rest.strip_prefix(b"/")
+                .is_some_and(|after| wildmatch(after, text))
+                || (0..=text.len()).any(|skip| wildmatch(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => {
+            let limit = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+
+            (0..=limit).any(|skip| wildmatch(rest, &text[skip..]))
+        }
+        [b'?', rest @ ..] => {
+            text.first().is_some_and(|&b| b != b'/') && wildmatch(rest, &text[1..])
+        }
+        [b'[', rest @ ..] => match (text.first(), bracket(rest)) {
+            (Some(&byte), Some((set, negated, rest))) => {
+                byte != b'/' && set_contains(set, byte) != negated && wildmatch(rest, &text[1..])
+            }
+            (_, None) => text.first() == Some(&b'[') && wildmatch(rest, &text[1..]),
+            (None, _) => false,
+        },
+        [b'\\', escaped, rest @ ..] => text.first() == Some(escaped) && wildmatch(rest, &text[1..]),
+        [byte, rest @ ..] => text.first() == Some(byte) && wildmatch(rest, &text[1..]),
+    }
+}
+
+/// Splits a bracket expression (after its `[`) into its set, whether it is negated, and the
+/// pattern after its `]`.
+fn bracket(pattern: &[u8]) -> Option<(&[u8], bool, &[u8])> {
+    let (negated, pattern) = match pattern.first() {
+        Some(b'!' | b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // a `]` right at the start belongs to the set
+    let end = pattern
+        .iter()
+        .skip(1)
+        .position(|&b| b == b']')
+        .map(|idx| idx + 1)?;
+
+    Some((&pattern[..end], negated, &pattern[end + 1..]))
+}
+
+fn set_contains(set: &[u8], byte: u8) -> bool {
+    let mut idx = 0;
+
+    while idx < set.len() {
+        if set.get(idx + 1) == Some(&b'-') && idx + 2 < set.len() {
+            if (set[idx]..=set[idx + 2]).contains(&byte) {
+                return true;
+            }
+
+            idx += 3;
+        } else {
+            if set[idx] == byte {
+                return true;
+            }
+
+            idx += 1;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_prefers_deeper_files_and_later_lines() {
+        let mut attributes = Attributes::default();
+
+        attributes.add(
+            "",
+            "# comment\n*.txt export-subst\ndocs/ export-ignore\n/top -diff\n",
+        );
+        attributes.add(
+            "sub/",
+            "*.txt -export-subst\nnote.txt !export-subst eol=lf\n",
+        );
+
+        assert_eq!(
+            attributes.get("a/b.txt", false, "export-subst"),
+            Some(&AttributeValue::Set)
+        );
+        assert_eq!(
+            attributes.get("sub/b.txt", false, "export-subst"),
+            Some(&AttributeValue::Unset)
+        );
+        assert_eq!(
+            attributes.get("sub/note.txt", false, "export-subst"),
+            Some(&AttributeValue::Unspecified)
+        );
+        assert!(attributes.is_set("sub/note.txt", false, "eol"));
+        assert!(attributes.is_set("docs", true, "export-ignore"));
+        assert!(!attributes.is_set("docs", false, "export-ignore"));
+        assert_eq!(
+            attributes.get("top", false, "diff"),
+            Some(&AttributeValue::Unset)
+        );
+        assert_eq!(attributes.get("a/top", false, "diff"), None);
+
+        attributes.remove("sub/");
+
+        assert!(attributes.is_set("sub/b.txt", false, "export-subst"));
+    }
+
+    #[test]
+    fn wildmatch_keeps_single_stars_within_a_directory() {
+        assert!(wildmatch(b"*.c", b"main.c"));
+        assert!(!wildmatch(b"*.c", b"src/main.c"));
+        assert!(wildmatch(b"src/**/*.c", b"src/main.c"));
+        assert!(wildmatch(b"src/**/*.c", b"src/a/b/main.c"));
+        assert!(wildmatch(b"v[0-9]?", b"v1a"));
+        assert!(!wildmatch(b"v[!0-9]", b"v1"));
+        assert!(wildmatch(b"a\\*", b"a*"));
+    }
+}
@@ -0,0 +1,193 @@
+use sha1::{Digest, Sha1};
+
+use crate::models::chunk_format::read_u32;
+use crate::models::ewah::Bitmap;
+
+const SIGNATURE: &[u8; 4] = b"BITM";
+const VERSION: u16 = 1;
+const HASH_SIZE: usize = 20;
+const HEADER_SIZE: usize = 12 + HASH_SIZE;
+const BITMAP_OPT_FULL_DAG: u16 = 0x1;
+const ENTRY_HEADER_SIZE: usize = 6;
+// how many earlier entries are tried as the base of an XOR-compressed entry
+const MAX_XOR_OFFSET: usize = 10;
+
+/// The reachability bitmap of one selected commit. `object_position` is the commit's position in
+/// sha order (in the pack index or the multi-pack-index the bitmap belongs to).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapEntry {
+    pub object_position: u32,
+    pub bitmap: Bitmap,
+}
+
+/// A `.bitmap` file. Bit positions follow the pack order of the pack (or the pseudo-pack order of
+/// the multi-pack-index) whose checksum is recorded in the header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapIndex {
+    pub checksum: String,
+    pub commits: Bitmap,
+    pub trees: Bitmap,
+    pub blobs: Bitmap,
+    pub tags: Bitmap,
+    pub entries: Vec<BitmapEntry>,
+}
+
+impl BitmapIndex {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE + HASH_SIZE || &data[0..4] != SIGNATURE {
+            return Err("not a bitmap index".to_string());
+        }
+
+        let version = u16::from_be_bytes([data[4], data[5]]);
+
+        if version != VERSION {
+            return Err(format!("unsupported bitmap index version {}", version));
+        }
+
+        let data_end = data.len() - HASH_SIZE;
+
+        if Sha1::digest(&data[..data_end]).as_slice() != &data[data_end..] {
+            return Err("bitmap index checksum mismatch".to_string());
+        }
+
+        let num_entries = read_u32(data, 8) as usize;
+        let mut pos = HEADER_SIZE;
+        let next_bitmap = |pos: &mut usize| -> Result<Bitmap, String> {
+            let (bitmap, size) = Bitmap::from_ewah(&data[*pos..data_end])?;
+            *pos += size;
+            Ok(bitmap)
+        };
+
+        let commits = next_bitmap(&mut pos)?;
+        let trees = next_bitmap(&mut pos)?;
+        let blobs = next_bitmap(&mut pos)?;
+        let tags = next_bitmap(&mut pos)?;
+        let mut entries: Vec<BitmapEntry> = Vec::with_capacity(num_entries);
+
+        for idx in 0..num_entries {
+            if pos + ENTRY_HEADER_SIZE > data_end {
+                return Err("bitmap index entries are truncated".to_string());
+            }
+
+            let object_position = read_u32(data, pos);
+            let xor_offset = data[pos + 4] as usize;
+            pos += ENTRY_HEADER_SIZE;
+
+            let mut bitmap = next_bitmap(&mut pos)?;
+
+            if xor_offset > 0 {
+                let base = idx
+                    .checked_sub(xor_offset)
+                    .map(|base_idx| &entries[base_idx].bitmap)
+                    .ok_or_else(|| format!("bitmap entry {} has an invalid XOR offset", idx))?;
+
+                bitmap.xor(base);
+            }
+
+            entries.push(BitmapEntry {
+                object_position,
+                bitmap,
+            });
+        }
+
+        // an optional name-hash cache and lookup table may follow; neither is needed for reading
+        Ok(Self {
+            checksum: hex::encode(&data[12..HEADER_SIZE]),
+            commits,
+            trees,
+            blobs,
+            tags,
+            entries,
+        })
+    }
+
+    /// Serializes the index, XOR-compressing each entry against a recent one when that is smaller.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut data = SIGNATURE.to_vec();
+
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&BITMAP_OPT_FULL_DAG.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        data.extend(hex::decode(&self.checksum).map_err(|err| err.to_string())?);
+
+        for bitmap in [&self.commits, &self.trees, &self.blobs, &self.tags] {
+            data.extend(bitmap.to_ewah());
+        }
+
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let mut best = (0, entry.bitmap.to_ewah());
+
+            for xor_offset in 1..=idx.min(MAX_XOR_OFFSET) {
+                let mut xored = entry.bitmap.clone();
+                xored.xor(&self.entries[idx - xor_offset].bitmap);
+                let encoded = xored.to_ewah();
+
+                if encoded.len() < best.1.len() {
+                    best = (xor_offset, encoded);
+                }
+            }
+
+            data.extend_from_slice(&entry.object_position.to_be_bytes());
+            data.push(best.0 as u8);
+            data.push(0);
+            data.extend(best.1);
+        }
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(positions: &[usize]) -> Bitmap {
+        let mut bitmap = Bitmap::new();
+
+        for &position in positions {
+            bitmap.set(position);
+        }
+
+        bitmap
+    }
+
+    #[test]
+    fn to_bytes_then_from_bytes_round_trips_xor_compressed_entries() {
+        let shared: Vec<usize> = (0..500).step_by(3).collect();
+        let mut larger = shared.clone();
+        larger.push(600);
+
+        let index = BitmapIndex {
+            checksum: "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed".to_string(),
+            commits: bitmap(&[0, 600]),
+            trees: bitmap(&[1]),
+            blobs: bitmap(&[2]),
+            tags: Bitmap::new(),
+            entries: vec![
+                BitmapEntry {
+                    object_position: 4,
+                    bitmap: bitmap(&shared),
+                },
+                BitmapEntry {
+                    object_position: 9,
+                    bitmap: bitmap(&larger),
+                },
+            ],
+        };
+
+        let data = index.to_bytes().unwrap();
+
+        // the second entry is stored as a one-bit XOR against the first: its EWAH data is a
+        // 12 byte header and trailer around one marker word and one literal word
+        assert_eq!(data[data.len() - HASH_SIZE - 28 - 2], 1);
+        assert_eq!(BitmapIndex::from_bytes(&data).unwrap(), index);
+    }
+
+    #[test]
+    fn from_bytes_returns_error_for_bad_signature() {
+        assert!(BitmapIndex::from_bytes(&[0; 64]).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+const SEED_0: u32 = 0x293a_e76f;
+const SEED_1: u32 = 0x7e64_6e2c;
+const BITS_PER_WORD: usize = 8;
+
+/// The parameters recorded in the header of a commit-graph `BDAT` chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomFilterSettings {
+    pub hash_version: u32,
+    pub num_hashes: u32,
+    pub bits_per_entry: u32,
+}
+
+impl Default for BloomFilterSettings {
+    fn default() -> Self {
+        Self {
+            hash_version: 1,
+            num_hashes: 7,
+            bits_per_entry: 10,
+        }
+    }
+}
+
+/// A changed-path Bloom filter for one commit, keyed by every path the commit changes relative to
+/// its first parent (and all of their leading directories).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    pub data: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// Paths beyond this many changes produce a "truncated large" filter that matches everything.
+    pub const MAX_CHANGED_PATHS: usize = 512;
+
+    pub fn from_paths(paths: &[String], settings: &BloomFilterSettings) -> Self {
+        if paths.len() > Self::MAX_CHANGED_PATHS {
+            return Self { data: vec![0xff] };
+        }
+
+        let num_bits = paths.len() * settings.bits_per_entry as usize;
+        let mut filter = Self {
+            data: vec![0; num_bits.div_ceil(BITS_PER_WORD).max(1)],
+        };
+
+        for path in paths {
+            for position in filter.bit_positions(path, settings) {
+                filter.data[position / BITS_PER_WORD] |= 1 << (position % BITS_PER_WORD);
+            }
+        }
+
+        filter
+    }
+
+    /// Returns false when `path` was definitely not changed; true means "maybe".
+    pub fn contains(&self, path: &str, settings: &BloomFilterSettings) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+
+        self.bit_positions(path, settings)
+            .into_iter()
+            .all(|position| {
+                self.data[position / BITS_PER_WORD] & (1 << (position % BITS_PER_WORD)) != 0
+            })
+    }
+
+    fn bit_positions(&self, path: &str, settings: &BloomFilterSettings) -> Vec<usize> {
+        // version 1 filters reproduce upstream's historical sign extension of path bytes
+        let signed_bytes = settings.hash_version == 1;
+        let hash0 = murmur3_32(SEED_0, path.as_bytes(), signed_bytes);
+        let hash1 = murmur3_32(SEED_1, path.as_bytes(), signed_bytes);
+        let num_bits = (self.data.len() * BITS_PER_WORD) as u64;
+
+        (0..settings.num_hashes)
+            .map(|idx| {
+                let hash = hash0.wrapping_add(idx.wrapping_mul(hash1));
+                (hash as u64 % num_bits) as usize
+            })
+            .collect()
+    }
+}
+
+/// Expands changed paths into Bloom filter keys: each path plus each of its leading directories.
+pub fn bloom_keys(changed_paths: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+
+    for path in changed_paths {
+        let mut prefix_end = Some(path.len());
+
+        while let Some(end) = prefix_end {
+            keys.push(path[..end].to_string());
+            prefix_end = path[..end].rfind('/');
+        }
+    }
+
+    keys.sort();
+    keys.dedup();
+
+    keys
+}
+
+pub fn murmur3_32(seed: u32, data: &[u8], signed_bytes: bool) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let byte = |b: u8| -> u32 {
+        if signed_bytes {
+            b as i8 as i32 as u32
+        } else {
+            b as u32
+        }
+    };
+
+    let mut hash = seed;
+    let blocks = data.chunks_exact(4);
+    let tail = blocks.remainder();
+
+    for block in blocks {
+        let mut k =
+            byte(block[0]) | byte(block[1]) << 8 | byte(block[2]) << 16 | byte(block[3]) << 24;
+
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+
+    let mut k = 0u32;
+
+    if tail.len() >= 3 {
+        k ^= byte(tail[2]) << 16;
+    }
+
+    if tail.len() >= 2 {
+        k ^= byte(tail[1]) << 8;
+    }
+
+    if !tail.is_empty() {
+        k ^= byte(tail[0]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_32_matches_reference_values() {
+        assert_eq!(murmur3_32(0, b"", false), 0);
+        assert_eq!(
+            murmur3_32(0, b"The quick brown fox jumps over the lazy dog", false),
+            0x2e4f_f723
+        );
+    }
+
+    #[test]
+    fn murmur3_32_sign_extends_high_bytes_only_when_asked() {
+        let data = "\u{99}\u{a2}\u{e2}".as_bytes();
+
+        assert_ne!(murmur3_32(0, data, true), murmur3_32(0, data, false));
+        assert_eq!(
+            murmur3_32(0, b"ascii", true),
+            murmur3_32(0, b"ascii", false)
+        );
+    }
+
+    #[test]
+    fn bloom_keys_include_leading_directories() {
+        let keys = bloom_keys(&["a/b/c.txt".to_string(), "a/d".to_string()]);
+
+        assert_eq!(keys, vec!["a", "a/b", "a/b/c.txt", "a/d"]);
+    }
+
+    #[test]
+    fn from_paths_contains_added_paths() {
+        let settings = BloomFilterSettings::default();
+        let keys = bloom_keys(&["src/main.rs".to_string()]);
+        let filter = BloomFilter::from_paths(&keys, &settings);
+
+        assert_eq!(filter.data.len(), 3);
+        assert!(filter.contains("src/main.rs", &settings));
+        assert!(filter.contains("src", &settings));
+        assert!(!filter.contains("README.md", &settings));
+    }
+
+    #[test]
+    fn from_paths_without_changes_matches_nothing() {
+        let settings = BloomFilterSettings::default();
+        let filter = BloomFilter::from_paths(&[], &settings);
+
+        assert_eq!(filter.data, vec![0]);
+        assert!(!filter.contains("anything", &settings));
+    }
+
+    #[test]
+    fn from_paths_with_too_many_changes_matches_everything() {
+        let settings = BloomFilterSettings::default();
+        let paths: Vec<String> = (0..=BloomFilter::MAX_CHANGED_PATHS)
+            .map(|idx| format!("file{}", idx))
+            .collect();
+        let filter = BloomFilter::from_paths(&paths, &settings);
+
+        assert_eq!(filter.data, vec![0xff]);
+        assert!(filter.contains("anything", &settings));
+    }
+}
@@ -0,0 +1,149 @@
+const V2_SIGNATURE: &str = "# v2 git bundle\n";
+const V3_SIGNATURE: &str = "# v3 git bundle\n";
+
+/// The text header of a bundle file, which a pack follows after a blank line. v3 bundles add
+/// `@key=value` capabilities; both list the prerequisite commits as `-<sha> <subject>` and the
+/// refs as `<sha> <name>`.
+#[derive(Debug, Default, PartialEq)]
+pub struct BundleHeader {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+    /// `(sha, subject)` pairs for the commits the pack builds on, which a repository must
+    /// already have to take the bundle.
+    pub prerequisites: Vec<(String, String)>,
+    /// `(name, sha)` pairs in the order they were written.
+    pub refs: Vec<(String, String)>,
+}
+
+impl BundleHeader {
+    /// Whether `data` starts like a bundle, without parsing the rest of the header.
+    pub fn is_bundle(data: &[u8]) -> bool {
+        data.starts_with(V2_SIGNATURE.as_bytes()) || data.starts_with(V3_SIGNATURE.as_bytes())
+    }
+
+    /// Parses the header at the start of `data`, returning it with the offset the pack starts
+    /// at. v3 capabilities other than `object-format=sha1` are refused.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), String> {
+        let mut header = Self {
+            version: if data.starts_with(V2_SIGNATURE.as_bytes()) {
+                2
+            } else if data.starts_with(V3_SIGNATURE.as_bytes()) {
+                3
+            } else {
+                return Err("does not look like a v2 or v3 bundle file".to_string());
+            },
+            ..Self::default()
+        };
+        let mut offset = V2_SIGNATURE.len();
+
+        loop {
+            let end = data[offset..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or("bundle header ends too early")?;
+            let line = std::str::from_utf8(&data[offset..offset + end])
+                .map_err(|_| "bundle header is not valid UTF-8".to_string())?;
+
+            offset += end + 1;
+
+            if line.is_empty() {
+                return Ok((header, offset));
+            }
+
+            if let Some(capability) = line.strip_prefix('@') {
+                if header.version < 3 || capability != "object-format=sha1" {
+                    return Err(format!("unknown capability '{}'", capability));
+                }
+
+                header.capabilities.push(capability.to_string());
+                continue;
+            }
+
+            let (prerequisite, line) = match line.strip_prefix('-') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (sha, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+            if sha.len() != 40 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("unrecognized header: {}", line));
+            }
+
+            if prerequisite {
+                header
+                    .prerequisites
+                    .push((sha.to_string(), rest.to_string()));
+            } else if rest.is_empty() {
+                return Err(format!("unrecognized header: {}", line));
+            } else {
+                header.refs.push((rest.to_string(), sha.to_string()));
+            }
+        }
+    }
+
+    /// The header's bytes, blank line included, ready for the pack to follow.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = String::from(if self.version >= 3 {
+            V3_SIGNATURE
+        } else {
+            V2_SIGNATURE
+        });
+
+        for capability in &self.capabilities {
+            header.push_str(&format!("@{}\n", capability));
+        }
+
+        for (sha, subject) in &self.prerequisites {
+            header.push_str(&format!("-{} {}\n", sha, subject));
+        }
+
+        for (name, sha) in &self.refs {
+            header.push_str(&format!("{} {}\n", sha, name));
+        }
+
+        header.push('\n');
+        header.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "1111111111111111111111111111111111111111";
+    const SHA2: &str = "2222222222222222222222222222222222222222";
+
+    #[test]
+    fn parse_reads_what_to_bytes_writes() {
+        let header = BundleHeader {
+            version: 3,
+            capabilities: vec!["object-format=sha1".to_string()],
+            prerequisites: vec![(SHA1.to_string(), "first".to_string())],
+            refs: vec![("refs/heads/main".to_string(), SHA2.to_string())],
+        };
+        let mut data = header.to_bytes();
+
+        assert_eq!(
+            String::from_utf8(data.clone()).unwrap(),
+            format!(
+                "# v3 git bundle\n@object-format=sha1\n-{} first\n{} refs/heads/main\n\n",
+                SHA1, SHA2
+            )
+        );
+
+        let header_len = data.len();
+        data.extend_from_slice(b"PACK");
+
+        assert_eq!(BundleHeader::parse(&data), Ok((header, header_len)));
+    }
+
+    #[test]
+    fn parse_returns_error_for_other_files() {
+        assert!(!BundleHeader::is_bundle(b"PACK"));
+        assert!(BundleHeader::parse(b"PACK").is_err());
+        assert_eq!(
+            BundleHeader::parse(b"# v2 git bundle\n@object-format=sha1\n\n"),
+            Err("unknown capability 'object-format=sha1'".to_string())
+        );
+    }
+}
@@ -0,0 +1,97 @@
+/// Helpers for the chunked file layout shared by commit-graph and multi-pack-index files:
+/// a header, a table of `(chunk id, offset)` pairs terminated by a zero id, then chunk data.
+const CHUNK_TABLE_ENTRY_SIZE: usize = 12;
+
+pub fn write_chunk_file(header: &[u8], chunks: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut buffer = header.to_vec();
+    let mut offset = (header.len() + (chunks.len() + 1) * CHUNK_TABLE_ENTRY_SIZE) as u64;
+
+    for (chunk_id, data) in chunks {
+        buffer.extend_from_slice(&chunk_id.to_be_bytes());
+        buffer.extend_from_slice(&offset.to_be_bytes());
+        offset += data.len() as u64;
+    }
+
+    buffer.extend_from_slice(&0u32.to_be_bytes());
+    buffer.extend_from_slice(&offset.to_be_bytes());
+
+    for (_, data) in chunks {
+        buffer.extend_from_slice(data);
+    }
+
+    buffer
+}
+
+/// Reads a chunk table starting at `table_offset` and returns each chunk's id and data.
+/// `data_end` is the offset where chunk data must end (usually the start of the trailing hash).
+pub fn read_chunk_table(
+    data: &[u8],
+    table_offset: usize,
+    num_chunks: usize,
+    data_end: usize,
+) -> Result<Vec<(u32, &[u8])>, String> {
+    let table_end = table_offset + (num_chunks + 1) * CHUNK_TABLE_ENTRY_SIZE;
+
+    if table_end > data_end {
+        return Err("chunk table is truncated".to_string());
+    }
+
+    let mut entries = Vec::new();
+
+    for idx in 0..=num_chunks {
+        let start = table_offset + idx * CHUNK_TABLE_ENTRY_SIZE;
+        let chunk_id = read_u32(data, start);
+        let offset = read_u64(data, start + 4) as usize;
+
+        entries.push((chunk_id, offset));
+    }
+
+    let mut chunks = Vec::new();
+
+    for pair in entries.windows(2) {
+        let (chunk_id, start) = pair[0];
+        let (_, end) = pair[1];
+
+        if chunk_id == 0 || start < table_end || start > end || end > data_end {
+            return Err(format!("invalid chunk offsets for chunk {:08x}", chunk_id));
+        }
+
+        chunks.push((chunk_id, &data[start..end]));
+    }
+
+    Ok(chunks)
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_chunk_file_then_read_chunk_table_round_trips() {
+        let header = b"TEST".to_vec();
+        let chunks = vec![(0x4141_4141, vec![1, 2, 3]), (0x4242_4242, vec![4])];
+        let file = write_chunk_file(&header, &chunks);
+
+        let parsed = read_chunk_table(&file, header.len(), 2, file.len()).unwrap();
+
+        assert_eq!(
+            parsed,
+            vec![(0x4141_4141, &[1u8, 2, 3][..]), (0x4242_4242, &[4u8][..])]
+        );
+    }
+
+    #[test]
+    fn read_chunk_table_returns_error_for_out_of_range_offsets() {
+        let file = write_chunk_file(b"TEST", &[(0x4141_4141, vec![1, 2, 3])]);
+
+        assert!(read_chunk_table(&file, 4, 1, file.len() - 1).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::models::git_object::GetContentString;
+
+#[derive(Debug, PartialEq)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub committer: String,
+    pub message: String,
+    pub content: Vec<u8>,
+}
+
+impl Commit {
+    pub fn new(content: Vec<u8>) -> Result<Self, String> {
+        let text = String::from_utf8_lossy(&content).to_string();
+        let (headers, message) = match text.find("\n\n") {
+            Some(idx) => (&text[..idx], &text[idx + 2..]),
+            None => (text.trim_end_matches('\n'), ""),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+
+        for line in headers.lines() {
+            // continuation lines of multi-line headers (gpgsig, mergetag) start with a space
+            if line.starts_with(' ') {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+
+            match key {
+                "tree" => tree = Some(Self::parse_sha(value)?),
+                "parent" => parents.push(Self::parse_sha(value)?),
+                "author" => author = Some(value.to_string()),
+                "committer" => committer = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            tree: tree.ok_or("commit is missing a tree")?,
+            parents,
+            author: author.ok_or("commit is missing an author")?,
+            committer: committer.ok_or("commit is missing a committer")?,
+            message: message.to_string(),
+            content,
+        })
+    }
+
+    pub fn committer_timestamp(&self) -> Result<i64, String> {
+        Self::parse_signature_timestamp(&self.committer)
+    }
+
+    /// Extracts the unix timestamp from an identity line such as
+    /// `Jane Doe <jane@example.com> 1700000000 +0100`.
+    pub fn parse_signature_timestamp(signature: &str) -> Result<i64, String> {
+        let after_email = signature
+            .rfind('>')
+            .map(|idx| &signature[idx + 1..])
+            .ok_or_else(|| format!("invalid signature: {}", signature))?;
+
+        after_email
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| format!("signature is missing a timestamp: {}", signature))?
+            .parse()
+            .map_err(|err| format!("error parsing signature timestamp: {}", err))
+    }
+
+    /// Checks the header layout the way upstream fsck does: `tree`, then any `parent` lines,
+    /// then `author` and `committer`, each with a well-formed identity.
+    pub fn check(&self) -> Result<(), String> {
+        let text = String::from_utf8_lossy(&self.content);
+        let mut lines = text.lines().peekable();
+
+        lines
+            .next()
+            .filter(|line| line.starts_with("tree "))
+            .ok_or("missingTree: invalid format - expected 'tree' line")?;
+
+        while lines.next_if(|line| line.starts_with("parent ")).is_some() {}
+
+        let author = lines
+            .next()
+            .and_then(|line| line.strip_prefix("author "))
+            .ok_or("missingAuthor: invalid format - expected 'author' line")?;
+
+        Self::check_signature(author)?;
+
+        let committer = lines
+            .next()
+            .and_then(|line| line.strip_prefix("committer "))
+            .ok_or("missingCommitter: invalid format - expected 'committer' line")?;
+
+        Self::check_signature(committer)
+    }
+
+    /// Validates an identity line such as `Jane Doe <jane@example.com> 1700000000 +0100`.
+    pub fn check_signature(signature: &str) -> Result<(), String> {
+        let email_start = signature
+            .find('<')
+            .ok_or("missingEmail: invalid author/committer line - missing email")?;
+
+        if email_start > 0 && !signature[..email_start].ends_with(' ') {
+            return Err(
+                "missingSpaceBeforeEmail: invalid author/committer line - missing space before email"
+                    .to_string(),
+            );
+        }
+
+        let email_end = signature[email_start..]
+            .find('>')
+            .map(|idx| email_start + idx)
+            .ok_or("badEmail: invalid author/committer line - bad email")?;
+        let date = signature[email_end + 1..].strip_prefix(' ').ok_or(
+            "missingSpaceBeforeDate: invalid author/committer line - missing space before date",
+        )?;
+        let (timestamp, timezone) = date
+            .split_once(' ')
+            .ok_or("badDate: invalid author/committer line - bad date")?;
+
+        if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("badDate: invalid author/committer line - bad date".to_string());
+        }
+
+        let valid_timezone = timezone.len() == 5
+            && (timezone.starts_with('+') || timezone.starts_with('-'))
+            && timezone[1..].bytes().all(|b| b.is_ascii_digit());
+
+        if !valid_timezone {
+            return Err("badTimezone: invalid author/committer line - bad time zone".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn parse_sha(value: &str) -> Result<String, String> {
+        if value.len() != 40 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("invalid sha in commit: {}", value));
+        }
+
+        Ok(value.to_string())
+    }
+}
+
+impl GetContentString for Commit {
+    fn get_content_string(&self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.content).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    const PARENT: &str = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+
+    #[test]
+    fn commit_new_parses_headers_and_message() {
+        let content = format!(
+            "tree {}\nparent {}\nauthor A <a@example.com> 1700000000 +0000\ncommitter C <c@example.com> 1700000100 +0100\n\nmessage\n",
+            TREE, PARENT
+        );
+        let commit = Commit::new(content.into_bytes()).unwrap();
+
+        assert_eq!(commit.tree, TREE);
+        assert_eq!(commit.parents, vec![PARENT.to_string()]);
+        assert_eq!(commit.author, "A <a@example.com> 1700000000 +0000");
+        assert_eq!(commit.message, "message\n");
+        assert_eq!(commit.committer_timestamp().unwrap(), 1700000100);
+    }
+
+    #[test]
+    fn commit_new_skips_multi_line_headers() {
+        let content = format!(
+            "tree {}\nauthor A <a@example.com> 1 +0000\ncommitter C <c@example.com> 2 +0000\ngpgsig -----BEGIN PGP SIGNATURE-----\n parent {}\n -----END PGP SIGNATURE-----\n\nsigned\n",
+            TREE, PARENT
+        );
+        let commit = Commit::new(content.into_bytes()).unwrap();
+
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.message, "signed\n");
+    }
+
+    #[test]
+    fn commit_new_returns_error_without_tree() {
+        let content =
+            "author A <a@example.com> 1 +0000\ncommitter C <c@example.com> 2 +0000\n\nmsg";
+        let result = Commit::new(content.as_bytes().to_vec());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_signature_timestamp_returns_error_for_missing_timestamp() {
+        let result = Commit::parse_signature_timestamp("A <a@example.com>");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_returns_error_for_headers_out_of_order() {
+        let content = format!(
+            "tree {}\ncommitter C <c@example.com> 2 +0000\nauthor A <a@example.com> 1 +0000\n\nmsg\n",
+            TREE
+        );
+        let commit = Commit::new(content.into_bytes()).unwrap();
+
+        assert_eq!(
+            commit.check().unwrap_err(),
+            "missingAuthor: invalid format - expected 'author' line"
+        );
+    }
+
+    #[test]
+    fn check_signature_returns_error_for_bad_time_zone() {
+        assert!(Commit::check_signature("A <a@example.com> 1700000000 +0100").is_ok());
+        assert_eq!(
+            Commit::check_signature("A <a@example.com> 1700000000 CET").unwrap_err(),
+            "badTimezone: invalid author/committer line - bad time zone"
+        );
+    }
+}
@@ -0,0 +1,814 @@
+use std::collections::HashMap;
+
+use sha1::{Digest, Sha1};
+
+use crate::models::bloom_filter::{BloomFilter, BloomFilterSettings};
+use crate::models::chunk_format::{read_chunk_table, read_u32, read_u64, write_chunk_file};
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const VERSION: u8 = 1;
+const HASH_VERSION_SHA1: u8 = 1;
+const HEADER_SIZE: usize = 8;
+const HASH_SIZE: usize = 20;
+const COMMIT_DATA_SIZE: usize = HASH_SIZE + 16;
+
+const CHUNK_OID_FANOUT: u32 = 0x4f49_4446; // "OIDF"
+const CHUNK_OID_LOOKUP: u32 = 0x4f49_444c; // "OIDL"
+const CHUNK_COMMIT_DATA: u32 = 0x4344_4154; // "CDAT"
+const CHUNK_GENERATION_DATA: u32 = 0x4744_4132; // "GDA2"
+const CHUNK_GENERATION_DATA_OVERFLOW: u32 = 0x4744_4f32; // "GDO2"
+const CHUNK_EXTRA_EDGES: u32 = 0x4544_4745; // "EDGE"
+const CHUNK_BLOOM_INDEXES: u32 = 0x4249_4458; // "BIDX"
+const CHUNK_BLOOM_DATA: u32 = 0x4244_4154; // "BDAT"
+const BLOOM_DATA_HEADER_SIZE: usize = 12;
+const CHUNK_BASE_GRAPHS: u32 = 0x4241_5345; // "BASE"
+
+const GRAPH_PARENT_NONE: u32 = 0x7000_0000;
+const GRAPH_EXTRA_EDGES_NEEDED: u32 = 0x8000_0000;
+const GRAPH_LAST_EDGE: u32 = 0x8000_0000;
+pub const GENERATION_NUMBER_V1_MAX: u32 = 0x3fff_ffff;
+const CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW: u32 = 0x8000_0000;
+
+/// A commit as recorded in a commit-graph, with both generation number versions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitGraphEntry {
+    pub sha: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub commit_time: u64,
+    pub topological_level: u32,
+    pub corrected_commit_date: Option<u64>,
+}
+
+/// The data needed to add a commit to a new commit-graph layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitGraphCommit {
+    pub sha: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub commit_time: u64,
+    pub bloom_filter: Option<BloomFilter>,
+}
+
+/// A single commit-graph file: either the whole graph or one layer of a split chain.
+#[derive(Debug)]
+pub struct CommitGraphLayer {
+    pub checksum: String,
+    pub base_graphs: Vec<String>,
+    oids: Vec<[u8; HASH_SIZE]>,
+    fanout: Vec<u32>,
+    commit_data: Vec<u8>,
+    extra_edges: Vec<u32>,
+    generation_data: Option<Vec<u32>>,
+    generation_data_overflow: Vec<u64>,
+    bloom_settings: Option<BloomFilterSettings>,
+    bloom_index: Vec<u32>,
+    bloom_data: Vec<u8>,
+}
+
+impl CommitGraphLayer {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE + HASH_SIZE || &data[0..4] != SIGNATURE {
+            return Err("not a commit-graph file".to_string());
+        }
+
+        if data[4] != VERSION {
+            return Err(format!("unsupported commit-graph version {}", data[4]));
+        }
+
+        if data[5] != HASH_VERSION_SHA1 {
+            return Err(format!("unsupported commit-graph hash version {}", data[5]));
+        }
+
+        let data_end = data.len() - HASH_SIZE;
+        let checksum = Sha1::digest(&data[..data_end]);
+
+        if checksum.as_slice() != &data[data_end..] {
+            return Err("commit-graph checksum mismatch".to_string());
+        }
+
+        let num_chunks = data[6] as usize;
+        let num_base_graphs = data[7] as usize;
+        let chunks: HashMap<u32, &[u8]> =
+            read_chunk_table(data, HEADER_SIZE, num_chunks, data_end)?
+                .into_iter()
+                .collect();
+
+        let required_chunk = |chunk_id: u32, name: &str| {
+            chunks
+                .get(&chunk_id)
+                .copied()
+                .ok_or_else(|| format!("commit-graph is missing the {} chunk", name))
+        };
+
+        let fanout_chunk = required_chunk(CHUNK_OID_FANOUT, "OIDF")?;
+
+        if fanout_chunk.len() != 256 * 4 {
+            return Err("commit-graph fanout chunk has the wrong size".to_string());
+        }
+
+        let fanout: Vec<u32> = (0..256)
+            .map(|idx| read_u32(fanout_chunk, idx * 4))
+            .collect();
+
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err("commit-graph fanout is not monotonic".to_string());
+        }
+
+        let num_commits = fanout[255] as usize;
+        let oid_chunk = required_chunk(CHUNK_OID_LOOKUP, "OIDL")?;
+        let commit_data = required_chunk(CHUNK_COMMIT_DATA, "CDAT")?;
+
+        if oid_chunk.len() != num_commits * HASH_SIZE
+            || commit_data.len() != num_commits * COMMIT_DATA_SIZE
+        {
+            return Err("commit-graph chunk sizes do not match the commit count".to_string());
+        }
+
+        let oids = oid_chunk
+            .chunks(HASH_SIZE)
+            .map(|oid| oid.try_into().unwrap())
+            .collect();
+
+        let generation_data = match chunks.get(&CHUNK_GENERATION_DATA) {
+            Some(chunk) if chunk.len() == num_commits * 4 => Some(
+                (0..num_commits)
+                    .map(|idx| read_u32(chunk, idx * 4))
+                    .collect(),
+            ),
+            Some(_) => return Err("commit-graph GDA2 chunk has the wrong size".to_string()),
+            None => None,
+        };
+
+        let generation_data_overflow = chunks
+            .get(&CHUNK_GENERATION_DATA_OVERFLOW)
+            .map(|chunk| {
+                (0..chunk.len() / 8)
+                    .map(|idx| read_u64(chunk, idx * 8))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let extra_edges = chunks
+            .get(&CHUNK_EXTRA_EDGES)
+            .map(|chunk| {
+                (0..chunk.len() / 4)
+                    .map(|idx| read_u32(chunk, idx * 4))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let base_graphs: Vec<String> = chunks
+            .get(&CHUNK_BASE_GRAPHS)
+            .map(|chunk| chunk.chunks(HASH_SIZE).map(hex::encode).collect())
+            .unwrap_or_default();
+
+        if base_graphs.len() != num_base_graphs {
+            return Err("commit-graph BASE chunk does not match the header".to_string());
+        }
+
+        // Bloom filters are optional: a layer missing either chunk simply has no filters
+        let (bloom_settings, bloom_index, bloom_data) = match (
+            chunks.get(&CHUNK_BLOOM_INDEXES),
+            chunks.get(&CHUNK_BLOOM_DATA),
+        ) {
+            (Some(index), Some(data))
+                if index.len() == num_commits * 4 && data.len() >= BLOOM_DATA_HEADER_SIZE =>
+            {
+                let settings = BloomFilterSettings {
+                    hash_version: read_u32(data, 0),
+                    num_hashes: read_u32(data, 4),
+                    bits_per_entry: read_u32(data, 8),
+                };
+                let index: Vec<u32> = (0..num_commits)
+                    .map(|idx| read_u32(index, idx * 4))
+                    .collect();
+                let data = data[BLOOM_DATA_HEADER_SIZE..].to_vec();
+
+                if index.windows(2).any(|pair| pair[0] > pair[1])
+                    || index
+                        .last()
+                        .map(|&end| end as usize > data.len())
+                        .unwrap_or(false)
+                {
+                    return Err("commit-graph BIDX chunk is corrupt".to_string());
+                }
+
+                (Some(settings), index, data)
+            }
+            _ => (None, Vec::new(), Vec::new()),
+        };
+
+        Ok(Self {
+            checksum: hex::encode(&data[data_end..]),
+            base_graphs,
+            oids,
+            fanout,
+            commit_data: commit_data.to_vec(),
+            extra_edges,
+            generation_data,
+            generation_data_overflow,
+            bloom_settings,
+            bloom_index,
+            bloom_data,
+        })
+    }
+
+    pub fn num_commits(&self) -> usize {
+        self.oids.len()
+    }
+
+    fn bloom_filter_at(&self, idx: usize) -> Option<(BloomFilter, BloomFilterSettings)> {
+        let settings = self.bloom_settings?;
+        let start = if idx == 0 {
+            0
+        } else {
+            self.bloom_index[idx - 1] as usize
+        };
+        let end = self.bloom_index[idx] as usize;
+
+        Some((
+            BloomFilter {
+                data: self.bloom_data[start..end].to_vec(),
+            },
+            settings,
+        ))
+    }
+
+    fn local_position(&self, oid: &[u8; HASH_SIZE]) -> Option<usize> {
+        let first_byte = oid[0] as usize;
+        let start = if first_byte == 0 {
+            0
+        } else {
+            self.fanout[first_byte - 1] as usize
+        };
+        let end = self.fanout[first_byte] as usize;
+
+        self.oids[start..end]
+            .binary_search(oid)
+            .ok()
+            .map(|idx| start + idx)
+    }
+}
+
+/// A commit-graph made of one or more layers, base layer first.
+/// Parent positions are global across layers: a layer's commits are numbered after all of its bases.
+#[derive(Debug)]
+pub struct CommitGraph {
+    pub layers: Vec<CommitGraphLayer>,
+}
+
+impl CommitGraph {
+    pub fn new(layers: Vec<CommitGraphLayer>) -> Result<Self, String> {
+        for (idx, layer) in layers.iter().enumerate() {
+            let expected_bases: Vec<&String> = layers[..idx].iter().map(|l| &l.checksum).collect();
+            let actual_bases: Vec<&String> = layer.base_graphs.iter().collect();
+
+            if expected_bases != actual_bases {
+                return Err(format!(
+                    "commit-graph layer {} does not match its base graphs",
+                    layer.checksum
+                ));
+            }
+        }
+
+        Ok(Self { layers })
+    }
+
+    pub fn num_commits(&self) -> usize {
+        self.layers.iter().map(|layer| layer.num_commits()).sum()
+    }
+
+    /// Generation number v2 is only usable when every layer carries it.
+    pub fn has_generation_v2(&self) -> bool {
+        self.layers
+            .iter()
+            .all(|layer| layer.generation_data.is_some())
+    }
+
+    pub fn has_bloom_filters(&self) -> bool {
+        self.layers
+            .iter()
+            .all(|layer| layer.bloom_settings.is_some())
+    }
+
+    pub fn position(&self, sha: &str) -> Option<u32> {
+        let oid: [u8; HASH_SIZE] = hex::decode(sha).ok()?.try_into().ok()?;
+        let mut base_count = 0;
+
+        for layer in &self.layers {
+            if let Some(idx) = layer.local_position(&oid) {
+                return Some((base_count + idx) as u32);
+            }
+
+            base_count += layer.num_commits();
+        }
+
+        None
+    }
+
+    /// Returns the changed-path Bloom filter stored for `sha`, if its layer has filters.
+    pub fn bloom_filter(&self, sha: &str) -> Option<(BloomFilter, BloomFilterSettings)> {
+        let (layer, idx) = self.layer_at(self.position(sha)?).ok()?;
+
+        layer.bloom_filter_at(idx)
+    }
+
+    pub fn lookup(&self, sha: &str) -> Result<Option<CommitGraphEntry>, String> {
+        match self.position(sha) {
+            Some(position) => Ok(Some(self.entry_at(position)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn layer_at(&self, position: u32) -> Result<(&CommitGraphLayer, usize), String> {
+        let mut local = position as usize;
+
+        for layer in &self.layers {
+            if local < layer.num_commits() {
+                return Ok((layer, local));
+            }
+
+            local -= layer.num_commits();
+        }
+
+        Err(format!(
+            "commit-graph position {} is out of range",
+            position
+        ))
+    }
+
+    pub fn sha_at(&self, position: u32) -> Result<String, String> {
+        let (layer, idx) = self.layer_at(position)?;
+
+        Ok(hex::encode(layer.oids[idx]))
+    }
+
+    pub fn entry_at(&self, position: u32) -> Result<CommitGraphEntry, String> {
+        let (layer, idx) = self.layer_at(position)?;
+        let data = &layer.commit_data[idx * COMMIT_DATA_SIZE..(idx + 1) * COMMIT_DATA_SIZE];
+        let first_parent = read_u32(data, HASH_SIZE);
+        let second_parent = read_u32(data, HASH_SIZE + 4);
+        let generation_and_time_high = read_u32(data, HASH_SIZE + 8);
+        let time_low = read_u32(data, HASH_SIZE + 12);
+
+        let mut parent_positions = Vec::new();
+
+        if first_parent != GRAPH_PARENT_NONE {
+            parent_positions.push(first_parent);
+        }
+
+        if second_parent & GRAPH_EXTRA_EDGES_NEEDED != 0 {
+            let mut edge_idx = (second_parent & !GRAPH_EXTRA_EDGES_NEEDED) as usize;
+
+            loop {
+                let edge = *layer
+                    .extra_edges
+                    .get(edge_idx)
+                    .ok_or("commit-graph extra edge list is truncated")?;
+
+                parent_positions.push(edge & !GRAPH_LAST_EDGE);
+
+                if edge & GRAPH_LAST_EDGE != 0 {
+                    break;
+                }
+
+                edge_idx += 1;
+            }
+        } else if second_parent != GRAPH_PARENT_NONE {
+            parent_positions.push(second_parent);
+        }
+
+        let parents = parent_positions
+            .into_iter()
+            .map(|parent| self.sha_at(parent))
+            .collect::<Result<Vec<String>, String>>()?;
+
+        let commit_time = (((generation_and_time_high & 0x3) as u64) << 32) | time_low as u64;
+        let corrected_commit_date = match &layer.generation_data {
+            Some(generation_data) if self.has_generation_v2() => {
+                let offset = generation_data[idx];
+                let offset = if offset & CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW != 0 {
+                    *layer
+                        .generation_data_overflow
+                        .get((offset & !CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW) as usize)
+                        .ok_or("commit-graph generation overflow list is truncated")?
+                } else {
+                    offset as u64
+                };
+
+                Some(commit_time + offset)
+            }
+            _ => None,
+        };
+
+        Ok(CommitGraphEntry {
+            sha: hex::encode(layer.oids[idx]),
+            tree: hex::encode(&data[..HASH_SIZE]),
+            parents,
+            commit_time,
+            topological_level: generation_and_time_high >> 2,
+            corrected_commit_date,
+        })
+    }
+
+    /// Serializes a new layer holding `commits` on top of `base`. Every parent must be either in
+    /// `commits` or already in `base`.
+    pub fn write_layer(
+        commits: &[CommitGraphCommit],
+        base: Option<&CommitGraph>,
+    ) -> Result<Vec<u8>, String> {
+        let mut commits: Vec<&CommitGraphCommit> = commits.iter().collect();
+        commits.sort_by(|a, b| a.sha.cmp(&b.sha));
+        commits.dedup_by(|a, b| a.sha == b.sha);
+
+        let base_count = base.map(|graph| graph.num_commits()).unwrap_or(0);
+        let local_positions: HashMap<&str, usize> = commits
+            .iter()
+            .enumerate()
+            .map(|(idx, commit)| (commit.sha.as_str(), idx))
+            .collect();
+
+        let global_position = |sha: &str| -> Result<u32, String> {
+            if let Some(idx) = local_positions.get(sha) {
+                return Ok((base_count + idx) as u32);
+            }
+
+            base.and_then(|graph| graph.position(sha))
+                .ok_or_else(|| format!("parent {} is missing from the commit-graph", sha))
+        };
+
+        let generations = Self::compute_generations(&commits, &local_positions, base)?;
+
+        let mut fanout = vec![0u32; 256];
+        let mut oid_lookup = Vec::new();
+        let mut commit_data = Vec::new();
+        let mut extra_edges: Vec<u32> = Vec::new();
+        let mut generation_data = Vec::new();
+        let mut generation_data_overflow = Vec::new();
+
+        for (idx, commit) in commits.iter().enumerate() {
+            let oid = hex::decode(&commit.sha).map_err(|err| err.to_string())?;
+            let tree = hex::decode(&commit.tree).map_err(|err| err.to_string())?;
+
+            fanout[oid[0] as usize] += 1;
+            oid_lookup.extend_from_slice(&oid);
+            commit_data.extend_from_slice(&tree);
+
+            let parent_positions = commit
+                .parents
+                .iter()
+                .map(|parent| global_position(parent))
+                .collect::<Result<Vec<u32>, String>>()?;
+
+            let first_parent = parent_positions
+                .first()
+                .copied()
+                .unwrap_or(GRAPH_PARENT_NONE);
+            let second_parent = match parent_positions.len() {
+                0 | 1 => GRAPH_PARENT_NONE,
+                2 => parent_positions[1],
+                _ => {
+                    let edge_idx = extra_edges.len() as u32;
+                    let last = parent_positions.len() - 2;
+
+                    for (position_idx, position) in parent_positions[1..].iter().enumerate() {
+                        let flag = if position_idx == last {
+                            GRAPH_LAST_EDGE
+                        } else {
+                            0
+                        };
+                        extra_edges.push(position | flag);
+                    }
+
+                    GRAPH_EXTRA_EDGES_NEEDED | edge_idx
+                }
+            };
+
+            let (topological_level, corrected_commit_date) = generations[idx];
+
+            commit_data.extend_from_slice(&first_parent.to_be_bytes());
+            commit_data.extend_from_slice(&second_parent.to_be_bytes());
+            commit_data.extend_from_slice(
+                &((topological_level << 2) | ((commit.commit_time >> 32) as u32 & 0x3))
+                    .to_be_bytes(),
+            );
+            commit_data.extend_from_slice(&(commit.commit_time as u32).to_be_bytes());
+
+            let offset = corrected_commit_date - commit.commit_time;
+
+            if offset >= CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW as u64 {
+                let overflow_idx = generation_data_overflow.len() as u32;
+
+                generation_data.extend_from_slice(
+                    &(CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW | overflow_idx).to_be_bytes(),
+                );
+                generation_data_overflow.extend_from_slice(&offset.to_be_bytes());
+            } else {
+                generation_data.extend_from_slice(&(offset as u32).to_be_bytes());
+            }
+        }
+
+        for idx in 1..256 {
+            fanout[idx] += fanout[idx - 1];
+        }
+
+        let mut chunks = vec![
+            (
+                CHUNK_OID_FANOUT,
+                fanout
+                    .iter()
+                    .flat_map(|count| count.to_be_bytes())
+                    .collect(),
+            ),
+            (CHUNK_OID_LOOKUP, oid_lookup),
+            (CHUNK_COMMIT_DATA, commit_data),
+            (CHUNK_GENERATION_DATA, generation_data),
+        ];
+
+        if !generation_data_overflow.is_empty() {
+            chunks.push((CHUNK_GENERATION_DATA_OVERFLOW, generation_data_overflow));
+        }
+
+        if !extra_edges.is_empty() {
+            chunks.push((
+                CHUNK_EXTRA_EDGES,
+                extra_edges
+                    .iter()
+                    .flat_map(|edge| edge.to_be_bytes())
+                    .collect(),
+            ));
+        }
+
+        let bloom_filters: Option<Vec<&BloomFilter>> = commits
+            .iter()
+            .map(|commit| commit.bloom_filter.as_ref())
+            .collect();
+
+        if let Some(bloom_filters) = bloom_filters.filter(|filters| !filters.is_empty()) {
+            let settings = BloomFilterSettings::default();
+            let mut bloom_index = Vec::new();
+            let mut bloom_data = Vec::new();
+
+            for value in [
+                settings.hash_version,
+                settings.num_hashes,
+                settings.bits_per_entry,
+            ] {
+                bloom_data.extend_from_slice(&value.to_be_bytes());
+            }
+
+            for filter in bloom_filters {
+                bloom_data.extend_from_slice(&filter.data);
+                bloom_index.extend_from_slice(
+                    &((bloom_data.len() - BLOOM_DATA_HEADER_SIZE) as u32).to_be_bytes(),
+                );
+            }
+
+            chunks.push((CHUNK_BLOOM_INDEXES, bloom_index));
+            chunks.push((CHUNK_BLOOM_DATA, bloom_data));
+        }
+
+        let base_layers = base.map(|graph| graph.layers.as_slice()).unwrap_or(&[]);
+
+        if !base_layers.is_empty() {
+            let mut base_chunk = Vec::new();
+
+            for layer in base_layers {
+                base_chunk.extend(hex::decode(&layer.checksum).map_err(|err| err.to_string())?);
+            }
+
+            chunks.push((CHUNK_BASE_GRAPHS, base_chunk));
+        }
+
+        let header = [
+            SIGNATURE[0],
+            SIGNATURE[1],
+            SIGNATURE[2],
+            SIGNATURE[3],
+            VERSION,
+            HASH_VERSION_SHA1,
+            chunks.len() as u8,
+            base_layers.len() as u8,
+        ];
+
+        let mut file = write_chunk_file(&header, &chunks);
+        let checksum = Sha1::digest(&file);
+        file.extend_from_slice(&checksum);
+
+        Ok(file)
+    }
+
+    /// Computes `(topological level, corrected commit date)` for each commit, parents first.
+    fn compute_generations(
+        commits: &[&CommitGraphCommit],
+        local_positions: &HashMap<&str, usize>,
+        base: Option<&CommitGraph>,
+    ) -> Result<Vec<(u32, u64)>, String> {
+        let mut generations: Vec<Option<(u32, u64)>> = vec![None; commits.len()];
+
+        for start in 0..commits.len() {
+            let mut stack = vec![start];
+
+            while let Some(&idx) = stack.last() {
+                if generations[idx].is_some() {
+                    stack.pop();
+                    continue;
+                }
+
+                let mut pending_parent = None;
+                let mut max_level = 0;
+                let mut max_corrected_date = 0;
+
+                for parent in &commits[idx].parents {
+                    let parent_generation = match local_positions.get(parent.as_str()) {
+                        Some(&parent_idx) => match generations[parent_idx] {
+                            Some(generation) => generation,
+                            None => {
+                                pending_parent = Some(parent_idx);
+                                break;
+                            }
+                        },
+                        None => {
+                            let entry = base
+                                .map(|graph| graph.lookup(parent))
+                                .transpose()?
+                                .flatten()
+                                .ok_or_else(|| {
+                                    format!("parent {} is missing from the commit-graph", parent)
+                                })?;
+
+                            (
+                                entry.topological_level,
+                                entry.corrected_commit_date.unwrap_or(entry.commit_time),
+                            )
+                        }
+                    };
+
+                    max_level = max_level.max(parent_generation.0);
+                    max_corrected_date = max_corrected_date.max(parent_generation.1 + 1);
+                }
+
+                match pending_parent {
+                    Some(parent_idx) => stack.push(parent_idx),
+                    None => {
+                        let level = (max_level + 1).min(GENERATION_NUMBER_V1_MAX);
+                        let corrected_date = max_corrected_date.max(commits[idx].commit_time);
+
+                        generations[idx] = Some((level, corrected_date));
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        Ok(generations.into_iter().map(|g| g.unwrap()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha(byte: u8) -> String {
+        hex::encode([byte; 20])
+    }
+
+    fn commit(byte: u8, parents: Vec<u8>, commit_time: u64) -> CommitGraphCommit {
+        CommitGraphCommit {
+            sha: sha(byte),
+            tree: sha(0xee),
+            parents: parents.into_iter().map(sha).collect(),
+            commit_time,
+            bloom_filter: None,
+        }
+    }
+
+    fn graph_from(commits: &[CommitGraphCommit]) -> CommitGraph {
+        let file = CommitGraph::write_layer(commits, None).unwrap();
+
+        CommitGraph::new(vec![CommitGraphLayer::from_bytes(&file).unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn write_layer_then_lookup_round_trips_commits() {
+        let graph = graph_from(&[
+            commit(0x10, vec![], 100),
+            commit(0x20, vec![0x10], 200),
+            commit(0x30, vec![0x10, 0x20], 300),
+        ]);
+
+        let entry = graph.lookup(&sha(0x30)).unwrap().unwrap();
+
+        assert_eq!(graph.num_commits(), 3);
+        assert_eq!(entry.tree, sha(0xee));
+        assert_eq!(entry.parents, vec![sha(0x10), sha(0x20)]);
+        assert_eq!(entry.commit_time, 300);
+        assert_eq!(entry.topological_level, 3);
+        assert_eq!(graph.lookup(&sha(0x40)).unwrap(), None);
+    }
+
+    #[test]
+    fn write_layer_stores_octopus_parents_in_extra_edges() {
+        let graph = graph_from(&[
+            commit(0x01, vec![], 1),
+            commit(0x02, vec![], 2),
+            commit(0x03, vec![], 3),
+            commit(0x04, vec![0x01, 0x02, 0x03], 4),
+        ]);
+
+        let entry = graph.lookup(&sha(0x04)).unwrap().unwrap();
+
+        assert_eq!(entry.parents, vec![sha(0x01), sha(0x02), sha(0x03)]);
+    }
+
+    #[test]
+    fn write_layer_computes_corrected_commit_dates() {
+        // the child claims to be older than its parent, so its corrected date is bumped
+        let graph = graph_from(&[commit(0x01, vec![], 500), commit(0x02, vec![0x01], 100)]);
+
+        let entry = graph.lookup(&sha(0x02)).unwrap().unwrap();
+
+        assert_eq!(entry.commit_time, 100);
+        assert_eq!(entry.corrected_commit_date, Some(501));
+    }
+
+    #[test]
+    fn write_layer_on_top_of_base_uses_global_positions() {
+        let base_file = CommitGraph::write_layer(&[commit(0x01, vec![], 1)], None).unwrap();
+        let base =
+            CommitGraph::new(vec![CommitGraphLayer::from_bytes(&base_file).unwrap()]).unwrap();
+
+        let top_file =
+            CommitGraph::write_layer(&[commit(0x02, vec![0x01], 2)], Some(&base)).unwrap();
+        let top = CommitGraphLayer::from_bytes(&top_file).unwrap();
+
+        let mut layers = base.layers;
+        layers.push(top);
+        let graph = CommitGraph::new(layers).unwrap();
+
+        let entry = graph.lookup(&sha(0x02)).unwrap().unwrap();
+
+        assert_eq!(graph.num_commits(), 2);
+        assert_eq!(entry.parents, vec![sha(0x01)]);
+        assert_eq!(entry.topological_level, 2);
+    }
+
+    #[test]
+    fn write_layer_stores_bloom_filters_when_every_commit_has_one() {
+        let settings = BloomFilterSettings::default();
+        let mut root = commit(0x01, vec![], 1);
+        let mut child = commit(0x02, vec![0x01], 2);
+        root.bloom_filter = Some(BloomFilter::from_paths(&["README".to_string()], &settings));
+        child.bloom_filter = Some(BloomFilter::from_paths(&["src".to_string()], &settings));
+
+        let graph = graph_from(&[root, child]);
+        let (filter, stored_settings) = graph.bloom_filter(&sha(0x02)).unwrap();
+
+        assert_eq!(stored_settings, settings);
+        assert!(filter.contains("src", &settings));
+        assert!(!filter.contains("README", &settings));
+    }
+
+    #[test]
+    fn write_layer_skips_bloom_filters_when_any_commit_lacks_one() {
+        let mut root = commit(0x01, vec![], 1);
+        root.bloom_filter = Some(BloomFilter::from_paths(
+            &[],
+            &BloomFilterSettings::default(),
+        ));
+
+        let graph = graph_from(&[root, commit(0x02, vec![0x01], 2)]);
+
+        assert_eq!(graph.bloom_filter(&sha(0x01)), None);
+    }
+
+    #[test]
+    fn write_layer_returns_error_for_missing_parent() {
+        let result = CommitGraph::write_layer(&[commit(0x02, vec![0x01], 2)], None);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_bytes_returns_error_for_corrupt_checksum() {
+        let mut file = CommitGraph::write_layer(&[commit(0x01, vec![], 1)], None).unwrap();
+        let last = file.len() - 1;
+        file[last] ^= 0xff;
+
+        assert!(CommitGraphLayer::from_bytes(&file).is_err());
+    }
+
+    #[test]
+    fn new_returns_error_for_mismatched_base_graphs() {
+        let first = CommitGraph::write_layer(&[commit(0x01, vec![], 1)], None).unwrap();
+        let second = CommitGraph::write_layer(&[commit(0x02, vec![], 2)], None).unwrap();
+
+        let result = CommitGraph::new(vec![
+            CommitGraphLayer::from_bytes(&first).unwrap(),
+            CommitGraphLayer::from_bytes(&second).unwrap(),
+        ]);
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,241 @@
+/// A parsed git config file. Keys are stored as `section.key` or `section.subsection.key`, with
+/// the section and key lowercased (they are case-insensitive) and the subsection kept as is.
+#[derive(Debug, Default)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        let mut section: Option<String> = None;
+        let mut lines = content.lines().enumerate();
+
+        while let Some((line_idx, line)) = lines.next() {
+            let mut line = line.to_string();
+
+            // a trailing backslash continues the value on the next line
+            while line.ends_with('\\') && !line.ends_with("\\\\") {
+                line.pop();
+
+                match lines.next() {
+                    Some((_, next)) => line.push_str(next),
+                    None => break,
+                }
+            }
+
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+                continue;
+            }
+
+            let bad_line = || format!("bad config line {}", line_idx + 1);
+
+            if let Some(rest) = trimmed.strip_prefix('[') {
+                let end = rest.find(']').ok_or_else(bad_line)?;
+
+                section = Some(parse_section_header(&rest[..end]).ok_or_else(bad_line)?);
+
+                let rest = rest[end + 1..].trim();
+
+                if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+                    continue;
+                }
+
+                // `[core] bare = true` puts an entry on the header line
+                entries.push(parse_entry(section.as_deref(), rest).ok_or_else(bad_line)?);
+                continue;
+            }
+
+            entries.push(parse_entry(section.as_deref(), trimmed).ok_or_else(bad_line)?);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Returns the last value set for `key`, which is matched like git does: section and key
+    /// case-insensitively, subsection exactly.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key)?;
+
+        self.entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value set for a multi-valued `key`, like `remote.origin.fetch`, in order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = match normalize_key(key) {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+
+        self.entries
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, String> {
+        self.get(key)
+            .map(|value| match value.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok(true),
+                "false" | "no" | "off" | "0" | "" => Ok(false),
+                _ => Err(format!(
+                    "bad boolean config value '{}' for '{}'",
+                    value, key
+                )),
+            })
+            .transpose()
+    }
+
+    /// Reads an integer, allowing git's `k`, `m` and `g` suffixes.
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>, String> {
+        self.get(key)
+            .map(|value| {
+                let lower = value.to_lowercase();
+                let (digits, factor) = match lower.chars().last() {
+                    Some('k') => (&lower[..lower.len() - 1], 1 << 10),
+                    Some('m') => (&lower[..lower.len() - 1], 1 << 20),
+                    Some('g') => (&lower[..lower.len() - 1], 1 << 30),
+                    _ => (lower.as_str(), 1),
+                };
+
+                digits
+                    .trim()
+                    .parse::<i64>()
+                    .map(|number| number * factor)
+                    .map_err(|_| format!("bad numeric config value '{}' for '{}'", value, key))
+            })
+            .transpose()
+    }
+}
+
+/// Turns `remote "origin"` into `remote.origin` and `Core` into `core`; the deprecated
+/// `[branch.main]` form lowercases the whole name.
+fn parse_section_header(header: &str) -> Option<String> {
+    let header = header.trim();
+
+    match header.split_once(char::is_whitespace) {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+            Some(format!(
+                "{}.{}",
+                name.to_lowercase(),
+                subsection.replace("\\\"", "\"").replace("\\\\", "\\")
+            ))
+        }
+        None => Some(header.to_lowercase()),
+    }
+}
+
+fn parse_entry(section: Option<&str>, line: &str) -> Option<(String, String)> {
+    let section = section?;
+    let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), parse_value(value)),
+        // a bare key is a boolean set to true
+        None => (line.trim(), "true".to_string()),
+    };
+
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+
+    Some((format!("{}.{}", section, key.to_lowercase()), value))
+}
+
+/// Strips comments and surrounding whitespace and resolves quotes and escapes.
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(other) => value.push(other),
+                    None => {}
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+
+    value
+}
+
+fn normalize_key(key: &str) -> Option<String> {
+    let (section, rest) = key.split_once('.')?;
+
+    Some(match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[core]\n\
+        \tbare = false\n\
+        \tfilemode\n\
+        [remote \"Origin\"]\n\
+        \turl = \"https://example.com/repo.git\" # upstream\n\
+        \tfetch = +refs/heads/*:refs/remotes/Origin/*\n\
+        [gc]\n\
+        \tauto = 1k\n\
+        \tauto = 2k ; the last value wins\n\
+        \tpruneExpire = 2.weeks.ago\n";
+
+    #[test]
+    fn get_matches_keys_like_git() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        assert_eq!(
+            config.get("remote.Origin.URL"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get("GC.pruneexpire"), Some("2.weeks.ago"));
+        assert_eq!(config.get_bool("core.fileMode").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(config.get_int("gc.auto").unwrap(), Some(2048));
+        assert_eq!(config.get_int("gc.autoPackLimit").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_returns_error_for_entries_outside_a_section() {
+        assert_eq!(
+            Config::parse("bare = true\n").unwrap_err(),
+            "bad config line 1"
+        );
+    }
+}
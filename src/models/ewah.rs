@@ -0,0 +1,248 @@
+use crate::models::chunk_format::{read_u32, read_u64};
+
+const RUNNING_LENGTH_BITS: u32 = 32;
+const LITERAL_WORDS_BITS: u32 = 31;
+const MAX_RUNNING_LENGTH: u64 = (1 << RUNNING_LENGTH_BITS) - 1;
+const MAX_LITERAL_WORDS: usize = (1 << LITERAL_WORDS_BITS) - 1;
+
+/// An uncompressed bitmap that converts to and from the EWAH run-length encoding used by
+/// `.bitmap` files: runs of all-zero or all-one words, each followed by literal words.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, position: usize) {
+        let word = position / 64;
+
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+
+        self.words[word] |= 1 << (position % 64);
+    }
+
+    pub fn get(&self, position: usize) -> bool {
+        self.words
+            .get(position / 64)
+            .map(|word| word & (1 << (position % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    pub fn or(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+
+        for (word, other_word) in self.words.iter_mut().zip(&other.words) {
+            *word |= other_word;
+        }
+    }
+
+    pub fn and_not(&mut self, other: &Bitmap) {
+        for (word, other_word) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other_word;
+        }
+
+        self.trim();
+    }
+
+    pub fn xor(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+
+        for (word, other_word) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other_word;
+        }
+
+        self.trim();
+    }
+
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Iterates over the positions of set bits in ascending order.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(idx, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| idx * 64 + bit)
+        })
+    }
+
+    fn trim(&mut self) {
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+
+    /// Decodes a serialized EWAH bitmap, returning it with the number of bytes consumed.
+    pub fn from_ewah(data: &[u8]) -> Result<(Self, usize), String> {
+        if data.len() < 8 {
+            return Err("EWAH bitmap is truncated".to_string());
+        }
+
+        let num_words = read_u32(data, 4) as usize;
+        let size = 8 + num_words * 8 + 4;
+
+        if data.len() < size {
+            return Err("EWAH bitmap is truncated".to_string());
+        }
+
+        let buffer: Vec<u64> = (0..num_words)
+            .map(|idx| read_u64(data, 8 + idx * 8))
+            .collect();
+        let mut words = Vec::new();
+        let mut pos = 0;
+
+        while pos < buffer.len() {
+            let marker = buffer[pos];
+            let running_bit = marker & 1 != 0;
+            let running_length = (marker >> 1) & MAX_RUNNING_LENGTH;
+            let literal_words = (marker >> (1 + RUNNING_LENGTH_BITS)) as usize;
+            let literals = buffer
+                .get(pos + 1..pos + 1 + literal_words)
+                .ok_or("EWAH bitmap literal words are truncated")?;
+
+            words.extend(std::iter::repeat_n(
+                if running_bit { u64::MAX } else { 0 },
+                running_length as usize,
+            ));
+            words.extend_from_slice(literals);
+            pos += 1 + literal_words;
+        }
+
+        let mut bitmap = Self { words };
+        bitmap.trim();
+
+        Ok((bitmap, size))
+    }
+
+    pub fn to_ewah(&self) -> Vec<u8> {
+        let mut buffer: Vec<u64> = Vec::new();
+        let mut last_marker = 0;
+        let mut idx = 0;
+
+        while idx < self.words.len() || buffer.is_empty() {
+            let mut running_bit = false;
+            let mut running_length = 0u64;
+
+            if let Some(&word) = self.words.get(idx).filter(|&&w| w == 0 || w == u64::MAX) {
+                running_bit = word == u64::MAX;
+
+                while self.words.get(idx) == Some(&word) && running_length < MAX_RUNNING_LENGTH {
+                    running_length += 1;
+                    idx += 1;
+                }
+            }
+
+            let literals_start = idx;
+
+            while idx < self.words.len()
+                && self.words[idx] != 0
+                && self.words[idx] != u64::MAX
+                && idx - literals_start < MAX_LITERAL_WORDS
+            {
+                idx += 1;
+            }
+
+            last_marker = buffer.len();
+            buffer.push(
+                running_bit as u64
+                    | running_length << 1
+                    | ((idx - literals_start) as u64) << (1 + RUNNING_LENGTH_BITS),
+            );
+            buffer.extend_from_slice(&self.words[literals_start..idx]);
+        }
+
+        let mut data = Vec::with_capacity(12 + buffer.len() * 8);
+
+        data.extend_from_slice(&((self.words.len() * 64) as u32).to_be_bytes());
+        data.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+
+        for word in buffer {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+
+        data.extend_from_slice(&(last_marker as u32).to_be_bytes());
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(positions: &[usize]) -> Bitmap {
+        let mut bitmap = Bitmap::new();
+
+        for &position in positions {
+            bitmap.set(position);
+        }
+
+        bitmap
+    }
+
+    #[test]
+    fn to_ewah_then_from_ewah_round_trips() {
+        let mut long_run = bitmap(&[3, 64 * 10 + 1, 64 * 10 + 7]);
+
+        for position in 64 * 20..64 * 23 {
+            long_run.set(position);
+        }
+
+        for original in [Bitmap::new(), bitmap(&[0]), long_run] {
+            let data = original.to_ewah();
+            let (decoded, size) = Bitmap::from_ewah(&data).unwrap();
+
+            assert_eq!(decoded, original);
+            assert_eq!(size, data.len());
+        }
+    }
+
+    #[test]
+    fn to_ewah_compresses_runs_of_clean_words() {
+        let mut ones = Bitmap::new();
+
+        for position in 0..64 * 100 {
+            ones.set(position);
+        }
+
+        // one marker word for the whole run
+        assert_eq!(ones.to_ewah().len(), 4 + 4 + 8 + 4);
+    }
+
+    #[test]
+    fn set_operations_combine_bitmaps() {
+        let mut result = bitmap(&[1, 2, 200]);
+
+        result.or(&bitmap(&[3]));
+        result.and_not(&bitmap(&[2, 200]));
+
+        assert_eq!(result.ones().collect::<Vec<usize>>(), vec![1, 3]);
+        assert_eq!(result.count(), 2);
+        assert_eq!(result, bitmap(&[1, 3]));
+
+        result.xor(&bitmap(&[1, 5]));
+
+        assert_eq!(result, bitmap(&[3, 5]));
+    }
+
+    #[test]
+    fn from_ewah_returns_error_for_truncated_data() {
+        let data = bitmap(&[1, 100]).to_ewah();
+
+        assert!(Bitmap::from_ewah(&data[..data.len() - 5]).is_err());
+    }
+}
@@ -15,7 +15,7 @@ impl GitObject {
         }
     }
 
-    pub fn from_object_file_buffer(file_buffer: &Vec<u8>) -> Result<Self, String> {
+    pub fn from_object_file_buffer(file_buffer: &[u8]) -> Result<Self, String> {
         let null_position = file_buffer
             .iter()
             .position(|&x| x == 0)
@@ -39,6 +39,8 @@ impl GitObject {
         match &self.object {
             Object::Blob(blob) => &blob.content,
             Object::Tree(tree) => &tree.content,
+            Object::Commit(commit) => &commit.content,
+            Object::Tag(tag) => &tag.content,
         }
     }
 }
@@ -0,0 +1,332 @@
+use sha1::{Digest, Sha1};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const HEADER_SIZE: usize = 12;
+const HASH_SIZE: usize = 20;
+// ctime, mtime, dev, ino, mode, uid, gid and size, all 32 bits
+const STAT_SIZE: usize = 40;
+const NAME_MASK: u16 = 0x0fff;
+const EXTENDED_FLAG: u16 = 0x4000;
+const GITLINK_MODE: u32 = 0o160000;
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+/// The file system data git compares to tell whether a file changed since it was staged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexStat {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub mode: u32,
+    pub sha: String,
+    pub path: String,
+    pub stat: IndexStat,
+}
+
+impl IndexEntry {
+    pub fn is_gitlink(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == GITLINK_MODE
+    }
+}
+
+/// The staging area (`.git/index`). Only the entries are kept; extensions are skipped.
+#[derive(Debug)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Parses index versions 2, 3 and 4.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE + HASH_SIZE || &data[0..4] != SIGNATURE {
+            return Err("not an index file".to_string());
+        }
+
+        let version = read_u32(data, 4);
+
+        if !(2..=4).contains(&version) {
+            return Err(format!("unsupported index version {}", version));
+        }
+
+        let data_end = data.len() - HASH_SIZE;
+
+        if Sha1::digest(&data[..data_end]).as_slice() != &data[data_end..] {
+            return Err("index checksum mismatch".to_string());
+        }
+
+        let num_entries = read_u32(data, 8) as usize;
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(num_entries);
+        let mut pos = HEADER_SIZE;
+
+        for _ in 0..num_entries {
+            let entry_start = pos;
+
+            if pos + STAT_SIZE + HASH_SIZE + 2 > data_end {
+                return Err("index entry is truncated".to_string());
+            }
+
+            let stat = IndexStat {
+                ctime: (read_u32(data, pos), read_u32(data, pos + 4)),
+                mtime: (read_u32(data, pos + 8), read_u32(data, pos + 12)),
+                dev: read_u32(data, pos + 16),
+                ino: read_u32(data, pos + 20),
+                uid: read_u32(data, pos + 28),
+                gid: read_u32(data, pos + 32),
+                size: read_u32(data, pos + 36),
+            };
+            let mode = read_u32(data, pos + 24);
+            let sha = hex::encode(&data[pos + STAT_SIZE..pos + STAT_SIZE + HASH_SIZE]);
+            let flags = u16::from_be_bytes([
+                data[pos + STAT_SIZE + HASH_SIZE],
+                data[pos + STAT_SIZE + HASH_SIZE + 1],
+            ]);
+
+            pos += STAT_SIZE + HASH_SIZE + 2;
+
+            if version >= 3 && flags & EXTENDED_FLAG != 0 {
+                pos += 2;
+            }
+
+            // version 4 stores each path as a suffix of the previous one
+            let prefix = if version == 4 {
+                let (strip, used) = read_varint(&data[pos..data_end])?;
+                let previous = entries
+                    .last()
+                    .map(|entry| entry.path.as_str())
+                    .unwrap_or("");
+                let kept = previous
+                    .len()
+                    .checked_sub(strip)
+                    .and_then(|kept| previous.get(..kept))
+                    .ok_or("index entry strips more than the previous path")?;
+
+                pos += used;
+
+                kept.to_string()
+            } else {
+                String::new()
+            };
+
+            let name_len = data[pos..data_end]
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or("index entry name is not terminated")?;
+            let path = format!(
+                "{}{}",
+                prefix,
+                String::from_utf8_lossy(&data[pos..pos + name_len])
+            );
+
+            if version < 4 && (flags & NAME_MASK) as usize != name_len.min(NAME_MASK as usize) {
+                return Err(format!("index entry {} has a bad name length", path));
+            }
+
+            pos += name_len + 1;
+
+            // versions 2 and 3 pad each entry with NULs to a multiple of eight bytes
+            if version < 4 {
+                pos = entry_start + (pos - entry_start).div_ceil(8) * 8;
+            }
+
+            entries.push(IndexEntry {
+                mode,
+                sha,
+                path,
+                stat,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Writes a version 2 index without extensions. Entries are sorted by path first, as git
+    /// requires.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        let mut data = SIGNATURE.to_vec();
+
+        entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+        for entry in entries {
+            let entry_start = data.len();
+            let stat = &entry.stat;
+
+            for value in [
+                stat.ctime.0,
+                stat.ctime.1,
+                stat.mtime.0,
+                stat.mtime.1,
+                stat.dev,
+                stat.ino,
+                entry.mode,
+                stat.uid,
+                stat.gid,
+                stat.size,
+            ] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+
+            data.extend(
+                hex::decode(&entry.sha)
+                    .map_err(|_| format!("index entry {} has a bad sha", entry.path))?,
+            );
+            data.extend_from_slice(
+                &(entry.path.len().min(NAME_MASK as usize) as u16).to_be_bytes(),
+            );
+            data.extend_from_slice(entry.path.as_bytes());
+
+            // at least one NUL, then padding to a multiple of eight bytes
+            data.push(0);
+
+            while !(data.len() - entry_start).is_multiple_of(8) {
+                data.push(0);
+            }
+        }
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        Ok(data)
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Reads the offset-style varint used by index v4 (and pack ofs-deltas).
+fn read_varint(data: &[u8]) -> Result<(usize, usize), String> {
+    let mut used = 0;
+    let mut next_byte = || -> Result<u8, String> {
+        let byte = *data.get(used).ok_or("index varint is truncated")?;
+        used += 1;
+        Ok(byte)
+    };
+
+    let mut byte = next_byte()?;
+    let mut value = (byte & 0x7f) as usize;
+
+    while byte & 0x80 != 0 {
+        byte = next_byte()?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+
+    Ok((value, used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "e69de29bb2d1d6434b8b29ae77e5c10d391e27a1";
+
+    fn index_bytes(version: u32, names: &[&[u8]]) -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(names.len() as u32).to_be_bytes());
+
+        for name in names {
+            let entry_start = data.len();
+
+            data.extend_from_slice(&[0; 24]);
+            data.extend_from_slice(&0o100644u32.to_be_bytes());
+            data.extend_from_slice(&[0; 12]);
+            data.extend(hex::decode(SHA).unwrap());
+
+            if version == 4 {
+                data.extend_from_slice(&0u16.to_be_bytes());
+                data.extend_from_slice(name);
+                data.push(0);
+            } else {
+                data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                data.extend_from_slice(name);
+                data.push(0);
+
+                while !(data.len() - entry_start).is_multiple_of(8) {
+                    data.push(0);
+                }
+            }
+        }
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        data
+    }
+
+    #[test]
+    fn from_bytes_reads_padded_entries() {
+        let index = Index::from_bytes(&index_bytes(2, &[b"a.txt", b"dir/b.txt"])).unwrap();
+
+        assert_eq!(
+            index.entries,
+            vec![
+                IndexEntry {
+                    mode: 0o100644,
+                    sha: SHA.to_string(),
+                    path: "a.txt".to_string(),
+                    stat: IndexStat::default(),
+                },
+                IndexEntry {
+                    mode: 0o100644,
+                    sha: SHA.to_string(),
+                    path: "dir/b.txt".to_string(),
+                    stat: IndexStat::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn from_bytes_expands_version_4_path_prefixes() {
+        // "dir/b.txt" then strip 5 bytes ("b.txt") and append "c.txt"
+        let index = Index::from_bytes(&index_bytes(4, &[b"\x00dir/b.txt", b"\x05c.txt"])).unwrap();
+        let paths: Vec<&str> = index
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+
+        assert_eq!(paths, vec!["dir/b.txt", "dir/c.txt"]);
+    }
+
+    #[test]
+    fn to_bytes_round_trips_through_from_bytes() {
+        let index = Index {
+            entries: vec![
+                IndexEntry {
+                    mode: 0o100755,
+                    sha: SHA.to_string(),
+                    path: "z/run.sh".to_string(),
+                    stat: IndexStat {
+                        mtime: (1_700_000_000, 5),
+                        size: 12,
+                        ..IndexStat::default()
+                    },
+                },
+                IndexEntry {
+                    mode: 0o100644,
+                    sha: SHA.to_string(),
+                    path: "a.txt".to_string(),
+                    stat: IndexStat::default(),
+                },
+            ],
+        };
+        let read_back = Index::from_bytes(&index.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(
+            read_back.entries,
+            vec![index.entries[1].clone(), index.entries[0].clone()]
+        );
+    }
+}
@@ -1,4 +1,22 @@
+pub mod attributes;
+pub mod bitmap_index;
 pub mod blob;
+pub mod bloom_filter;
+pub mod bundle;
+pub mod chunk_format;
+pub mod commit;
+pub mod commit_graph;
+pub mod config;
+pub mod credential;
+pub mod ewah;
 pub mod git_object;
+pub mod index;
+pub mod multi_pack_index;
 pub mod object;
+pub mod object_filter;
+pub mod pack;
+pub mod pack_index;
+pub mod pkt_line;
+pub mod refspec;
+pub mod tag;
 pub mod tree;
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use sha1::{Digest, Sha1};
+
+use crate::models::chunk_format::{read_chunk_table, read_u32, read_u64, write_chunk_file};
+
+const SIGNATURE: &[u8; 4] = b"MIDX";
+const VERSION: u8 = 1;
+const HASH_VERSION_SHA1: u8 = 1;
+const HEADER_SIZE: usize = 12;
+const HASH_SIZE: usize = 20;
+
+const CHUNK_PACK_NAMES: u32 = 0x504e_414d; // "PNAM"
+const CHUNK_OID_FANOUT: u32 = 0x4f49_4446; // "OIDF"
+const CHUNK_OID_LOOKUP: u32 = 0x4f49_444c; // "OIDL"
+const CHUNK_OBJECT_OFFSETS: u32 = 0x4f4f_4646; // "OOFF"
+const CHUNK_LARGE_OFFSETS: u32 = 0x4c4f_4646; // "LOFF"
+const CHUNK_REVERSE_INDEX: u32 = 0x5249_4458; // "RIDX"
+
+const LARGE_OFFSET_NEEDED: u32 = 0x8000_0000;
+const NOT_PREFERRED: u64 = 1 << 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiPackIndexEntry {
+    pub sha: String,
+    pub pack_id: u32,
+    pub offset: u64,
+}
+
+/// A multi-pack-index: one sha-sorted lookup table over the objects of several packs.
+#[derive(Debug)]
+pub struct MultiPackIndex {
+    pub checksum: String,
+    pub pack_names: Vec<String>,
+    pub entries: Vec<MultiPackIndexEntry>,
+    /// Sha-order positions listed in pseudo-pack order (empty without a `RIDX` chunk).
+    pub pseudo_pack_order: Vec<u32>,
+}
+
+impl MultiPackIndex {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE + HASH_SIZE || &data[0..4] != SIGNATURE {
+            return Err("not a multi-pack-index file".to_string());
+        }
+
+        if data[4] != VERSION {
+            return Err(format!("unsupported multi-pack-index version {}", data[4]));
+        }
+
+        if data[5] != HASH_VERSION_SHA1 {
+            return Err(format!(
+                "unsupported multi-pack-index hash version {}",
+                data[5]
+            ));
+        }
+
+        let data_end = data.len() - HASH_SIZE;
+
+        if Sha1::digest(&data[..data_end]).as_slice() != &data[data_end..] {
+            return Err("multi-pack-index checksum mismatch".to_string());
+        }
+
+        let num_chunks = data[6] as usize;
+        let num_packs = read_u32(data, 8) as usize;
+        let chunks: HashMap<u32, &[u8]> =
+            read_chunk_table(data, HEADER_SIZE, num_chunks, data_end)?
+                .into_iter()
+                .collect();
+        let required_chunk = |chunk_id: u32, name: &str| {
+            chunks
+                .get(&chunk_id)
+                .copied()
+                .ok_or_else(|| format!("multi-pack-index is missing the {} chunk", name))
+        };
+
+        let pack_names: Vec<String> = required_chunk(CHUNK_PACK_NAMES, "PNAM")?
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect();
+
+        if pack_names.len() != num_packs {
+            return Err("multi-pack-index PNAM chunk does not match the header".to_string());
+        }
+
+        let fanout = required_chunk(CHUNK_OID_FANOUT, "OIDF")?;
+
+        if fanout.len() != 256 * 4 {
+            return Err("multi-pack-index fanout chunk has the wrong size".to_string());
+        }
+
+        let num_objects = read_u32(fanout, 255 * 4) as usize;
+        let oids = required_chunk(CHUNK_OID_LOOKUP, "OIDL")?;
+        let offsets = required_chunk(CHUNK_OBJECT_OFFSETS, "OOFF")?;
+        let large_offsets = chunks.get(&CHUNK_LARGE_OFFSETS).copied().unwrap_or(&[]);
+
+        if oids.len() != num_objects * HASH_SIZE || offsets.len() != num_objects * 8 {
+            return Err("multi-pack-index chunk sizes do not match the object count".to_string());
+        }
+
+        let mut entries = Vec::with_capacity(num_objects);
+
+        for idx in 0..num_objects {
+            let pack_id = read_u32(offsets, idx * 8);
+            let offset = read_u32(offsets, idx * 8 + 4);
+            let offset = if offset & LARGE_OFFSET_NEEDED != 0 {
+                let large_idx = (offset & !LARGE_OFFSET_NEEDED) as usize;
+
+                if (large_idx + 1) * 8 > large_offsets.len() {
+                    return Err("multi-pack-index large offset is out of range".to_string());
+                }
+
+                read_u64(large_offsets, large_idx * 8)
+            } else {
+                offset as u64
+            };
+
+            if pack_id as usize >= num_packs {
+                return Err(format!(
+                    "multi-pack-index refers to missing pack {}",
+                    pack_id
+                ));
+            }
+
+            entries.push(MultiPackIndexEntry {
+                sha: hex::encode(&oids[idx * HASH_SIZE..(idx + 1) * HASH_SIZE]),
+                pack_id,
+                offset,
+            });
+        }
+
+        let pseudo_pack_order = match chunks.get(&CHUNK_REVERSE_INDEX) {
+            Some(chunk) if chunk.len() == num_objects * 4 => (0..num_objects)
+                .map(|idx| read_u32(chunk, idx * 4))
+                .collect(),
+            Some(_) => return Err("multi-pack-index RIDX chunk has the wrong size".to_string()),
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            checksum: hex::encode(&data[data_end..]),
+            pack_names,
+            entries,
+            pseudo_pack_order,
+        })
+    }
+
+    /// Serializes a multi-pack-index. `pack_names` must be sorted and `entries` must hold each
+    /// object once, sorted by sha. Objects from `preferred_pack` come first in pseudo-pack order.
+    pub fn write(
+        pack_names: &[String],
+        entries: &[MultiPackIndexEntry],
+        preferred_pack: Option<u32>,
+    ) -> Result<Vec<u8>, String> {
+        let mut header = SIGNATURE.to_vec();
+        let mut pack_names_chunk = Vec::new();
+        let mut fanout = [0u32; 256];
+        let mut oids = Vec::new();
+        let mut offsets = Vec::new();
+        let mut large_offsets = Vec::new();
+
+        for name in pack_names {
+            pack_names_chunk.extend_from_slice(name.as_bytes());
+            pack_names_chunk.push(0);
+        }
+
+        while pack_names_chunk.len() % 4 != 0 {
+            pack_names_chunk.push(0);
+        }
+
+        for entry in entries {
+            let oid = hex::decode(&entry.sha).map_err(|err| err.to_string())?;
+            let offset = if entry.offset >= LARGE_OFFSET_NEEDED as u64 {
+                large_offsets.extend_from_slice(&entry.offset.to_be_bytes());
+                (large_offsets.len() / 8 - 1) as u32 | LARGE_OFFSET_NEEDED
+            } else {
+                entry.offset as u32
+            };
+
+            fanout[oid[0] as usize] += 1;
+            oids.extend(oid);
+            offsets.extend_from_slice(&entry.pack_id.to_be_bytes());
+            offsets.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        let mut fanout_chunk = Vec::with_capacity(256 * 4);
+        let mut total = 0;
+
+        for count in fanout {
+            total += count;
+            fanout_chunk.extend_from_slice(&total.to_be_bytes());
+        }
+
+        let mut pseudo_pack_order: Vec<usize> = (0..entries.len()).collect();
+
+        pseudo_pack_order.sort_by_key(|&idx| {
+            let entry = &entries[idx];
+            let preference = if Some(entry.pack_id) == preferred_pack {
+                0
+            } else {
+                NOT_PREFERRED
+            };
+
+            (preference | entry.pack_id as u64, entry.offset)
+        });
+
+        let reverse_index: Vec<u8> = pseudo_pack_order
+            .into_iter()
+            .flat_map(|idx| (idx as u32).to_be_bytes())
+            .collect();
+
+        let mut chunks = vec![
+            (CHUNK_PACK_NAMES, pack_names_chunk),
+            (CHUNK_OID_FANOUT, fanout_chunk),
+            (CHUNK_OID_LOOKUP, oids),
+            (CHUNK_OBJECT_OFFSETS, offsets),
+        ];
+
+        if !large_offsets.is_empty() {
+            chunks.push((CHUNK_LARGE_OFFSETS, large_offsets));
+        }
+
+        chunks.push((CHUNK_REVERSE_INDEX, reverse_index));
+
+        header.extend_from_slice(&[VERSION, HASH_VERSION_SHA1, chunks.len() as u8, 0]);
+        header.extend_from_slice(&(pack_names.len() as u32).to_be_bytes());
+
+        let mut data = write_chunk_file(&header, &chunks);
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        Ok(data)
+    }
+
+    pub fn position(&self, sha: &str) -> Option<usize> {
+        self.entries
+            .binary_search_by(|entry| entry.sha.as_str().cmp(sha))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(sha: &str, pack_id: u32, offset: u64) -> MultiPackIndexEntry {
+        MultiPackIndexEntry {
+            sha: sha.to_string(),
+            pack_id,
+            offset,
+        }
+    }
+
+    #[test]
+    fn write_then_from_bytes_round_trips() {
+        let pack_names = vec!["pack-a.idx".to_string(), "pack-b.idx".to_string()];
+        let entries = vec![
+            entry("0100000000000000000000000000000000000000", 0, 12),
+            entry("0200000000000000000000000000000000000000", 1, 12),
+            entry("ff00000000000000000000000000000000000000", 1, 0x1_0000_0000),
+        ];
+
+        let data = MultiPackIndex::write(&pack_names, &entries, Some(1)).unwrap();
+        let midx = MultiPackIndex::from_bytes(&data).unwrap();
+
+        assert_eq!(midx.pack_names, pack_names);
+        assert_eq!(midx.entries, entries);
+        // objects from the preferred pack come first, then the rest by pack and offset
+        assert_eq!(midx.pseudo_pack_order, vec![1, 2, 0]);
+        assert_eq!(
+            midx.position("ff00000000000000000000000000000000000000"),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn from_bytes_returns_error_for_unknown_pack_id() {
+        let data = MultiPackIndex::write(
+            &["pack-a.idx".to_string()],
+            &[entry("0100000000000000000000000000000000000000", 3, 12)],
+            None,
+        )
+        .unwrap();
+
+        assert!(MultiPackIndex::from_bytes(&data).is_err());
+    }
+}
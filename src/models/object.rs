@@ -1,13 +1,17 @@
 use core::str;
 
 use crate::models::blob::Blob;
+use crate::models::commit::Commit;
 use crate::models::git_object::GetContentString;
+use crate::models::tag::Tag;
 use crate::models::tree::Tree;
 
 #[derive(Debug, PartialEq)]
 pub enum Object {
     Blob(Blob),
     Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
 }
 
 impl Object {
@@ -15,6 +19,8 @@ impl Object {
         let object_type = match type_str {
             "blob" => Object::Blob(Blob::new(content)),
             "tree" => Object::Tree(Tree::new(content)?),
+            "commit" => Object::Commit(Commit::new(content)?),
+            "tag" => Object::Tag(Tag::new(content)?),
             _ => return Err(format!("Object type not recognized: {}", type_str)),
         };
 
@@ -25,6 +31,8 @@ impl Object {
         match self {
             Object::Blob(_) => "blob",
             Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Tag(_) => "tag",
         }
     }
 }
@@ -34,6 +42,8 @@ impl GetContentString for Object {
         match self {
             Object::Blob(blob) => blob.get_content_string(),
             Object::Tree(tree) => tree.get_content_string(),
+            Object::Commit(commit) => commit.get_content_string(),
+            Object::Tag(tag) => tag.get_content_string(),
         }
     }
 }
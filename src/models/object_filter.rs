@@ -0,0 +1,104 @@
+use std::fmt;
+
+/// A partial clone filter, as given to `--filter` and sent to upload-pack: which objects a pack
+/// leaves out, for the remote to send later when they are needed. Objects asked for by name are
+/// never left out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectFilter {
+    /// `blob:none`: no blobs at all.
+    BlobNone,
+    /// `blob:limit=<n>`: no blobs of `n` bytes or more. `n` may end in `k`, `m` or `g`.
+    BlobLimit(u64),
+    /// `tree:<depth>`: no trees or blobs `depth` or more levels below the root tree, which is
+    /// level 0, so `tree:0` leaves only commits and tags.
+    TreeDepth(usize),
+}
+
+impl ObjectFilter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("fatal: invalid filter-spec '{}'\n", spec);
+
+        if spec == "blob:none" {
+            return Ok(ObjectFilter::BlobNone);
+        }
+
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            let (digits, scale) = match limit.to_lowercase().chars().last() {
+                Some('k') => (&limit[..limit.len() - 1], 1 << 10),
+                Some('m') => (&limit[..limit.len() - 1], 1 << 20),
+                Some('g') => (&limit[..limit.len() - 1], 1 << 30),
+                _ => (limit, 1),
+            };
+
+            return digits
+                .parse::<u64>()
+                .map(|limit| ObjectFilter::BlobLimit(limit * scale))
+                .map_err(|_| invalid());
+        }
+
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return depth
+                .parse()
+                .map(ObjectFilter::TreeDepth)
+                .map_err(|_| invalid());
+        }
+
+        Err(invalid())
+    }
+
+    /// Whether an object of `object_type` and `size` bytes, `depth` levels below its root tree
+    /// (counting the root tree itself as 0), is left out.
+    pub fn omits(&self, object_type: &str, size: usize, depth: usize) -> bool {
+        match (self, object_type) {
+            (ObjectFilter::BlobNone, "blob") => true,
+            (ObjectFilter::BlobLimit(limit), "blob") => size as u64 >= *limit,
+            (ObjectFilter::TreeDepth(max), "tree" | "blob") => depth >= *max,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ObjectFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectFilter::BlobNone => write!(f, "blob:none"),
+            ObjectFilter::BlobLimit(limit) => write!(f, "blob:limit={}", limit),
+            ObjectFilter::TreeDepth(depth) => write!(f, "tree:{}", depth),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_every_kind_of_filter() {
+        assert_eq!(ObjectFilter::parse("blob:none"), Ok(ObjectFilter::BlobNone));
+        assert_eq!(
+            ObjectFilter::parse("blob:limit=2k"),
+            Ok(ObjectFilter::BlobLimit(2048))
+        );
+        assert_eq!(
+            ObjectFilter::parse("tree:1"),
+            Ok(ObjectFilter::TreeDepth(1))
+        );
+        assert_eq!(
+            ObjectFilter::parse("sparse:oid=HEAD"),
+            Err("fatal: invalid filter-spec 'sparse:oid=HEAD'\n".to_string())
+        );
+        assert_eq!(ObjectFilter::BlobLimit(2048).to_string(), "blob:limit=2048");
+    }
+
+    #[test]
+    fn omits_follows_the_filter_kind() {
+        assert!(ObjectFilter::BlobNone.omits("blob", 0, 1));
+        assert!(!ObjectFilter::BlobNone.omits("tree", 0, 0));
+        assert!(ObjectFilter::BlobLimit(10).omits("blob", 10, 1));
+        assert!(!ObjectFilter::BlobLimit(10).omits("blob", 9, 1));
+        assert!(ObjectFilter::TreeDepth(0).omits("tree", 0, 0));
+        assert!(!ObjectFilter::TreeDepth(1).omits("tree", 0, 0));
+        assert!(ObjectFilter::TreeDepth(1).omits("blob", 3, 1));
+        assert!(!ObjectFilter::TreeDepth(0).omits("commit", 0, 0));
+    }
+}
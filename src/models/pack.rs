@@ -0,0 +1,771 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use sha1::{Digest, Sha1};
+
+use crate::models::pack_index::PackIndexEntry;
+
+const SIGNATURE: &[u8; 4] = b"PACK";
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 12;
+const HASH_SIZE: usize = 20;
+/// The most deflate can expand its input, rounded up.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// How an entry's data is stored: as a whole object or as a delta against a base object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackEntryKind {
+    Base(String),
+    OfsDelta(u64),
+    RefDelta(String),
+}
+
+/// One decoded pack entry. `data` is the inflated object (or delta) and `end` is the offset
+/// where the next entry starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackEntry {
+    pub kind: PackEntryKind,
+    pub data: Vec<u8>,
+    pub end: u64,
+}
+
+/// An object's type and content.
+pub type RawObject = (String, Vec<u8>);
+
+/// A `.pack` file held in memory.
+#[derive(Debug)]
+pub struct Pack {
+    data: Vec<u8>,
+}
+
+impl Pack {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE + HASH_SIZE || &data[0..4] != SIGNATURE {
+            return Err("not a pack file".to_string());
+        }
+
+        let version = u32::from_be_bytes(data[4..8].try_into().unwrap());
+
+        if version != VERSION && version != 3 {
+            return Err(format!("unsupported pack version {}", version));
+        }
+
+        Ok(Self { data })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn num_objects(&self) -> u32 {
+        u32::from_be_bytes(self.data[8..12].try_into().unwrap())
+    }
+
+    pub fn checksum(&self) -> String {
+        hex::encode(&self.data[self.data.len() - HASH_SIZE..])
+    }
+
+    pub fn verify_checksum(&self) -> Result<(), String> {
+        let data_end = self.data.len() - HASH_SIZE;
+
+        if Sha1::digest(&self.data[..data_end]).as_slice() != &self.data[data_end..] {
+            return Err("pack checksum mismatch".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn entry_at(&self, offset: u64) -> Result<PackEntry, String> {
+        let data_end = self.data.len() - HASH_SIZE;
+        let mut pos = offset as usize;
+        let mut next_byte = || -> Result<u8, String> {
+            let byte = *self
+                .data
+                .get(pos)
+                .filter(|_| pos < data_end)
+                .ok_or_else(|| format!("pack entry at {} is truncated", offset))?;
+            pos += 1;
+            Ok(byte)
+        };
+
+        let mut byte = next_byte()?;
+        let type_code = (byte >> 4) & 0x7;
+        let mut size = (byte & 0x0f) as u64;
+        let mut shift = 4;
+
+        while byte & 0x80 != 0 {
+            // another seven bits would no longer fit
+            if shift > u64::BITS - 7 {
+                return Err(format!("pack entry at {} has a bad object header", offset));
+            }
+
+            byte = next_byte()?;
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+
+        let kind = match type_code {
+            OBJ_OFS_DELTA => {
+                byte = next_byte()?;
+                let mut distance = (byte & 0x7f) as u64;
+
+                while byte & 0x80 != 0 {
+                    byte = next_byte()?;
+                    distance = distance
+                        .checked_add(1)
+                        .filter(|distance| distance.leading_zeros() >= 7)
+                        .map(|distance| (distance << 7) | (byte & 0x7f) as u64)
+                        .ok_or_else(|| {
+                            format!("pack entry at {} has an invalid base offset", offset)
+                        })?;
+                }
+
+                // a distance of 0 would make the entry its own base
+                PackEntryKind::OfsDelta(
+                    offset
+                        .checked_sub(distance)
+                        .filter(|_| distance > 0)
+                        .ok_or_else(|| {
+                            format!("pack entry at {} has an invalid base offset", offset)
+                        })?,
+                )
+            }
+            OBJ_REF_DELTA => {
+                let base = self
+                    .data
+                    .get(pos..pos + HASH_SIZE)
+                    .filter(|_| pos + HASH_SIZE <= data_end)
+                    .ok_or_else(|| format!("pack entry at {} is truncated", offset))?;
+                pos += HASH_SIZE;
+
+                PackEntryKind::RefDelta(hex::encode(base))
+            }
+            code => PackEntryKind::Base(type_name(code)?.to_string()),
+        };
+
+        // deflate cannot grow data more than about a thousandfold, so a size beyond that of what
+        // is left of the pack is a lie, and inflating it could only waste memory
+        if size > ((data_end - pos) as u64).saturating_mul(MAX_DEFLATE_RATIO) {
+            return Err(format!("pack entry at {} has the wrong size", offset));
+        }
+
+        let mut decoder = ZlibDecoder::new(&self.data[pos..data_end]);
+        // the header's size is not trusted with an allocation, and one byte past it is enough to
+        // tell that the entry is too big
+        let mut data = Vec::new();
+
+        (&mut decoder)
+            .take(size.saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|err| format!("error inflating pack entry at {}: {}", offset, err))?;
+
+        if data.len() as u64 != size {
+            return Err(format!("pack entry at {} has the wrong size", offset));
+        }
+
+        Ok(PackEntry {
+            kind,
+            data,
+            end: (pos as u64) + decoder.total_in(),
+        })
+    }
+
+    /// Reads the object stored at `offset`, applying deltas. Bases of ref deltas are fetched
+    /// through `resolve_base`, which lets the caller look them up in the pack index or elsewhere.
+    pub fn read_object_at(
+        &self,
+        offset: u64,
+        resolve_base: &dyn Fn(&str) -> Result<RawObject, String>,
+    ) -> Result<(String, Vec<u8>), String> {
+        let mut deltas = Vec::new();
+        let mut entry = self.entry_at(offset)?;
+
+        let (object_type, mut data) = loop {
+            match entry.kind {
+                PackEntryKind::Base(object_type) => break (object_type, entry.data),
+                PackEntryKind::OfsDelta(base_offset) => {
+                    deltas.push(entry.data);
+                    entry = self.entry_at(base_offset)?;
+                }
+                PackEntryKind::RefDelta(base) => {
+                    deltas.push(entry.data);
+                    break resolve_base(&base)?;
+                }
+            }
+        };
+
+        while let Some(delta) = deltas.pop() {
+            data = apply_delta(&data, &delta)?;
+        }
+
+        Ok((object_type, data))
+    }
+
+    /// Walks every entry in the pack and returns its index entries, resolving deltas on the way.
+    /// Ref deltas against objects outside the pack (thin packs) are resolved with `resolve_external`.
+    pub fn index_entries(
+        &self,
+        resolve_external: &dyn Fn(&str) -> Result<Option<RawObject>, String>,
+    ) -> Result<Vec<PackIndexEntry>, String> {
+        let mut entries = Vec::new();
+        let mut offset = HEADER_SIZE as u64;
+
+        for _ in 0..self.num_objects() {
+            let entry = self.entry_at(offset)?;
+            let mut crc = Crc::new();
+
+            crc.update(&self.data[offset as usize..entry.end as usize]);
+            entries.push((offset, crc.sum(), entry.kind, entry.data));
+            offset = entry.end;
+        }
+
+        if offset as usize != self.data.len() - HASH_SIZE {
+            return Err("pack has trailing data after its last object".to_string());
+        }
+
+        let mut resolved: HashMap<u64, (String, Vec<u8>)> = HashMap::new();
+        let mut offsets_by_sha: HashMap<String, u64> = HashMap::new();
+        let mut index_entries = Vec::new();
+        let mut pending: Vec<usize> = (0..entries.len()).collect();
+
+        while !pending.is_empty() {
+            let mut unresolved = Vec::new();
+
+            for idx in pending.iter().copied() {
+                let (offset, crc32, kind, data) = &entries[idx];
+                let (object_type, content) = match kind {
+                    PackEntryKind::Base(object_type) => (object_type.clone(), data.clone()),
+                    PackEntryKind::OfsDelta(base_offset) => match resolved.get(base_offset) {
+                        Some((object_type, base)) => {
+                            (object_type.clone(), apply_delta(base, data)?)
+                        }
+                        None => {
+                            unresolved.push(idx);
+                            continue;
+                        }
+                    },
+                    PackEntryKind::RefDelta(base_sha) => {
+                        let base = match offsets_by_sha.get(base_sha) {
+                            Some(base_offset) => resolved.get(base_offset).cloned(),
+                            None => resolve_external(base_sha)?,
+                        };
+
+                        match base {
+                            Some((object_type, base)) => (object_type, apply_delta(&base, data)?),
+                            None => {
+                                unresolved.push(idx);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                let sha = object_sha(&object_type, &content);
+
+                offsets_by_sha.insert(sha.clone(), *offset);
+                resolved.insert(*offset, (object_type, content));
+                index_entries.push(PackIndexEntry {
+                    sha,
+                    offset: *offset,
+                    crc32: *crc32,
+                });
+            }
+
+            if unresolved.len() == pending.len() {
+                return Err(format!(
+                    "pack has {} deltas with missing bases",
+                    unresolved.len()
+                ));
+            }
+
+            pending = unresolved;
+        }
+
+        Ok(index_entries)
+    }
+
+    /// Completes a thin pack the way `index-pack --fix-thin` does: the bases its ref deltas take
+    /// from outside the pack are appended whole, so the pack stands on its own. Returns the
+    /// completed pack (this one when it was not thin) and its index entries.
+    pub fn fix_thin(
+        self,
+        resolve_external: &dyn Fn(&str) -> Result<Option<RawObject>, String>,
+    ) -> Result<(Pack, Vec<PackIndexEntry>), String> {
+        let mut entries = self.index_entries(resolve_external)?;
+        let mut missing = Vec::new();
+
+        for entry in &entries {
+            if let PackEntryKind::RefDelta(base) = self.entry_at(entry.offset)?.kind {
+                if !entries.iter().any(|entry| entry.sha == base) && !missing.contains(&base) {
+                    missing.push(base);
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok((self, entries));
+        }
+
+        let mut data = self.data;
+        let num_objects = entries.len() + missing.len();
+
+        data.truncate(data.len() - HASH_SIZE);
+        data[8..12].copy_from_slice(&(num_objects as u32).to_be_bytes());
+
+        for base in missing {
+            let (object_type, content) = resolve_external(&base)?
+                .ok_or_else(|| format!("pack has a delta with missing base {}", base))?;
+
+            entries.push(append_entry(&mut data, &object_type, &content)?);
+        }
+
+        let checksum = Sha1::digest(&data);
+
+        data.extend_from_slice(&checksum);
+
+        Ok((Pack { data }, entries))
+    }
+}
+
+/// Reads exactly one pack from a stream where nothing else marks where it ends, as a push
+/// arrives on stdin: the header says how many entries follow, each entry's zlib stream ends
+/// itself, and the checksum closes the pack. The checksum is not verified here.
+pub fn read_pack<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, String> {
+    let mut recorder = Recorder {
+        inner: reader,
+        data: Vec::new(),
+    };
+    let mut header = [0; HEADER_SIZE];
+
+    recorder.read_exact_or_err(&mut header)?;
+    Pack::from_bytes([&header[..], &[0; HASH_SIZE]].concat())?;
+
+    let num_objects = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+    for _ in 0..num_objects {
+        let mut byte = [0; 1];
+
+        recorder.read_exact_or_err(&mut byte)?;
+
+        let type_code = (byte[0] >> 4) & 0x7;
+
+        while byte[0] & 0x80 != 0 {
+            recorder.read_exact_or_err(&mut byte)?;
+        }
+
+        match type_code {
+            OBJ_OFS_DELTA => {
+                recorder.read_exact_or_err(&mut byte)?;
+
+                while byte[0] & 0x80 != 0 {
+                    recorder.read_exact_or_err(&mut byte)?;
+                }
+            }
+            OBJ_REF_DELTA => recorder.read_exact_or_err(&mut [0; HASH_SIZE])?,
+            code => {
+                type_name(code)?;
+            }
+        }
+
+        // the buffered decoder consumes only the bytes of its own stream
+        io::copy(&mut ZlibDecoder::new(&mut recorder), &mut io::sink())
+            .map_err(|err| format!("error reading pack: {}", err))?;
+    }
+
+    recorder.read_exact_or_err(&mut [0; HASH_SIZE])?;
+
+    Ok(recorder.data)
+}
+
+/// Keeps a copy of every byte consumed from the reader it wraps.
+struct Recorder<'a, R: BufRead> {
+    inner: &'a mut R,
+    data: Vec<u8>,
+}
+
+impl<R: BufRead> Recorder<'_, R> {
+    fn read_exact_or_err(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        self.read_exact(buf)
+            .map_err(|_| "pack is truncated".to_string())
+    }
+}
+
+impl<R: BufRead> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the buffer is already filled, so this only looks at it again
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.data.extend_from_slice(&buf[..amt]);
+        }
+
+        self.inner.consume(amt);
+    }
+}
+
+/// Builds a pack holding every object undeltified, returning its bytes and index entries.
+pub fn write_pack(objects: &[(String, Vec<u8>)]) -> Result<(Vec<u8>, Vec<PackIndexEntry>), String> {
+    let mut data = SIGNATURE.to_vec();
+    let mut index_entries = Vec::new();
+
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    data.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+
+    for (object_type, content) in objects {
+        index_entries.push(append_entry(&mut data, object_type, content)?);
+    }
+
+    let checksum = Sha1::digest(&data);
+
+    data.extend_from_slice(&checksum);
+
+    Ok((data, index_entries))
+}
+
+/// Appends one undeltified entry to pack data and returns its index entry.
+fn append_entry(
+    data: &mut Vec<u8>,
+    object_type: &str,
+    content: &[u8],
+) -> Result<PackIndexEntry, String> {
+    let offset = data.len();
+    let mut size = content.len() as u64;
+    let mut byte = (type_code(object_type)? << 4) | (size & 0x0f) as u8;
+
+    size >>= 4;
+
+    while size != 0 {
+        data.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+
+    data.push(byte);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+
+    encoder.write_all(content).map_err(|err| err.to_string())?;
+    data.extend(encoder.finish().map_err(|err| err.to_string())?);
+
+    let mut crc = Crc::new();
+
+    crc.update(&data[offset..]);
+
+    Ok(PackIndexEntry {
+        sha: object_sha(object_type, content),
+        offset: offset as u64,
+        crc32: crc.sum(),
+    })
+}
+
+/// Applies a git delta (source size, target size, then copy/insert instructions) to `base`.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let mut pos = 0;
+    let read_size = |pos: &mut usize| -> Result<usize, String> {
+        let mut size = 0usize;
+        let mut shift = 0;
+
+        loop {
+            if shift > usize::BITS - 7 {
+                return Err("delta header size is too large".to_string());
+            }
+
+            let byte = *delta.get(*pos).ok_or("delta header is truncated")?;
+            *pos += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+
+    let source_size = read_size(&mut pos)?;
+    let target_size = read_size(&mut pos)?;
+
+    if source_size != base.len() {
+        return Err("delta base has the wrong size".to_string());
+    }
+
+    // grown as the commands run rather than sized from the header, which may lie
+    let mut target = Vec::new();
+
+    while pos < delta.len() {
+        let command = delta[pos];
+        pos += 1;
+
+        if command & 0x80 != 0 {
+            let mut fields = [0usize; 7];
+
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if command & (1 << bit) != 0 {
+                    *field = *delta.get(pos).ok_or("delta copy command is truncated")? as usize;
+                    pos += 1;
+                }
+            }
+
+            let copy_offset = fields[0] | fields[1] << 8 | fields[2] << 16 | fields[3] << 24;
+            let copy_size = match fields[4] | fields[5] << 8 | fields[6] << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            let source = copy_offset
+                .checked_add(copy_size)
+                .and_then(|copy_end| base.get(copy_offset..copy_end))
+                .ok_or("delta copies beyond the end of its base")?;
+
+            target.extend_from_slice(source);
+        } else if command != 0 {
+            let insert = delta
+                .get(pos..pos + command as usize)
+                .ok_or("delta insert command is truncated")?;
+
+            target.extend_from_slice(insert);
+            pos += command as usize;
+        } else {
+            return Err("delta has a reserved zero command".to_string());
+        }
+
+        if target.len() > target_size {
+            return Err("delta result has the wrong size".to_string());
+        }
+    }
+
+    if target.len() != target_size {
+        return Err("delta result has the wrong size".to_string());
+    }
+
+    Ok(target)
+}
+
+pub fn object_sha(object_type: &str, content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+
+    hasher.update(format!("{} {}\0", object_type, content.len()).as_bytes());
+    hasher.update(content);
+
+    hex::encode(hasher.finalize())
+}
+
+fn type_code(object_type: &str) -> Result<u8, String> {
+    match object_type {
+        "commit" => Ok(OBJ_COMMIT),
+        "tree" => Ok(OBJ_TREE),
+        "blob" => Ok(OBJ_BLOB),
+        "tag" => Ok(OBJ_TAG),
+        _ => Err(format!("cannot pack object of type {}", object_type)),
+    }
+}
+
+fn type_name(type_code: u8) -> Result<&'static str, String> {
+    match type_code {
+        OBJ_COMMIT => Ok("commit"),
+        OBJ_TREE => Ok("tree"),
+        OBJ_BLOB => Ok("blob"),
+        OBJ_TAG => Ok("tag"),
+        _ => Err(format!("invalid pack object type {}", type_code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn objects() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("blob".to_string(), b"hello world\n".to_vec()),
+            ("blob".to_string(), vec![b'x'; 300]),
+        ]
+    }
+
+    #[test]
+    fn write_pack_then_read_entries_round_trips() {
+        let (data, index_entries) = write_pack(&objects()).unwrap();
+        let pack = Pack::from_bytes(data).unwrap();
+
+        pack.verify_checksum().unwrap();
+        assert_eq!(pack.num_objects(), 2);
+        assert_eq!(
+            index_entries[0].sha,
+            "3b18e512dba79e4c8300dd08aeb37f8e728b8dad"
+        );
+
+        let (object_type, content) = pack
+            .read_object_at(index_entries[1].offset, &|_| unreachable!())
+            .unwrap();
+
+        assert_eq!(object_type, "blob");
+        assert_eq!(content, vec![b'x'; 300]);
+        assert_eq!(pack.index_entries(&|_| Ok(None)).unwrap(), index_entries);
+    }
+
+    #[test]
+    fn read_pack_stops_at_the_end_of_the_pack() {
+        let (data, _) = write_pack(&objects()).unwrap();
+        let stream = [data.as_slice(), b"0000"].concat();
+        let mut reader = stream.as_slice();
+
+        assert_eq!(read_pack(&mut reader).unwrap(), data);
+        assert_eq!(reader, b"0000");
+        assert_eq!(
+            read_pack(&mut &data[..data.len() - 1]).unwrap_err(),
+            "pack is truncated"
+        );
+    }
+
+    #[test]
+    fn apply_delta_copies_and_inserts() {
+        // source size 11, target size 12, copy 6 bytes from offset 0, insert "there!"
+        let delta = [11, 12, 0x90, 6, 6, b't', b'h', b'e', b'r', b'e', b'!'];
+
+        let target = apply_delta(b"hello world", &delta).unwrap();
+
+        assert_eq!(target, b"hello there!");
+    }
+
+    #[test]
+    fn apply_delta_returns_error_for_wrong_base_size() {
+        assert!(apply_delta(b"short", &[11, 0]).is_err());
+    }
+
+    #[test]
+    fn malformed_sizes_and_offsets_are_errors_rather_than_overflows() {
+        let pack_with = |entry: &[u8]| {
+            let mut data = SIGNATURE.to_vec();
+
+            data.extend_from_slice(&VERSION.to_be_bytes());
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(entry);
+            data.extend_from_slice(&[0; HASH_SIZE]);
+
+            Pack::from_bytes(data).unwrap()
+        };
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello").unwrap();
+        let hello = encoder.finish().unwrap();
+        // a blob claiming 2^40 bytes, which must not be allocated up front
+        let huge = [
+            &[(OBJ_BLOB << 4) | 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+            &hello[..],
+        ]
+        .concat();
+
+        assert_eq!(
+            pack_with(&[&[(OBJ_BLOB << 4) | 0x8f][..], &[0xff; 10], &[0x01]].concat())
+                .entry_at(12)
+                .unwrap_err(),
+            "pack entry at 12 has a bad object header"
+        );
+        assert_eq!(
+            pack_with(&[&[OBJ_OFS_DELTA << 4][..], &[0xff; 10], &[0x01]].concat())
+                .entry_at(12)
+                .unwrap_err(),
+            "pack entry at 12 has an invalid base offset"
+        );
+        assert_eq!(
+            pack_with(&[&[OBJ_OFS_DELTA << 4][..], &[0x00]].concat())
+                .entry_at(12)
+                .unwrap_err(),
+            "pack entry at 12 has an invalid base offset"
+        );
+        assert_eq!(
+            pack_with(&huge).entry_at(12).unwrap_err(),
+            "pack entry at 12 has the wrong size"
+        );
+        // `hello` where the header claims four bytes
+        assert_eq!(
+            pack_with(&[&[(OBJ_BLOB << 4) | 4][..], &hello].concat())
+                .entry_at(12)
+                .unwrap_err(),
+            "pack entry at 12 has the wrong size"
+        );
+        assert_eq!(
+            apply_delta(b"", &[&[0][..], &[0xff; 9], &[0x01]].concat()).unwrap_err(),
+            "delta header size is too large"
+        );
+        // claims a target of 2^56 bytes, then makes one
+        assert_eq!(
+            apply_delta(
+                b"",
+                &[0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 1, b'x']
+            )
+            .unwrap_err(),
+            "delta result has the wrong size"
+        );
+    }
+
+    /// A pack holding one ref delta against the blob `hello world`, which it leaves out.
+    fn thin_pack() -> (Vec<u8>, RawObject) {
+        let base = ("blob".to_string(), b"hello world".to_vec());
+        let mut data = SIGNATURE.to_vec();
+
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        // ref delta header: type 7, size 11
+        data.push((OBJ_REF_DELTA << 4) | 11);
+        data.extend(hex::decode(object_sha(&base.0, &base.1)).unwrap());
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&[11, 12, 0x90, 6, 6, b't', b'h', b'e', b'r', b'e', b'!'])
+            .unwrap();
+        data.extend(encoder.finish().unwrap());
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        (data, base)
+    }
+
+    #[test]
+    fn index_entries_resolves_ref_deltas_against_external_bases() {
+        let (data, base) = thin_pack();
+        let base_sha = object_sha(&base.0, &base.1);
+        let pack = Pack::from_bytes(data).unwrap();
+        let entries = pack
+            .index_entries(&|sha| Ok((sha == base_sha).then(|| base.clone())))
+            .unwrap();
+
+        assert_eq!(entries[0].sha, object_sha("blob", b"hello there!"));
+        assert!(pack.index_entries(&|_| Ok(None)).is_err());
+    }
+
+    #[test]
+    fn fix_thin_appends_the_missing_bases() {
+        let (data, base) = thin_pack();
+        let base_sha = object_sha(&base.0, &base.1);
+        let (pack, entries) = Pack::from_bytes(data)
+            .unwrap()
+            .fix_thin(&|sha| Ok((sha == base_sha).then(|| base.clone())))
+            .unwrap();
+
+        pack.verify_checksum().unwrap();
+        assert_eq!(pack.num_objects(), 2);
+        assert_eq!(entries[1].sha, base_sha);
+        assert_eq!(pack.index_entries(&|_| Ok(None)).unwrap().len(), 2);
+    }
+}
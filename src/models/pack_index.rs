@@ -0,0 +1,237 @@
+use sha1::{Digest, Sha1};
+
+use crate::models::chunk_format::{read_u32, read_u64};
+
+const SIGNATURE: &[u8; 4] = b"\xfftOc";
+const VERSION: u32 = 2;
+const HASH_SIZE: usize = 20;
+const FANOUT_SIZE: usize = 256 * 4;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackIndexEntry {
+    pub sha: String,
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+/// A pack `.idx` file: the pack's objects sorted by sha, with their offsets into the pack.
+#[derive(Debug)]
+pub struct PackIndex {
+    pub entries: Vec<PackIndexEntry>,
+    pub pack_checksum: String,
+}
+
+impl PackIndex {
+    pub fn new(mut entries: Vec<PackIndexEntry>, pack_checksum: &str) -> Self {
+        entries.sort_by(|a, b| a.sha.cmp(&b.sha));
+
+        Self {
+            entries,
+            pack_checksum: pack_checksum.to_string(),
+        }
+    }
+
+    /// Parses a version 2 index, or a headerless version 1 index.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < FANOUT_SIZE + 2 * HASH_SIZE {
+            return Err("pack index is truncated".to_string());
+        }
+
+        let data_end = data.len() - HASH_SIZE;
+
+        if Sha1::digest(&data[..data_end]).as_slice() != &data[data_end..] {
+            return Err("pack index checksum mismatch".to_string());
+        }
+
+        let pack_checksum = hex::encode(&data[data_end - HASH_SIZE..data_end]);
+        let is_v2 = &data[0..4] == SIGNATURE;
+        let fanout_start = if is_v2 { 8 } else { 0 };
+
+        if is_v2 && read_u32(data, 4) != VERSION {
+            return Err(format!(
+                "unsupported pack index version {}",
+                read_u32(data, 4)
+            ));
+        }
+
+        let num_objects = read_u32(data, fanout_start + FANOUT_SIZE - 4) as usize;
+        let tables_start = fanout_start + FANOUT_SIZE;
+        let mut entries = Vec::with_capacity(num_objects);
+
+        if is_v2 {
+            let crc_start = tables_start + num_objects * HASH_SIZE;
+            let offsets_start = crc_start + num_objects * 4;
+            let large_offsets_start = offsets_start + num_objects * 4;
+
+            if large_offsets_start > data_end - HASH_SIZE {
+                return Err("pack index is truncated".to_string());
+            }
+
+            for idx in 0..num_objects {
+                let sha_start = tables_start + idx * HASH_SIZE;
+                let offset = read_u32(data, offsets_start + idx * 4);
+                let offset = if offset & LARGE_OFFSET_FLAG != 0 {
+                    let large_start =
+                        large_offsets_start + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+
+                    if large_start + 8 > data_end - HASH_SIZE {
+                        return Err("pack index large offset is out of range".to_string());
+                    }
+
+                    read_u64(data, large_start)
+                } else {
+                    offset as u64
+                };
+
+                entries.push(PackIndexEntry {
+                    sha: hex::encode(&data[sha_start..sha_start + HASH_SIZE]),
+                    offset,
+                    crc32: read_u32(data, crc_start + idx * 4),
+                });
+            }
+        } else {
+            if tables_start + num_objects * (4 + HASH_SIZE) > data_end - HASH_SIZE {
+                return Err("pack index is truncated".to_string());
+            }
+
+            for idx in 0..num_objects {
+                let entry_start = tables_start + idx * (4 + HASH_SIZE);
+
+                entries.push(PackIndexEntry {
+                    sha: hex::encode(&data[entry_start + 4..entry_start + 4 + HASH_SIZE]),
+                    offset: read_u32(data, entry_start) as u64,
+                    crc32: 0,
+                });
+            }
+        }
+
+        if entries.windows(2).any(|pair| pair[0].sha >= pair[1].sha) {
+            return Err("pack index is not sorted".to_string());
+        }
+
+        Ok(Self {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    /// Serializes the index in version 2 format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut data = SIGNATURE.to_vec();
+        let mut fanout = [0u32; 256];
+        let mut large_offsets = Vec::new();
+
+        data.extend_from_slice(&VERSION.to_be_bytes());
+
+        for entry in &self.entries {
+            let first_byte = u8::from_str_radix(&entry.sha[..2], 16).map_err(|e| e.to_string())?;
+
+            fanout[first_byte as usize] += 1;
+        }
+
+        let mut total = 0;
+
+        for count in fanout {
+            total += count;
+            data.extend_from_slice(&total.to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            data.extend(hex::decode(&entry.sha).map_err(|e| e.to_string())?);
+        }
+
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            let offset = if entry.offset >= LARGE_OFFSET_FLAG as u64 {
+                large_offsets.push(entry.offset);
+                (large_offsets.len() as u32 - 1) | LARGE_OFFSET_FLAG
+            } else {
+                entry.offset as u32
+            };
+
+            data.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        for offset in large_offsets {
+            data.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        data.extend(hex::decode(&self.pack_checksum).map_err(|e| e.to_string())?);
+
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        Ok(data)
+    }
+
+    /// Returns the object's position in sha order.
+    pub fn position(&self, sha: &str) -> Option<usize> {
+        self.entries
+            .binary_search_by(|entry| entry.sha.as_str().cmp(sha))
+            .ok()
+    }
+
+    pub fn find_offset(&self, sha: &str) -> Option<u64> {
+        self.position(sha).map(|idx| self.entries[idx].offset)
+    }
+
+    /// Returns the sha-order positions of the objects sorted by their offset in the pack.
+    pub fn pack_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+
+        order.sort_by_key(|&idx| self.entries[idx].offset);
+
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(sha: &str, offset: u64) -> PackIndexEntry {
+        PackIndexEntry {
+            sha: sha.to_string(),
+            offset,
+            crc32: 7,
+        }
+    }
+
+    #[test]
+    fn to_bytes_then_from_bytes_round_trips_with_large_offsets() {
+        let index = PackIndex::new(
+            vec![
+                entry("ff00000000000000000000000000000000000000", 12),
+                entry("0100000000000000000000000000000000000000", 0x1_0000_0000),
+            ],
+            "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed",
+        );
+
+        let parsed = PackIndex::from_bytes(&index.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(parsed.entries, index.entries);
+        assert_eq!(parsed.pack_checksum, index.pack_checksum);
+        assert_eq!(
+            parsed.find_offset("0100000000000000000000000000000000000000"),
+            Some(0x1_0000_0000)
+        );
+        assert_eq!(parsed.pack_order(), vec![1, 0]);
+    }
+
+    #[test]
+    fn from_bytes_returns_error_for_corrupt_checksum() {
+        let index = PackIndex::new(
+            vec![entry("0100000000000000000000000000000000000000", 12)],
+            "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed",
+        );
+        let mut data = index.to_bytes().unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+
+        assert!(PackIndex::from_bytes(&data).is_err());
+    }
+}
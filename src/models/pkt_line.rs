@@ -0,0 +1,291 @@
+use std::io::{Read, Write};
+
+/// The largest packet allowed on the wire, including the four byte length prefix.
+pub const MAX_PACKET_SIZE: usize = 65520;
+/// The largest payload that fits in one packet.
+pub const MAX_PACKET_DATA: usize = MAX_PACKET_SIZE - 4;
+
+const FLUSH_PACKET: &[u8; 4] = b"0000";
+const DELIM_PACKET: &[u8; 4] = b"0001";
+
+pub const SIDEBAND_DATA: u8 = 1;
+pub const SIDEBAND_PROGRESS: u8 = 2;
+pub const SIDEBAND_ERROR: u8 = 3;
+
+/// One pkt-line. Besides data packets there are the special zero-payload packets: flush
+/// (`0000`) ends a message, delim (`0001`) separates sections in protocol v2 and
+/// response-end (`0002`) ends a stateless v2 response.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+/// Reads pkt-lines from a byte stream. Malformed lengths and early EOF are errors, and an
+/// `ERR <message>` packet from the other side is turned into an error too.
+pub struct PktLineReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// Gives back the underlying stream, e.g. to read a pack that is sent without framing.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub fn read_packet(&mut self) -> Result<Packet, String> {
+        let mut length_bytes = [0; 4];
+
+        self.read_exact(&mut length_bytes)?;
+
+        let length = parse_length(&length_bytes)?;
+        let packet = match length {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            // an empty data packet, which upstream never sends but tolerates
+            4 => Packet::Data(Vec::new()),
+            3 => return Err(format!("protocol error: bad line length {}", length)),
+            _ if length > MAX_PACKET_SIZE => {
+                return Err(format!("protocol error: bad line length {}", length))
+            }
+            _ => {
+                let mut data = vec![0; length - 4];
+
+                self.read_exact(&mut data)?;
+                Packet::Data(data)
+            }
+        };
+
+        if let Packet::Data(data) = &packet {
+            if let Some(message) = data.strip_prefix(b"ERR ") {
+                return Err(format!(
+                    "remote error: {}",
+                    String::from_utf8_lossy(message).trim_end()
+                ));
+            }
+        }
+
+        Ok(packet)
+    }
+
+    /// Reads a text line, dropping its trailing newline. Returns `None` for a flush, delim or
+    /// response-end packet.
+    pub fn read_line(&mut self) -> Result<Option<String>, String> {
+        match self.read_packet()? {
+            Packet::Data(data) => {
+                let line = String::from_utf8(data)
+                    .map_err(|_| "protocol error: line is not valid UTF-8".to_string())?;
+
+                Ok(Some(line.strip_suffix('\n').unwrap_or(&line).to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads text lines up to the next flush (or delim or response-end) packet.
+    pub fn read_lines(&mut self) -> Result<Vec<String>, String> {
+        let mut lines = Vec::new();
+
+        while let Some(line) = self.read_line()? {
+            lines.push(line);
+        }
+
+        Ok(lines)
+    }
+
+    /// Demultiplexes a side-band stream up to its flush packet: band 1 goes to `data`, band 2
+    /// (progress) to `progress`, and band 3 aborts with the remote's error message.
+    pub fn read_sideband<D: Write, P: Write>(
+        &mut self,
+        data: &mut D,
+        progress: &mut P,
+    ) -> Result<(), String> {
+        loop {
+            let payload = match self.read_packet()? {
+                Packet::Data(payload) => payload,
+                _ => return Ok(()),
+            };
+            let (band, content) = payload
+                .split_first()
+                .ok_or("protocol error: empty side-band packet")?;
+
+            match *band {
+                SIDEBAND_DATA => data.write_all(content),
+                SIDEBAND_PROGRESS => progress.write_all(content),
+                SIDEBAND_ERROR => {
+                    return Err(format!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(content).trim_end()
+                    ))
+                }
+                _ => return Err(format!("protocol error: bad band #{}", band)),
+            }
+            .map_err(|err| err.to_string())?;
+        }
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        self.reader
+            .read_exact(buffer)
+            .map_err(|_| "the remote end hung up unexpectedly".to_string())
+    }
+}
+
+/// Writes pkt-lines to a byte stream.
+pub struct PktLineWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PktLineWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() > MAX_PACKET_DATA {
+            return Err(format!(
+                "protocol error: packet of {} bytes is too long",
+                data.len()
+            ));
+        }
+
+        self.write_raw(format!("{:04x}", data.len() + 4).as_bytes())?;
+        self.write_raw(data)
+    }
+
+    /// Writes a text line, adding the trailing newline the protocol expects.
+    pub fn write_line(&mut self, line: &str) -> Result<(), String> {
+        self.write_data(format!("{}\n", line).as_bytes())
+    }
+
+    pub fn write_flush(&mut self) -> Result<(), String> {
+        self.write_raw(FLUSH_PACKET)?;
+        self.flush()
+    }
+
+    pub fn write_delim(&mut self) -> Result<(), String> {
+        self.write_raw(DELIM_PACKET)
+    }
+
+    /// Sends `data` on a side-band channel, split into as many packets as needed.
+    pub fn write_sideband(&mut self, band: u8, data: &[u8]) -> Result<(), String> {
+        for chunk in data.chunks(MAX_PACKET_DATA - 1) {
+            self.write_data(&[&[band], chunk].concat())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes bytes without any framing, as a pack is sent when side-band was not negotiated.
+    pub fn write_unframed(&mut self, data: &[u8]) -> Result<(), String> {
+        self.write_raw(data)
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|err| err.to_string())
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> Result<(), String> {
+        self.writer.write_all(data).map_err(|err| err.to_string())
+    }
+}
+
+fn parse_length(bytes: &[u8; 4]) -> Result<usize, String> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|digits| usize::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| {
+            format!(
+                "protocol error: bad line length character: {}",
+                String::from_utf8_lossy(bytes)
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_round_trip() {
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        writer.write_line("want abc").unwrap();
+        writer.write_delim().unwrap();
+        writer.write_data(b"").unwrap();
+        writer.write_flush().unwrap();
+
+        let mut bytes = writer.into_inner();
+        // a response-end, which only servers send
+        bytes.extend(b"0002");
+
+        assert_eq!(bytes, b"000dwant abc\n0001000400000002");
+
+        let mut reader = PktLineReader::new(bytes.as_slice());
+
+        assert_eq!(reader.read_line().unwrap(), Some("want abc".to_string()));
+        assert_eq!(reader.read_packet().unwrap(), Packet::Delim);
+        assert_eq!(reader.read_packet().unwrap(), Packet::Data(Vec::new()));
+        assert_eq!(reader.read_packet().unwrap(), Packet::Flush);
+        assert_eq!(reader.read_packet().unwrap(), Packet::ResponseEnd);
+        assert_eq!(
+            reader.read_packet().unwrap_err(),
+            "the remote end hung up unexpectedly"
+        );
+    }
+
+    #[test]
+    fn read_packet_returns_error_for_malformed_lengths() {
+        let read = |bytes: &[u8]| PktLineReader::new(bytes).read_packet().unwrap_err();
+
+        assert_eq!(
+            read(b"00zz"),
+            "protocol error: bad line length character: 00zz"
+        );
+        assert_eq!(read(b"0003"), "protocol error: bad line length 3");
+        assert_eq!(read(b"fff1"), "protocol error: bad line length 65521");
+        assert_eq!(read(b"0009abc"), "the remote end hung up unexpectedly");
+        assert_eq!(read(b"000fERR denied\n"), "remote error: denied");
+    }
+
+    #[test]
+    fn sideband_is_split_and_demultiplexed() {
+        let data = vec![7; MAX_PACKET_DATA + 10];
+        let mut writer = PktLineWriter::new(Vec::new());
+
+        writer
+            .write_sideband(SIDEBAND_PROGRESS, b"Counting\r")
+            .unwrap();
+        writer.write_sideband(SIDEBAND_DATA, &data).unwrap();
+        writer.write_flush().unwrap();
+        writer.write_sideband(SIDEBAND_ERROR, b"oops\n").unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PktLineReader::new(bytes.as_slice());
+        let mut received = Vec::new();
+        let mut progress = Vec::new();
+
+        reader.read_sideband(&mut received, &mut progress).unwrap();
+
+        assert_eq!(received, data);
+        assert_eq!(progress, b"Counting\r");
+        assert_eq!(
+            reader
+                .read_sideband(&mut received, &mut progress)
+                .unwrap_err(),
+            "remote error: oops"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+/// A refspec like `+refs/heads/*:refs/remotes/origin/*`: which refs to take from the source
+/// side (`src`), where to store them (`dst`), and with `+`, whether to store them even when the
+/// update is not a fast-forward. Either side may hold one `*`, which then matches the same text
+/// on both.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst)),
+            None => (spec, None),
+        };
+        let stars = |side: &str| side.matches('*').count();
+        let valid = stars(src) <= 1
+            && dst.is_none_or(|dst| stars(dst) == stars(src) && !dst.is_empty())
+            && (stars(src) == 0 || dst.is_some());
+
+        if !valid {
+            return Err(format!("fatal: invalid refspec '{}'\n", spec));
+        }
+
+        Ok(Self {
+            force,
+            src: src.to_string(),
+            dst: dst.map(|dst| dst.to_string()),
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Whether the source side matches `name`. A source without `refs/` is a short name, which
+    /// matches the ref it abbreviates the way upstream resolves `main` or `v1.0`.
+    pub fn matches_src(&self, name: &str) -> bool {
+        if self.is_glob() {
+            return glob_match(&self.src, name).is_some();
+        }
+
+        expand_short_name(&self.src).iter().any(|full| full == name)
+    }
+
+    /// Where `name`, which must match the source side, is stored; `None` when the refspec has
+    /// no destination.
+    pub fn map_src(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_deref()?;
+
+        match glob_match(&self.src, name) {
+            Some(matched) if self.is_glob() => Some(dst.replacen('*', matched, 1)),
+            _ if dst.starts_with("refs/") || dst == "HEAD" => Some(dst.to_string()),
+            _ => Some(format!("refs/heads/{}", dst)),
+        }
+    }
+
+    /// The source ref a destination ref `name` would come from, for pruning refs whose source
+    /// is gone. Only refspecs with a destination map back.
+    pub fn map_dst(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_deref()?;
+
+        if self.is_glob() {
+            return glob_match(dst, name).map(|matched| self.src.replacen('*', matched, 1));
+        }
+
+        (dst == name).then(|| self.src.clone())
+    }
+
+    /// The ref-prefixes that can match the source side, for servers that filter what they list.
+    pub fn src_prefixes(&self) -> Vec<String> {
+        match self.src.split_once('*') {
+            Some((prefix, _)) => vec![prefix.to_string()],
+            None => expand_short_name(&self.src),
+        }
+    }
+}
+
+/// The full ref names a short name may stand for, most specific first.
+fn expand_short_name(name: &str) -> Vec<String> {
+    if name.starts_with("refs/") || name == "HEAD" {
+        return vec![name.to_string()];
+    }
+
+    vec![
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ]
+}
+
+/// Matches `name` against a pattern with at most one `*` and returns what the `*` matched.
+fn glob_match<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => name
+            .strip_prefix(prefix)?
+            .strip_suffix(suffix)
+            .filter(|matched| !matched.is_empty()),
+        None => (pattern == name).then_some(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_refspecs_map_both_ways() {
+        let refspec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+
+        assert!(refspec.force);
+        assert!(refspec.matches_src("refs/heads/feature/x"));
+        assert!(!refspec.matches_src("refs/tags/v1"));
+        assert_eq!(
+            refspec.map_src("refs/heads/feature/x"),
+            Some("refs/remotes/origin/feature/x".to_string())
+        );
+        assert_eq!(
+            refspec.map_dst("refs/remotes/origin/main"),
+            Some("refs/heads/main".to_string())
+        );
+        assert_eq!(refspec.src_prefixes(), vec!["refs/heads/"]);
+    }
+
+    #[test]
+    fn short_names_expand_like_revisions() {
+        let refspec = Refspec::parse("main:topic").unwrap();
+
+        assert!(refspec.matches_src("refs/heads/main"));
+        assert!(!refspec.matches_src("refs/heads/mainline"));
+        assert_eq!(
+            refspec.map_src("refs/heads/main"),
+            Some("refs/heads/topic".to_string())
+        );
+        assert_eq!(Refspec::parse("v1").unwrap().map_src("refs/tags/v1"), None);
+    }
+
+    #[test]
+    fn parse_returns_error_for_unbalanced_globs() {
+        assert!(Refspec::parse("refs/heads/*:refs/remotes/origin/main").is_err());
+        assert!(Refspec::parse("refs/heads/*").is_err());
+        assert!(Refspec::parse("refs/*/*:refs/*/*").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::models::commit::Commit;
+use crate::models::git_object::GetContentString;
+
+const OBJECT_TYPES: [&str; 4] = ["blob", "tree", "commit", "tag"];
+
+/// An annotated tag. Parsing is strict, like upstream's fsck: the `object`, `type` and `tag`
+/// headers must come first and in that order.
+#[derive(Debug, PartialEq)]
+pub struct Tag {
+    pub object: String,
+    pub object_type: String,
+    pub name: String,
+    pub tagger: Option<String>,
+    pub message: String,
+    pub content: Vec<u8>,
+}
+
+impl Tag {
+    pub fn new(content: Vec<u8>) -> Result<Self, String> {
+        let text = String::from_utf8_lossy(&content).to_string();
+        let (headers, message) = match text.find("\n\n") {
+            Some(idx) => (&text[..idx], &text[idx + 2..]),
+            None => (text.trim_end_matches('\n'), ""),
+        };
+        let mut lines = headers.lines().peekable();
+
+        let object = lines
+            .next()
+            .and_then(|line| line.strip_prefix("object "))
+            .ok_or("missingObject: invalid format - expected 'object' line")?;
+
+        if object.len() != 40 || !object.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("badObjectSha1: invalid 'object' line format - bad sha1".to_string());
+        }
+
+        let object_type = lines
+            .next()
+            .and_then(|line| line.strip_prefix("type "))
+            .ok_or("missingTypeEntry: invalid format - unexpected end after 'type' line")?;
+
+        if !OBJECT_TYPES.contains(&object_type) {
+            return Err("badType: invalid 'type' value".to_string());
+        }
+
+        let name = lines
+            .next()
+            .and_then(|line| line.strip_prefix("tag "))
+            .ok_or("missingTagEntry: invalid format - unexpected end after 'type' line")?;
+        let tagger = match lines.peek().and_then(|line| line.strip_prefix("tagger ")) {
+            Some(tagger) => {
+                Commit::check_signature(tagger)?;
+                lines.next();
+                Some(tagger.to_string())
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            object: object.to_string(),
+            object_type: object_type.to_string(),
+            name: name.to_string(),
+            tagger,
+            message: message.to_string(),
+            content,
+        })
+    }
+}
+
+impl GetContentString for Tag {
+    fn get_content_string(&self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.content).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJECT: &str = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+
+    #[test]
+    fn tag_new_parses_headers_and_message() {
+        let content = format!(
+            "object {}\ntype commit\ntag v1.0\ntagger T <t@example.com> 1700000000 +0000\n\nrelease\n",
+            OBJECT
+        );
+        let tag = Tag::new(content.into_bytes()).unwrap();
+
+        assert_eq!(tag.object, OBJECT);
+        assert_eq!(tag.object_type, "commit");
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(
+            tag.tagger.as_deref(),
+            Some("T <t@example.com> 1700000000 +0000")
+        );
+        assert_eq!(tag.message, "release\n");
+    }
+
+    #[test]
+    fn tag_new_returns_error_for_headers_out_of_order() {
+        let content = format!("type commit\nobject {}\ntag v1.0\n\nrelease\n", OBJECT);
+
+        assert_eq!(
+            Tag::new(content.into_bytes()).unwrap_err(),
+            "missingObject: invalid format - expected 'object' line"
+        );
+    }
+}
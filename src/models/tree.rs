@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fmt;
 
 use crate::models::git_object::GetContentString;
@@ -16,11 +17,57 @@ impl Tree {
         })
     }
 
-    fn content_to_tree_entries(content: &Vec<u8>) -> Result<Vec<TreeEntry>, String> {
+    /// The entry checks upstream fsck makes on top of what parsing already enforces: names must
+    /// be unique, sorted (directories compare as if they ended in '/') and safe to check out.
+    pub fn check(&self) -> Result<(), String> {
+        let mut names: HashSet<&str> = HashSet::new();
+        let mut previous_key: Option<String> = None;
+
+        for entry in &self.tree_entries {
+            let name = entry.name.as_str();
+
+            if name.contains('/') {
+                return Err("fullPathname: contains full pathnames".to_string());
+            }
+
+            match name {
+                "" => return Err("emptyName: contains empty pathname".to_string()),
+                "." => return Err("hasDot: contains '.'".to_string()),
+                ".." => return Err("hasDotdot: contains '..'".to_string()),
+                _ if name.eq_ignore_ascii_case(".git") => {
+                    return Err("hasDotgit: contains '.git'".to_string())
+                }
+                _ => {}
+            }
+
+            if entry.sha.bytes().all(|b| b == b'0') {
+                return Err("nullSha1: contains entries pointing to null sha1".to_string());
+            }
+
+            if !names.insert(name) {
+                return Err("duplicateEntries: contains duplicate file entries".to_string());
+            }
+
+            let key = match entry.mode {
+                TreeEntryMode::Directory => format!("{}/", name),
+                _ => name.to_string(),
+            };
+
+            if previous_key.is_some_and(|previous| previous > key) {
+                return Err("treeNotSorted: not properly sorted".to_string());
+            }
+
+            previous_key = Some(key);
+        }
+
+        Ok(())
+    }
+
+    fn content_to_tree_entries(content: &[u8]) -> Result<Vec<TreeEntry>, String> {
         let mut tree_entries: Vec<TreeEntry> = Vec::new();
         let mut content_slice = &content[0..];
 
-        while content_slice.len() > 0 {
+        while !content_slice.is_empty() {
             let mut slice_start_position;
             let mut end_position;
 
@@ -43,7 +90,7 @@ impl Tree {
 
             let name = Self::parse_string_from_content(content_slice, 0, end_position);
 
-            if name.len() == 0 {
+            if name.is_empty() {
                 return Err("Name missing".to_string());
             }
 
@@ -92,7 +139,7 @@ impl GetContentString for Tree {
 
             content.push_str(entry_content.as_str());
 
-            content.push_str("\n");
+            content.push('\n');
         }
 
         print!("{}", content);
@@ -107,35 +154,37 @@ impl Tree {
         for tree_entry in &self.tree_entries {
             names.push_str(tree_entry.name.as_str());
 
-            names.push_str("\n");
+            names.push('\n');
         }
 
         names
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TreeEntry {
-    mode: TreeEntryMode,
-    sha: String,
-    name: String,
+    pub mode: TreeEntryMode,
+    pub sha: String,
+    pub name: String,
 }
 
 impl TreeEntry {
-    fn object_type(&self) -> String {
+    pub fn object_type(&self) -> String {
         match self.mode {
             TreeEntryMode::Directory => "tree".to_string(),
+            TreeEntryMode::Submodule => "commit".to_string(),
             _ => "blob".to_string(),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum TreeEntryMode {
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeEntryMode {
     RegularFile,
     ExecutableFile,
     SymbolicLink,
     Directory,
+    Submodule,
 }
 
 impl TreeEntryMode {
@@ -145,6 +194,7 @@ impl TreeEntryMode {
             "100755" => TreeEntryMode::ExecutableFile,
             "120000" => TreeEntryMode::SymbolicLink,
             "40000" => TreeEntryMode::Directory,
+            "160000" => TreeEntryMode::Submodule,
             _ => {
                 return Err(format!(
                     "tree entry mode string not recognized (Given {})",
@@ -164,6 +214,7 @@ impl fmt::Display for TreeEntryMode {
             TreeEntryMode::ExecutableFile => "100755",
             TreeEntryMode::SymbolicLink => "120000",
             TreeEntryMode::Directory => "040000",
+            TreeEntryMode::Submodule => "160000",
         };
 
         write!(f, "{}", mode_str)
@@ -291,6 +342,16 @@ mod tests {
         assert_eq!(content, "040000 tree abc123 dir1\n");
     }
 
+    #[test]
+    fn tree_object_new_with_submodule_entry() {
+        let content = b"160000 vendor\0".to_vec();
+        let content = [content, vec![4; 20]].concat();
+        let tree_object = Tree::new(content).unwrap();
+
+        assert_eq!(tree_object.tree_entries[0].mode, TreeEntryMode::Submodule);
+        assert_eq!(tree_object.tree_entries[0].object_type(), "commit");
+    }
+
     #[test]
     fn get_content_returns_correct_format_for_symbolic_link_entry() {
         let tree_entries = vec![TreeEntry {
@@ -307,4 +368,45 @@ mod tests {
 
         assert_eq!(content, "120000 blob abc123 link1\n");
     }
+
+    #[test]
+    fn check_accepts_directories_sorted_with_a_trailing_slash() {
+        let content = [
+            b"100644 a.txt\0".to_vec(),
+            vec![1; 20],
+            b"40000 a\0".to_vec(),
+            vec![2; 20],
+        ]
+        .concat();
+        let tree_object = Tree::new(content).unwrap();
+
+        assert!(tree_object.check().is_ok());
+    }
+
+    #[test]
+    fn check_returns_error_for_unsorted_and_duplicate_entries() {
+        let unsorted = [
+            b"100644 b\0".to_vec(),
+            vec![1; 20],
+            b"100644 a\0".to_vec(),
+            vec![2; 20],
+        ]
+        .concat();
+        let duplicate = [
+            b"100644 a\0".to_vec(),
+            vec![1; 20],
+            b"40000 a\0".to_vec(),
+            vec![2; 20],
+        ]
+        .concat();
+
+        assert_eq!(
+            Tree::new(unsorted).unwrap().check().unwrap_err(),
+            "treeNotSorted: not properly sorted"
+        );
+        assert_eq!(
+            Tree::new(duplicate).unwrap().check().unwrap_err(),
+            "duplicateEntries: contains duplicate file entries"
+        );
+    }
 }